@@ -0,0 +1,51 @@
+use protocol::address::Address;
+use wallet::Wallet;
+
+static USAGE: &'static str = "Usage: annotate ADDRESS NAME [VALUE]";
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    // The rest of the arguments are the value, so it doesn't have to be
+    // quoted to have spaces in it.
+    let (encoded, name, value) = match args {
+        [ref address, ref name] => (address.as_slice(), name.as_slice(), None),
+        [ref address, ref name, value..] => (address.as_slice(), name.as_slice(), Some(value.connect(" "))),
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    let address = match Address::decode(encoded) {
+        Some(address) => address,
+        None => {
+            println!("'{}' is not a valid Bitcoin address.", encoded);
+            return;
+        }
+    };
+
+    let mut wallet = match Wallet::load(&wallet_path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error loading wallet: {}", e);
+            return;
+        }
+    };
+
+    match wallet.set_metadata(&address, name, value.as_ref().map(|value| value.as_slice())) {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Can't annotate {}: {}.", encoded, e);
+            return;
+        }
+    }
+
+    match value {
+        Some(ref value) => println!("Set {} of {} to '{}'.", name, encoded, value),
+        None => println!("Removed {} from {}.", name, encoded)
+    }
+
+    match wallet.save() {
+        Ok(_) => {}
+        Err(e) => println!("Error saving wallet: {}", e)
+    }
+}
@@ -0,0 +1,40 @@
+use protocol::bip38;
+use protocol::bip38::{MAX_LOT, MAX_SEQUENCE};
+use util::secret;
+
+static USAGE: &'static str = "Usage: bip38-code [LOT SEQUENCE]";
+
+pub fn run(_: Path, args: &[String]) {
+    let lot_and_sequence = match args {
+        [] => None,
+        [ref lot, ref sequence] => {
+            match (from_str::<u32>(lot.as_slice()), from_str::<u32>(sequence.as_slice())) {
+                (Some(lot), Some(sequence)) if lot <= MAX_LOT && sequence <= MAX_SEQUENCE => Some((lot, sequence)),
+                _ => {
+                    println!("The lot must be from 0 to {}, and the sequence number from 0 to {}.",
+                             MAX_LOT, MAX_SEQUENCE);
+                    return;
+                }
+            }
+        }
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    // The passphrase is all it takes to decrypt every key made from the code,
+    // and nothing else is kept, so it has to be remembered or written down.
+    let mut passphrase = match super::prompt_new_passphrase() {
+        Some(passphrase) => passphrase,
+        None => return
+    };
+    println!("Making the code, which takes a few seconds...");
+    let code = bip38::intermediate_code(passphrase.as_slice(), lot_and_sequence);
+    unsafe { secret::wipe(passphrase.as_mut_vec().as_mut_slice()); }
+
+    println!("{}", code);
+    println!("Give this code to whoever is making the keys, with bip38-gen or any other");
+    println!("BIP 38 software. They can't decrypt the keys, but anyone with the code can");
+    println!("make keys that look like yours, so only give it to someone you trust.");
+}
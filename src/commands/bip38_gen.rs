@@ -0,0 +1,40 @@
+use protocol::bip38;
+
+static USAGE: &'static str = "Usage: bip38-gen [--compressed] CODE [N]";
+
+pub fn run(_: Path, args: &[String]) {
+    // No wallet is needed, or touched: the keys can only be decrypted with
+    // the passphrase that the code was made from, so they're printed for
+    // its owner to import.
+    let (compressed, args) = match args {
+        [ref option, rest..] if option.as_slice() == "--compressed" => (true, rest),
+        _ => (false, args)
+    };
+
+    let (code, n) = match args {
+        [ref code] => (code.as_slice(), 1),
+        [ref code, ref n] => {
+            match from_str::<uint>(n.as_slice()) {
+                Some(n) if n > 0 => (code.as_slice(), n),
+                _ => {
+                    println!("'{}' is not a valid number of keys.", n);
+                    return;
+                }
+            }
+        }
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    for _ in range(0, n) {
+        match bip38::encrypt_from_intermediate_code(code, compressed) {
+            Some((encrypted, address)) => println!("{} {}", address.encode(), encrypted),
+            None => {
+                println!("'{}' is not a valid BIP 38 intermediate code.", code);
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::os;
+
+use wallet;
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    if !args.is_empty() {
+        println!("Usage: check");
+        os::set_exit_status(2);
+        return;
+    }
+
+    // Backup scripts can tell from the exit status whether the wallet file is
+    // valid (0), has problems (1) or couldn't be checked at all (2).
+    match wallet::check(&wallet_path) {
+        Ok(problems) => {
+            for problem in problems.iter() {
+                println!("{}", problem);
+            }
+
+            if problems.is_empty() {
+                println!("{} is valid.", wallet_path.display());
+            } else {
+                println!("Found {} problem{} in {}.", problems.len(), if problems.len() == 1 { "" } else { "s" },
+                         wallet_path.display());
+                os::set_exit_status(1);
+            }
+        }
+        Err(e) => {
+            println!("Error reading wallet: {}", e);
+            os::set_exit_status(2);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use protocol::extended_key::{ExtendedPrivateKey, CHAIN_CODE_LENGTH};
+use protocol::private_key;
+use protocol::private_key::PrivateKey;
+use protocol::share::Share;
+
+static USAGE: &'static str = "Usage: combine [--type p2pkh|p2sh-p2wpkh|p2wpkh|p2tr] [--gap-limit N] [--used FILE]";
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    // This restores a wallet like restore does, but from the shares made by
+    // split instead of a mnemonic. The shares are read from standard input
+    // so that they don't end up in the shell's history.
+    let options = match super::restore::parse_options(args, USAGE) {
+        Some(options) => options,
+        None => return
+    };
+
+    let mut shares: Vec<Share> = Vec::new();
+    loop {
+        // The first share says how many are needed.
+        let threshold = shares.as_slice().first().map(|share| share.get_threshold());
+        if threshold == Some(shares.len()) { break; }
+
+        let message = match threshold {
+            Some(threshold) => format!("Share {} of {} (blank to stop): ", shares.len() + 1, threshold),
+            None => format!("Share 1 (blank to stop): ")
+        };
+        let mut phrase = super::prompt_secret(message.as_slice());
+        if phrase.as_slice().trim().is_empty() {
+            println!("No wallet was created.");
+            return;
+        }
+
+        let share = Share::parse(phrase.as_slice());
+        super::wipe_string(&mut phrase);
+        let share = match share {
+            Some(share) => share,
+            None => {
+                println!("That isn't a valid share. Check that every word is spelled correctly");
+                println!("and that they're in the right order.");
+                continue;
+            }
+        };
+
+        if shares.iter().any(|other| other.get_index() == share.get_index()) {
+            println!("That share has already been entered.");
+        } else if shares.iter().any(|other| other.get_identifier() != share.get_identifier()) {
+            println!("That share is from a different split than the first one.");
+        } else {
+            shares.push(share);
+        }
+    }
+
+    let secret = match Share::combine(shares.as_slice()) {
+        Ok(secret) => secret,
+        Err(e) => {
+            println!("The shares can't be combined: {}.", e);
+            return;
+        }
+    };
+
+    // The secret is the master key's chain code, then its private key.
+    let master_key = if secret.len() == CHAIN_CODE_LENGTH + private_key::LENGTH {
+        let (chain_code, key) = secret.as_slice().split_at(CHAIN_CODE_LENGTH);
+        PrivateKey::new(key).map(|key| ExtendedPrivateKey::from_master_parts(chain_code, key))
+    } else {
+        None
+    };
+
+    match master_key {
+        Some(master_key) => super::restore::restore_wallet(wallet_path, master_key, &options),
+        None => println!("The shares don't hold a wallet's master key.")
+    }
+}
@@ -0,0 +1,57 @@
+use protocol::multisig::MultisigType;
+use wallet::Wallet;
+
+static USAGE: &'static str = "Usage: cosigner-key [--type p2wsh|p2sh-p2wsh] [--account N]";
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    let mut multisig_type = MultisigType::P2WSH;
+    let mut account = 0u32;
+
+    let mut i = 0;
+    while i < args.len() {
+        if i + 1 >= args.len() {
+            println!("{}", USAGE);
+            return;
+        }
+
+        let value = args[i + 1].as_slice();
+        match args[i].as_slice() {
+            "--type" => {
+                multisig_type = match MultisigType::from_name(value) {
+                    Some(multisig_type) => multisig_type,
+                    None => {
+                        println!("'{}' is not a valid multisig type.", value);
+                        return;
+                    }
+                };
+            }
+            "--account" => {
+                account = match super::parse_account(value) {
+                    Some(account) => account,
+                    None => return
+                };
+            }
+            _ => {
+                println!("{}", USAGE);
+                return;
+            }
+        }
+        i += 2;
+    }
+
+    let wallet = match Wallet::load(&wallet_path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error loading wallet: {}", e);
+            return;
+        }
+    };
+
+    match wallet.cosigner_key(multisig_type, account) {
+        Some(cosigner) => {
+            println!("Give this key to the other cosigners of a {} multisig wallet:", multisig_type.name());
+            println!("{}", cosigner.encode());
+        }
+        None => println!("Only HD wallets can be multisig cosigners.")
+    }
+}
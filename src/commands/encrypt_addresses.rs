@@ -0,0 +1,40 @@
+use wallet::Wallet;
+
+static USAGE: &'static str = "Usage: encrypt-addresses on|off";
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    let encrypt_addresses = match args {
+        [ref value] if value.as_slice() == "on" => true,
+        [ref value] if value.as_slice() == "off" => false,
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    let mut wallet = match Wallet::load(&wallet_path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error loading wallet: {}", e);
+            return;
+        }
+    };
+
+    if wallet.get_encrypt_addresses() == encrypt_addresses {
+        println!("The wallet's addresses are already {}.",
+                 if encrypt_addresses { "encrypted" } else { "in plaintext" });
+        return;
+    }
+
+    wallet.set_encrypt_addresses(encrypt_addresses);
+    match wallet.save() {
+        Ok(_) => {
+            if encrypt_addresses {
+                println!("The wallet's aliases and addresses are now encrypted.");
+            } else {
+                println!("The wallet's aliases and addresses are now stored in plaintext.");
+            }
+        }
+        Err(e) => println!("Error saving wallet: {}", e)
+    }
+}
@@ -0,0 +1,370 @@
+use std::io::{File, MemReader};
+use std::num::Float;
+
+use protocol::address::{Address, AddressType};
+use protocol::bip38;
+use protocol::private_key::PrivateKey;
+use util::base58;
+use util::qr::{QrCode, ErrorCorrection};
+use util::secret;
+use wallet::Wallet;
+use wallet_parser;
+
+static USAGE: &'static str = "Usage: export --paper [--svg] [--bip38] [ADDRESS] FILE\n       \
+                               export --bip38 ADDRESS";
+
+// The wallet file's values that also go on the page as QR codes, since
+// they're long strings of hex that would be tedious to type back in.
+static QR_KEYS: [&'static str, ..6] = ["!salt", "!iv", "!encrypted_data", "!encrypted_keys", "!addresses_iv",
+                                        "!encrypted_addresses"];
+
+// The most hex digits in each QR code, which keeps them to version 15 (77
+// modules across) in SVG and version 5 (37 modules across) in text, where
+// each module takes two characters.
+static SVG_QR_CHUNK_LENGTH: uint = 600;
+static TEXT_QR_CHUNK_LENGTH: uint = 120;
+
+// The layout of the SVG page, in millimetres: an A4 width, and up to two QR
+// codes side by side.
+static SVG_PAGE_WIDTH: f64 = 210.0;
+static SVG_MARGIN: f64 = 15.0;
+static SVG_LINE_HEIGHT: f64 = 3.4;
+static SVG_FONT_SIZE: f64 = 2.6;
+static SVG_QR_WIDTH: f64 = 85.0;
+
+// The light border around each QR code, in modules.
+static QUIET_ZONE: uint = 4;
+static TEXT_QUIET_ZONE: uint = 2;
+
+// A page to print: a title, lines of text, and labelled QR codes.
+struct Page {
+    title: String,
+    lines: Vec<String>,
+    codes: Vec<(String, QrCode)>
+}
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    let mut paper = false;
+    let mut svg = false;
+    let mut bip38 = false;
+    let mut rest = Vec::new();
+    for arg in args.iter() {
+        match arg.as_slice() {
+            "--paper" => paper = true,
+            "--svg" => svg = true,
+            "--bip38" => bip38 = true,
+            value if !value.starts_with("-") => rest.push(value),
+            _ => {
+                println!("{}", USAGE);
+                return;
+            }
+        }
+    }
+
+    // A BIP 38 key is for a single address, and without --paper it's just
+    // printed.
+    let (address, output_path) = match rest.as_slice() {
+        [path] if paper && !bip38 => (None, Some(Path::new(path))),
+        [address, path] if paper => (Some(address), Some(Path::new(path))),
+        [address] if bip38 && !paper => (Some(address), None),
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    let wallet = match Wallet::load(&wallet_path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error loading wallet: {}", e);
+            return;
+        }
+    };
+
+    let key = match address {
+        Some(address) => {
+            match find_private_key(&wallet, address) {
+                Some(key) => Some(key),
+                None => return
+            }
+        }
+        None => None
+    };
+
+    let encrypted = if bip38 {
+        let (ref decoded, private_key) = *key.as_ref().unwrap();
+        let mut passphrase = match super::prompt_new_passphrase() {
+            Some(passphrase) => passphrase,
+            None => return
+        };
+        println!("Encrypting, which takes a few seconds...");
+        let compressed = decoded.get_type() != AddressType::P2PKH || private_key.to_compressed_address() == *decoded;
+        let encrypted = bip38::encrypt(private_key, compressed, passphrase.as_slice());
+        super::wipe_string(&mut passphrase);
+        Some(encrypted)
+    } else {
+        None
+    };
+
+    let output_path = match output_path {
+        Some(output_path) => output_path,
+        None => {
+            println!("{}", encrypted.unwrap());
+            return;
+        }
+    };
+
+    let chunk_length = if svg { SVG_QR_CHUNK_LENGTH } else { TEXT_QR_CHUNK_LENGTH };
+    let mut page = match key {
+        Some((ref decoded, private_key)) => {
+            address_page(address.unwrap(), decoded, private_key, encrypted.as_ref().map(|key| key.as_slice()))
+        }
+        None => {
+            match wallet_page(&wallet_path, chunk_length) {
+                Some(page) => page,
+                None => return
+            }
+        }
+    };
+
+    let mut output = if svg { render_svg(&page) } else { render_text(&page) };
+    match File::create(&output_path).write_str(output.as_slice()) {
+        Ok(_) => {
+            println!("Paper backup written to {}.", output_path.display());
+            if address.is_some() && !bip38 {
+                println!("It holds an unencrypted private key, so delete it securely once it's printed.");
+            }
+        }
+        Err(e) => println!("Error writing {}: {}", output_path.display(), e)
+    }
+
+    // An address's page has its private key in it.
+    unsafe {
+        secret::wipe(output.as_mut_vec().as_mut_slice());
+        for line in page.lines.iter_mut() {
+            secret::wipe(line.as_mut_vec().as_mut_slice());
+        }
+    }
+}
+
+// Makes a page with the whole wallet file on it, and QR codes of its
+// encrypted data, salt and IVs. Prints an error and returns None if the file
+// can't be read or a value is too long for a QR code.
+fn wallet_page(wallet_path: &Path, chunk_length: uint) -> Option<Page> {
+    let contents = match File::open(wallet_path).read_to_string() {
+        Ok(contents) => contents,
+        Err(e) => {
+            println!("Error reading wallet: {}", e);
+            return None;
+        }
+    };
+    let name = wallet_path.display().to_string();
+    let parsed = match wallet_parser::parse(&mut MemReader::new(contents.clone().into_bytes()), name.as_slice()) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            println!("Error reading wallet: {}", e);
+            return None;
+        }
+    };
+
+    let mut lines = vec![
+        format!("This is a backup of the wallet file {}, printed in full below.", wallet_path.display()),
+        "To restore it, type the file back in. The QR codes hold its long hex".to_string(),
+        "values, in uppercase: each one is labelled with the key it belongs to,".to_string(),
+        "each encrypted key has codes of its own, and a value split over".to_string(),
+        "several codes is their parts joined in order.".to_string(),
+        "The private keys stay encrypted with the wallet's passphrase, and the".to_string(),
+        "comments in the file explain how to decrypt them without Cykas.".to_string(),
+        "".to_string()
+    ];
+    lines.extend(contents.as_slice().lines().map(|line| line.to_string()));
+
+    let mut codes = Vec::new();
+    for key in QR_KEYS.iter() {
+        let values = match parsed.iter().find(|&&(ref k, _)| k.as_slice() == *key) {
+            Some(&(_, ref values)) => values,
+            None => continue
+        };
+
+        // Each line of '!encrypted_keys' is a key encrypted on its own, so
+        // it's labelled on its own too. Other values are only split over
+        // lines to keep them short.
+        let parts = if *key == "!encrypted_keys" {
+            values.iter().enumerate().map(|(i, value)| {
+                (format!("{}, key {} of {}", key, i + 1, values.len()), value.clone())
+            }).collect::<Vec<(String, String)>>()
+        } else {
+            vec![(key.to_string(), values.concat())]
+        };
+
+        for &(ref part_label, ref value) in parts.iter() {
+            let value = value.as_slice().chars().map(|ch| ch.to_uppercase()).collect::<String>();
+            let chunks: Vec<&[u8]> = value.as_bytes().chunks(chunk_length).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let label = if chunks.len() == 1 {
+                    part_label.clone()
+                } else {
+                    format!("{} ({} of {})", part_label, i + 1, chunks.len())
+                };
+                match QrCode::encode(*chunk, ErrorCorrection::Medium) {
+                    Some(code) => codes.push((label, code)),
+                    None => {
+                        println!("The value of '{}' is too long for a QR code.", key);
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    Some(Page { title: "Cykas wallet backup".to_string(), lines: lines, codes: codes })
+}
+
+// Finds the private key for an address in the wallet. Prints an error and
+// returns None if the address isn't valid or the wallet doesn't have its key.
+fn find_private_key<'a>(wallet: &'a Wallet, address: &str) -> Option<(Address, &'a PrivateKey)> {
+    let decoded = match Address::decode(address) {
+        Some(decoded) => decoded,
+        None => {
+            println!("'{}' is not a valid address.", address);
+            return None;
+        }
+    };
+    match wallet.get_private_key(&decoded) {
+        Some(private_key) => Some((decoded, private_key)),
+        None => {
+            println!("The wallet doesn't have the private key for {}, or it's a multisig address.", address);
+            None
+        }
+    }
+}
+
+// Makes a page for a single address, with its private key in WIF, or
+// encrypted with BIP 38 if it's given that way.
+fn address_page(address: &str, decoded: &Address, private_key: &PrivateKey, encrypted: Option<&str>) -> Page {
+    // Other wallets tell which addresses a WIF key is for from its
+    // compression flag and the descriptor it's imported with.
+    let (wif, descriptor) = match decoded.get_type() {
+        AddressType::P2PKH if private_key.to_compressed_address() == *decoded => {
+            (private_key.to_compressed_wif(), "pkh(KEY)")
+        }
+        AddressType::P2PKH => (private_key.to_wif(), "pkh(KEY)"),
+        AddressType::P2SH => (private_key.to_compressed_wif(), "sh(wpkh(KEY))"),
+        AddressType::P2TR => (private_key.to_compressed_wif(), "tr(KEY)"),
+        _ => (private_key.to_compressed_wif(), "wpkh(KEY)")
+    };
+    let mut wif = base58::encode(wif.as_slice());
+
+    // Bech32 addresses can be uppercase, which fits in a smaller QR code.
+    let address_qr = match decoded.get_type() {
+        AddressType::P2WPKH | AddressType::P2WSH | AddressType::P2TR => {
+            address.chars().map(|ch| ch.to_uppercase()).collect::<String>()
+        }
+        _ => address.to_string()
+    };
+
+    let (lines, codes) = {
+        let (lines, key_label, key) = match encrypted {
+            Some(encrypted) => (vec![
+                format!("Address: {}", address),
+                format!("Private key (BIP 38): {}", encrypted),
+                "".to_string(),
+                "The private key is encrypted with a passphrase that isn't on this page.".to_string(),
+                "To spend the bitcoins sent to the address, import the key with the".to_string(),
+                "passphrase into a wallet that supports BIP 38, such as with Cykas's".to_string(),
+                format!("import command, or decrypt it and import it as {}.", descriptor)
+            ], "Private key (BIP 38)", encrypted),
+            None => (vec![
+                format!("Address: {}", address),
+                format!("Private key (WIF): {}", wif),
+                "".to_string(),
+                "The private key is NOT encrypted. Anyone who sees this page can spend".to_string(),
+                "the bitcoins sent to the address, so keep it somewhere safe.".to_string(),
+                format!("To spend them with another wallet, import the key as {}.", descriptor)
+            ], "Private key (WIF)", wif.as_slice())
+        };
+        let codes = vec![
+            ("Address".to_string(), QrCode::encode(address_qr.as_bytes(), ErrorCorrection::Medium).unwrap()),
+            (key_label.to_string(), QrCode::encode(key.as_bytes(), ErrorCorrection::Medium).unwrap())
+        ];
+        (lines, codes)
+    };
+    unsafe { secret::wipe(wif.as_mut_vec().as_mut_slice()); }
+
+    Page { title: "Cykas paper wallet".to_string(), lines: lines, codes: codes }
+}
+
+// Renders the page as plain text, with the QR codes drawn in '#'s.
+fn render_text(page: &Page) -> String {
+    let mut output = String::new();
+    output.push_str(format!("{}\n{}\n\n", page.title, "=".repeat(page.title.len())).as_slice());
+    for line in page.lines.iter() {
+        output.push_str(format!("{}\n", line).as_slice());
+    }
+    for &(ref label, ref code) in page.codes.iter() {
+        output.push_str(format!("\n{}\n", label).as_slice());
+        for row in code.to_ascii(TEXT_QUIET_ZONE).iter() {
+            output.push_str(format!("{}\n", row.as_slice().trim_right()).as_slice());
+        }
+    }
+    output
+}
+
+// Renders the page as an SVG image the width of an A4 page, and as long as
+// it needs to be.
+fn render_svg(page: &Page) -> String {
+    let mut body = String::new();
+    let mut y = SVG_MARGIN;
+
+    body.push_str(format!("<text x=\"{:.2}\" y=\"{:.2}\" font-size=\"6\" font-weight=\"bold\">{}</text>\n",
+                          SVG_MARGIN, y + 6.0, escape_xml(page.title.as_slice())).as_slice());
+    y += 12.0;
+
+    for line in page.lines.iter() {
+        y += SVG_LINE_HEIGHT;
+        body.push_str(format!("<text x=\"{:.2}\" y=\"{:.2}\">{}</text>\n",
+                              SVG_MARGIN, y, escape_xml(line.as_slice())).as_slice());
+    }
+    y += SVG_LINE_HEIGHT * 2.0;
+
+    // The codes go in rows of two, each with its label above it.
+    for row in page.codes.as_slice().chunks(2) {
+        let mut row_height = 0.0f64;
+        for (i, &(ref label, ref code)) in row.iter().enumerate() {
+            let x = SVG_MARGIN + i as f64 * (SVG_QR_WIDTH + SVG_MARGIN);
+            let modules = (code.get_size() + QUIET_ZONE * 2) as f64;
+            let module_size = (SVG_QR_WIDTH / modules).min(1.0);
+            let quiet_zone = QUIET_ZONE as f64 * module_size;
+
+            body.push_str(format!("<text x=\"{:.2}\" y=\"{:.2}\">{}</text>\n",
+                                  x, y + SVG_LINE_HEIGHT, escape_xml(label.as_slice())).as_slice());
+            body.push_str(code.to_svg_path(x + quiet_zone, y + SVG_LINE_HEIGHT + quiet_zone, module_size).as_slice());
+            body.push_str("\n");
+            row_height = row_height.max(SVG_LINE_HEIGHT + modules * module_size);
+        }
+        y += row_height + SVG_LINE_HEIGHT;
+    }
+    y += SVG_MARGIN;
+
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0:.2}mm\" height=\"{1:.2}mm\" \
+             viewBox=\"0 0 {0:.2} {1:.2}\">\n\
+             <rect width=\"100%\" height=\"100%\" fill=\"#fff\"/>\n\
+             <g font-family=\"monospace\" font-size=\"{2:.2}\" xml:space=\"preserve\">\n\
+             {3}</g>\n\
+             </svg>\n", SVG_PAGE_WIDTH, y, SVG_FONT_SIZE, body)
+}
+
+// Escapes the characters that can't appear as themselves in XML text.
+fn escape_xml(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            _ => result.push(ch)
+        }
+    }
+    result
+}
@@ -0,0 +1,97 @@
+use std::io::File;
+
+use wallet::Wallet;
+
+static USAGE: &'static str = "Usage: export-watchonly [--addresses | --xpub] [FILE]";
+
+// What to describe the wallet's addresses with.
+enum Format {
+    Descriptors,
+    Addresses,
+    AccountXpubs
+}
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    let mut format = Format::Descriptors;
+    let mut output_path = None;
+
+    for arg in args.iter() {
+        match arg.as_slice() {
+            "--addresses" => format = Format::Addresses,
+            "--xpub" => format = Format::AccountXpubs,
+            path if !path.starts_with("-") && output_path.is_none() => {
+                output_path = Some(Path::new(path));
+            }
+            _ => {
+                println!("{}", USAGE);
+                return;
+            }
+        }
+    }
+
+    let wallet = match Wallet::load(&wallet_path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error loading wallet: {}", e);
+            return;
+        }
+    };
+
+    // Only public data goes in the output: descriptors with public keys,
+    // extended public keys, or addresses. Lines starting with '#' are
+    // comments naming the alias the lines after them belong to.
+    let mut lines = Vec::new();
+    match format {
+        Format::Descriptors | Format::Addresses => {
+            for alias in wallet.get_aliases().into_iter() {
+                lines.push(format!("# {}", alias));
+                match format {
+                    Format::Addresses => {
+                        for address in wallet.get_addresses(alias).unwrap().iter() {
+                            lines.push(address.encode());
+                        }
+                    }
+                    _ => lines.push_all(wallet.get_descriptors(alias).unwrap().as_slice())
+                }
+            }
+        }
+        Format::AccountXpubs => {
+            let descriptors = wallet.get_account_descriptors();
+            if descriptors.is_empty() {
+                println!("The wallet has no accounts that can be described with an xpub. Only");
+                println!("HD wallets with addresses in the standard layout have them.");
+                return;
+            }
+            lines = descriptors;
+        }
+    }
+
+    match output_path {
+        Some(path) => {
+            let mut file = match File::create(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    println!("Error creating {}: {}", path.display(), e);
+                    return;
+                }
+            };
+
+            for line in lines.iter() {
+                match writeln!(&mut file, "{}", line) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Error writing {}: {}", path.display(), e);
+                        return;
+                    }
+                }
+            }
+
+            println!("Watch-only data written to {}.", path.display());
+        }
+        None => {
+            for line in lines.iter() {
+                println!("{}", line);
+            }
+        }
+    }
+}
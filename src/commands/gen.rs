@@ -0,0 +1,143 @@
+use protocol::address::AddressType;
+use protocol::private_key::{PrivateKey, MIN_DICE_ROLLS};
+use util::entropy;
+use util::entropy::{Dice, EntropySource};
+use wallet::Wallet;
+
+static USAGE: &'static str = "Usage: gen [--type TYPE] [--dice] ALIAS [N]";
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    let mut address_type = None;
+    let mut dice = false;
+
+    let mut i = 0;
+    while i < args.len() && args[i].as_slice().starts_with("-") {
+        match args[i].as_slice() {
+            "--dice" => {
+                dice = true;
+                i += 1;
+            }
+            "--type" if i + 1 < args.len() => {
+                let value = args[i + 1].as_slice();
+                address_type = match super::parse_address_type(value) {
+                    Some(address_type) => Some(address_type),
+                    None => {
+                        println!("'{}' is not a valid address type.", value);
+                        return;
+                    }
+                };
+                i += 2;
+            }
+            _ => {
+                println!("{}", USAGE);
+                return;
+            }
+        }
+    }
+
+    let (alias, n) = match args.slice_from(i) {
+        [ref alias] => (alias.as_slice(), 1),
+        [ref alias, ref n] if !dice => {
+            match from_str::<uint>(n.as_slice()) {
+                Some(n) if n > 0 => (alias.as_slice(), n),
+                _ => {
+                    println!("'{}' is not a valid number of addresses.", n);
+                    return;
+                }
+            }
+        }
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    if alias.is_empty() || !alias.chars().all(|ch| ch.is_alphanumeric() || ch == '_') {
+        println!("Aliases can only contain letters, numbers and underscores.");
+        return;
+    }
+
+    let mut wallet = match Wallet::load(&wallet_path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error loading wallet: {}", e);
+            return;
+        }
+    };
+
+    if wallet.get_multisig(alias).is_some() {
+        println!("'{}' is a multisig alias; use multisig to add addresses to it.", alias);
+        return;
+    }
+
+    // HD wallets default to native SegWit addresses, like restore does.
+    let address_type = match address_type {
+        Some(address_type) => address_type,
+        None if wallet.get_master_key().is_some() => AddressType::P2WPKH,
+        None => AddressType::P2PKH
+    };
+
+    if dice {
+        let private_key = match dice_private_key() {
+            Some(private_key) => private_key,
+            None => return
+        };
+        let address = wallet.add_private_key(alias, private_key, address_type);
+        println!("Added {} to '{}'.", address.encode(), alias);
+    } else {
+        match wallet.gen_multiple(alias, n, address_type) {
+            Ok(()) => println!("Added {} address{} to '{}'.", n, if n == 1 { "" } else { "es" }, alias),
+            Err(e) => {
+                println!("Error generating addresses: {}", e);
+                return;
+            }
+        }
+    }
+
+    match wallet.save() {
+        Ok(_) => {}
+        Err(e) => println!("Error saving wallet: {}", e)
+    }
+}
+
+// Asks for dice rolls and converts them to a private key, as described in
+// PrivateKey::from_dice_rolls(). Prints an error and returns None if there
+// aren't enough rolls, they don't pass the entropy health tests, or they
+// don't give a valid key.
+fn dice_private_key() -> Option<PrivateKey> {
+    println!("Roll a six-sided die at least {} times and type in the numbers it shows,", MIN_DICE_ROLLS);
+    println!("in order, all on one line. Spaces between them are optional.");
+    let mut line = super::prompt("Rolls: ");
+    let rolls = Dice::parse(line.as_slice(), 6);
+    super::wipe_string(&mut line);
+
+    let samples = match rolls.map(|mut rolls| rolls.read()) {
+        Some(Ok(samples)) => samples,
+        _ => {
+            println!("The rolls can only be the numbers 1 to 6.");
+            return None;
+        }
+    };
+
+    if samples.data.len() < MIN_DICE_ROLLS {
+        println!("Only {} rolls were given, but at least {} are needed for 256 bits of entropy.",
+                 samples.data.len(), MIN_DICE_ROLLS);
+        return None;
+    }
+
+    match entropy::health_test(samples.data.as_slice(), samples.symbols) {
+        Ok(()) => {}
+        Err(test) => {
+            println!("The rolls failed the {} test, so they don't look random. Please roll again.", test);
+            return None;
+        }
+    }
+
+    match PrivateKey::from_dice_rolls(samples.data.as_slice()) {
+        Some(private_key) => Some(private_key),
+        None => {
+            println!("The rolls don't give a valid private key. Please roll again.");
+            None
+        }
+    }
+}
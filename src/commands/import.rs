@@ -0,0 +1,79 @@
+use protocol::address::AddressType;
+use protocol::bip38;
+use util::secret;
+use wallet::Wallet;
+
+static USAGE: &'static str = "Usage: import [--type TYPE] ALIAS";
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    let (address_type, alias) = match args {
+        [ref alias] => (None, alias.as_slice()),
+        [ref option, ref value, ref alias] if option.as_slice() == "--type" => {
+            match super::parse_address_type(value.as_slice()) {
+                Some(address_type) => (Some(address_type), alias.as_slice()),
+                None => {
+                    println!("'{}' is not a valid address type.", value);
+                    return;
+                }
+            }
+        }
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    if alias.is_empty() || !alias.chars().all(|ch| ch.is_alphanumeric() || ch == '_') {
+        println!("Aliases can only contain letters, numbers and underscores.");
+        return;
+    }
+
+    let mut wallet = match Wallet::load(&wallet_path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error loading wallet: {}", e);
+            return;
+        }
+    };
+
+    if wallet.get_multisig(alias).is_some() {
+        println!("'{}' is a multisig alias; use multisig to add addresses to it.", alias);
+        return;
+    }
+
+    let encrypted = super::prompt("BIP 38 encrypted private key: ");
+    let mut passphrase = super::prompt_secret("Passphrase: ");
+    println!("Decrypting, which takes a few seconds...");
+    let result = bip38::decrypt(encrypted.as_slice(), passphrase.as_slice());
+    unsafe { secret::wipe(passphrase.as_mut_vec().as_mut_slice()); }
+
+    let (private_key, compressed) = match result {
+        Ok(decrypted) => decrypted,
+        Err(e) => {
+            println!("The key can't be decrypted: {}.", e);
+            return;
+        }
+    };
+
+    // The key was made for the P2PKH address of its compressed or
+    // uncompressed public key. Keys added from outside the HD layout get the
+    // uncompressed kind, so a compressed key is imported as P2WPKH unless
+    // another type is asked for.
+    let address_type = match address_type {
+        Some(address_type) => address_type,
+        None if compressed => AddressType::P2WPKH,
+        None => AddressType::P2PKH
+    };
+    if compressed && address_type == AddressType::P2PKH {
+        println!("WARNING: the key is for the P2PKH address of its compressed public key, and the");
+        println!("wallet gives imported keys the uncompressed one, so its address will differ.");
+    }
+
+    let address = wallet.add_private_key(alias, private_key, address_type);
+    println!("Added {} to '{}'.", address.encode(), alias);
+
+    match wallet.save() {
+        Ok(_) => {}
+        Err(e) => println!("Error saving wallet: {}", e)
+    }
+}
@@ -1,2 +1,152 @@
 pub mod new;
+pub mod gen;
+pub mod restore;
+pub mod split;
+pub mod combine;
+pub mod export_watchonly;
+pub mod cosigner_key;
+pub mod multisig;
+pub mod sign_psbt;
+pub mod scan_psbt;
+pub mod prove;
+pub mod verify_proof;
+pub mod encrypt_addresses;
+pub mod export;
+pub mod import;
+pub mod bip38_code;
+pub mod bip38_gen;
+pub mod show;
+pub mod annotate;
+pub mod check;
 
+use std::io;
+
+use protocol::address::AddressType;
+use protocol::derivation_path::HARDENED;
+use util::qr::{QrCode, ErrorCorrection};
+use util::secret;
+use util::terminal::EchoOff;
+use util::ur;
+
+// The light border around QR codes shown in the terminal, in modules.
+static QR_QUIET_ZONE: uint = 4;
+
+// The longest fragment of a message in each part of a multi-part UR, which
+// keeps its QR codes small enough to read off a screen.
+static UR_MAX_FRAGMENT_LENGTH: uint = 100;
+
+// Prints the given message and reads a line from standard input, returning it
+// without the line ending. Returns an empty string if nothing can be read.
+fn prompt(message: &str) -> String {
+    print!("{}", message);
+    io::stdio::flush();
+
+    match io::stdin().read_line() {
+        Ok(mut line) => {
+            let result = line.as_slice().trim_right_chars(|ch: char| ch == '\n' || ch == '\r').to_string();
+            wipe_string(&mut line);
+            result
+        }
+        Err(_) => String::new()
+    }
+}
+
+// Like prompt(), but for a secret, like a passphrase or mnemonic words: if
+// standard input is a terminal, what's typed isn't shown. The caller should
+// wipe the String with wipe_string() once it's done with it.
+fn prompt_secret(message: &str) -> String {
+    let line = {
+        let _echo_off = EchoOff::new();
+        prompt(message)
+    };
+    // The line break that was typed wasn't shown either.
+    println!("");
+    line
+}
+
+// Overwrites a String that held a secret with zeros.
+fn wipe_string(string: &mut String) {
+    unsafe { secret::wipe(string.as_mut_vec().as_mut_slice()); }
+}
+
+// Asks for a new passphrase, twice, without showing it. Prints an error and
+// returns None if it's empty or the two don't match.
+fn prompt_new_passphrase() -> Option<String> {
+    let mut passphrase = prompt_secret("Passphrase: ");
+    if passphrase.is_empty() {
+        println!("The passphrase can't be empty.");
+        return None;
+    }
+    let mut repeated = prompt_secret("Repeat the passphrase: ");
+    let matches = repeated == passphrase;
+    wipe_string(&mut repeated);
+    if !matches {
+        println!("The passphrases don't match.");
+        wipe_string(&mut passphrase);
+        return None;
+    }
+    Some(passphrase)
+}
+
+// Parses the name of an address type as given on the command line.
+fn parse_address_type(name: &str) -> Option<AddressType> {
+    match name {
+        "p2pkh" => Some(AddressType::P2PKH),
+        "p2sh-p2wpkh" => Some(AddressType::P2SH),
+        "p2wpkh" => Some(AddressType::P2WPKH),
+        "p2tr" => Some(AddressType::P2TR),
+        _ => None
+    }
+}
+
+// Parses an account number as given on the command line. Prints an error and
+// returns None if it isn't valid.
+fn parse_account(value: &str) -> Option<u32> {
+    match from_str::<u32>(value) {
+        Some(account) if account < HARDENED => Some(account),
+        _ => {
+            println!("'{}' is not a valid account number.", value);
+            None
+        }
+    }
+}
+
+// Prints the data as a QR code in the terminal. Strings that can be read in
+// uppercase, like URs and bech32 addresses, should be passed that way so
+// they fit in a smaller code.
+fn print_qr(data: &str) {
+    match QrCode::encode(data.as_bytes(), ErrorCorrection::Low) {
+        Some(code) => {
+            // Terminals usually have light text on a dark background.
+            for line in code.to_unicode(QR_QUIET_ZONE, true).iter() {
+                println!("{}", line);
+            }
+        }
+        None => println!("That's too long for a QR code.")
+    }
+}
+
+// Shows a message as a UR of the given type in QR codes. A message too big
+// for one code is split into parts, which are shown one after another, over
+// and over, until the user has scanned them all and stops.
+fn print_ur(ur_type: &str, message: &[u8]) {
+    let parts: Vec<String> = ur::encode(ur_type, message, UR_MAX_FRAGMENT_LENGTH).into_iter().map(|part| {
+        part.as_slice().chars().map(|ch| ch.to_uppercase()).collect()
+    }).collect();
+
+    if parts.len() == 1 {
+        print_qr(parts[0].as_slice());
+        return;
+    }
+
+    for (i, part) in parts.iter().enumerate().cycle() {
+        println!("Part {} of {}:", i + 1, parts.len());
+        print_qr(part.as_slice());
+        print!("Press Enter for the next part, or type q to stop: ");
+        io::stdio::flush();
+        match io::stdin().read_line() {
+            Ok(line) => if line.as_slice().trim() == "q" { break; },
+            Err(_) => break
+        }
+    }
+}
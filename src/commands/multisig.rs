@@ -0,0 +1,122 @@
+use protocol::multisig::{Multisig, MultisigType, Cosigner};
+use wallet::Wallet;
+
+static USAGE: &'static str = "Usage: multisig [--type p2wsh|p2sh-p2wsh] [--account N]... ALIAS M COSIGNER...";
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    let mut multisig_type = MultisigType::P2WSH;
+    let mut accounts = Vec::new();
+
+    let mut i = 0;
+    while i < args.len() && args[i].as_slice().starts_with("-") {
+        if i + 1 >= args.len() {
+            println!("{}", USAGE);
+            return;
+        }
+
+        let value = args[i + 1].as_slice();
+        match args[i].as_slice() {
+            "--type" => {
+                multisig_type = match MultisigType::from_name(value) {
+                    Some(multisig_type) => multisig_type,
+                    None => {
+                        println!("'{}' is not a valid multisig type.", value);
+                        return;
+                    }
+                };
+            }
+            "--account" => {
+                match super::parse_account(value) {
+                    Some(account) => accounts.push(account),
+                    None => return
+                }
+            }
+            _ => {
+                println!("{}", USAGE);
+                return;
+            }
+        }
+        i += 2;
+    }
+
+    // Our own keys come from the given accounts, or the first one.
+    if accounts.is_empty() {
+        accounts.push(0);
+    }
+
+    let (alias, threshold, cosigner_keys) = match args.slice_from(i) {
+        [ref alias, ref threshold, cosigner_keys..] => (alias.as_slice(), threshold.as_slice(), cosigner_keys),
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    if alias.is_empty() || !alias.chars().all(|ch| ch.is_alphanumeric() || ch == '_') {
+        println!("Aliases can only contain letters, numbers and underscores.");
+        return;
+    }
+
+    let threshold: uint = match from_str(threshold) {
+        Some(threshold) => threshold,
+        None => {
+            println!("'{}' is not a valid number of signatures.", threshold);
+            return;
+        }
+    };
+
+    let mut wallet = match Wallet::load(&wallet_path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error loading wallet: {}", e);
+            return;
+        }
+    };
+
+    if wallet.get_addresses(alias).is_some() {
+        println!("The alias '{}' is already used.", alias);
+        return;
+    }
+
+    let mut cosigners = Vec::with_capacity(accounts.len() + cosigner_keys.len());
+    for &account in accounts.iter() {
+        match wallet.cosigner_key(multisig_type, account) {
+            Some(cosigner) => cosigners.push(cosigner),
+            None => {
+                println!("Only HD wallets can be multisig cosigners.");
+                return;
+            }
+        }
+    }
+    for key in cosigner_keys.iter() {
+        match Cosigner::parse(key.as_slice()) {
+            Some(cosigner) => cosigners.push(cosigner),
+            None => {
+                println!("'{}' is not a valid cosigner key. It should look like", key);
+                println!("[fingerprint/48'/0'/0'/2']xpub..., as printed by cosigner-key.");
+                return;
+            }
+        }
+    }
+
+    let key_count = cosigners.len();
+    let multisig = match Multisig::new(threshold, multisig_type, cosigners) {
+        Some(multisig) => multisig,
+        None => {
+            println!("A {}-of-{} multisig wallet isn't possible. The number of signatures", threshold, key_count);
+            println!("has to be between 1 and the number of keys, there can be at most 15");
+            println!("keys, and no key can be given twice.");
+            return;
+        }
+    };
+
+    wallet.add_multisig(alias, multisig);
+
+    match wallet.save() {
+        Ok(_) => {
+            println!("Added {} multisig alias '{}'. Its first address is:", multisig_type.name(), alias);
+            println!("{}", wallet.get_addresses(alias).unwrap()[0].encode());
+        }
+        Err(e) => println!("Error saving wallet: {}", e)
+    };
+}
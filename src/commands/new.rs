@@ -1,9 +1,33 @@
+use std::io::IoResult;
+
+use protocol::extended_key::ExtendedPrivateKey;
+use protocol::mnemonic::{Mnemonic, DEFAULT_WORD_COUNT};
+use util::entropy::EntropyPool;
 use wallet::Wallet;
 
 pub fn run(wallet_path: Path, args: &[String]) {
-    assert!(args.is_empty());
-
-    let wallet = Wallet::new(&wallet_path);
+    let wallet = match args {
+        [] => Wallet::new(&wallet_path),
+        [ref flag] if flag.as_slice() == "--hd" => {
+            match generate_master_key() {
+                Ok(master_key) => Wallet::new_hd(&wallet_path, master_key),
+                Err(e) => {
+                    println!("Error generating the master key: {}", e);
+                    return;
+                }
+            }
+        }
+        [ref flag] if flag.as_slice() == "--mnemonic" => {
+            match mnemonic_master_key() {
+                Some(master_key) => Wallet::new_hd(&wallet_path, master_key),
+                None => return
+            }
+        }
+        _ => {
+            println!("Usage: new [--hd | --mnemonic]");
+            return;
+        }
+    };
 
     match wallet.save() {
         Ok(_) => println!("New wallet saved to {}.", wallet_path.display()),
@@ -11,3 +35,60 @@ pub fn run(wallet_path: Path, args: &[String]) {
     };
 }
 
+// Creates a master key from a random 32-byte seed. If the seed happens to
+// give an invalid key, just try another one.
+fn generate_master_key() -> IoResult<ExtendedPrivateKey> {
+    loop {
+        let seed = try!(EntropyPool::system().gather());
+        match ExtendedPrivateKey::from_seed(seed.get_data()) {
+            Some(master_key) => return Ok(master_key),
+            None => continue
+        }
+    }
+}
+
+// Generates a new mnemonic, shows it to the user, and creates a master key
+// from it and a passphrase. Returns None if the mnemonic can't be generated,
+// or the user doesn't confirm they've written the words down.
+fn mnemonic_master_key() -> Option<ExtendedPrivateKey> {
+    let mnemonic = match Mnemonic::generate(DEFAULT_WORD_COUNT) {
+        Ok(mnemonic) => mnemonic,
+        Err(e) => {
+            println!("Error generating a mnemonic: {}", e);
+            return None;
+        }
+    };
+
+    println!("Write down these words in order and keep them somewhere safe. Anyone");
+    println!("who has them (and your passphrase, if you set one) can spend your bitcoins.");
+    println!("");
+    for (i, word) in mnemonic.get_words().iter().enumerate() {
+        println!("  {:>2}. {}", i + 1, word);
+    }
+    println!("");
+
+    let mut passphrase = super::prompt_secret("BIP 39 passphrase (leave blank for none): ");
+    if !passphrase.is_empty() {
+        let mut repeated = super::prompt_secret("Repeat the passphrase: ");
+        let matches = repeated == passphrase;
+        super::wipe_string(&mut repeated);
+        if !matches {
+            println!("The passphrases don't match, no wallet was created.");
+            super::wipe_string(&mut passphrase);
+            return None;
+        }
+    }
+
+    let confirmation = super::prompt("Type 'yes' once you have written the words down: ");
+    if confirmation.as_slice() != "yes" {
+        println!("No wallet was created.");
+        super::wipe_string(&mut passphrase);
+        return None;
+    }
+
+    // A 64-byte seed from PBKDF2 giving an invalid master key is about as
+    // likely as guessing someone's private key.
+    let master_key = ExtendedPrivateKey::from_seed(mnemonic.to_seed(passphrase.as_slice()).as_slice());
+    super::wipe_string(&mut passphrase);
+    master_key
+}
@@ -0,0 +1,47 @@
+use std::io::File;
+
+use util::hash;
+use wallet::Wallet;
+
+static USAGE: &'static str = "Usage: prove CHALLENGE [FILE]";
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    let (challenge, output_path) = match args {
+        [ref challenge] => (challenge.as_slice(), None),
+        [ref challenge, ref path] => (challenge.as_slice(), Some(Path::new(path.as_slice()))),
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    // The hash is of the file exactly as it is, so the proof is tied to
+    // this copy of it.
+    let contents = match File::open(&wallet_path).read_to_end() {
+        Ok(contents) => contents,
+        Err(e) => {
+            println!("Error reading wallet: {}", e);
+            return;
+        }
+    };
+
+    let wallet = match Wallet::load(&wallet_path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error loading wallet: {}", e);
+            return;
+        }
+    };
+
+    let proof = wallet.prove(challenge, hash::sha256(contents.as_slice()).as_slice());
+
+    match output_path {
+        Some(path) => {
+            match File::create(&path).write_str(proof.encode().as_slice()) {
+                Ok(_) => println!("Proof for {} address(es) written to {}.", proof.keys.len(), path.display()),
+                Err(e) => println!("Error writing {}: {}", path.display(), e)
+            }
+        }
+        None => print!("{}", proof.encode())
+    }
+}
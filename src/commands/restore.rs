@@ -0,0 +1,160 @@
+use std::io::{BufferedReader, File};
+use serialize::hex::ToHex;
+
+use protocol::address::{Address, AddressType};
+use protocol::extended_key::ExtendedPrivateKey;
+use protocol::mnemonic::Mnemonic;
+use wallet::{Wallet, DEFAULT_GAP_LIMIT};
+
+// The alias that discovered receiving addresses are put under.
+static RECEIVE_ALIAS: &'static str = "receive";
+
+static USAGE: &'static str = "Usage: restore [--type p2pkh|p2sh-p2wpkh|p2wpkh|p2tr] [--gap-limit N] [--used FILE]";
+
+// How the addresses of a restored wallet are found, as given on the command
+// line. `combine` takes the same options.
+pub struct DiscoveryOptions {
+    address_type: AddressType,
+    gap_limit: uint,
+    used: Vec<Address>
+}
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    // The words are read from standard input so that they don't end up in the
+    // shell's history.
+    let options = match parse_options(args, USAGE) {
+        Some(options) => options,
+        None => return
+    };
+
+    let mut phrase = super::prompt_secret("Mnemonic words, separated by spaces: ");
+    let mnemonic = Mnemonic::parse(phrase.as_slice());
+    super::wipe_string(&mut phrase);
+    let mnemonic = match mnemonic {
+        Some(mnemonic) => mnemonic,
+        None => {
+            println!("That isn't a valid BIP 39 mnemonic. Check that every word is spelled");
+            println!("correctly and that they're in the right order.");
+            return;
+        }
+    };
+
+    let mut passphrase = super::prompt_secret("BIP 39 passphrase (leave blank for none): ");
+    let master_key = ExtendedPrivateKey::from_seed(mnemonic.to_seed(passphrase.as_slice()).as_slice());
+    super::wipe_string(&mut passphrase);
+
+    let master_key = match master_key {
+        Some(master_key) => master_key,
+        None => {
+            println!("The mnemonic gives an invalid master key.");
+            return;
+        }
+    };
+
+    restore_wallet(wallet_path, master_key, &options);
+}
+
+// Parses the --type, --gap-limit and --used options. Prints an error (or the
+// given usage) and returns None if they aren't valid.
+pub fn parse_options(args: &[String], usage: &str) -> Option<DiscoveryOptions> {
+    let mut options = DiscoveryOptions {
+        address_type: AddressType::P2WPKH,
+        gap_limit: DEFAULT_GAP_LIMIT,
+        used: Vec::new()
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        if i + 1 >= args.len() {
+            println!("{}", usage);
+            return None;
+        }
+
+        let value = args[i + 1].as_slice();
+        match args[i].as_slice() {
+            "--type" => {
+                options.address_type = match super::parse_address_type(value) {
+                    Some(address_type) => address_type,
+                    None => {
+                        println!("'{}' is not a valid address type.", value);
+                        return None;
+                    }
+                };
+            }
+            "--gap-limit" => {
+                options.gap_limit = match from_str(value) {
+                    Some(n) if n > 0 => n,
+                    _ => {
+                        println!("The gap limit must be a positive number.");
+                        return None;
+                    }
+                };
+            }
+            "--used" => {
+                options.used = match read_addresses(&Path::new(value)) {
+                    Some(addresses) => addresses,
+                    None => return None
+                };
+            }
+            _ => {
+                println!("{}", usage);
+                return None;
+            }
+        }
+        i += 2;
+    }
+
+    Some(options)
+}
+
+// Creates an HD wallet with the given master key, finds its accounts'
+// addresses, and saves it.
+pub fn restore_wallet(wallet_path: Path, master_key: ExtendedPrivateKey, options: &DiscoveryOptions) {
+    println!("Master key fingerprint: {}", master_key.fingerprint().to_hex());
+
+    let mut wallet = Wallet::new_hd(&wallet_path, master_key);
+    let accounts = wallet.discover(RECEIVE_ALIAS, options.address_type, options.gap_limit, options.used.as_slice());
+    println!("Found {} account(s).", accounts);
+
+    match wallet.save() {
+        Ok(_) => println!("Restored wallet saved to {}.", wallet_path.display()),
+        Err(e) => println!("Error saving wallet: {}", e)
+    };
+}
+
+// Reads a list of used addresses, one per line. Blank lines are ignored.
+// Prints an error and returns None if the file can't be read or has an
+// invalid address in it.
+fn read_addresses(path: &Path) -> Option<Vec<Address>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("Error reading {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    let mut addresses = Vec::new();
+    for (line_number, line) in BufferedReader::new(file).lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                println!("Error reading {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        let address = line.as_slice().trim();
+        if address.is_empty() { continue; }
+
+        match Address::decode(address) {
+            Some(address) => addresses.push(address),
+            None => {
+                println!("{}:{}: '{}' is not a valid address.", path.display(), line_number + 1, address);
+                return None;
+            }
+        }
+    }
+
+    Some(addresses)
+}
@@ -0,0 +1,148 @@
+use std::io::File;
+
+use serialize::hex::FromHex;
+
+use protocol::psbt::Psbt;
+use protocol::transaction::Transaction;
+use util::png;
+use util::qr_scanner;
+use util::ur;
+
+static USAGE: &'static str = "Usage: scan-psbt [--qr] FILE IMAGE...";
+
+// The UR types that a PSBT comes in. Older wallets use "psbt", and "bytes"
+// is used for anything at all, so it could also be a raw transaction.
+static LEGACY_PSBT_TYPE: &'static str = "psbt";
+static BYTES_TYPE: &'static str = "bytes";
+
+pub fn run(_: Path, args: &[String]) {
+    // The images are photos of QR codes, or frames of a video of a multi-part
+    // UR, in any order. Frames without a code, or with one that's already
+    // been read, are skipped. With --qr, the PSBT that's written is shown as
+    // a UR in QR codes too, so that it can be passed on to another device,
+    // such as when it was made from an unsigned transaction.
+    let (args, qr) = match args {
+        [ref flag, rest..] if flag.as_slice() == "--qr" => (rest, true),
+        _ => (args, false)
+    };
+    if args.len() < 2 {
+        println!("{}", USAGE);
+        return;
+    }
+    let psbt_path = Path::new(args[0].as_slice());
+
+    let mut decoder = ur::Decoder::new();
+    let mut psbt = None;
+    for image_path in args.slice_from(1).iter().map(|arg| Path::new(arg.as_slice())) {
+        let image = match File::open(&image_path).read_to_end().and_then(|data| png::decode(data.as_slice())) {
+            Ok(image) => image,
+            Err(e) => {
+                println!("Error reading {}: {}", image_path.display(), e);
+                return;
+            }
+        };
+
+        let text = match qr_scanner::scan(&image) {
+            Some(data) => match String::from_utf8(data) {
+                Ok(text) => text.as_slice().trim().to_string(),
+                Err(_) => {
+                    println!("{}: the QR code doesn't contain text.", image_path.display());
+                    continue;
+                }
+            },
+            None => {
+                println!("{}: no QR code found.", image_path.display());
+                continue;
+            }
+        };
+
+        if !text.as_slice().starts_with("ur:") && !text.as_slice().starts_with("UR:") {
+            psbt = parse_plain(text.as_slice());
+            if psbt.is_none() {
+                println!("{}: the QR code doesn't contain a PSBT or transaction.", image_path.display());
+                continue;
+            }
+            break;
+        }
+
+        if !decoder.receive(text.as_slice()) {
+            println!("{}: the QR code isn't part of the same UR, or the UR is corrupt.", image_path.display());
+            continue;
+        }
+        let (done, total) = decoder.get_progress();
+        println!("{}: {} of {} part(s) of the UR.", image_path.display(), done, total);
+
+        match decoder.get_message() {
+            Some(message) => {
+                psbt = parse_ur(decoder.get_ur_type().unwrap(), message);
+                if psbt.is_none() {
+                    println!("The UR doesn't contain a PSBT or transaction.");
+                    return;
+                }
+                break;
+            }
+            None => ()
+        }
+    }
+
+    let psbt = match psbt {
+        Some(psbt) => psbt,
+        None => {
+            println!("Not enough of the UR's parts could be read. Scan some more frames.");
+            return;
+        }
+    };
+
+    match File::create(&psbt_path).write_line(psbt.encode().as_slice()) {
+        Ok(_) => println!("Wrote the PSBT to {}, to sign with sign-psbt.", psbt_path.display()),
+        Err(e) => {
+            println!("Error writing {}: {}", psbt_path.display(), e);
+            return;
+        }
+    }
+
+    if qr {
+        super::print_ur(ur::PSBT_TYPE, ur::cbor_bytes(psbt.serialize().as_slice()).as_slice());
+    }
+}
+
+// Gets a PSBT from a UR's message. A raw transaction is accepted too, in a
+// "bytes" UR.
+fn parse_ur(ur_type: &str, message: &[u8]) -> Option<Psbt> {
+    let data = match ur::decode_cbor_bytes(message) {
+        Some(data) => data,
+        None => return None
+    };
+
+    if ur_type == ur::PSBT_TYPE || ur_type == LEGACY_PSBT_TYPE {
+        Psbt::parse(data.as_slice())
+    } else if ur_type == BYTES_TYPE {
+        Psbt::parse(data.as_slice()).or_else(|| from_transaction(data.as_slice()))
+    } else {
+        None
+    }
+}
+
+// Gets a PSBT from a plain QR code, which has the PSBT in base64 or a raw
+// transaction in hex.
+fn parse_plain(text: &str) -> Option<Psbt> {
+    match Psbt::decode(text) {
+        Some(psbt) => Some(psbt),
+        None => match text.from_hex() {
+            Ok(data) => from_transaction(data.as_slice()),
+            Err(_) => None
+        }
+    }
+}
+
+// Makes a PSBT for an unsigned transaction. It doesn't have the outputs that
+// the transaction's inputs spend, which the wallet needs to sign them, so the
+// user is warned.
+fn from_transaction(data: &[u8]) -> Option<Psbt> {
+    let psbt = Transaction::parse(data).and_then(|tx| Psbt::new(tx));
+    if psbt.is_some() {
+        println!("WARNING: this is an unsigned transaction, not a PSBT, so it doesn't say what");
+        println!("its inputs spend. It can't be signed until the wallet that made it adds that.");
+    }
+    psbt
+}
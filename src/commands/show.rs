@@ -0,0 +1,57 @@
+use protocol::address::AddressType;
+use wallet::Wallet;
+
+static USAGE: &'static str = "Usage: show [--qr] ALIAS";
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    let (alias, qr) = match args {
+        [ref alias] => (alias.as_slice(), false),
+        [ref flag, ref alias] if flag.as_slice() == "--qr" => (alias.as_slice(), true),
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    let wallet = match Wallet::load(&wallet_path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error loading wallet: {}", e);
+            return;
+        }
+    };
+
+    let addresses = match wallet.get_addresses(alias) {
+        Some(addresses) => addresses,
+        None => {
+            println!("The wallet has no alias '{}'.", alias);
+            return;
+        }
+    };
+
+    for address in addresses.iter() {
+        let encoded = address.encode();
+        println!("{}", encoded);
+
+        // Its metadata, like a label, is listed under it.
+        match wallet.get_metadata(address) {
+            Some(metadata) => {
+                for &(ref name, ref value) in metadata.iter() {
+                    println!("  {}: {}", name, value);
+                }
+            }
+            None => {}
+        }
+
+        if qr {
+            // Bech32 addresses are case-insensitive, and uppercase fits in a
+            // smaller code.
+            match address.get_type() {
+                AddressType::P2WPKH | AddressType::P2WSH | AddressType::P2TR => {
+                    super::print_qr(encoded.as_slice().chars().map(|ch| ch.to_uppercase()).collect::<String>().as_slice());
+                }
+                _ => super::print_qr(encoded.as_slice())
+            }
+        }
+    }
+}
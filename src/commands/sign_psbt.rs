@@ -0,0 +1,63 @@
+use std::io::File;
+
+use protocol::psbt::Psbt;
+use util::ur;
+use wallet::Wallet;
+
+static USAGE: &'static str = "Usage: sign-psbt [--qr] FILE";
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    // The PSBT is signed in place, so that it can be passed on to the next
+    // cosigner as it is. With --qr, it's also shown as a UR in QR codes, to
+    // be scanned on the other side of the air gap.
+    let (psbt_path, qr) = match args {
+        [ref path] => (Path::new(path.as_slice()), false),
+        [ref flag, ref path] if flag.as_slice() == "--qr" => (Path::new(path.as_slice()), true),
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    let contents = match File::open(&psbt_path).read_to_string() {
+        Ok(contents) => contents,
+        Err(e) => {
+            println!("Error reading {}: {}", psbt_path.display(), e);
+            return;
+        }
+    };
+
+    let mut psbt = match Psbt::decode(contents.as_slice()) {
+        Some(psbt) => psbt,
+        None => {
+            println!("{} doesn't contain a valid base64-encoded PSBT.", psbt_path.display());
+            return;
+        }
+    };
+
+    let wallet = match Wallet::load(&wallet_path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error loading wallet: {}", e);
+            return;
+        }
+    };
+
+    let signature_count = wallet.sign_psbt(&mut psbt);
+    if signature_count == 0 {
+        println!("None of the PSBT's inputs could be signed by this wallet.");
+        return;
+    }
+
+    match File::create(&psbt_path).write_line(psbt.encode().as_slice()) {
+        Ok(_) => println!("Added {} signature(s) to {}.", signature_count, psbt_path.display()),
+        Err(e) => {
+            println!("Error writing {}: {}", psbt_path.display(), e);
+            return;
+        }
+    }
+
+    if qr {
+        super::print_ur(ur::PSBT_TYPE, ur::cbor_bytes(psbt.serialize().as_slice()).as_slice());
+    }
+}
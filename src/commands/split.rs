@@ -0,0 +1,83 @@
+use serialize::hex::ToHex;
+
+use protocol::extended_key::CHAIN_CODE_LENGTH;
+use protocol::private_key;
+use protocol::share::{Share, MAX_SHARES};
+use util::secret;
+use util::secret::SecretBytes;
+use wallet::Wallet;
+
+static USAGE: &'static str = "Usage: split THRESHOLD COUNT";
+
+// The number of words on each line of a share, to make them easier to copy.
+static WORDS_PER_LINE: uint = 6;
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    // The shares are shown one at a time, so that each can be written down
+    // (or handed over) before the next one is on the screen.
+    let (threshold, count) = match args {
+        [ref threshold, ref count] => {
+            match (from_str::<uint>(threshold.as_slice()), from_str::<uint>(count.as_slice())) {
+                (Some(threshold), Some(count)) if threshold >= 1 && threshold <= count && count <= MAX_SHARES => {
+                    (threshold, count)
+                }
+                _ => {
+                    println!("THRESHOLD and COUNT must be numbers, with 1 <= THRESHOLD <= COUNT <= {}.", MAX_SHARES);
+                    return;
+                }
+            }
+        }
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    let wallet = match Wallet::load(&wallet_path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            println!("Error loading wallet: {}", e);
+            return;
+        }
+    };
+
+    let master_key = match wallet.get_master_key() {
+        Some(master_key) => master_key,
+        None => {
+            println!("Only an HD wallet can be split into shares, since its keys all come from");
+            println!("its master key. Back up the wallet file of any other wallet instead.");
+            return;
+        }
+    };
+
+    // The secret is the master key as it's kept in the wallet file: its chain
+    // code, then its private key.
+    let mut secret = SecretBytes::with_capacity(CHAIN_CODE_LENGTH + private_key::LENGTH);
+    secret.push_all(master_key.chain_code.as_slice());
+    secret.push_all(master_key.private_key.get_data());
+    let shares = Share::split(secret.as_slice(), threshold, count);
+
+    println!("Master key fingerprint: {}", master_key.fingerprint().to_hex());
+    println!("Any {} of these {} shares can restore the wallet's keys with combine, and", threshold, count);
+    println!("fewer say nothing about them. Give each share to a different person to keep");
+    println!("somewhere safe. Keys that weren't derived from the master key, like ones");
+    println!("made with gen --dice, aren't in the shares.");
+
+    for share in shares.iter() {
+        println!("");
+        println!("Share {} of {}:", share.get_index(), count);
+
+        let mut phrase = share.to_phrase();
+        {
+            let words: Vec<&str> = phrase.as_slice().words().collect();
+            for (i, line) in words.chunks(WORDS_PER_LINE).enumerate() {
+                println!("  {:>2}. {}", i * WORDS_PER_LINE + 1, line.connect(" "));
+            }
+        }
+        unsafe { secret::wipe(phrase.as_mut_vec().as_mut_slice()); }
+
+        if share.get_index() < count {
+            super::prompt("Press Enter for the next share: ");
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::io::File;
+
+use protocol::proof::Proof;
+use util::hash;
+use wallet;
+
+static USAGE: &'static str = "Usage: verify-proof CHALLENGE FILE";
+
+pub fn run(wallet_path: Path, args: &[String]) {
+    let (challenge, proof_path) = match args {
+        [ref challenge, ref path] => (challenge.as_slice(), Path::new(path.as_slice())),
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    let contents = match File::open(&proof_path).read_to_string() {
+        Ok(contents) => contents,
+        Err(e) => {
+            println!("Error reading {}: {}", proof_path.display(), e);
+            return;
+        }
+    };
+
+    let proof = match Proof::parse(contents.as_slice(), proof_path.display().to_string().as_slice()) {
+        Ok(proof) => proof,
+        Err(e) => {
+            println!("Error reading {}: {}", proof_path.display(), e);
+            return;
+        }
+    };
+
+    // Only the plaintext part of the wallet file is needed, so this works
+    // without the passphrase.
+    let wallet_contents = match File::open(&wallet_path).read_to_end() {
+        Ok(contents) => contents,
+        Err(e) => {
+            println!("Error reading wallet: {}", e);
+            return;
+        }
+    };
+    let addresses = match wallet::read_addresses(&wallet_path) {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            println!("Error reading wallet: {}", e);
+            return;
+        }
+    };
+
+    let mut valid = true;
+
+    if proof.challenge.as_slice() != challenge {
+        println!("The proof is for the challenge '{}', not '{}'.", proof.challenge, challenge);
+        valid = false;
+    }
+
+    if proof.wallet_hash != hash::sha256(wallet_contents.as_slice()) {
+        println!("The proof was made for a different version of the wallet file.");
+        valid = false;
+    }
+
+    let mut proven = 0u;
+    for address in addresses.iter() {
+        match proof.find(address) {
+            Some(key_proof) if key_proof.verify(challenge, proof.wallet_hash.as_slice()) => proven += 1,
+            Some(_) => {
+                println!("The proof for {} is invalid.", address.encode());
+                valid = false;
+            }
+            None => {
+                println!("There's no proof for {}.", address.encode());
+                valid = false;
+            }
+        }
+    }
+
+    if valid {
+        println!("The proof is valid: the wallet holds the private keys for all {} of its addresses.", proven);
+    } else {
+        println!("The proof is NOT valid ({} of {} addresses proven).", proven, addresses.len());
+    }
+}
@@ -17,7 +17,81 @@ fn print_usage(program: &str) {
     println!("Usage: {} <command> [args...]", program);
     println!("");
     println!("Available commands:");
-    println!("  new            Create a new wallet");
+    println!("  new [--hd | --mnemonic]");
+    println!("                 Create a new wallet, optionally with a BIP 32 master key");
+    println!("                 (from a BIP 39 mnemonic, with --mnemonic)");
+    println!("  gen [--type TYPE] [--dice] ALIAS [N]");
+    println!("                 Add N new addresses (default 1) of TYPE (p2pkh, p2sh-p2wpkh,");
+    println!("                 p2wpkh or p2tr; by default p2wpkh in an HD wallet, or else");
+    println!("                 p2pkh) to ALIAS, or with --dice, one address");
+    println!("                 whose private key is made from at least 100 dice rolls");
+    println!("  restore [--type TYPE] [--gap-limit N] [--used FILE]");
+    println!("                 Create an HD wallet from a BIP 39 mnemonic, finding its");
+    println!("                 accounts' addresses (of TYPE: p2pkh, p2sh-p2wpkh, p2wpkh");
+    println!("                 or p2tr) up to N unused in a row, with FILE listing the");
+    println!("                 addresses that have been used");
+    println!("  split THRESHOLD COUNT");
+    println!("                 Split an HD wallet's master key into COUNT shares, written as");
+    println!("                 words, any THRESHOLD of which can restore it");
+    println!("  combine [--type TYPE] [--gap-limit N] [--used FILE]");
+    println!("                 Restore an HD wallet from the shares made by split, finding");
+    println!("                 its addresses like restore does");
+    println!("  export-watchonly [--addresses | --xpub] [FILE]");
+    println!("                 Write output descriptors for the wallet's addresses (or");
+    println!("                 just the addresses, or its accounts' xpubs) to FILE or");
+    println!("                 standard output, for a watch-only wallet to import");
+    println!("  cosigner-key [--type TYPE] [--account N]");
+    println!("                 Print the wallet's BIP 48 account key for multisig wallets");
+    println!("                 of TYPE (p2wsh or p2sh-p2wsh), to give to other cosigners");
+    println!("  multisig [--type TYPE] [--account N]... ALIAS M COSIGNER...");
+    println!("                 Add an M-of-N multisig alias made of the wallet's own keys");
+    println!("                 (from the given accounts) and the COSIGNER keys printed by");
+    println!("                 their wallets' cosigner-key");
+    println!("  show [--qr] ALIAS");
+    println!("                 List the addresses with ALIAS and their metadata, each with a");
+    println!("                 QR code with --qr");
+    println!("  annotate ADDRESS NAME [VALUE]");
+    println!("                 Set metadata NAME (like label, date, invoice or reuse) on");
+    println!("                 ADDRESS to VALUE, or remove it if no VALUE is given");
+    println!("  check");
+    println!("                 Check the wallet file without changing it: that its addresses");
+    println!("                 are valid and listed once, and that it decrypts to a private");
+    println!("                 key for each one. Exits with status 0 if it's valid, 1 if it");
+    println!("                 has problems, or 2 if it can't be read");
+    println!("  sign-psbt [--qr] FILE");
+    println!("                 Add the wallet's signatures to the base64 PSBT in FILE,");
+    println!("                 for inputs spending from its multisig addresses, and with");
+    println!("                 --qr, show the signed PSBT as a (multi-part) UR in QR codes");
+    println!("  scan-psbt [--qr] FILE IMAGE...");
+    println!("                 Read a PSBT or unsigned transaction from QR codes (or a");
+    println!("                 multi-part UR) in the PNG IMAGEs, writing it to FILE in");
+    println!("                 base64 for sign-psbt, and with --qr, showing it as a UR too");
+    println!("  prove CHALLENGE [FILE]");
+    println!("                 Sign CHALLENGE and a hash of the wallet file with every key,");
+    println!("                 writing the proof to FILE or standard output");
+    println!("  verify-proof CHALLENGE FILE");
+    println!("                 Check that the proof in FILE has a valid signature for every");
+    println!("                 address in the wallet file, without decrypting it");
+    println!("  export --paper [--svg] [--bip38] [ADDRESS] FILE");
+    println!("                 Write a printable backup of the wallet file with QR codes of");
+    println!("                 its encrypted data (or of ADDRESS and its private key, which");
+    println!("                 is unencrypted unless --bip38 is given) to FILE, as text or SVG");
+    println!("  export --bip38 ADDRESS");
+    println!("                 Print ADDRESS's private key encrypted with a passphrase, as a");
+    println!("                 BIP 38 key");
+    println!("  import [--type TYPE] ALIAS");
+    println!("                 Decrypt a BIP 38 key with its passphrase and add it to ALIAS,");
+    println!("                 as an address of TYPE (by default p2pkh for a key made for an");
+    println!("                 uncompressed public key, and p2wpkh otherwise)");
+    println!("  bip38-code [LOT SEQUENCE]");
+    println!("                 Make a BIP 38 intermediate code from a passphrase, for someone");
+    println!("                 else to make keys with that only the passphrase can decrypt");
+    println!("  bip38-gen [--compressed] CODE [N]");
+    println!("                 Print N (default 1) new BIP 38 keys and their addresses, made");
+    println!("                 from the intermediate CODE, without using the wallet");
+    println!("  encrypt-addresses on|off");
+    println!("                 Encrypt the wallet's aliases and addresses along with its");
+    println!("                 private keys, or store them in plaintext again");
 }
 
 fn main() {
@@ -40,6 +114,44 @@ fn main() {
 
         if command == "new" {
             commands::new::run(wallet_path, args_rest);
+        } else if command == "gen" {
+            commands::gen::run(wallet_path, args_rest);
+        } else if command == "restore" {
+            commands::restore::run(wallet_path, args_rest);
+        } else if command == "split" {
+            commands::split::run(wallet_path, args_rest);
+        } else if command == "combine" {
+            commands::combine::run(wallet_path, args_rest);
+        } else if command == "export-watchonly" {
+            commands::export_watchonly::run(wallet_path, args_rest);
+        } else if command == "cosigner-key" {
+            commands::cosigner_key::run(wallet_path, args_rest);
+        } else if command == "multisig" {
+            commands::multisig::run(wallet_path, args_rest);
+        } else if command == "show" {
+            commands::show::run(wallet_path, args_rest);
+        } else if command == "annotate" {
+            commands::annotate::run(wallet_path, args_rest);
+        } else if command == "check" {
+            commands::check::run(wallet_path, args_rest);
+        } else if command == "sign-psbt" {
+            commands::sign_psbt::run(wallet_path, args_rest);
+        } else if command == "scan-psbt" {
+            commands::scan_psbt::run(wallet_path, args_rest);
+        } else if command == "prove" {
+            commands::prove::run(wallet_path, args_rest);
+        } else if command == "verify-proof" {
+            commands::verify_proof::run(wallet_path, args_rest);
+        } else if command == "export" {
+            commands::export::run(wallet_path, args_rest);
+        } else if command == "import" {
+            commands::import::run(wallet_path, args_rest);
+        } else if command == "bip38-code" {
+            commands::bip38_code::run(wallet_path, args_rest);
+        } else if command == "bip38-gen" {
+            commands::bip38_gen::run(wallet_path, args_rest);
+        } else if command == "encrypt-addresses" {
+            commands::encrypt_addresses::run(wallet_path, args_rest);
         } else {
             println!("'{}' is not a valid command!", command);
         }
@@ -1,27 +1,80 @@
 //! Bitcoin address representation.
 
-use openssl;
-use openssl::crypto::hash::HashType::{SHA256, RIPEMD160};
-
 use util::wif;
+use util::hash;
+use util::base58;
+use util::bech32;
+use protocol::script;
+use protocol::taproot;
 use protocol::public_key::PublicKey;
 use protocol::private_key::PrivateKey;
 
-// Length of a raw Bitcoin address.
+// Length of a raw base-58 Bitcoin address.
 static LENGTH: uint = 25;
 
-// This byte must be at the start of every standard Bitcoin address. (In
-// base-58, a zero byte maps to a '1' character, which is why addresses start
-// with a one.)
-static VERSION_BYTE: u8 = 0x00;
+// Length of a raw native P2WPKH address: the witness version followed by the
+// 20-byte public key hash.
+static P2WPKH_LENGTH: uint = 21;
+
+// Length of a raw taproot address: the witness version followed by the 32-byte
+// output key. Native P2WSH addresses are the same length, with a 32-byte
+// script hash instead.
+static TAPROOT_LENGTH: uint = 33;
+static P2WSH_LENGTH: uint = 33;
+
+// This byte must be at the start of every standard pay-to-public-key-hash
+// Bitcoin address. (In base-58, a zero byte maps to a '1' character, which is
+// why these addresses start with a one.)
+static P2PKH_VERSION_BYTE: u8 = 0x00;
+
+// This byte must be at the start of every pay-to-script-hash Bitcoin address.
+// (It maps to a '3' character in base-58.)
+static P2SH_VERSION_BYTE: u8 = 0x05;
+
+// Native P2WPKH and P2WSH outputs are version 0 witness programs, and taproot
+// outputs are version 1 witness programs.
+static P2WPKH_WITNESS_VERSION: u8 = 0x00;
+static P2WSH_WITNESS_VERSION: u8 = 0x00;
+static TAPROOT_WITNESS_VERSION: u8 = 0x01;
+
+// The human-readable part of mainnet bech32 addresses.
+static SEGWIT_HRP: &'static str = "bc";
+
+// The version bytes of testnet P2PKH and P2SH addresses, and the human-readable
+// parts of testnet and regtest bech32 addresses. Cykas is mainnet-only, but
+// these are recognized, to say why they aren't valid.
+static TESTNET_VERSION_BYTES: [u8, ..2] = [0x6f, 0xc4];
+static OTHER_NETWORK_HRPS: [&'static str, ..2] = ["tb", "bcrt"];
+
+/// The kinds of address Cykas knows about, as determined by the version byte.
+#[deriving(Clone, Copy, PartialEq, Show)]
+pub enum AddressType {
+    /// Pay-to-public-key-hash, i.e. a `1...` address.
+    P2PKH,
+    /// Pay-to-script-hash, i.e. a `3...` address. The only script Cykas
+    /// derives these from is a P2WPKH witness program (nested SegWit).
+    P2SH,
+    /// Native pay-to-witness-public-key-hash, i.e. a `bc1q...` address.
+    P2WPKH,
+    /// Native pay-to-witness-script-hash, i.e. a longer `bc1q...` address.
+    P2WSH,
+    /// Pay-to-taproot, i.e. a `bc1p...` address. Cykas only derives these
+    /// from a single key with no script tree, as in BIP 86.
+    P2TR
+}
 
-/// Represents a raw Bitcoin address. The bytes of an address are laid out like
-/// this:
+/// Represents a raw Bitcoin address. The bytes of a base-58 address are laid
+/// out like this:
 ///
 ///     vhhhhhhhhhhhhhhhhhhhhcccc
 ///
-/// Where `v` is the version byte, `h` is a 20-byte hash of the public key, and
-/// `c` is the 4-byte checksum.
+/// Where `v` is the version byte, `h` is a 20-byte hash of the public key (or
+/// of the script, for P2SH addresses), and `c` is the 4-byte checksum.
+///
+/// Native SegWit addresses are encoded with bech32 or bech32m instead, which
+/// have their own checksum, so their raw bytes are just the witness version
+/// followed by the witness program: a 20-byte public key hash for P2WPKH, a
+/// 32-byte script hash for P2WSH, or a 32-byte output key for taproot.
 #[deriving(Clone, PartialEq, Show)]
 pub struct Address(Vec<u8>);
 
@@ -38,54 +91,317 @@ impl Address {
 
     // Checks if the given raw address data is valid.
     fn is_valid(data: &[u8]) -> bool {
+        if data.len() == P2WPKH_LENGTH || data.len() == TAPROOT_LENGTH {
+            Address::is_valid_witness(data[0], data.slice_from(1))
+        } else {
+            Address::is_valid_base58(data)
+        }
+    }
+
+    // Checks if the given witness version and program, decoded from bech32 or
+    // bech32m, are a kind of SegWit address Cykas knows about: a version 0
+    // P2WPKH or P2WSH program, or a version 1 taproot output key.
+    fn is_valid_witness(version: u8, program: &[u8]) -> bool {
+        if version == P2WPKH_WITNESS_VERSION && program.len() == P2WPKH_LENGTH - 1 {
+            true
+        } else if version == P2WSH_WITNESS_VERSION && program.len() == P2WSH_LENGTH - 1 {
+            true
+        } else {
+            version == TAPROOT_WITNESS_VERSION && program.len() == TAPROOT_LENGTH - 1
+        }
+    }
+
+    // Checks if the given data, decoded from base-58, is a valid P2PKH or P2SH
+    // address. Only these have a base-58 form: the shorter SegWit data
+    // doesn't, and it has no checksum of its own to catch a base-58 string
+    // that's been cut short.
+    fn is_valid_base58(data: &[u8]) -> bool {
         data.len() == LENGTH &&
-        data[0] == VERSION_BYTE &&
+        (data[0] == P2PKH_VERSION_BYTE || data[0] == P2SH_VERSION_BYTE) &&
         wif::check(data)
     }
 
-    /// Creates an Address from a PublicKey.
+    /// Parses an address as it's written in a wallet file or given to the
+    /// user: base-58 for P2PKH and P2SH addresses, bech32 for native P2WPKH
+    /// and P2WSH addresses, or bech32m for taproot addresses. Returns None if the
+    /// string isn't a valid address.
+    pub fn decode(string: &str) -> Option<Address> {
+        // Bech32 strings can be all-lowercase or all-uppercase. Version 0
+        // needs a bech32 checksum and later versions a bech32m one, which
+        // decode_segwit_address() checks.
+        if string.starts_with("bc1") || string.starts_with("BC1") {
+            match bech32::decode_segwit_address(SEGWIT_HRP, string) {
+                Some((version, ref program)) if Address::is_valid_witness(version, program.as_slice()) => {
+                    let mut data = vec![version];
+                    data.push_all(program.as_slice());
+                    Some(Address(data))
+                }
+                _ => None
+            }
+        } else {
+            match base58::decode(string) {
+                Some(ref data) if Address::is_valid_base58(data.as_slice()) => Some(Address(data.clone())),
+                _ => None
+            }
+        }
+    }
+
+    /// Parses an address like `decode()` does, but says what's wrong with the
+    /// string if it isn't a valid address: that it's for another network, or
+    /// its checksum is wrong, and so on.
+    pub fn check(string: &str) -> Result<Address, &'static str> {
+        let lowercase: String = string.chars().map(|ch| ch.to_lowercase()).collect();
+        let lowercase = lowercase.as_slice();
+
+        if lowercase.starts_with("bc1") {
+            return match (Address::decode(string), bech32::decode(string)) {
+                (Some(address), _) => Ok(address),
+                (None, None) => Err("it isn't valid bech32, or its checksum is wrong"),
+                (None, Some(_)) => Err("it isn't a kind of SegWit address Cykas knows about, or has the wrong \
+                                        checksum for its witness version")
+            };
+        }
+        if OTHER_NETWORK_HRPS.iter().any(|hrp| lowercase.starts_with(format!("{}1", hrp).as_slice())) {
+            return Err("it's a testnet or regtest address");
+        }
+
+        let data = match base58::decode(string) {
+            Some(data) => data,
+            None => return Err("it isn't valid base-58")
+        };
+        if data.len() != LENGTH {
+            Err("it's the wrong length")
+        } else if !wif::check(data.as_slice()) {
+            Err("its checksum is wrong")
+        } else if TESTNET_VERSION_BYTES.iter().any(|&version| version == data[0]) {
+            Err("it's a testnet address")
+        } else {
+            match Address::new(data.as_slice()) {
+                Some(address) => Ok(address),
+                None => Err("its version byte isn't for a P2PKH or P2SH address")
+            }
+        }
+    }
+
+    /// Encodes the address as a string, the inverse of `decode()`.
+    pub fn encode(&self) -> String {
+        match self.get_type() {
+            AddressType::P2WPKH | AddressType::P2WSH | AddressType::P2TR => {
+                bech32::encode_segwit_address(SEGWIT_HRP, self.get_data()[0], self.get_hash())
+            }
+            _ => base58::encode(self.get_data())
+        }
+    }
+
+    /// Creates a P2PKH Address from a PublicKey.
     pub fn from_public_key(public_key: &PublicKey) -> Address {
         // The meat of a Bitcoin address is a RIPEMD-160 hash of a SHA-256 hash
         // of the public key.
-        let public_key_sha = openssl::crypto::hash::hash(SHA256, public_key.get_data());
-        let public_key_ripemd = openssl::crypto::hash::hash(RIPEMD160, public_key_sha.as_slice());
+        let public_key_hash = hash::hash160(public_key.get_data());
 
         // Encode it in WIF format, which puts the version byte in front and a
         // 4-byte checksum at the end.
-        let data = wif::encode(public_key_ripemd.as_slice(), VERSION_BYTE);
+        let data = wif::encode(public_key_hash.as_slice(), P2PKH_VERSION_BYTE);
 
         Address(data)
     }
 
-    /// Creates an Address from a PrivateKey.
+    /// Creates a P2PKH Address from a PrivateKey.
     pub fn from_private_key(private_key: &PrivateKey) -> Address {
         let public_key = PublicKey::from_private_key(private_key);
         Address::from_public_key(&public_key)
     }
 
+    /// Creates a P2PKH Address from the compressed form of a PublicKey. The
+    /// P2PKH addresses of HD wallets use this form, as in BIP 44, and so do
+    /// other wallets' keys that are marked as compressed, such as compressed
+    /// WIF and BIP 38 keys. Cykas's other P2PKH addresses use the
+    /// uncompressed form.
+    pub fn from_compressed_public_key(public_key: &PublicKey) -> Address {
+        let public_key_hash = hash::hash160(public_key.to_compressed().as_slice());
+        Address(wif::encode(public_key_hash.as_slice(), P2PKH_VERSION_BYTE))
+    }
+
+    /// Creates a P2SH Address that can be spent by satisfying the given
+    /// redeem script.
+    pub fn from_redeem_script(redeem_script: &[u8]) -> Address {
+        let script_hash = hash::hash160(redeem_script);
+        Address(wif::encode(script_hash.as_slice(), P2SH_VERSION_BYTE))
+    }
+
+    /// Creates a P2SH-wrapped P2WPKH Address from a PublicKey. The redeem
+    /// script is a version 0 witness program, so spending it requires the
+    /// script from `script::p2sh_p2wpkh_script_sig()` and the witness from
+    /// `script::p2wpkh_witness()`.
+    pub fn p2sh_p2wpkh_from_public_key(public_key: &PublicKey) -> Address {
+        let redeem_script = script::p2wpkh_redeem_script(public_key);
+        Address::from_redeem_script(redeem_script.as_slice())
+    }
+
+    /// Creates a P2SH-wrapped P2WPKH Address from a PrivateKey.
+    pub fn p2sh_p2wpkh_from_private_key(private_key: &PrivateKey) -> Address {
+        let public_key = PublicKey::from_private_key(private_key);
+        Address::p2sh_p2wpkh_from_public_key(&public_key)
+    }
+
+    /// Creates a native P2WPKH Address from a PublicKey. Like P2SH-P2WPKH,
+    /// it pays to the hash of the compressed public key.
+    pub fn p2wpkh_from_public_key(public_key: &PublicKey) -> Address {
+        let public_key_hash = hash::hash160(public_key.to_compressed().as_slice());
+
+        let mut data = vec![P2WPKH_WITNESS_VERSION];
+        data.push_all(public_key_hash.as_slice());
+        Address(data)
+    }
+
+    /// Creates a native P2WPKH Address from a PrivateKey.
+    pub fn p2wpkh_from_private_key(private_key: &PrivateKey) -> Address {
+        let public_key = PublicKey::from_private_key(private_key);
+        Address::p2wpkh_from_public_key(&public_key)
+    }
+
+    /// Creates a native P2WSH Address that can be spent by satisfying the
+    /// given witness script.
+    pub fn p2wsh_from_witness_script(witness_script: &[u8]) -> Address {
+        let mut data = vec![P2WSH_WITNESS_VERSION];
+        data.push_all(hash::sha256(witness_script).as_slice());
+        Address(data)
+    }
+
+    /// Finds the Address that a scriptPubKey locks an output to. Returns None
+    /// if the script isn't one of the standard kinds that have an address.
+    pub fn from_script_pubkey(script_pubkey: &[u8]) -> Option<Address> {
+        // Each kind of script is recognized by building the script for the
+        // hash in the right place and checking that it's the same.
+        let len = script_pubkey.len();
+        if len == 25 && script::p2pkh_script_pubkey(script_pubkey.slice(3, 23)).as_slice() == script_pubkey {
+            Some(Address(wif::encode(script_pubkey.slice(3, 23), P2PKH_VERSION_BYTE)))
+        } else if len == 23 && script::p2sh_script_pubkey(script_pubkey.slice(2, 22)).as_slice() == script_pubkey {
+            Some(Address(wif::encode(script_pubkey.slice(2, 22), P2SH_VERSION_BYTE)))
+        } else if len == 22 && script::p2wpkh_script_pubkey(script_pubkey.slice_from(2)).as_slice() == script_pubkey {
+            let mut data = vec![P2WPKH_WITNESS_VERSION];
+            data.push_all(script_pubkey.slice_from(2));
+            Some(Address(data))
+        } else if len == 34 && script::p2wsh_script_pubkey(script_pubkey.slice_from(2)).as_slice() == script_pubkey {
+            let mut data = vec![P2WSH_WITNESS_VERSION];
+            data.push_all(script_pubkey.slice_from(2));
+            Some(Address(data))
+        } else if len == 34 && script::p2tr_script_pubkey(script_pubkey.slice_from(2)).as_slice() == script_pubkey {
+            Some(Address::p2tr_from_output_key(script_pubkey.slice_from(2)))
+        } else {
+            None
+        }
+    }
+
+    /// Creates a P2TR Address paying to the given 32-byte x-only output key.
+    pub fn p2tr_from_output_key(output_key: &[u8]) -> Address {
+        assert_eq!(output_key.len(), TAPROOT_LENGTH - 1);
+
+        let mut data = vec![TAPROOT_WITNESS_VERSION];
+        data.push_all(output_key);
+        Address(data)
+    }
+
+    /// Creates a P2TR Address from a PublicKey, using it as the internal key
+    /// with no script tree. The output key is the internal key tweaked with a
+    /// hash of itself, as recommended by BIP 341.
+    pub fn p2tr_from_public_key(public_key: &PublicKey) -> Address {
+        // The internal key is the X coordinate only.
+        let internal_key = public_key.to_compressed().slice_from(1).to_vec();
+
+        // The X coordinate of a valid public key is always on the curve, and
+        // the tweak being invalid would mean we'd found a SHA-256 preimage.
+        let output_key = taproot::tweak_public_key(internal_key.as_slice(), None).unwrap();
+
+        Address::p2tr_from_output_key(output_key.as_slice())
+    }
+
+    /// Creates a P2TR Address from a PrivateKey.
+    pub fn p2tr_from_private_key(private_key: &PrivateKey) -> Address {
+        let public_key = PublicKey::from_private_key(private_key);
+        Address::p2tr_from_public_key(&public_key)
+    }
+
     /// Gets the raw address as a slice of bytes.
     pub fn get_data(&self) -> &[u8] {
         let Address(ref data) = *self;
         data.as_slice()
     }
+
+    /// Gets the type of the address, based on its length and version byte.
+    pub fn get_type(&self) -> AddressType {
+        if self.get_data().len() == P2WPKH_LENGTH {
+            AddressType::P2WPKH
+        } else if self.get_data().len() == P2WSH_LENGTH && self.get_data()[0] == P2WSH_WITNESS_VERSION {
+            AddressType::P2WSH
+        } else if self.get_data().len() == TAPROOT_LENGTH {
+            AddressType::P2TR
+        } else if self.get_data()[0] == P2SH_VERSION_BYTE {
+            AddressType::P2SH
+        } else {
+            AddressType::P2PKH
+        }
+    }
+
+    /// Gets the 20-byte public key hash or script hash the address pays to,
+    /// the 32-byte script hash for P2WSH addresses, or the 32-byte output key
+    /// for taproot addresses.
+    pub fn get_hash(&self) -> &[u8] {
+        match self.get_type() {
+            AddressType::P2WPKH | AddressType::P2WSH | AddressType::P2TR => self.get_data().slice_from(1),
+            _ => self.get_data().slice(1, LENGTH - 4)
+        }
+    }
+
+    /// Returns the scriptPubKey that locks an output to this address.
+    pub fn to_script_pubkey(&self) -> Vec<u8> {
+        match self.get_type() {
+            AddressType::P2PKH => script::p2pkh_script_pubkey(self.get_hash()),
+            AddressType::P2SH => script::p2sh_script_pubkey(self.get_hash()),
+            AddressType::P2WPKH => script::p2wpkh_script_pubkey(self.get_hash()),
+            AddressType::P2WSH => script::p2wsh_script_pubkey(self.get_hash()),
+            AddressType::P2TR => script::p2tr_script_pubkey(self.get_hash())
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use serialize::hex::FromHex;
+    use serialize::hex::{ToHex, FromHex};
 
     use util::base58;
+    use protocol::script;
     use protocol::public_key::PublicKey;
     use protocol::private_key::PrivateKey;
 
-    use super::Address;
+    use super::{Address, AddressType};
 
     #[test]
     fn test_new() {
         let data = base58::decode("19gL5Rq1uc5yspAtbM7NyDs1godKnGHMar").unwrap();
         let address = Address::new(data.as_slice());
         assert!(address.is_some());
-        assert_eq!(address.unwrap().get_data(), data.as_slice());
+        let address = address.unwrap();
+        assert_eq!(address.get_data(), data.as_slice());
+        assert_eq!(address.get_type(), AddressType::P2PKH);
+    }
+
+    #[test]
+    fn test_new_p2sh() {
+        let data = base58::decode("3JvL6Ymt8MVWiCNHC7oWU6nLeHNJKLZGLN").unwrap();
+        let address = Address::new(data.as_slice());
+        assert!(address.is_some());
+        let address = address.unwrap();
+        assert_eq!(address.get_data(), data.as_slice());
+        assert_eq!(address.get_type(), AddressType::P2SH);
+    }
+
+    #[test]
+    fn test_new_invalid_version_byte() {
+        // Valid checksum, but the version byte is 0x6f (testnet).
+        let data = base58::decode("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn").unwrap();
+        let address = Address::new(data.as_slice());
+        assert!(address.is_none());
     }
 
     #[test]
@@ -106,6 +422,16 @@ mod tests {
         assert_eq!(address_base58.as_slice(), "1BN7qZoGjmpwD3nSLrFy6xfdDQbTvQDUbs");
     }
 
+    #[test]
+    fn test_from_compressed_public_key() {
+        // The key from BIP 38's "TestingOneTwoThree" test vectors.
+        let data = "CBF4B9F70470856BB4F40F80B87EDB90865997FFEE6DF315AB166D713AF433A5";
+        let data = data.from_hex().unwrap();
+        let private_key = PrivateKey::new(data.as_slice()).unwrap();
+        let address = Address::from_compressed_public_key(&private_key.to_public_key());
+        assert_eq!(address.encode().as_slice(), "164MQi977u9GUteHr4EPH27VkkdxmfCvGW");
+    }
+
     #[test]
     fn test_from_private_key() {
         let data = "F704C5F491F6B1235E6571AD10157A29782A71DF33A8FD7298A50B5CF0A65281";
@@ -115,5 +441,167 @@ mod tests {
         let address_base58 = base58::encode(address.get_data());
         assert_eq!(address_base58.as_slice(), "19pXLZXnPJjN1h2EWjzodArVV867Vqpo6p");
     }
+
+    #[test]
+    fn test_p2sh_p2wpkh_from_private_key() {
+        let data = "0000000000000000000000000000000000000000000000000000000000000001";
+        let data = data.from_hex().unwrap();
+        let private_key = PrivateKey::new(data.as_slice()).unwrap();
+        let address = Address::p2sh_p2wpkh_from_private_key(&private_key);
+        let address_base58 = base58::encode(address.get_data());
+        assert_eq!(address_base58.as_slice(), "3JvL6Ymt8MVWiCNHC7oWU6nLeHNJKLZGLN");
+        assert_eq!(address.get_type(), AddressType::P2SH);
+    }
+
+    #[test]
+    fn test_p2sh_p2wpkh_from_public_key() {
+        let data = "CBBEC41B016517C3DA8E2F88BDACB293802CECF1AE2C47A7CB5D4BDA28353B5B";
+        let data = data.from_hex().unwrap();
+        let public_key = PrivateKey::new(data.as_slice()).unwrap().to_public_key();
+        let address = Address::p2sh_p2wpkh_from_public_key(&public_key);
+        let address_base58 = base58::encode(address.get_data());
+        assert_eq!(address_base58.as_slice(), "32eF994S6syKv4FaLaikazrPYmW7C7YkWp");
+    }
+
+    #[test]
+    fn test_to_script_pubkey() {
+        let data = base58::decode("14ydpwhvtVBMjt5NrechP46UKLSY7jYn7q").unwrap();
+        let address = Address::new(data.as_slice()).unwrap();
+        let expected = format!("76A914{}88AC", address.get_hash().to_hex());
+        assert_eq!(address.to_script_pubkey(), expected.as_slice().from_hex().unwrap());
+
+        let data = base58::decode("3JvL6Ymt8MVWiCNHC7oWU6nLeHNJKLZGLN").unwrap();
+        let address = Address::new(data.as_slice()).unwrap();
+        let expected = format!("A914{}87", address.get_hash().to_hex());
+        assert_eq!(address.to_script_pubkey(), expected.as_slice().from_hex().unwrap());
+
+        let address = "bc1p2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dps59h4z5";
+        let address = Address::decode(address).unwrap();
+        let expected = "512053A1F6E454DF1AA2776A2814A721372D6258050DE330B3C6D10EE8F4E0DDA343";
+        assert_eq!(address.to_script_pubkey(), expected.from_hex().unwrap());
+    }
+
+    #[test]
+    fn test_p2wpkh_from_private_key() {
+        let data = "0000000000000000000000000000000000000000000000000000000000000001";
+        let data = data.from_hex().unwrap();
+        let private_key = PrivateKey::new(data.as_slice()).unwrap();
+        let address = Address::p2wpkh_from_private_key(&private_key);
+        assert_eq!(address.get_type(), AddressType::P2WPKH);
+        assert_eq!(address.get_hash().to_hex().as_slice(), "751e76e8199196d454941c45d1b3a323f1433bd6");
+        assert_eq!(address.encode().as_slice(), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+        assert_eq!(address.to_script_pubkey(),
+                   "0014751E76E8199196D454941C45D1B3A323F1433BD6".from_hex().unwrap());
+    }
+
+    #[test]
+    fn test_p2tr_from_public_key() {
+        // From the BIP 341 wallet test vectors, with no script tree.
+        let data = "04D6889CB081036E0FAEFA3A35157AD71086B123B2B144B649798B494C300A961D\
+                      6EC0323AFED19AA09E3F37B8271D66DF4F5F3B67AC57FFB6E097D669426E6648";
+        let data = data.from_hex().unwrap();
+        let public_key = PublicKey::new(data.as_slice()).unwrap();
+        let address = Address::p2tr_from_public_key(&public_key);
+        assert_eq!(address.get_type(), AddressType::P2TR);
+        assert_eq!(address.get_hash().to_hex().as_slice(),
+                   "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343");
+        assert_eq!(address.encode().as_slice(),
+                   "bc1p2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dps59h4z5");
+    }
+
+    #[test]
+    fn test_p2tr_from_private_key() {
+        // From the BIP 86 test vectors (first receiving address).
+        let data = "41F41D69260DF4CF277826A9B65A3717E4EEDDBEEDF637F212CA096576479361";
+        let data = data.from_hex().unwrap();
+        let private_key = PrivateKey::new(data.as_slice()).unwrap();
+        let address = Address::p2tr_from_private_key(&private_key);
+        assert_eq!(address.encode().as_slice(),
+                   "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr");
+    }
+
+    #[test]
+    fn test_p2wsh_from_witness_script() {
+        // From the BIP 173 examples.
+        let public_key = "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798".from_hex().unwrap();
+        let address = Address::p2wsh_from_witness_script(script::p2pk_script(public_key.as_slice()).as_slice());
+        assert_eq!(address.get_type(), AddressType::P2WSH);
+        assert_eq!(address.encode().as_slice(),
+                   "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3");
+    }
+
+    #[test]
+    fn test_from_script_pubkey() {
+        let addresses = [
+            "19gL5Rq1uc5yspAtbM7NyDs1godKnGHMar",
+            "3JvL6Ymt8MVWiCNHC7oWU6nLeHNJKLZGLN",
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3",
+            "bc1p2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dps59h4z5"
+        ];
+        for string in addresses.iter() {
+            let address = Address::decode(*string).unwrap();
+            assert_eq!(Address::from_script_pubkey(address.to_script_pubkey().as_slice()), Some(address));
+        }
+
+        // A bare multisig script has no address.
+        let script = "5121020000000000000000000000000000000000000000000000000000000000000000\
+                      51AE".from_hex().unwrap();
+        assert!(Address::from_script_pubkey(script.as_slice()).is_none());
+    }
+
+    #[test]
+    fn test_decode_encode() {
+        let addresses = [
+            "19gL5Rq1uc5yspAtbM7NyDs1godKnGHMar",
+            "3JvL6Ymt8MVWiCNHC7oWU6nLeHNJKLZGLN",
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3",
+            "bc1p2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dps59h4z5"
+        ];
+        for string in addresses.iter() {
+            let address = Address::decode(*string).unwrap();
+            assert_eq!(address.encode().as_slice(), *string);
+        }
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // Invalid base-58 checksum.
+        assert!(Address::decode("18gL5Rq1uc5yspAtbM7NyDs1godKnGHMar").is_none());
+        // A P2PKH address cut short, which decodes to 21 bytes starting with
+        // a zero, like the raw data of a P2WPKH address.
+        assert!(Address::decode("19gL5Rq1uc5yspAtbM7NyDs1godK").is_none());
+        // Witness version 2 isn't supported.
+        assert!(Address::decode("bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs").is_none());
+        // Taproot address with a bech32 (instead of bech32m) checksum.
+        assert!(Address::decode("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd").is_none());
+        // Native P2WSH address with a bech32m (instead of bech32) checksum.
+        assert!(Address::decode("bc1q2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dpstw8sl2").is_none());
+        // Base-58 strings that decode to 33 bytes starting with 0x01 and 0x00,
+        // like the raw data of taproot and P2WSH addresses.
+        assert!(Address::decode("PrnTCWRECsXmNGhZckwC84kf3boFC548UkqBpN7ThWu8").is_none());
+        assert!(Address::decode("16dU5hifdQpnzoBv9Paktvqx8o79vSrz2zhsrxSghBZer").is_none());
+        // Testnet taproot address.
+        assert!(Address::decode("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c").is_none());
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(Address::check("19gL5Rq1uc5yspAtbM7NyDs1godKnGHMar"),
+                   Ok(Address::decode("19gL5Rq1uc5yspAtbM7NyDs1godKnGHMar").unwrap()));
+        assert_eq!(Address::check("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4"),
+                   Ok(Address::decode("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap()));
+
+        assert_eq!(Address::check("18gL5Rq1uc5yspAtbM7NyDs1godKnGHMar"), Err("its checksum is wrong"));
+        assert_eq!(Address::check("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn"), Err("it's a testnet address"));
+        assert_eq!(Address::check("19gL5Rq1uc5yspAtbM7NyDs1godKnGHMa0"), Err("it isn't valid base-58"));
+        assert_eq!(Address::check("19gL5Rq1uc5yspAtbM7NyDs1godKnG"), Err("it's the wrong length"));
+        assert_eq!(Address::check("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c"),
+                   Err("it's a testnet or regtest address"));
+        assert_eq!(Address::check("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5"),
+                   Err("it isn't valid bech32, or its checksum is wrong"));
+        assert!(Address::check("bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs").is_err());
+    }
 }
 
@@ -0,0 +1,467 @@
+//! BIP 38 encrypted private keys: a private key encrypted with a passphrase
+//! and written as a `6P...` string, so that it can be printed or written down
+//! and still be safe if someone finds it.
+//!
+//! There are two kinds. One is a key encrypted directly, which takes the key
+//! and the passphrase to make. The other is made by "EC multiplication": the
+//! owner of the passphrase makes an intermediate code from it, a
+//! `passphrase...` string, and gives it to someone else, like a printer of
+//! paper wallets, who can make as many encrypted keys and their addresses
+//! from it as they like without being able to decrypt any of them.
+//!
+//! Both are slow to decrypt on purpose, since the passphrase is stretched
+//! with scrypt. BIP 38 has passphrases normalized to Unicode NFC first; here
+//! they're used exactly as they're typed, which is the same thing for
+//! anything typed on an ordinary keyboard.
+
+use openssl;
+
+use protocol::address::Address;
+use protocol::private_key::PrivateKey;
+use protocol::public_key::PublicKey;
+use util::base58;
+use util::ecdsa;
+use util::hash;
+use util::secp256k1::{Point, Scalar};
+use util::secret::SecretBytes;
+use util::wif;
+
+/// The highest lot number an intermediate code can have.
+pub static MAX_LOT: u32 = 1048575;
+
+/// The highest sequence number an intermediate code can have.
+pub static MAX_SEQUENCE: u32 = 4095;
+
+// Every encrypted key starts with this byte, and then one of the next two,
+// for a key that was encrypted directly or made by EC multiplication. In
+// base-58, this is what makes them start with "6P".
+static PREFIX: u8 = 0x01;
+static NON_EC_MULTIPLIED: u8 = 0x42;
+static EC_MULTIPLIED: u8 = 0x43;
+
+// The bits of the flag byte. A directly encrypted key always has the first
+// two set.
+static FLAG_NON_EC_MULTIPLIED: u8 = 0xC0;
+static FLAG_COMPRESSED: u8 = 0x20;
+static FLAG_LOT_SEQUENCE: u8 = 0x04;
+
+// The length of an encrypted key, less the prefix and the checksum.
+static ENCRYPTED_LENGTH: uint = 38;
+
+// Every intermediate code starts with these bytes, and then one of the next
+// two, for a code with or without a lot and sequence number. In base-58,
+// this is what makes them start with "passphrase".
+static MAGIC: &'static [u8] = &[0x2C, 0xE9, 0xB3, 0xE1, 0xFF, 0x39, 0xE2];
+static MAGIC_LOT_SEQUENCE: u8 = 0x51;
+static MAGIC_NO_LOT_SEQUENCE: u8 = 0x53;
+
+// The length of an intermediate code, less the first magic byte and the
+// checksum: the rest of the magic bytes, the owner entropy and the passpoint.
+static INTERMEDIATE_CODE_LENGTH: uint = 48;
+
+// The owner entropy in an intermediate code is 8 bytes, of which only the
+// first 4 are random if there's a lot and sequence number.
+static OWNER_ENTROPY_LENGTH: uint = 8;
+static OWNER_SALT_LENGTH: uint = 4;
+
+// The random seed that an EC multiplied key is made from.
+static SEED_LENGTH: uint = 24;
+
+// The scrypt parameters for stretching the passphrase, and for the cheaper
+// step of making a key from an intermediate code.
+static SCRYPT_COST: uint = 16384;
+static SCRYPT_BLOCK_SIZE: uint = 8;
+static SCRYPT_PARALLELISM: uint = 8;
+static EC_SCRYPT_COST: uint = 1024;
+static EC_SCRYPT_BLOCK_SIZE: uint = 1;
+static EC_SCRYPT_PARALLELISM: uint = 1;
+
+static INVALID_KEY: &'static str = "not a valid BIP 38 encrypted key";
+static WRONG_PASSPHRASE: &'static str = "the passphrase is wrong";
+
+/// Encrypts a private key with a passphrase. If `compressed` is true, the
+/// key's address is the one for its compressed public key, and that's the
+/// address other wallets will use when it's decrypted.
+pub fn encrypt(private_key: &PrivateKey, compressed: bool, passphrase: &str) -> String {
+    let address_hash = address_hash(&private_key.to_public_key(), compressed);
+    let derived = SecretBytes::new(hash::scrypt(passphrase.as_bytes(), address_hash.as_slice(), SCRYPT_COST,
+                                                SCRYPT_BLOCK_SIZE, SCRYPT_PARALLELISM, 64));
+    let (half1, half2) = derived.as_slice().split_at(32);
+    let key = private_key.get_data();
+
+    let mut data = vec![NON_EC_MULTIPLIED, FLAG_NON_EC_MULTIPLIED | if compressed { FLAG_COMPRESSED } else { 0 }];
+    data.push_all(address_hash.as_slice());
+    data.push_all(aes_encrypt(half2, xor(key.slice_to(16), half1.slice_to(16)).as_slice()).as_slice());
+    data.push_all(aes_encrypt(half2, xor(key.slice_from(16), half1.slice_from(16)).as_slice()).as_slice());
+
+    // Encode it in WIF format, which puts the prefix in front and a 4-byte
+    // checksum at the end.
+    base58::encode(wif::encode(data.as_slice(), PREFIX).as_slice())
+}
+
+/// Decrypts an encrypted key of either kind with its passphrase. Returns the
+/// key, and whether its address is the one for its compressed public key.
+pub fn decrypt(encrypted: &str, passphrase: &str) -> Result<(PrivateKey, bool), &'static str> {
+    let data = match base58::decode(encrypted.trim()).and_then(|data| wif::decode(data.as_slice(), PREFIX)) {
+        Some(data) => data,
+        None => return Err(INVALID_KEY)
+    };
+    if data.len() != ENCRYPTED_LENGTH { return Err(INVALID_KEY); }
+    let flag = data[1];
+    let compressed = flag & FLAG_COMPRESSED != 0;
+
+    let private_key = if data[0] == NON_EC_MULTIPLIED && flag & !FLAG_COMPRESSED == FLAG_NON_EC_MULTIPLIED {
+        decrypt_non_ec_multiplied(data.as_slice(), passphrase)
+    } else if data[0] == EC_MULTIPLIED && flag & !(FLAG_COMPRESSED | FLAG_LOT_SEQUENCE) == 0 {
+        decrypt_ec_multiplied(data.as_slice(), passphrase, flag & FLAG_LOT_SEQUENCE != 0)
+    } else {
+        return Err(INVALID_KEY);
+    };
+
+    // The address hash is all there is to tell a wrong passphrase from a
+    // right one.
+    match private_key {
+        Some(private_key) => {
+            if address_hash(&private_key.to_public_key(), compressed).as_slice() == data.slice(2, 6) {
+                Ok((private_key, compressed))
+            } else {
+                Err(WRONG_PASSPHRASE)
+            }
+        }
+        None => Err(WRONG_PASSPHRASE)
+    }
+}
+
+/// Makes an intermediate code from a passphrase, for someone else to make
+/// encrypted keys with using `encrypt_from_intermediate_code()`. The code
+/// can have a lot and sequence number, which every key made from it has
+/// too. Fails if the lot is more than `MAX_LOT` or the sequence number is
+/// more than `MAX_SEQUENCE`.
+pub fn intermediate_code(passphrase: &str, lot_and_sequence: Option<(u32, u32)>) -> String {
+    let salt_length = if lot_and_sequence.is_some() { OWNER_SALT_LENGTH } else { OWNER_ENTROPY_LENGTH };
+    loop {
+        let owner_salt = openssl::crypto::rand::rand_bytes(salt_length);
+        match intermediate_code_with_salt(passphrase, owner_salt.as_slice(), lot_and_sequence) {
+            Some(code) => return code,
+            // The stretched passphrase wasn't a valid private key, which is
+            // astronomically unlikely, so try another salt.
+            None => continue
+        }
+    }
+}
+
+/// Makes a new encrypted key from an intermediate code, and returns it with
+/// its address. Nothing is needed but the code, and the key can only be
+/// decrypted with the passphrase the code was made from. Returns None if the
+/// code isn't valid.
+pub fn encrypt_from_intermediate_code(code: &str, compressed: bool) -> Option<(String, Address)> {
+    let (owner_entropy, pass_point, lot_sequence) = match parse_intermediate_code(code) {
+        Some(parsed) => parsed,
+        None => return None
+    };
+    loop {
+        let seed = SecretBytes::new(openssl::crypto::rand::rand_bytes(SEED_LENGTH));
+        match encrypt_with_seed(owner_entropy.as_slice(), &pass_point, lot_sequence, compressed, seed.as_slice()) {
+            Some(result) => return Some(result),
+            // The seed didn't hash to a valid factor, which is astronomically
+            // unlikely, so try another one.
+            None => continue
+        }
+    }
+}
+
+// Decrypts a key that was encrypted directly. The data is the encrypted key
+// less the prefix and the checksum.
+fn decrypt_non_ec_multiplied(data: &[u8], passphrase: &str) -> Option<PrivateKey> {
+    let derived = SecretBytes::new(hash::scrypt(passphrase.as_bytes(), data.slice(2, 6), SCRYPT_COST,
+                                                SCRYPT_BLOCK_SIZE, SCRYPT_PARALLELISM, 64));
+    let (half1, half2) = derived.as_slice().split_at(32);
+
+    let mut key = SecretBytes::with_capacity(32);
+    key.push_all(xor(aes_decrypt(half2, data.slice(6, 22)).as_slice(), half1.slice_to(16)).as_slice());
+    key.push_all(xor(aes_decrypt(half2, data.slice(22, 38)).as_slice(), half1.slice_from(16)).as_slice());
+    PrivateKey::new(key.as_slice())
+}
+
+// Decrypts a key that was made by EC multiplication, by finding the seed it
+// was made from. The key is the passfactor, from the passphrase, multiplied
+// by a factor from the seed.
+fn decrypt_ec_multiplied(data: &[u8], passphrase: &str, lot_sequence: bool) -> Option<PrivateKey> {
+    let owner_entropy = data.slice(6, 14);
+    let pass_factor = pass_factor(passphrase, owner_entropy, lot_sequence);
+    if !ecdsa::is_valid_private_key(pass_factor.as_slice()) { return None; }
+    let pass_point = Point::generator().mul_secret(&Scalar::from_bytes(pass_factor.as_slice()).unwrap());
+
+    let derived = derive_from_pass_point(&pass_point, data.slice(2, 6), owner_entropy);
+    let (half1, half2) = derived.as_slice().split_at(32);
+
+    // The second encrypted block is the end of the first one followed by the
+    // end of the seed.
+    let part2 = xor(aes_decrypt(half2, data.slice(22, 38)).as_slice(), half1.slice_from(16));
+    let mut part1 = data.slice(14, 22).to_vec();
+    part1.push_all(part2.as_slice().slice_to(8));
+
+    let mut seed = SecretBytes::with_capacity(SEED_LENGTH);
+    seed.push_all(xor(aes_decrypt(half2, part1.as_slice()).as_slice(), half1.slice_to(16)).as_slice());
+    seed.push_all(part2.as_slice().slice_from(8));
+
+    let factor = SecretBytes::new(hash::double_sha256(seed.as_slice()));
+    let key = Scalar::from_bytes(pass_factor.as_slice()).unwrap().mul(&Scalar::reduce(factor.as_slice()));
+    let key = SecretBytes::new(key.to_bytes());
+    PrivateKey::new(key.as_slice())
+}
+
+// Makes an intermediate code from the random part of the owner entropy: 8
+// bytes without a lot and sequence number, or 4 bytes with them. Returns
+// None if the passfactor isn't a valid private key.
+fn intermediate_code_with_salt(passphrase: &str, owner_salt: &[u8],
+                               lot_and_sequence: Option<(u32, u32)>) -> Option<String> {
+    let mut owner_entropy = owner_salt.to_vec();
+    let magic = match lot_and_sequence {
+        Some((lot, sequence)) => {
+            assert!(lot <= MAX_LOT && sequence <= MAX_SEQUENCE);
+            let number = lot * (MAX_SEQUENCE + 1) + sequence;
+            owner_entropy.push_all(&[(number >> 24) as u8, (number >> 16) as u8, (number >> 8) as u8, number as u8]);
+            MAGIC_LOT_SEQUENCE
+        }
+        None => MAGIC_NO_LOT_SEQUENCE
+    };
+    assert_eq!(owner_entropy.len(), OWNER_ENTROPY_LENGTH);
+
+    let pass_factor = pass_factor(passphrase, owner_entropy.as_slice(), lot_and_sequence.is_some());
+    if !ecdsa::is_valid_private_key(pass_factor.as_slice()) { return None; }
+    let pass_point = Point::generator().mul_secret(&Scalar::from_bytes(pass_factor.as_slice()).unwrap());
+
+    let mut data = MAGIC.slice_from(1).to_vec();
+    data.push(magic);
+    data.push_all(owner_entropy.as_slice());
+    data.push_all(pass_point.to_compressed().as_slice());
+    Some(base58::encode(wif::encode(data.as_slice(), MAGIC[0]).as_slice()))
+}
+
+// Parses an intermediate code into its owner entropy and passpoint, and
+// whether it has a lot and sequence number.
+fn parse_intermediate_code(code: &str) -> Option<(Vec<u8>, Point, bool)> {
+    let data = match base58::decode(code.trim()).and_then(|data| wif::decode(data.as_slice(), MAGIC[0])) {
+        Some(data) => data,
+        None => return None
+    };
+    if data.len() != INTERMEDIATE_CODE_LENGTH || data.slice_to(6) != MAGIC.slice_from(1) { return None; }
+
+    let lot_sequence = if data[6] == MAGIC_LOT_SEQUENCE {
+        true
+    } else if data[6] == MAGIC_NO_LOT_SEQUENCE {
+        false
+    } else {
+        return None;
+    };
+
+    let owner_entropy = data.slice(7, 7 + OWNER_ENTROPY_LENGTH).to_vec();
+    match Point::from_bytes(data.slice_from(7 + OWNER_ENTROPY_LENGTH)) {
+        Some(pass_point) => Some((owner_entropy, pass_point, lot_sequence)),
+        None => None
+    }
+}
+
+// Makes an encrypted key and its address from the parts of an intermediate
+// code and a random seed. Returns None if the seed doesn't give a valid
+// factor to multiply the passpoint by.
+fn encrypt_with_seed(owner_entropy: &[u8], pass_point: &Point, lot_sequence: bool, compressed: bool,
+                     seed: &[u8]) -> Option<(String, Address)> {
+    let factor = SecretBytes::new(hash::double_sha256(seed));
+    if !ecdsa::is_valid_private_key(factor.as_slice()) { return None; }
+
+    let point = pass_point.mul_secret(&Scalar::from_bytes(factor.as_slice()).unwrap());
+    let public_key = PublicKey::new(point.to_uncompressed().as_slice()).unwrap();
+    let address = if compressed {
+        Address::from_compressed_public_key(&public_key)
+    } else {
+        Address::from_public_key(&public_key)
+    };
+    let address_hash = address_hash(&public_key, compressed);
+
+    let derived = derive_from_pass_point(pass_point, address_hash.as_slice(), owner_entropy);
+    let (half1, half2) = derived.as_slice().split_at(32);
+
+    let part1 = aes_encrypt(half2, xor(seed.slice_to(16), half1.slice_to(16)).as_slice());
+    let mut block2 = SecretBytes::with_capacity(16);
+    block2.push_all(part1.slice_from(8));
+    block2.push_all(seed.slice_from(16));
+    let part2 = aes_encrypt(half2, xor(block2.as_slice(), half1.slice_from(16)).as_slice());
+
+    let mut flag = if compressed { FLAG_COMPRESSED } else { 0 };
+    if lot_sequence { flag |= FLAG_LOT_SEQUENCE; }
+
+    let mut data = vec![EC_MULTIPLIED, flag];
+    data.push_all(address_hash.as_slice());
+    data.push_all(owner_entropy);
+    data.push_all(part1.slice_to(8));
+    data.push_all(part2.as_slice());
+    Some((base58::encode(wif::encode(data.as_slice(), PREFIX).as_slice()), address))
+}
+
+// Stretches the passphrase into the passfactor, the private key that the
+// passpoint in an intermediate code is the public key of. With a lot and
+// sequence number, only the first 4 bytes of the owner entropy are the salt,
+// and the whole of it is hashed in after.
+fn pass_factor(passphrase: &str, owner_entropy: &[u8], lot_sequence: bool) -> SecretBytes {
+    let salt = if lot_sequence { owner_entropy.slice_to(OWNER_SALT_LENGTH) } else { owner_entropy };
+    let pre_factor = SecretBytes::new(hash::scrypt(passphrase.as_bytes(), salt, SCRYPT_COST,
+                                                   SCRYPT_BLOCK_SIZE, SCRYPT_PARALLELISM, 32));
+    if !lot_sequence { return pre_factor; }
+
+    let mut data = SecretBytes::with_capacity(32 + OWNER_ENTROPY_LENGTH);
+    data.push_all(pre_factor.as_slice());
+    data.push_all(owner_entropy);
+    SecretBytes::new(hash::double_sha256(data.as_slice()))
+}
+
+// Derives the key that encrypts the seed of an EC multiplied key.
+fn derive_from_pass_point(pass_point: &Point, address_hash: &[u8], owner_entropy: &[u8]) -> SecretBytes {
+    let mut salt = address_hash.to_vec();
+    salt.push_all(owner_entropy);
+    SecretBytes::new(hash::scrypt(pass_point.to_compressed().as_slice(), salt.as_slice(), EC_SCRYPT_COST,
+                                  EC_SCRYPT_BLOCK_SIZE, EC_SCRYPT_PARALLELISM, 64))
+}
+
+// Finds the first 4 bytes of the double SHA-256 hash of a key's P2PKH
+// address, which an encrypted key has in the clear.
+fn address_hash(public_key: &PublicKey, compressed: bool) -> Vec<u8> {
+    let address = if compressed {
+        Address::from_compressed_public_key(public_key)
+    } else {
+        Address::from_public_key(public_key)
+    };
+    hash::double_sha256(address.encode().as_bytes()).slice_to(4).to_vec()
+}
+
+// XORs two slices of the same length together.
+fn xor(a: &[u8], b: &[u8]) -> SecretBytes {
+    assert_eq!(a.len(), b.len());
+    SecretBytes::new(a.iter().zip(b.iter()).map(|(x, y)| *x ^ *y).collect())
+}
+
+// Encrypts a single 16-byte block with AES-256, with no padding.
+fn aes_encrypt(key: &[u8], block: &[u8]) -> Vec<u8> {
+    aes(openssl::crypto::symm::Mode::Encrypt, key, block)
+}
+
+// Decrypts a single 16-byte block with AES-256, with no padding.
+fn aes_decrypt(key: &[u8], block: &[u8]) -> SecretBytes {
+    SecretBytes::new(aes(openssl::crypto::symm::Mode::Decrypt, key, block))
+}
+
+fn aes(mode: openssl::crypto::symm::Mode, key: &[u8], block: &[u8]) -> Vec<u8> {
+    assert_eq!(block.len(), 16);
+    let crypter = openssl::crypto::symm::Crypter::new(openssl::crypto::symm::Type::AES_256_ECB);
+    crypter.init(mode, key, vec![]);
+    crypter.pad(false);
+    let mut result = crypter.update(block);
+    result.push_all(crypter.finalize().as_slice());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::FromHex;
+
+    use protocol::address::Address;
+    use protocol::private_key::PrivateKey;
+    use super::{encrypt, decrypt, intermediate_code, intermediate_code_with_salt,
+                encrypt_from_intermediate_code, parse_intermediate_code, encrypt_with_seed};
+
+    // The test vectors are from BIP 38. Each one takes a second or so, since
+    // that's the point of scrypt.
+
+    #[test]
+    fn test_encrypt() {
+        let key = "CBF4B9F70470856BB4F40F80B87EDB90865997FFEE6DF315AB166D713AF433A5".from_hex().unwrap();
+        let key = PrivateKey::new(key.as_slice()).unwrap();
+        assert_eq!(encrypt(&key, false, "TestingOneTwoThree").as_slice(),
+                   "6PRVWUbkzzsbcVac2qwfssoUJAN1Xhrg6bNk8J7Nzm5H7kxEbn2Nh2ZoGg");
+
+        let key = "09C2686880095B1A4C249EE3AC4EEA8A014F11E6F986D0B5025AC1F39AFBD9AE".from_hex().unwrap();
+        let key = PrivateKey::new(key.as_slice()).unwrap();
+        assert_eq!(encrypt(&key, true, "Satoshi").as_slice(),
+                   "6PYLtMnXvfG3oJde97zRyLYFZCYizPU5T3LwgdYJz1fRhh16bU7u6PPmY7");
+    }
+
+    #[test]
+    fn test_decrypt() {
+        let vectors = [
+            ("6PRVWUbkzzsbcVac2qwfssoUJAN1Xhrg6bNk8J7Nzm5H7kxEbn2Nh2ZoGg", "TestingOneTwoThree",
+             "CBF4B9F70470856BB4F40F80B87EDB90865997FFEE6DF315AB166D713AF433A5", false),
+            ("6PYLtMnXvfG3oJde97zRyLYFZCYizPU5T3LwgdYJz1fRhh16bU7u6PPmY7", "Satoshi",
+             "09C2686880095B1A4C249EE3AC4EEA8A014F11E6F986D0B5025AC1F39AFBD9AE", true),
+            // EC multiplied, without and with a lot and sequence number.
+            ("6PfQu77ygVyJLZjfvMLyhLMQbYnu5uguoJJ4kMCLqWwPEdfpwANVS76gTX", "TestingOneTwoThree",
+             "A43A940577F4E97F5C4D39EB14FF083A98187C64EA7C99EF7CE460833959A519", false),
+            ("6PgNBNNzDkKdhkT6uJntUXwwzQV8Rr2tZcbkDcuC9DZRsS6AtHts4Ypo1j", "MOLON LABE",
+             "44EA95AFBF138356A05EA32110DFD627232D0F2991AD221187BE356F19FA8190", false)
+        ];
+        for &(encrypted, passphrase, key, compressed) in vectors.iter() {
+            let (private_key, is_compressed) = decrypt(encrypted, passphrase).unwrap();
+            assert_eq!(private_key.get_data(), key.from_hex().unwrap().as_slice());
+            assert_eq!(is_compressed, compressed);
+        }
+    }
+
+    #[test]
+    fn test_decrypt_invalid() {
+        let encrypted = "6PRVWUbkzzsbcVac2qwfssoUJAN1Xhrg6bNk8J7Nzm5H7kxEbn2Nh2ZoGg";
+        assert!(decrypt(encrypted, "TestingOneTwoThre3").is_err());
+        assert!(decrypt(encrypted.slice_to(57), "TestingOneTwoThree").is_err());
+        assert!(decrypt("5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ", "TestingOneTwoThree").is_err());
+        assert!(decrypt("", "TestingOneTwoThree").is_err());
+    }
+
+    #[test]
+    fn test_intermediate_code_with_salt() {
+        // The codes that BIP 38's EC multiplied test vectors were made from.
+        let code = intermediate_code_with_salt("TestingOneTwoThree", "A50DBA6772CB9383".from_hex().unwrap().as_slice(), None);
+        assert_eq!(code.unwrap().as_slice(), "passphrasepxFy57B9v8HtUsszJYKReoNDV6VHjUSGt8EVJmux9n1J3Ltf1gRxyDGXqnf9qm");
+
+        let code = intermediate_code_with_salt("MOLON LABE", "4FCA5A97".from_hex().unwrap().as_slice(), Some((263183, 1)));
+        assert_eq!(code.unwrap().as_slice(), "passphraseaB8feaLQDENqCgr4gKZpmf4VoaT6qdjJNJiv7fsKvjqavcJxvuR1hy25aTu5sX");
+    }
+
+    #[test]
+    fn test_encrypt_with_seed() {
+        let seed: Vec<u8> = range(0, 24u8).collect();
+        let vectors = [
+            ("passphrasepxFy57B9v8HtUsszJYKReoNDV6VHjUSGt8EVJmux9n1J3Ltf1gRxyDGXqnf9qm", false,
+             "6PfUEgLj25Xb5Que4T11Va1C6PDbhCBsMxhdUNTLxBiSDtXKyShwTPeMVh", "1PzjHWeqgqsC9goidp3URKcd8C5nXKznNF"),
+            ("passphrasepxFy57B9v8HtUsszJYKReoNDV6VHjUSGt8EVJmux9n1J3Ltf1gRxyDGXqnf9qm", true,
+             "6PnT8gpBDk9K4J1r82G2ZV9paccZBYZvjyYQjimYdMrQx416TL4Zb7ueNi", "19LHD5M1LQKMiaz7uDDxAbTaD8BXKKzxGj"),
+            ("passphraseaB8feaLQDENqCgr4gKZpmf4VoaT6qdjJNJiv7fsKvjqavcJxvuR1hy25aTu5sX", true,
+             "6PoLGAaRtGiV2cjpiemhPA8EcWN74baCjbZqTT7y6kRvXNF93tuTEicMMi", "1EmyXsnQVKmwqspwGwN2Yg4zxMQLvNc7UY")
+        ];
+        for &(code, compressed, expected, address) in vectors.iter() {
+            let (owner_entropy, pass_point, lot_sequence) = parse_intermediate_code(code).unwrap();
+            let (encrypted, generated_address) = encrypt_with_seed(owner_entropy.as_slice(), &pass_point,
+                                                                   lot_sequence, compressed, seed.as_slice()).unwrap();
+            assert_eq!(encrypted.as_slice(), expected);
+            assert_eq!(generated_address.encode().as_slice(), address);
+        }
+
+        let (private_key, compressed) = decrypt("6PoLGAaRtGiV2cjpiemhPA8EcWN74baCjbZqTT7y6kRvXNF93tuTEicMMi", "MOLON LABE").unwrap();
+        assert!(compressed);
+        let address = Address::from_compressed_public_key(&private_key.to_public_key());
+        assert_eq!(address.encode().as_slice(), "1EmyXsnQVKmwqspwGwN2Yg4zxMQLvNc7UY");
+    }
+
+    #[test]
+    fn test_encrypt_from_intermediate_code() {
+        let code = intermediate_code("Satoshi", None);
+        assert!(code.as_slice().starts_with("passphrase"));
+
+        let (encrypted, address) = encrypt_from_intermediate_code(code.as_slice(), false).unwrap();
+        assert!(encrypted.as_slice().starts_with("6P"));
+        let (private_key, compressed) = decrypt(encrypted.as_slice(), "Satoshi").unwrap();
+        assert!(!compressed);
+        assert_eq!(private_key.to_address().encode(), address.encode());
+
+        assert!(encrypt_from_intermediate_code("passphrase", false).is_none());
+        assert!(encrypt_from_intermediate_code("6PRVWUbkzzsbcVac2qwfssoUJAN1Xhrg6bNk8J7Nzm5H7kxEbn2Nh2ZoGg", false).is_none());
+    }
+}
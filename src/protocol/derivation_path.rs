@@ -0,0 +1,183 @@
+//! BIP 32 derivation paths, like `m/0'/0'/5'`, and the standard account
+//! layout from BIP 44 that most wallets use.
+
+use protocol::address::AddressType;
+
+/// Child indexes at or above this value are hardened. In a path, they're
+/// written as the index minus this value followed by an apostrophe.
+pub static HARDENED: u32 = 0x80000000;
+
+/// The coin type for Bitcoin in account paths, from SLIP 44.
+pub static BITCOIN_COIN_TYPE: u32 = 0;
+
+/// The chain in an account that addresses given out to receive payments are
+/// derived from.
+pub static EXTERNAL_CHAIN: u32 = 0;
+
+/// The chain in an account that change addresses are derived from.
+pub static INTERNAL_CHAIN: u32 = 1;
+
+// The BIP 48 script type of native SegWit multisig accounts.
+static P2WSH_SCRIPT_TYPE: u32 = 2;
+
+/// A sequence of child indexes leading from a master key to a descendant key.
+#[deriving(Clone, PartialEq, Show)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    /// Creates a DerivationPath from a list of child indexes.
+    pub fn new(indexes: &[u32]) -> DerivationPath {
+        DerivationPath(indexes.to_vec())
+    }
+
+    /// Creates the path of an account in the standard layout:
+    ///
+    ///     m/purpose'/coin_type'/account'
+    ///
+    /// Where the purpose depends on the type of address the account uses: 44
+    /// for P2PKH (BIP 44), 49 for P2SH-P2WPKH (BIP 49), 84 for P2WPKH (BIP 84),
+    /// and 86 for P2TR (BIP 86). Addresses are derived below this as
+    /// `chain/index`, where the chain is external or internal (change).
+    ///
+    /// P2WSH accounts are for multisig, and follow BIP 48 instead, which adds
+    /// a script type of 2 (for native SegWit) after the account:
+    ///
+    ///     m/48'/coin_type'/account'/2'
+    pub fn account(address_type: AddressType, account: u32) -> DerivationPath {
+        let purpose = match address_type {
+            AddressType::P2PKH => 44,
+            AddressType::P2SH => 49,
+            AddressType::P2WPKH => 84,
+            AddressType::P2WSH => 48,
+            AddressType::P2TR => 86
+        };
+
+        let mut indexes = vec![purpose + HARDENED, BITCOIN_COIN_TYPE + HARDENED, account + HARDENED];
+        if address_type == AddressType::P2WSH {
+            indexes.push(P2WSH_SCRIPT_TYPE + HARDENED);
+        }
+        DerivationPath(indexes)
+    }
+
+    /// Parses a derivation path written in the usual notation: an `m`,
+    /// followed by child indexes separated by slashes. Hardened indexes end
+    /// with an apostrophe (or an `h`). Returns None if the path is invalid.
+    pub fn parse(string: &str) -> Option<DerivationPath> {
+        let mut components = string.split('/');
+
+        if components.next() != Some("m") { return None; }
+
+        let mut indexes = Vec::new();
+        for component in components {
+            let (number, hardened) = if component.ends_with("'") || component.ends_with("h") {
+                (component.slice_to(component.len() - 1), true)
+            } else {
+                (component, false)
+            };
+
+            // Only plain decimal digits are allowed, so no signs or spaces.
+            if number.is_empty() || !number.chars().all(|ch| ch.is_digit(10)) {
+                return None;
+            }
+
+            let index: u32 = match from_str(number) {
+                Some(index) => index,
+                None => return None
+            };
+            if index >= HARDENED { return None; }
+
+            indexes.push(if hardened { index + HARDENED } else { index });
+        }
+
+        Some(DerivationPath(indexes))
+    }
+
+    /// Gets the child indexes that make up the path.
+    pub fn get_indexes(&self) -> &[u32] {
+        let DerivationPath(ref indexes) = *self;
+        indexes.as_slice()
+    }
+
+    /// Checks whether this path starts with all of the indexes in `other`.
+    pub fn starts_with(&self, other: &DerivationPath) -> bool {
+        self.get_indexes().starts_with(other.get_indexes())
+    }
+
+    /// Returns a new path with the given child index appended.
+    pub fn child(&self, index: u32) -> DerivationPath {
+        let mut indexes = self.get_indexes().to_vec();
+        indexes.push(index);
+        DerivationPath(indexes)
+    }
+
+    /// Writes the path in the notation that `parse()` accepts, using
+    /// apostrophes for hardened indexes.
+    pub fn encode(&self) -> String {
+        let mut result = "m".to_string();
+        for &index in self.get_indexes().iter() {
+            if index >= HARDENED {
+                result.push_str(format!("/{}'", index - HARDENED).as_slice());
+            } else {
+                result.push_str(format!("/{}", index).as_slice());
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::address::AddressType;
+
+    use super::{DerivationPath, HARDENED};
+
+    #[test]
+    fn test_parse() {
+        let path = DerivationPath::parse("m/0'/1/2h/2/1000000000").unwrap();
+        assert_eq!(path.get_indexes(), [HARDENED, 1, HARDENED + 2, 2, 1000000000].as_slice());
+
+        let path = DerivationPath::parse("m").unwrap();
+        assert!(path.get_indexes().is_empty());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(DerivationPath::parse("").is_none());
+        assert!(DerivationPath::parse("0/1").is_none());
+        assert!(DerivationPath::parse("m/").is_none());
+        assert!(DerivationPath::parse("m/-1").is_none());
+        assert!(DerivationPath::parse("m/1''").is_none());
+        assert!(DerivationPath::parse("m/2147483648").is_none());
+        assert!(DerivationPath::parse("m/0x10").is_none());
+    }
+
+    #[test]
+    fn test_encode() {
+        let path = DerivationPath::new(&[HARDENED + 44, HARDENED, HARDENED, 1, 5]);
+        assert_eq!(path.encode().as_slice(), "m/44'/0'/0'/1/5");
+        assert_eq!(DerivationPath::parse("m/44h/0h/0'/1/5"), Some(path));
+    }
+
+    #[test]
+    fn test_child() {
+        let path = DerivationPath::parse("m/0'").unwrap();
+        assert_eq!(path.child(7).encode().as_slice(), "m/0'/7");
+    }
+
+    #[test]
+    fn test_account() {
+        let path = DerivationPath::account(AddressType::P2PKH, 0);
+        assert_eq!(path.encode().as_slice(), "m/44'/0'/0'");
+        let path = DerivationPath::account(AddressType::P2SH, 1);
+        assert_eq!(path.encode().as_slice(), "m/49'/0'/1'");
+        let path = DerivationPath::account(AddressType::P2WPKH, 0);
+        assert_eq!(path.encode().as_slice(), "m/84'/0'/0'");
+        let path = DerivationPath::account(AddressType::P2WSH, 0);
+        assert_eq!(path.encode().as_slice(), "m/48'/0'/0'/2'");
+        let path = DerivationPath::account(AddressType::P2TR, 2);
+        assert_eq!(path.encode().as_slice(), "m/86'/0'/2'");
+
+        assert!(path.child(0).child(5).starts_with(&path));
+        assert!(!path.starts_with(&path.child(0)));
+    }
+}
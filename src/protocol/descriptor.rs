@@ -0,0 +1,723 @@
+//! Output script descriptors (BIP 380 and the BIPs that follow it), which
+//! describe the scripts a wallet pays to in a form other wallets can import.
+//!
+//! The descriptors understood here are `pk()`, `pkh()`, `wpkh()`, `sh()`,
+//! `wsh()`, `tr()` (with a key only, no script tree), `multi()`,
+//! `sortedmulti()` and `addr()`. Keys can be hex public keys or xpubs, with
+//! or without an origin, but never private keys.
+
+use serialize::hex::{ToHex, FromHex};
+
+use util::hash;
+use util::secp256k1::Point;
+use protocol::address::{Address, AddressType};
+use protocol::derivation_path::{DerivationPath, HARDENED};
+use protocol::extended_key::ExtendedPublicKey;
+use protocol::public_key::PublicKey;
+use protocol::script;
+use protocol::taproot;
+
+// The characters allowed in a descriptor, in the order the checksum algorithm
+// numbers them. The most common characters come first, so that their numbers
+// fit in 5 bits.
+static INPUT_CHARSET: &'static str = "0123456789()[],'/*abcdefgh@:$%{}\
+                                      IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~\
+                                      ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+
+// The characters a checksum is written with, the same as in bech32.
+static CHECKSUM_CHARSET: &'static [u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+// The generator of the BCH code the checksum is based on.
+static GENERATOR: [u64, ..5] = [0xf5dee51989, 0xa9fdca3312, 0x1bab10e32d, 0x3706b1677a, 0x644d626ffd];
+
+/// The number of characters in a checksum.
+pub static CHECKSUM_LENGTH: uint = 8;
+
+/// Where a key in a descriptor came from: the fingerprint of the master key
+/// and the path the key was derived at.
+#[deriving(Clone, PartialEq, Show)]
+pub struct KeyOrigin {
+    pub fingerprint: Vec<u8>,
+    pub path: DerivationPath
+}
+
+/// A public key as it's written in a descriptor.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Key {
+    /// A public key in hex: compressed, uncompressed, or (only in `tr()`)
+    /// x-only.
+    Plain(Vec<u8>),
+    /// An extended public key and the path of the key to use below it. If
+    /// the key is ranged (written with `/*` at the end), the index of the
+    /// script being derived is appended to the path.
+    Extended(ExtendedPublicKey, DerivationPath, bool)
+}
+
+/// A key in a descriptor, with its origin if one was given.
+#[deriving(Clone, PartialEq, Show)]
+pub struct DescriptorKey {
+    pub origin: Option<KeyOrigin>,
+    pub key: Key
+}
+
+/// A parsed output descriptor. A descriptor inside `sh()` or `wsh()`
+/// describes the redeem script or witness script.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Descriptor {
+    Pk(DescriptorKey),
+    Pkh(DescriptorKey),
+    Wpkh(DescriptorKey),
+    Sh(Box<Descriptor>),
+    Wsh(Box<Descriptor>),
+    Tr(DescriptorKey),
+    Multi(uint, Vec<DescriptorKey>),
+    SortedMulti(uint, Vec<DescriptorKey>),
+    Addr(Address)
+}
+
+// Where in a descriptor a function or key appears, which decides what's
+// allowed there: SegWit scripts only allow compressed keys, and x-only keys
+// are only allowed in taproot.
+#[deriving(Clone, Copy, PartialEq)]
+enum Context {
+    Top,
+    Sh,
+    Segwit,
+    Taproot
+}
+
+impl Descriptor {
+    /// Parses a descriptor. The checksum can be left off, but if it's given,
+    /// it has to be right. Returns None if the descriptor is invalid or uses
+    /// something Cykas doesn't understand.
+    pub fn parse(string: &str) -> Option<Descriptor> {
+        let descriptor = match string.find('#') {
+            Some(position) => {
+                let descriptor = string.slice_to(position);
+                let given_checksum = string.slice_from(position + 1);
+                if checksum(descriptor) != Some(given_checksum.to_string()) {
+                    return None;
+                }
+                descriptor
+            }
+            None => string
+        };
+
+        parse_function(descriptor, Context::Top)
+    }
+
+    /// Writes the descriptor in the form `parse()` reads, with a checksum.
+    pub fn encode(&self) -> String {
+        // Everything written here is made of valid descriptor characters.
+        add_checksum(self.encode_without_checksum().as_slice()).unwrap()
+    }
+
+    /// Checks whether the descriptor has a ranged key, so that it describes
+    /// a different script for each index.
+    pub fn is_ranged(&self) -> bool {
+        match *self {
+            Descriptor::Pk(ref key) | Descriptor::Pkh(ref key) |
+            Descriptor::Wpkh(ref key) | Descriptor::Tr(ref key) => key.is_ranged(),
+            Descriptor::Sh(ref inner) | Descriptor::Wsh(ref inner) => inner.is_ranged(),
+            Descriptor::Multi(_, ref keys) | Descriptor::SortedMulti(_, ref keys) => {
+                keys.iter().any(|key| key.is_ranged())
+            }
+            Descriptor::Addr(_) => false
+        }
+    }
+
+    /// Derives the script the descriptor describes at the given index, which
+    /// is ignored if the descriptor isn't ranged. At the top level, this is a
+    /// scriptPubKey. Returns None if a key can't be derived at the index.
+    pub fn script_pubkey(&self, index: u32) -> Option<Vec<u8>> {
+        match *self {
+            Descriptor::Pk(ref key) => {
+                key.derive(index).map(|public_key| script::p2pk_script(public_key.as_slice()))
+            }
+            Descriptor::Pkh(ref key) => {
+                key.derive(index).map(|public_key| {
+                    script::p2pkh_script_pubkey(hash::hash160(public_key.as_slice()).as_slice())
+                })
+            }
+            Descriptor::Wpkh(ref key) => {
+                key.derive(index).map(|public_key| {
+                    script::p2wpkh_script_pubkey(hash::hash160(public_key.as_slice()).as_slice())
+                })
+            }
+            Descriptor::Sh(ref inner) => {
+                inner.script_pubkey(index).map(|redeem_script| {
+                    script::p2sh_script_pubkey(hash::hash160(redeem_script.as_slice()).as_slice())
+                })
+            }
+            Descriptor::Wsh(ref inner) => {
+                inner.script_pubkey(index).map(|witness_script| {
+                    script::p2wsh_script_pubkey(hash::sha256(witness_script.as_slice()).as_slice())
+                })
+            }
+            Descriptor::Tr(ref key) => {
+                key.derive(index).and_then(|public_key| {
+                    // A compressed key is used as the internal key without
+                    // its first byte, like an x-only key.
+                    let internal_key = if public_key.len() == 33 {
+                        public_key.slice_from(1)
+                    } else {
+                        public_key.as_slice()
+                    };
+                    taproot::tweak_public_key(internal_key, None)
+                }).map(|output_key| script::p2tr_script_pubkey(output_key.as_slice()))
+            }
+            Descriptor::Multi(threshold, ref keys) => {
+                derive_keys(keys.as_slice(), index).map(|public_keys| {
+                    script::multisig_script(threshold, public_keys.as_slice())
+                })
+            }
+            Descriptor::SortedMulti(threshold, ref keys) => {
+                derive_keys(keys.as_slice(), index).map(|mut public_keys| {
+                    public_keys.sort();
+                    script::multisig_script(threshold, public_keys.as_slice())
+                })
+            }
+            Descriptor::Addr(ref address) => Some(address.to_script_pubkey())
+        }
+    }
+
+    /// Derives the address the descriptor describes at the given index.
+    /// Returns None if a key can't be derived at the index, or if the script
+    /// has no address (like a bare `multi()`).
+    pub fn address(&self, index: u32) -> Option<Address> {
+        self.script_pubkey(index).and_then(|script_pubkey| {
+            Address::from_script_pubkey(script_pubkey.as_slice())
+        })
+    }
+
+    fn encode_without_checksum(&self) -> String {
+        match *self {
+            Descriptor::Pk(ref key) => format!("pk({})", key.encode()),
+            Descriptor::Pkh(ref key) => format!("pkh({})", key.encode()),
+            Descriptor::Wpkh(ref key) => format!("wpkh({})", key.encode()),
+            Descriptor::Sh(ref inner) => format!("sh({})", inner.encode_without_checksum()),
+            Descriptor::Wsh(ref inner) => format!("wsh({})", inner.encode_without_checksum()),
+            Descriptor::Tr(ref key) => format!("tr({})", key.encode()),
+            Descriptor::Multi(threshold, ref keys) => {
+                format!("multi({},{})", threshold, encode_keys(keys.as_slice()))
+            }
+            Descriptor::SortedMulti(threshold, ref keys) => {
+                format!("sortedmulti({},{})", threshold, encode_keys(keys.as_slice()))
+            }
+            Descriptor::Addr(ref address) => from_address(address)
+        }
+    }
+}
+
+impl DescriptorKey {
+    // Parses a key expression: an optional origin in square brackets,
+    // followed by a hex public key, or by an xpub and the path below it.
+    fn parse(string: &str, context: Context) -> Option<DescriptorKey> {
+        let (origin, key) = if string.starts_with("[") {
+            let close = match string.find(']') {
+                Some(close) => close,
+                None => return None
+            };
+            match KeyOrigin::parse(string.slice(1, close)) {
+                Some(origin) => (Some(origin), string.slice_from(close + 1)),
+                None => return None
+            }
+        } else {
+            (None, string)
+        };
+
+        let key = if key.starts_with("xpub") {
+            // Keys derived from an xpub are compressed, so they're allowed
+            // anywhere.
+            match parse_extended_key(key) {
+                Some(key) => key,
+                None => return None
+            }
+        } else {
+            let data = match key.from_hex() {
+                Ok(data) => data,
+                Err(_) => return None
+            };
+
+            let is_valid = match data.len() {
+                33 => Point::from_bytes(data.as_slice()).is_some(),
+                65 => {
+                    (context == Context::Top || context == Context::Sh) &&
+                    Point::from_bytes(data.as_slice()).is_some()
+                }
+                32 => context == Context::Taproot && Point::lift_x(data.as_slice()).is_some(),
+                _ => false
+            };
+            if !is_valid { return None; }
+
+            Key::Plain(data)
+        };
+
+        Some(DescriptorKey { origin: origin, key: key })
+    }
+
+    /// Checks whether the key is an xpub with `/*` at the end of its path.
+    pub fn is_ranged(&self) -> bool {
+        match self.key {
+            Key::Extended(_, _, ranged) => ranged,
+            Key::Plain(_) => false
+        }
+    }
+
+    /// Derives the public key for the given index (which is ignored unless
+    /// the key is ranged), serialized the way scripts use it. Returns None if
+    /// the key can't be derived at the index.
+    pub fn derive(&self, index: u32) -> Option<Vec<u8>> {
+        match self.key {
+            Key::Plain(ref data) => Some(data.clone()),
+            Key::Extended(ref extended_key, ref path, ranged) => {
+                let path = if ranged { path.child(index) } else { path.clone() };
+                extended_key.derive_path(&path).map(|key| key.public_key.to_compressed())
+            }
+        }
+    }
+
+    /// Writes the key expression the way it's parsed.
+    pub fn encode(&self) -> String {
+        let mut result = match self.origin {
+            Some(ref origin) => key_origin(origin.fingerprint.as_slice(), &origin.path),
+            None => String::new()
+        };
+
+        match self.key {
+            Key::Plain(ref data) => result.push_str(data.to_hex().as_slice()),
+            Key::Extended(ref extended_key, ref path, ranged) => {
+                result.push_str(extended_key.encode().as_slice());
+                result.push_str(path.encode().as_slice().slice_from(1));
+                if ranged { result.push_str("/*"); }
+            }
+        }
+        result
+    }
+}
+
+impl KeyOrigin {
+    /// Parses the inside of a key origin's square brackets: a 4-byte
+    /// fingerprint in hex, followed by the path. Returns None if either is
+    /// invalid.
+    pub fn parse(string: &str) -> Option<KeyOrigin> {
+        let (fingerprint, path) = match string.find('/') {
+            Some(position) => (string.slice_to(position), string.slice_from(position)),
+            None => (string, "")
+        };
+
+        let fingerprint = match fingerprint.from_hex() {
+            Ok(ref data) if fingerprint.len() == 8 && data.len() == 4 => data.clone(),
+            _ => return None
+        };
+
+        DerivationPath::parse(format!("m{}", path).as_slice()).map(|path| {
+            KeyOrigin { fingerprint: fingerprint, path: path }
+        })
+    }
+}
+
+/// Computes the checksum of a descriptor (without the `#` and any existing
+/// checksum). Returns None if the descriptor contains a character that
+/// descriptors can't contain.
+pub fn checksum(descriptor: &str) -> Option<String> {
+    let mut symbols = Vec::with_capacity(descriptor.len() * 4 / 3 + CHECKSUM_LENGTH);
+    let mut groups = Vec::with_capacity(3);
+
+    // Each character is split into its low 5 bits, which are fed in directly,
+    // and the rest, which are combined with the next two characters' before
+    // being fed in.
+    for ch in descriptor.chars() {
+        let position = match INPUT_CHARSET.find(ch) {
+            Some(position) => position as u64,
+            None => return None
+        };
+
+        symbols.push(position & 31);
+        groups.push(position >> 5);
+        if groups.len() == 3 {
+            symbols.push(groups[0] * 9 + groups[1] * 3 + groups[2]);
+            groups.clear();
+        }
+    }
+    match groups.len() {
+        1 => symbols.push(groups[0]),
+        2 => symbols.push(groups[0] * 3 + groups[1]),
+        _ => {}
+    }
+
+    for _ in range(0, CHECKSUM_LENGTH) {
+        symbols.push(0);
+    }
+
+    let checksum = polymod(symbols.as_slice()) ^ 1;
+
+    let mut result = String::with_capacity(CHECKSUM_LENGTH);
+    for i in range(0, CHECKSUM_LENGTH) {
+        let value = (checksum >> (5 * (CHECKSUM_LENGTH - 1 - i))) & 31;
+        result.push(CHECKSUM_CHARSET[value as uint] as char);
+    }
+    Some(result)
+}
+
+/// Appends a `#` and the checksum to a descriptor. Returns None if the
+/// descriptor contains a character that descriptors can't contain.
+pub fn add_checksum(descriptor: &str) -> Option<String> {
+    checksum(descriptor).map(|checksum| format!("{}#{}", descriptor, checksum))
+}
+
+/// Writes the origin of a key derived from a master key with the given
+/// fingerprint, like `[d34db33f/84'/0'/0']`. Wallets use this to tell which
+/// keys they can sign for.
+pub fn key_origin(fingerprint: &[u8], path: &DerivationPath) -> String {
+    // The path is written without its leading "m".
+    format!("[{}{}]", fingerprint.to_hex(), path.encode().as_slice().slice_from(1))
+}
+
+/// Describes an address that Cykas derived from a public key, with the key's
+/// origin (which may be empty) written before it. The result doesn't have a
+/// checksum. Fails for P2WSH, since Cykas doesn't derive those addresses from
+/// a single key.
+pub fn from_public_key(address: &Address, public_key: &PublicKey, origin: &str) -> String {
+    match address.get_type() {
+        // P2PKH addresses can be made from either form of the key.
+        AddressType::P2PKH if public_key.to_compressed_address() == *address => {
+            format!("pkh({}{})", origin, public_key.to_compressed().to_hex())
+        }
+        AddressType::P2PKH => format!("pkh({}{})", origin, public_key.get_data().to_hex()),
+        AddressType::P2SH => format!("sh(wpkh({}{}))", origin, public_key.to_compressed().to_hex()),
+        AddressType::P2WPKH => format!("wpkh({}{})", origin, public_key.to_compressed().to_hex()),
+        AddressType::P2WSH => panic!("P2WSH addresses aren't derived from a single key"),
+        AddressType::P2TR => format!("tr({}{})", origin, public_key.to_compressed().slice_from(1).to_hex())
+    }
+}
+
+/// Describes every address of the given type on one chain of an account, as
+/// a ranged descriptor with the account's extended public key. The origin
+/// should be that of the account key. The result doesn't have a checksum.
+///
+/// Keys derived from an extended key are always compressed, as the P2PKH
+/// addresses of HD wallets are, but not those of older Cykas wallets. Fails
+/// for P2WSH, like `from_public_key()`.
+pub fn from_extended_public_key(address_type: AddressType, account_key: &ExtendedPublicKey,
+                                origin: &str, chain: u32) -> String {
+    let key = format!("{}{}/{}/*", origin, account_key.encode(), chain);
+    match address_type {
+        AddressType::P2PKH => format!("pkh({})", key),
+        AddressType::P2SH => format!("sh(wpkh({}))", key),
+        AddressType::P2WPKH => format!("wpkh({})", key),
+        AddressType::P2WSH => panic!("P2WSH addresses aren't derived from a single key"),
+        AddressType::P2TR => format!("tr({})", key)
+    }
+}
+
+/// Describes a single address, without saying anything about its keys. The
+/// result doesn't have a checksum.
+pub fn from_address(address: &Address) -> String {
+    format!("addr({})", address.encode())
+}
+
+// Parses a descriptor function, like `wpkh(...)`, checking that it's allowed
+// in the given context.
+fn parse_function(string: &str, context: Context) -> Option<Descriptor> {
+    let open = match string.find('(') {
+        Some(open) => open,
+        None => return None
+    };
+    if !string.ends_with(")") { return None; }
+
+    let name = string.slice_to(open);
+    let args = string.slice(open + 1, string.len() - 1);
+
+    match name {
+        "pk" => DescriptorKey::parse(args, context).map(|key| Descriptor::Pk(key)),
+        "pkh" => DescriptorKey::parse(args, context).map(|key| Descriptor::Pkh(key)),
+        "wpkh" if context != Context::Segwit => {
+            DescriptorKey::parse(args, Context::Segwit).map(|key| Descriptor::Wpkh(key))
+        }
+        "sh" if context == Context::Top => {
+            parse_function(args, Context::Sh).map(|inner| Descriptor::Sh(box inner))
+        }
+        "wsh" if context != Context::Segwit => {
+            parse_function(args, Context::Segwit).map(|inner| Descriptor::Wsh(box inner))
+        }
+        "tr" if context == Context::Top => {
+            DescriptorKey::parse(args, Context::Taproot).map(|key| Descriptor::Tr(key))
+        }
+        "multi" => parse_multi(args, context).map(|(threshold, keys)| Descriptor::Multi(threshold, keys)),
+        "sortedmulti" => {
+            parse_multi(args, context).map(|(threshold, keys)| Descriptor::SortedMulti(threshold, keys))
+        }
+        "addr" if context == Context::Top => Address::decode(args).map(|address| Descriptor::Addr(address)),
+        _ => None
+    }
+}
+
+// Parses the arguments of `multi()` or `sortedmulti()`: the threshold, then
+// the keys.
+fn parse_multi(args: &str, context: Context) -> Option<(uint, Vec<DescriptorKey>)> {
+    let mut args = args.split(',');
+
+    let threshold = match args.next().and_then(|threshold| from_str::<uint>(threshold)) {
+        Some(threshold) => threshold,
+        None => return None
+    };
+
+    let mut keys = Vec::new();
+    for arg in args {
+        match DescriptorKey::parse(arg, context) {
+            Some(key) => keys.push(key),
+            None => return None
+        }
+    }
+
+    if threshold < 1 || threshold > keys.len() || keys.len() > script::MAX_MULTISIG_KEYS {
+        return None;
+    }
+    Some((threshold, keys))
+}
+
+// Parses an xpub followed by the path of the key below it, which can end in
+// `/*`. The path can't have hardened indexes, since those can't be derived
+// from a public key.
+fn parse_extended_key(string: &str) -> Option<Key> {
+    let (encoded, path) = match string.find('/') {
+        Some(position) => (string.slice_to(position), string.slice_from(position)),
+        None => (string, "")
+    };
+
+    let extended_key = match ExtendedPublicKey::decode(encoded) {
+        Some(extended_key) => extended_key,
+        None => return None
+    };
+
+    let (path, ranged) = if path.ends_with("/*") {
+        (path.slice_to(path.len() - 2), true)
+    } else {
+        (path, false)
+    };
+
+    let path = match DerivationPath::parse(format!("m{}", path).as_slice()) {
+        Some(path) => path,
+        None => return None
+    };
+    if path.get_indexes().iter().any(|&index| index >= HARDENED) { return None; }
+
+    Some(Key::Extended(extended_key, path, ranged))
+}
+
+// Derives each of the keys at the given index.
+fn derive_keys(keys: &[DescriptorKey], index: u32) -> Option<Vec<Vec<u8>>> {
+    let mut result = Vec::with_capacity(keys.len());
+    for key in keys.iter() {
+        match key.derive(index) {
+            Some(public_key) => result.push(public_key),
+            None => return None
+        }
+    }
+    Some(result)
+}
+
+// Writes a list of keys separated by commas.
+fn encode_keys(keys: &[DescriptorKey]) -> String {
+    let encoded: Vec<String> = keys.iter().map(|key| key.encode()).collect();
+    encoded.connect(",")
+}
+
+// Computes the checksum's BCH code over 5-bit symbols. The result is 40 bits.
+fn polymod(symbols: &[u64]) -> u64 {
+    let mut checksum = 1u64;
+    for &value in symbols.iter() {
+        let top = checksum >> 35;
+        checksum = ((checksum & 0x7ffffffff) << 5) ^ value;
+        for i in range(0, GENERATOR.len()) {
+            if (top >> i) & 1 == 1 {
+                checksum ^= GENERATOR[i];
+            }
+        }
+    }
+    checksum
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::FromHex;
+
+    use protocol::address::Address;
+    use protocol::derivation_path::DerivationPath;
+    use protocol::private_key::PrivateKey;
+
+    use super::{checksum, add_checksum, key_origin, from_public_key, from_address};
+    use super::Descriptor;
+
+    // Parses a descriptor, checking that it's written back the same way.
+    fn parse(string: &str) -> Descriptor {
+        let descriptor = Descriptor::parse(string).unwrap();
+        assert_eq!(descriptor.encode().as_slice(), string);
+        descriptor
+    }
+
+    #[test]
+    fn test_checksum() {
+        let descriptor = "pkh(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)";
+        assert_eq!(checksum(descriptor), Some("8fhd9pwu".to_string()));
+        assert_eq!(add_checksum("raw(deadbeef)"), Some("raw(deadbeef)#89f8spxm".to_string()));
+    }
+
+    #[test]
+    fn test_checksum_invalid_character() {
+        assert!(checksum("addr(1é)").is_none());
+        assert!(checksum("raw(dead\nbeef)").is_none());
+    }
+
+    #[test]
+    fn test_from_public_key() {
+        let data = "0000000000000000000000000000000000000000000000000000000000000001".from_hex().unwrap();
+        let public_key = PrivateKey::new(data.as_slice()).unwrap().to_public_key();
+
+        let fingerprint = "d34db33f".from_hex().unwrap();
+        let path = DerivationPath::parse("m/84'/0'/0'/0/5").unwrap();
+        let origin = key_origin(fingerprint.as_slice(), &path);
+        assert_eq!(origin.as_slice(), "[d34db33f/84'/0'/0'/0/5]");
+
+        let descriptor = from_public_key(&public_key.to_p2wpkh_address(), &public_key, origin.as_slice());
+        assert_eq!(add_checksum(descriptor.as_slice()).unwrap().as_slice(),
+                   "wpkh([d34db33f/84'/0'/0'/0/5]0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)#8jrpn66u");
+
+        let descriptor = from_public_key(&public_key.to_p2tr_address(), &public_key, "");
+        assert_eq!(add_checksum(descriptor.as_slice()).unwrap().as_slice(),
+                   "tr(79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)#gxjkeue2");
+
+        let descriptor = from_public_key(&public_key.to_compressed_address(), &public_key, "");
+        assert_eq!(descriptor.as_slice(),
+                   "pkh(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)");
+    }
+
+    #[test]
+    fn test_from_address() {
+        let address = Address::decode("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        assert_eq!(add_checksum(from_address(&address).as_slice()).unwrap().as_slice(),
+                   "addr(bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4)#uyjndxcw");
+    }
+
+    #[test]
+    fn test_parse_pkh() {
+        let descriptor = parse("pkh(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)#8fhd9pwu");
+        assert!(!descriptor.is_ranged());
+        assert_eq!(descriptor.address(0).unwrap().encode().as_slice(), "1cMh228HTCiwS8ZsaakH8A8wze1JR5ZsP");
+
+        // The checksum is optional.
+        let without_checksum = "pkh(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)";
+        assert_eq!(Descriptor::parse(without_checksum), Some(descriptor));
+    }
+
+    #[test]
+    fn test_parse_wpkh() {
+        // The BIP 84 test vectors' account, from the mnemonic "abandon
+        // abandon ... about".
+        let descriptor = parse("wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3Xyu\
+                                vPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/0/*)#wc3n3van");
+        assert!(descriptor.is_ranged());
+        assert_eq!(descriptor.address(0).unwrap().encode().as_slice(),
+                   "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+        assert_eq!(descriptor.script_pubkey(0).unwrap(),
+                   "0014C0CEBCD6C3D3CA8C75DC5EC62EBE55330EF910E2".from_hex().unwrap());
+
+        let descriptor = parse("wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3Xyu\
+                                vPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/1/*)#lv5jvedt");
+        assert_eq!(descriptor.address(1).unwrap().encode().as_slice(),
+                   "bc1qggnasd834t54yulsep6fta8lpjekv4zj6gv5rf");
+    }
+
+    #[test]
+    fn test_parse_sh_wpkh() {
+        // The first receiving address from the BIP 49 test vectors.
+        let descriptor = parse("sh(wpkh(xpub6C6nQwHaWbSrzs5tZ1q7m5R9cPK9eYpNMFesiXsYrgc1P8bvLLAet9JfHjYXKjToD8cB\
+                                RswJXXbbFpXgwsswVPAZzKMa1jUp2kVkGVUaJa7/0/0))#mg8vkpjl");
+        assert!(!descriptor.is_ranged());
+        assert_eq!(descriptor.address(0).unwrap().encode().as_slice(), "37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf");
+    }
+
+    #[test]
+    fn test_parse_tr() {
+        // The first receiving address from the BIP 86 test vectors.
+        let descriptor = parse("tr([73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYW\
+                                cLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/*)#rg247h69");
+        assert_eq!(descriptor.address(0).unwrap().encode().as_slice(),
+                   "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr");
+
+        // An x-only key gives the same address as the compressed key.
+        let descriptor = Descriptor::parse("tr(79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)").unwrap();
+        let compressed = Descriptor::parse("tr(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)").unwrap();
+        assert_eq!(descriptor.address(0), compressed.address(0));
+    }
+
+    #[test]
+    fn test_parse_multi() {
+        let descriptor = parse("sh(sortedmulti(2,02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9,\
+                                0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798,\
+                                02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5))#ks43hvg8");
+        assert_eq!(descriptor.address(0).unwrap().encode().as_slice(), "33hG2q39jRi2NqicRJB4ggY1J8EJm97Szz");
+
+        let descriptor = parse("wsh(multi(2,02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9,\
+                                0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798,\
+                                02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5))#h2qhwaws");
+        assert_eq!(descriptor.address(0).unwrap().encode().as_slice(),
+                   "bc1qxqvgnukh04w787z9vtrgfy43p4ldmpd9th5cpe7v2cqncntpa56qgpu0t7");
+
+        let descriptor = parse("sh(wsh(multi(1,0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798,\
+                                02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)))#e0dt5wt4");
+        assert_eq!(descriptor.address(0).unwrap().encode().as_slice(), "3JSuZ22mPR2aD5bf77KsGi36V5ar3p87LX");
+
+        // A bare multisig script has no address.
+        let descriptor = parse("multi(1,0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)#gs8559k9");
+        assert!(descriptor.script_pubkey(0).is_some());
+        assert!(descriptor.address(0).is_none());
+    }
+
+    #[test]
+    fn test_parse_addr() {
+        let descriptor = parse("addr(bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4)#uyjndxcw");
+        assert_eq!(descriptor.address(0).unwrap().encode().as_slice(),
+                   "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let key = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let uncompressed_key = "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798\
+                                483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+        let x_only_key = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let xpub = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+
+        let invalid = [
+            // Wrong checksum.
+            "pkh(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)#8fhd9pwv".to_string(),
+            // Uncompressed keys aren't allowed in SegWit scripts.
+            format!("wpkh({})", uncompressed_key),
+            format!("wsh(pk({}))", uncompressed_key),
+            // X-only keys are only allowed in taproot.
+            format!("pkh({})", x_only_key),
+            // Functions in the wrong places.
+            format!("wsh(wsh(pk({})))", key),
+            format!("sh(tr({}))", key),
+            format!("sh(sh(pk({})))", key),
+            format!("sh(addr(bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4))"),
+            // Thresholds that can't be met.
+            format!("multi(2,{})", key),
+            format!("multi(0,{})", key),
+            // Hardened derivation below an xpub.
+            format!("wpkh({}/0'/*)", xpub),
+            // A bad key origin.
+            format!("wpkh([73c5da0/84'/0'/0']{}/0/*)", xpub),
+            format!("wpkh([73c5da0a/84'/0'/0'{}/0/*)", xpub),
+            // Not even a function.
+            format!("wpkh({}", key),
+            format!("{}", key),
+            format!("foo({})", key)
+        ];
+        for string in invalid.iter() {
+            assert!(Descriptor::parse(string.as_slice()).is_none(), "{} should be invalid", string);
+        }
+    }
+}
@@ -0,0 +1,479 @@
+//! BIP 32 hierarchical deterministic keys. An extended key is a private or
+//! public key together with a 32-byte chain code, from which any number of
+//! child keys can be derived.
+
+use util::base58;
+use util::hash;
+use util::wif;
+use util::secp256k1;
+use util::secp256k1::{Point, Scalar};
+use util::secret;
+use protocol::private_key::PrivateKey;
+use protocol::public_key::PublicKey;
+use protocol::derivation_path::{DerivationPath, HARDENED};
+
+// The version bytes at the start of serialized mainnet extended keys. (In
+// base-58, these make them start with "xprv" and "xpub".)
+static XPRV_VERSION: &'static [u8] = &[0x04, 0x88, 0xAD, 0xE4];
+static XPUB_VERSION: &'static [u8] = &[0x04, 0x88, 0xB2, 0x1E];
+
+// The length of a serialized extended key, not counting the checksum.
+static SERIALIZED_LENGTH: uint = 78;
+
+// The HMAC key used to turn a seed into a master key.
+static MASTER_HMAC_KEY: &'static [u8] = b"Bitcoin seed";
+
+// Seeds must be between 128 and 512 bits long.
+static MIN_SEED_LENGTH: uint = 16;
+static MAX_SEED_LENGTH: uint = 64;
+
+/// The length of a chain code.
+pub static CHAIN_CODE_LENGTH: uint = 32;
+
+/// A private key that child keys can be derived from.
+#[deriving(Clone, PartialEq, Show)]
+pub struct ExtendedPrivateKey {
+    /// How many derivations away from the master key this key is.
+    pub depth: u8,
+    /// The first four bytes of the parent key's hash160, or all zeroes for
+    /// the master key.
+    pub parent_fingerprint: Vec<u8>,
+    /// The index this key was derived with, or zero for the master key.
+    pub child_number: u32,
+    pub chain_code: Vec<u8>,
+    pub private_key: PrivateKey
+}
+
+/// A public key that non-hardened child public keys can be derived from,
+/// without knowing any private keys.
+#[deriving(Clone, PartialEq, Show)]
+pub struct ExtendedPublicKey {
+    pub depth: u8,
+    pub parent_fingerprint: Vec<u8>,
+    pub child_number: u32,
+    pub chain_code: Vec<u8>,
+    pub public_key: PublicKey
+}
+
+impl ExtendedPrivateKey {
+    /// Creates the master key from a seed, which must be between 16 and 64
+    /// bytes long. Returns None if the seed is the wrong length, or if it
+    /// happens to produce an invalid key.
+    pub fn from_seed(seed: &[u8]) -> Option<ExtendedPrivateKey> {
+        if seed.len() < MIN_SEED_LENGTH || seed.len() > MAX_SEED_LENGTH { return None; }
+
+        let mut digest = hash::hmac_sha512(MASTER_HMAC_KEY, seed);
+        let master_key = {
+            let (key, chain_code) = digest.as_slice().split_at(32);
+            PrivateKey::new(key).map(|private_key| {
+                ExtendedPrivateKey {
+                    depth: 0,
+                    parent_fingerprint: vec![0, 0, 0, 0],
+                    child_number: 0,
+                    chain_code: chain_code.to_vec(),
+                    private_key: private_key
+                }
+            })
+        };
+
+        // The digest holds a copy of the private key.
+        secret::wipe(digest.as_mut_slice());
+        master_key
+    }
+
+    /// Creates the master key directly from its chain code and private key,
+    /// as they're stored in the wallet file.
+    pub fn from_master_parts(chain_code: &[u8], private_key: PrivateKey) -> ExtendedPrivateKey {
+        assert_eq!(chain_code.len(), CHAIN_CODE_LENGTH);
+
+        ExtendedPrivateKey {
+            depth: 0,
+            parent_fingerprint: vec![0, 0, 0, 0],
+            child_number: 0,
+            chain_code: chain_code.to_vec(),
+            private_key: private_key
+        }
+    }
+
+    /// Derives the child key with the given index. Indexes from `HARDENED`
+    /// upwards give hardened keys, whose public keys can't be derived from
+    /// the parent public key. Returns None if the index happens to produce
+    /// an invalid key, in which case BIP 32 says to skip to the next one.
+    pub fn derive_child(&self, index: u32) -> Option<ExtendedPrivateKey> {
+        let mut data = Vec::with_capacity(37);
+        if index >= HARDENED {
+            data.push(0x00);
+            data.push_all(self.private_key.get_data());
+        } else {
+            data.push_all(self.private_key.to_public_key().to_compressed().as_slice());
+        }
+        push_u32_be(&mut data, index);
+
+        let mut digest = hash::hmac_sha512(self.chain_code.as_slice(), data.as_slice());
+        // For hardened keys, the HMAC data holds a copy of the parent key.
+        secret::wipe(data.as_mut_slice());
+
+        let child_key = {
+            let (tweak, chain_code) = digest.as_slice().split_at(32);
+
+            match Scalar::from_bytes(tweak) {
+                Some(tweak) => {
+                    let key = tweak.add(&Scalar::from_bytes(self.private_key.get_data()).unwrap());
+                    let mut key_data = key.to_bytes();
+                    let child_key = PrivateKey::new(key_data.as_slice()).map(|private_key| {
+                        ExtendedPrivateKey {
+                            depth: self.depth + 1,
+                            parent_fingerprint: self.fingerprint(),
+                            child_number: index,
+                            chain_code: chain_code.to_vec(),
+                            private_key: private_key
+                        }
+                    });
+                    secret::wipe(key_data.as_mut_slice());
+                    child_key
+                },
+                None => None
+            }
+        };
+
+        secret::wipe(digest.as_mut_slice());
+        child_key
+    }
+
+    /// Derives the descendant key at the given path, relative to this key.
+    pub fn derive_path(&self, path: &DerivationPath) -> Option<ExtendedPrivateKey> {
+        let mut key = self.clone();
+        for &index in path.get_indexes().iter() {
+            key = match key.derive_child(index) {
+                Some(child) => child,
+                None => return None
+            };
+        }
+        Some(key)
+    }
+
+    /// Gets the extended public key that corresponds to this key.
+    pub fn to_extended_public_key(&self) -> ExtendedPublicKey {
+        ExtendedPublicKey {
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint.clone(),
+            child_number: self.child_number,
+            chain_code: self.chain_code.clone(),
+            public_key: self.private_key.to_public_key()
+        }
+    }
+
+    /// Gets the key's fingerprint, which identifies it as the parent of its
+    /// children.
+    pub fn fingerprint(&self) -> Vec<u8> {
+        fingerprint(&self.private_key.to_public_key())
+    }
+
+    /// Serializes the key in base-58, as an "xprv..." string.
+    pub fn encode(&self) -> String {
+        let mut key_data = vec![0x00];
+        key_data.push_all(self.private_key.get_data());
+        let encoded = encode(XPRV_VERSION, self.depth, self.parent_fingerprint.as_slice(),
+                             self.child_number, self.chain_code.as_slice(), key_data.as_slice());
+        secret::wipe(key_data.as_mut_slice());
+        encoded
+    }
+
+    /// Parses an "xprv..." string. Returns None if it's invalid.
+    pub fn decode(string: &str) -> Option<ExtendedPrivateKey> {
+        let (depth, parent_fingerprint, child_number, chain_code, mut key_data) =
+            match decode(string, XPRV_VERSION) {
+                Some(fields) => fields,
+                None => return None
+            };
+
+        let private_key = if key_data[0] == 0x00 {
+            PrivateKey::new(key_data.slice_from(1))
+        } else {
+            None
+        };
+        secret::wipe(key_data.as_mut_slice());
+
+        private_key.map(|private_key| {
+            ExtendedPrivateKey {
+                depth: depth,
+                parent_fingerprint: parent_fingerprint,
+                child_number: child_number,
+                chain_code: chain_code,
+                private_key: private_key
+            }
+        })
+    }
+}
+
+impl ExtendedPublicKey {
+    /// Derives the child public key with the given index, which must not be
+    /// hardened. Returns None if the index is hardened or happens to produce
+    /// an invalid key.
+    pub fn derive_child(&self, index: u32) -> Option<ExtendedPublicKey> {
+        if index >= HARDENED { return None; }
+
+        let compressed = self.public_key.to_compressed();
+        let mut data = Vec::with_capacity(37);
+        data.push_all(compressed.as_slice());
+        push_u32_be(&mut data, index);
+
+        let digest = hash::hmac_sha512(self.chain_code.as_slice(), data.as_slice());
+        let (tweak, chain_code) = digest.as_slice().split_at(32);
+
+        let tweak = secp256k1::from_bytes(tweak);
+        if tweak >= secp256k1::curve_order() { return None; }
+
+        let parent_point = Point::from_bytes(compressed.as_slice()).unwrap();
+        let point = Point::generator().mul(&tweak).add(&parent_point);
+        if point.is_infinity() { return None; }
+
+        PublicKey::new(point.to_uncompressed().as_slice()).map(|public_key| {
+            ExtendedPublicKey {
+                depth: self.depth + 1,
+                parent_fingerprint: self.fingerprint(),
+                child_number: index,
+                chain_code: chain_code.to_vec(),
+                public_key: public_key
+            }
+        })
+    }
+
+    /// Derives the descendant public key at the given path, relative to this
+    /// key. Returns None if the path contains a hardened index.
+    pub fn derive_path(&self, path: &DerivationPath) -> Option<ExtendedPublicKey> {
+        let mut key = self.clone();
+        for &index in path.get_indexes().iter() {
+            key = match key.derive_child(index) {
+                Some(child) => child,
+                None => return None
+            };
+        }
+        Some(key)
+    }
+
+    /// Gets the key's fingerprint, which identifies it as the parent of its
+    /// children.
+    pub fn fingerprint(&self) -> Vec<u8> {
+        fingerprint(&self.public_key)
+    }
+
+    /// Serializes the key in base-58, as an "xpub..." string.
+    pub fn encode(&self) -> String {
+        encode(XPUB_VERSION, self.depth, self.parent_fingerprint.as_slice(),
+               self.child_number, self.chain_code.as_slice(),
+               self.public_key.to_compressed().as_slice())
+    }
+
+    /// Parses an "xpub..." string. Returns None if it's invalid, including if
+    /// the public key isn't on the curve.
+    pub fn decode(string: &str) -> Option<ExtendedPublicKey> {
+        let (depth, parent_fingerprint, child_number, chain_code, key_data) =
+            match decode(string, XPUB_VERSION) {
+                Some(fields) => fields,
+                None => return None
+            };
+
+        let point = match Point::from_bytes(key_data.as_slice()) {
+            Some(point) => point,
+            None => return None
+        };
+
+        PublicKey::new(point.to_uncompressed().as_slice()).map(|public_key| {
+            ExtendedPublicKey {
+                depth: depth,
+                parent_fingerprint: parent_fingerprint,
+                child_number: child_number,
+                chain_code: chain_code,
+                public_key: public_key
+            }
+        })
+    }
+}
+
+// Computes the fingerprint of a public key: the first four bytes of the
+// hash160 of its compressed form.
+fn fingerprint(public_key: &PublicKey) -> Vec<u8> {
+    let key_hash = hash::hash160(public_key.to_compressed().as_slice());
+    key_hash.slice_to(4).to_vec()
+}
+
+// Appends a 32-bit unsigned integer in big-endian order, which is how BIP 32
+// encodes child indexes.
+fn push_u32_be(output: &mut Vec<u8>, n: u32) {
+    for i in range(0, 4u).rev() {
+        output.push((n >> (8 * i)) as u8);
+    }
+}
+
+// Serializes the fields of an extended key in the BIP 32 format and encodes it
+// in base-58 with a checksum. `key_data` is 33 bytes: a compressed public key,
+// or a zero byte followed by a private key.
+fn encode(version: &[u8], depth: u8, parent_fingerprint: &[u8], child_number: u32,
+          chain_code: &[u8], key_data: &[u8]) -> String {
+    let mut data = Vec::with_capacity(SERIALIZED_LENGTH);
+    data.push_all(version.slice_from(1));
+    data.push(depth);
+    data.push_all(parent_fingerprint);
+    push_u32_be(&mut data, child_number);
+    data.push_all(chain_code);
+    data.push_all(key_data);
+
+    // The WIF encoding is just a version byte and a checksum, so it works
+    // for the longer version here if we give it the first byte separately.
+    let mut checked = wif::encode(data.as_slice(), version[0]);
+    let encoded = base58::encode(checked.as_slice());
+
+    // For private keys, these are copies of the key.
+    secret::wipe(data.as_mut_slice());
+    secret::wipe(checked.as_mut_slice());
+    encoded
+}
+
+// Decodes a base-58 extended key with the given version bytes, returning its
+// depth, parent fingerprint, child number, chain code and 33 bytes of key
+// data. Returns None if it's invalid.
+fn decode(string: &str, version: &[u8]) -> Option<(u8, Vec<u8>, u32, Vec<u8>, Vec<u8>)> {
+    let data = match base58::decode(string) {
+        Some(data) => data,
+        None => return None
+    };
+    let mut data = match wif::decode(data.as_slice(), version[0]) {
+        Some(data) => data,
+        None => return None
+    };
+
+    if data.len() != SERIALIZED_LENGTH - 1 || data.slice_to(3) != version.slice_from(1) {
+        return None;
+    }
+
+    let depth = data[3];
+    let parent_fingerprint = data.slice(4, 8).to_vec();
+    let child_number = data.slice(8, 12).iter().fold(0u32, |n, byte| (n << 8) | (*byte as u32));
+    let chain_code = data.slice(12, 44).to_vec();
+    let key_data = data.slice(44, 77).to_vec();
+    secret::wipe(data.as_mut_slice());
+
+    // The master key can't have a parent or a child number.
+    if depth == 0 && (parent_fingerprint != vec![0, 0, 0, 0] || child_number != 0) {
+        return None;
+    }
+
+    Some((depth, parent_fingerprint, child_number, chain_code, key_data))
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::{ToHex, FromHex};
+
+    use protocol::derivation_path::DerivationPath;
+
+    use super::{ExtendedPrivateKey, ExtendedPublicKey};
+
+    // Checks a chain of keys from the BIP 32 test vectors. Each entry is a
+    // path followed by the expected xpub and xprv.
+    fn check_vector(seed: &str, chain: &[(&str, &str, &str)]) {
+        let seed = seed.from_hex().unwrap();
+        let master = ExtendedPrivateKey::from_seed(seed.as_slice()).unwrap();
+
+        for &(path, xpub, xprv) in chain.iter() {
+            let path = DerivationPath::parse(path).unwrap();
+            let key = master.derive_path(&path).unwrap();
+            assert_eq!(key.encode().as_slice(), xprv);
+            assert_eq!(key.to_extended_public_key().encode().as_slice(), xpub);
+        }
+    }
+
+    #[test]
+    fn test_vector_1() {
+        check_vector("000102030405060708090a0b0c0d0e0f", &[
+            ("m",
+             "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8",
+             "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi"),
+            ("m/0'",
+             "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw",
+             "xprv9uHRZZhk6KAJC1avXpDAp4MDc3sQKNxDiPvvkX8Br5ngLNv1TxvUxt4cV1rGL5hj6KCesnDYUhd7oWgT11eZG7XnxHrnYeSvkzY7d2bhkJ7"),
+            ("m/0'/1",
+             "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ",
+             "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs"),
+            ("m/0'/1/2'/2/1000000000",
+             "xpub6H1LXWLaKsWFhvm6RVpEL9P4KfRZSW7abD2ttkWP3SSQvnyA8FSVqNTEcYFgJS2UaFcxupHiYkro49S8yGasTvXEYBVPamhGW6cFJodrTHy",
+             "xprvA41z7zogVVwxVSgdKUHDy1SKmdb533PjDz7J6N6mV6uS3ze1ai8FHa8kmHScGpWmj4WggLyQjgPie1rFSruoUihUZREPSL39UNdE3BBDu76")
+        ]);
+    }
+
+    #[test]
+    fn test_vector_2() {
+        check_vector("fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542", &[
+            ("m/0",
+             "xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH",
+             "xprv9vHkqa6EV4sPZHYqZznhT2NPtPCjKuDKGY38FBWLvgaDx45zo9WQRUT3dKYnjwih2yJD9mkrocEZXo1ex8G81dwSM1fwqWpWkeS3v86pgKt"),
+            ("m/0/2147483647'/1/2147483646'/2",
+             "xpub6FnCn6nSzZAw5Tw7cgR9bi15UV96gLZhjDstkXXxvCLsUXBGXPdSnLFbdpq8p9HmGsApME5hQTZ3emM2rnY5agb9rXpVGyy3bdW6EEgAtqt",
+             "xprvA2nrNbFZABcdryreWet9Ea4LvTJcGsqrMzxHx98MMrotbir7yrKCEXw7nadnHM8Dq38EGfSh6dqA9QWTyefMLEcBYJUuekgW4BYPJcr9E7j")
+        ]);
+    }
+
+    #[test]
+    fn test_vector_3() {
+        // Retention of leading zeroes in derived private keys.
+        check_vector("4b381541583be4423346c643850da4b320e46a87ae3d2a4e6da11eba819cd4acba45d239319ac14f863b8d5ab5a0d0c64d2e8a1e7d1457df2e5a3c51c73235be", &[
+            ("m/0'",
+             "xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y",
+             "xprv9uPDJpEQgRQfDcW7BkF7eTya6RPxXeJCqCJGHuCJ4GiRVLzkTXBAJMu2qaMWPrS7AANYqdq6vcBcBUdJCVVFceUvJFjaPdGZ2y9WACViL4L")
+        ]);
+    }
+
+    #[test]
+    fn test_derive_public_child() {
+        let seed = "000102030405060708090a0b0c0d0e0f".from_hex().unwrap();
+        let master = ExtendedPrivateKey::from_seed(seed.as_slice()).unwrap();
+        let parent = master.derive_child(0x80000000).unwrap();
+
+        // Deriving a normal child from the xpub gives the same public key as
+        // deriving it from the xprv.
+        let from_private = parent.derive_child(1).unwrap().to_extended_public_key();
+        let from_public = parent.to_extended_public_key().derive_child(1).unwrap();
+        assert_eq!(from_public, from_private);
+
+        // Hardened children can't be derived from public keys.
+        assert!(parent.to_extended_public_key().derive_child(0x80000000).is_none());
+    }
+
+    #[test]
+    fn test_decode_encode() {
+        let xprv = "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs";
+        let key = ExtendedPrivateKey::decode(xprv).unwrap();
+        assert_eq!(key.depth, 2);
+        assert_eq!(key.child_number, 1);
+        assert_eq!(key.fingerprint().to_hex().as_slice(), "bef5a2f9");
+        assert_eq!(key.encode().as_slice(), xprv);
+
+        let xpub = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+        let key = ExtendedPublicKey::decode(xpub).unwrap();
+        assert_eq!(key.parent_fingerprint.to_hex().as_slice(), "5c1bd648");
+        assert_eq!(key.encode().as_slice(), xpub);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // From the BIP 32 invalid extended key test vectors.
+        let invalid = [
+            // Public key version with private key data.
+            "xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6LBpB85b3D2yc8sfvZU521AAwdZafEz7mnzBBsz4wKY5fTtTQBm",
+            // Private key version with public key data.
+            "xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFGTQQD3dC4H2D5GBj7vWvSQaaBv5cxi9gafk7NF3pnBju6dwKvH",
+            // Private key of zero.
+            "xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzF93Y5wvzdUayhgkkFoicQZcP3y52uPPxFnfoLZB21Teqt1VvEHx",
+            // Non-zero parent fingerprint with zero depth.
+            "xpub661no6RGEX3uJkY4bNnPcw4URcQTrSibUZ4NqJEw5eBkv7ovTwgiT91XX27VbEXGENhYRCf7hyEbWrR3FewATdCEebj6znwMfQkhRYHRLpJ",
+            // Invalid public key.
+            "xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6Q5JXayek4PRsn35jii4veMimro1xefsM58PgBMrvdYre8QyULY"
+        ];
+
+        for string in invalid.iter() {
+            assert!(ExtendedPrivateKey::decode(*string).is_none());
+            assert!(ExtendedPublicKey::decode(*string).is_none());
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! BIP 39 mnemonic sentences, which encode a wallet's seed as a list of
+//! common English words that are easy to write down and read back.
+
+use std::fmt;
+use std::io::IoResult;
+
+use util::bytes::{read_bits, write_bits};
+use util::hash;
+use util::secret;
+use util::secret::SecretBytes;
+use util::entropy::{EntropyPool, SourceRecord};
+
+// The BIP 39 English word list, one word per line. It's the same file as the
+// one published with BIP 39, so it can be checked against that.
+static WORDLIST: &'static str = include_str!("mnemonic_english.txt");
+
+// The number of words in the word list.
+static WORDLIST_LENGTH: uint = 2048;
+
+/// The number of bits that each word encodes.
+pub static BITS_PER_WORD: uint = 11;
+
+// The number of PBKDF2 iterations used to turn a mnemonic into a seed.
+static SEED_ITERATIONS: uint = 2048;
+
+// The length of the seed derived from a mnemonic.
+static SEED_LENGTH: uint = 64;
+
+/// The number of words in a newly generated mnemonic. 24 words encode 256 bits
+/// of entropy.
+pub static DEFAULT_WORD_COUNT: uint = 24;
+
+/// A valid BIP 39 mnemonic: a list of 12, 15, 18, 21 or 24 words from the
+/// word list, the last of which includes a checksum of the entropy. The words
+/// are wiped from memory when it's dropped, and never shown when it's
+/// formatted.
+#[deriving(Clone, PartialEq)]
+pub struct Mnemonic(Vec<String>);
+
+impl Mnemonic {
+    /// Generates a new mnemonic with the given number of words from random
+    /// entropy, using openssl's random bytes generator. Fails if the word
+    /// count isn't one of the ones BIP 39 allows. Returns an error if the
+    /// random bytes keep failing the entropy health tests.
+    pub fn generate(word_count: uint) -> IoResult<Mnemonic> {
+        Mnemonic::generate_from(&mut EntropyPool::system(), word_count).map(|(mnemonic, _)| mnemonic)
+    }
+
+    /// Generates a new mnemonic with the given number of words from the
+    /// entropy sources in the given pool, and returns it with the record of
+    /// which sources went into it. Fails if the word count isn't one of the
+    /// ones BIP 39 allows. Returns an error if the pool can't gather enough
+    /// entropy that passes the health tests.
+    pub fn generate_from(pool: &mut EntropyPool, word_count: uint) -> IoResult<(Mnemonic, Vec<SourceRecord>)> {
+        assert!(is_valid_word_count(word_count));
+
+        let entropy = try!(pool.gather());
+        let mnemonic = Mnemonic::from_entropy(entropy.get_data().slice_to(word_count * 4 / 3)).unwrap();
+        Ok((mnemonic, entropy.get_sources().to_vec()))
+    }
+
+    /// Encodes the given entropy as a mnemonic. The entropy must be 16, 20,
+    /// 24, 28 or 32 bytes long; otherwise None is returned.
+    pub fn from_entropy(entropy: &[u8]) -> Option<Mnemonic> {
+        if entropy.len() < 16 || entropy.len() > 32 || entropy.len() % 4 != 0 {
+            return None;
+        }
+
+        // One bit of checksum is added for every 32 bits of entropy, making
+        // the total a multiple of 11 bits.
+        let checksum = hash::sha256(entropy);
+        let mut data = entropy.to_vec();
+        data.push(checksum[0]);
+        let bit_count = entropy.len() * 8 + entropy.len() / 4;
+
+        let words = wordlist();
+        let mnemonic = {
+            let indexes = range(0, bit_count / BITS_PER_WORD).map(|i| {
+                read_bits(data.as_slice(), i * BITS_PER_WORD, BITS_PER_WORD)
+            });
+            Mnemonic(indexes.map(|index| words[index].to_string()).collect())
+        };
+
+        secret::wipe(data.as_mut_slice());
+        Some(mnemonic)
+    }
+
+    /// Parses a mnemonic from a string of words separated by whitespace.
+    /// Returns None if there's the wrong number of words, any of them aren't
+    /// in the word list, or the checksum doesn't match.
+    pub fn parse(phrase: &str) -> Option<Mnemonic> {
+        let words: Vec<String> = phrase.words().map(|word| {
+            word.chars().map(|ch| ch.to_lowercase()).collect()
+        }).collect();
+
+        if !is_valid_word_count(words.len()) { return None; }
+
+        let mnemonic = Mnemonic(words);
+        match mnemonic.to_entropy() {
+            Some(entropy) => {
+                // Re-encoding the entropy gives the same words only if the
+                // checksum bits are right.
+                let entropy = SecretBytes::new(entropy);
+                if Mnemonic::from_entropy(entropy.as_slice()) == Some(mnemonic.clone()) {
+                    Some(mnemonic)
+                } else {
+                    None
+                }
+            }
+            None => None
+        }
+    }
+
+    /// Gets the words of the mnemonic.
+    pub fn get_words(&self) -> &[String] {
+        let Mnemonic(ref words) = *self;
+        words.as_slice()
+    }
+
+    /// Returns the words joined by spaces, in the form that's hashed into the
+    /// seed.
+    pub fn to_phrase(&self) -> String {
+        let words: Vec<&str> = self.get_words().iter().map(|word| word.as_slice()).collect();
+        words.connect(" ")
+    }
+
+    /// Decodes the entropy the mnemonic encodes, without checking the
+    /// checksum. Returns None if a word isn't in the word list.
+    fn to_entropy(&self) -> Option<Vec<u8>> {
+        let words = wordlist();
+        let bit_count = self.get_words().len() * BITS_PER_WORD;
+        let entropy_length = (bit_count - bit_count / 33) / 8;
+
+        let mut data = Vec::from_elem(entropy_length + 1, 0u8);
+        for (i, word) in self.get_words().iter().enumerate() {
+            let index = match words.iter().position(|w| *w == word.as_slice()) {
+                Some(index) => index,
+                None => return None
+            };
+            write_bits(data.as_mut_slice(), i * BITS_PER_WORD, BITS_PER_WORD, index);
+        }
+
+        data.truncate(entropy_length);
+        Some(data)
+    }
+
+    /// Derives the 64-byte BIP 32 seed from the mnemonic and an optional
+    /// passphrase (which may be empty). Any passphrase gives a valid seed, so
+    /// a mistyped passphrase gives a different wallet rather than an error.
+    pub fn to_seed(&self, passphrase: &str) -> SecretBytes {
+        // BIP 39 normalizes both strings to Unicode NFKD. The words are plain
+        // ASCII, so only the passphrase can be affected.
+        let mut passphrase: String = passphrase.nfkd_chars().collect();
+        let mut salt = format!("mnemonic{}", passphrase);
+        let mut phrase = self.to_phrase();
+
+        let seed = hash::pbkdf2_hmac_sha512(phrase.as_bytes(), salt.as_bytes(),
+                                            SEED_ITERATIONS, SEED_LENGTH);
+
+        unsafe {
+            secret::wipe(passphrase.as_mut_vec().as_mut_slice());
+            secret::wipe(salt.as_mut_vec().as_mut_slice());
+            secret::wipe(phrase.as_mut_vec().as_mut_slice());
+        }
+        SecretBytes::new(seed)
+    }
+}
+
+impl fmt::Show for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Mnemonic([{} words hidden])", self.get_words().len())
+    }
+}
+
+impl Drop for Mnemonic {
+    fn drop(&mut self) {
+        let Mnemonic(ref mut words) = *self;
+        for word in words.iter_mut() {
+            unsafe { secret::wipe(word.as_mut_vec().as_mut_slice()); }
+        }
+    }
+}
+
+// Checks whether BIP 39 allows a mnemonic with the given number of words.
+fn is_valid_word_count(word_count: uint) -> bool {
+    word_count >= 12 && word_count <= 24 && word_count % 3 == 0
+}
+
+/// Splits the word list into words, which are already in sorted order. Other
+/// formats that are written down as words, like wallet shares, use it too.
+pub fn wordlist() -> Vec<&'static str> {
+    let words: Vec<&'static str> = WORDLIST.lines().collect();
+    assert_eq!(words.len(), WORDLIST_LENGTH);
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::{ToHex, FromHex};
+
+    use protocol::extended_key::ExtendedPrivateKey;
+
+    use super::{Mnemonic, wordlist};
+
+    // Checks a vector from the BIP 39 reference test vectors, which all use
+    // the passphrase "TREZOR".
+    fn check_vector(entropy: &str, phrase: &str, seed: &str) {
+        let entropy = entropy.from_hex().unwrap();
+        let mnemonic = Mnemonic::from_entropy(entropy.as_slice()).unwrap();
+        assert_eq!(mnemonic.to_phrase().as_slice(), phrase);
+        assert_eq!(Mnemonic::parse(phrase), Some(mnemonic.clone()));
+        assert_eq!(mnemonic.to_seed("TREZOR").as_slice().to_hex().as_slice(), seed);
+    }
+
+    #[test]
+    fn test_wordlist() {
+        let words = wordlist();
+        assert_eq!(words[0], "abandon");
+        assert_eq!(words[2047], "zoo");
+        for i in range(1, words.len()) {
+            assert!(words[i - 1] < words[i]);
+        }
+    }
+
+    #[test]
+    fn test_vectors_12_words() {
+        check_vector("00000000000000000000000000000000",
+                     "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+                     "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e5349553\
+                      1f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04");
+        check_vector("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                     "legal winner thank year wave sausage worth useful legal winner thank yellow",
+                     "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6f\
+                      a457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607");
+        check_vector("80808080808080808080808080808080",
+                     "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+                     "d71de856f81a8acc65e6fc851a38d4d7ec216fd0796d0a6827a3ad6ed5511a30\
+                      fa280f12eb2e47ed2ac03b5c462a0358d18d69fe4f985ec81778c1b370b652a8");
+        check_vector("ffffffffffffffffffffffffffffffff",
+                     "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong",
+                     "ac27495480225222079d7be181583751e86f571027b0497b5b5d11218e0a8a13\
+                      332572917f0f8e5a589620c6f15b11c61dee327651a14c34e18231052e48c069");
+        check_vector("9e885d952ad362caeb4efe34a8e91bd2",
+                     "ozone drill grab fiber curtain grace pudding thank cruise elder eight picnic",
+                     "274ddc525802f7c828d8ef7ddbcdc5304e87ac3535913611fbbfa986d0c9e547\
+                      6c91689f9c8a54fd55bd38606aa6a8595ad213d4c9c9f9aca3fb217069a41028");
+    }
+
+    #[test]
+    fn test_vectors_24_words() {
+        check_vector("0000000000000000000000000000000000000000000000000000000000000000",
+                     "abandon abandon abandon abandon abandon abandon abandon abandon \
+                      abandon abandon abandon abandon abandon abandon abandon abandon \
+                      abandon abandon abandon abandon abandon abandon abandon art",
+                     "bda85446c68413707090a52022edd26a1c9462295029f2e60cd7c4f2bbd30971\
+                      70af7a4d73245cafa9c3cca8d561a7c3de6f5d4a10be8ed2a5e608d68f92fcc8");
+        check_vector("ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+                     "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo \
+                      zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+                     "dd48c104698c30cfe2b6142103248622fb7bb0ff692eebb00089b32d22484e16\
+                      13912f0a5b694407be899ffd31ed3992c456cdf60f5d4564b8ba3f05a69890ad");
+    }
+
+    #[test]
+    fn test_master_key() {
+        let mnemonic = Mnemonic::parse("abandon abandon abandon abandon abandon abandon \
+                                        abandon abandon abandon abandon abandon about").unwrap();
+        let master_key = ExtendedPrivateKey::from_seed(mnemonic.to_seed("TREZOR").as_slice()).unwrap();
+        assert_eq!(master_key.encode().as_slice(),
+                   "xprv9s21ZrQH143K3h3fDYiay8mocZ3afhfULfb5GX8kCBdno77K4HiA15Tg23wpbeF1pLfs1c5SPmYHrEpTuuRhxMwvKDwqdKiGJS9XFKzUsAF");
+    }
+
+    #[test]
+    fn test_parse() {
+        // Extra whitespace and uppercase letters are fine.
+        let mnemonic = Mnemonic::parse("  Legal winner thank year wave sausage\n\
+                                        worth useful legal winner thank YELLOW ");
+        assert!(mnemonic.is_some());
+        assert_eq!(mnemonic.unwrap().get_words().len(), 12);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        // Bad checksum.
+        assert!(Mnemonic::parse("abandon abandon abandon abandon abandon abandon \
+                                 abandon abandon abandon abandon abandon abandon").is_none());
+        // Word not in the list.
+        assert!(Mnemonic::parse("abandon abandon abandon abandon abandon abandon \
+                                 abandon abandon abandon abandon abandon aboot").is_none());
+        // Wrong number of words.
+        assert!(Mnemonic::parse("zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong").is_none());
+        assert!(Mnemonic::parse("").is_none());
+    }
+
+    #[test]
+    fn test_generate() {
+        let mnemonic = Mnemonic::generate(24).unwrap();
+        assert_eq!(mnemonic.get_words().len(), 24);
+        assert_eq!(Mnemonic::parse(mnemonic.to_phrase().as_slice()), Some(mnemonic.clone()));
+    }
+
+    #[test]
+    fn test_from_entropy_invalid_length() {
+        let entropy = Vec::from_elem(15, 0u8);
+        assert!(Mnemonic::from_entropy(entropy.as_slice()).is_none());
+        let entropy = Vec::from_elem(33, 0u8);
+        assert!(Mnemonic::from_entropy(entropy.as_slice()).is_none());
+    }
+}
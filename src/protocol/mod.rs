@@ -3,4 +3,16 @@
 pub mod private_key;
 pub mod public_key;
 pub mod address;
-
+pub mod derivation_path;
+pub mod extended_key;
+pub mod mnemonic;
+pub mod share;
+pub mod bip38;
+pub mod script;
+pub mod transaction;
+pub mod taproot;
+pub mod segwit;
+pub mod descriptor;
+pub mod multisig;
+pub mod psbt;
+pub mod proof;
@@ -0,0 +1,291 @@
+//! m-of-n multisig wallets made of extended public keys, one per cosigner,
+//! laid out as in BIP 48. Addresses are derived as `sortedmulti()` (BIP 67)
+//! witness scripts, either native (P2WSH) or wrapped in P2SH.
+
+use protocol::address::Address;
+use protocol::derivation_path::{DerivationPath, HARDENED, BITCOIN_COIN_TYPE};
+use protocol::descriptor::{Descriptor, DescriptorKey, Key, KeyOrigin};
+use protocol::extended_key::ExtendedPublicKey;
+use protocol::script::MAX_MULTISIG_KEYS;
+use protocol::script;
+use util::hash;
+
+// The purpose of multisig account paths, from BIP 48.
+static MULTISIG_PURPOSE: u32 = 48;
+
+/// The kind of script a multisig wallet's addresses pay to.
+#[deriving(Clone, Copy, PartialEq, Show)]
+pub enum MultisigType {
+    /// A native SegWit witness script hash.
+    P2WSH,
+    /// A witness script hash wrapped in P2SH, for wallets that can't send
+    /// to bech32 addresses.
+    P2SHP2WSH
+}
+
+/// A cosigner's account key, with the fingerprint of their master key and the
+/// path it was derived at, so that each cosigner can tell which key is theirs.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Cosigner {
+    pub origin: KeyOrigin,
+    pub account_key: ExtendedPublicKey
+}
+
+/// An m-of-n multisig wallet. Each address is derived from all of the
+/// cosigners' account keys at the same `chain/index`.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Multisig {
+    threshold: uint,
+    multisig_type: MultisigType,
+    cosigners: Vec<Cosigner>
+}
+
+impl MultisigType {
+    /// Parses the name of a multisig type as given on the command line:
+    /// `p2wsh` or `p2sh-p2wsh`.
+    pub fn from_name(name: &str) -> Option<MultisigType> {
+        match name {
+            "p2wsh" => Some(MultisigType::P2WSH),
+            "p2sh-p2wsh" => Some(MultisigType::P2SHP2WSH),
+            _ => None
+        }
+    }
+
+    /// Gets the name of the multisig type, the inverse of `from_name()`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            MultisigType::P2WSH => "p2wsh",
+            MultisigType::P2SHP2WSH => "p2sh-p2wsh"
+        }
+    }
+
+    /// Creates the path of a multisig account of this type, as in BIP 48:
+    ///
+    ///     m/48'/coin_type'/account'/script_type'
+    ///
+    /// Where the script type is 1 for P2SH-P2WSH and 2 for P2WSH.
+    pub fn account_path(&self, account: u32) -> DerivationPath {
+        let script_type = match *self {
+            MultisigType::P2SHP2WSH => 1,
+            MultisigType::P2WSH => 2
+        };
+        DerivationPath::new(&[MULTISIG_PURPOSE + HARDENED, BITCOIN_COIN_TYPE + HARDENED,
+                              account + HARDENED, script_type + HARDENED])
+    }
+}
+
+impl Cosigner {
+    /// Parses a cosigner's key written the way it is in a descriptor, with
+    /// its origin: `[fingerprint/path]xpub...`. Returns None if it's invalid.
+    pub fn parse(string: &str) -> Option<Cosigner> {
+        if !string.starts_with("[") { return None; }
+
+        let close = match string.find(']') {
+            Some(close) => close,
+            None => return None
+        };
+
+        let origin = match KeyOrigin::parse(string.slice(1, close)) {
+            Some(origin) => origin,
+            None => return None
+        };
+
+        ExtendedPublicKey::decode(string.slice_from(close + 1)).map(|account_key| {
+            Cosigner { origin: origin, account_key: account_key }
+        })
+    }
+
+    /// Writes the key in the form `parse()` reads.
+    pub fn encode(&self) -> String {
+        self.descriptor_key(None).encode()
+    }
+
+    // Makes a descriptor key for the cosigner's keys on the given chain, at
+    // the given index or ranged over all of them.
+    fn descriptor_key(&self, chain_index: Option<(u32, Option<u32>)>) -> DescriptorKey {
+        let key = match chain_index {
+            Some((chain, Some(index))) => {
+                Key::Extended(self.account_key.clone(), DerivationPath::new(&[chain, index]), false)
+            }
+            Some((chain, None)) => {
+                Key::Extended(self.account_key.clone(), DerivationPath::new(&[chain]), true)
+            }
+            None => Key::Extended(self.account_key.clone(), DerivationPath::new(&[]), false)
+        };
+        DescriptorKey { origin: Some(self.origin.clone()), key: key }
+    }
+}
+
+impl Multisig {
+    /// Creates a multisig wallet that needs `threshold` of the cosigners'
+    /// keys to spend. Returns None unless 1 <= threshold <= n <= 15, or if
+    /// the same key is given twice.
+    pub fn new(threshold: uint, multisig_type: MultisigType, cosigners: Vec<Cosigner>) -> Option<Multisig> {
+        if threshold == 0 || threshold > cosigners.len() || cosigners.len() > MAX_MULTISIG_KEYS {
+            return None;
+        }
+
+        for (i, cosigner) in cosigners.iter().enumerate() {
+            if cosigners.slice_to(i).iter().any(|other| other.account_key == cosigner.account_key) {
+                return None;
+            }
+        }
+
+        Some(Multisig { threshold: threshold, multisig_type: multisig_type, cosigners: cosigners })
+    }
+
+    /// Gets the number of signatures needed to spend.
+    pub fn get_threshold(&self) -> uint {
+        self.threshold
+    }
+
+    pub fn get_type(&self) -> MultisigType {
+        self.multisig_type
+    }
+
+    /// Gets the cosigners, in the order they were given. (The order of the
+    /// keys in each witness script is sorted instead.)
+    pub fn get_cosigners(&self) -> &[Cosigner] {
+        self.cosigners.as_slice()
+    }
+
+    /// Describes every address on the given chain as a ranged descriptor.
+    pub fn to_descriptor(&self, chain: u32) -> Descriptor {
+        self.wrap(self.sorted_multi(Some((chain, None))))
+    }
+
+    /// Describes the single address at `chain/index`.
+    pub fn to_descriptor_at(&self, chain: u32, index: u32) -> Descriptor {
+        self.wrap(self.sorted_multi(Some((chain, Some(index)))))
+    }
+
+    /// Derives the witness script at `chain/index`. Returns None if one of
+    /// the cosigners' keys can't be derived there.
+    pub fn witness_script(&self, chain: u32, index: u32) -> Option<Vec<u8>> {
+        self.sorted_multi(Some((chain, Some(index)))).script_pubkey(index)
+    }
+
+    /// Derives the redeem script at `chain/index`, which is the P2WSH
+    /// scriptPubKey of the witness script. Returns None for native P2WSH
+    /// wallets, which don't have one.
+    pub fn redeem_script(&self, chain: u32, index: u32) -> Option<Vec<u8>> {
+        match self.multisig_type {
+            MultisigType::P2WSH => None,
+            MultisigType::P2SHP2WSH => {
+                self.witness_script(chain, index).map(|witness_script| {
+                    script::p2wsh_script_pubkey(hash::sha256(witness_script.as_slice()).as_slice())
+                })
+            }
+        }
+    }
+
+    /// Derives the address at `chain/index`. Returns None if one of the
+    /// cosigners' keys can't be derived there.
+    pub fn address(&self, chain: u32, index: u32) -> Option<Address> {
+        self.to_descriptor_at(chain, index).address(index)
+    }
+
+    fn sorted_multi(&self, chain_index: Option<(u32, Option<u32>)>) -> Descriptor {
+        let keys = self.cosigners.iter().map(|cosigner| cosigner.descriptor_key(chain_index)).collect();
+        Descriptor::SortedMulti(self.threshold, keys)
+    }
+
+    fn wrap(&self, multi: Descriptor) -> Descriptor {
+        match self.multisig_type {
+            MultisigType::P2WSH => Descriptor::Wsh(box multi),
+            MultisigType::P2SHP2WSH => Descriptor::Sh(box Descriptor::Wsh(box multi))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::ToHex;
+
+    use super::{Cosigner, Multisig, MultisigType};
+
+    // The BIP 48 P2WSH account keys (m/48'/0'/0'/2') of the BIP 39 test
+    // mnemonics "abandon ... about", "zoo ... wrong", and "legal winner
+    // ... yellow".
+    static P2WSH_COSIGNERS: [&'static str, ..3] = [
+        "[73c5da0a/48'/0'/0'/2']xpub6DkFAXWQ2dHxq2vatrt9qyA3bXYU4ToWQwCHbf5XB2mSTexcHZC\
+         eKS1VZYcPoBd5X8yVcbXFHJR9R8UCVpt82VX1VhR28mCyxUFL4r6KFrf",
+        "[3f635a63/48'/0'/0'/2']xpub6FHZCoNb3tg3o1GAJQxSwgFNF8mLRtTk2GgkF7n5rwzoxBhUEdF\
+         Wa8cyZRHqytAzKZWsKz8627cQEMCCfR5GDSv6yXegqirpgDUX41Pxybr",
+        "[b8688df1/48'/0'/0'/2']xpub6FQya7zGhR92kacYsNnjreouvnHJMpXYsUXnW6NJJAJRCKsa26T\
+         zDy4LdnGhEurr3d6y1J8PJ7EEMKQp74XTqYvmGJNogYXSKDszYHtF8mX"
+    ];
+
+    // The same mnemonics' P2SH-P2WSH account keys (m/48'/0'/0'/1').
+    static P2SH_P2WSH_COSIGNERS: [&'static str, ..3] = [
+        "[73c5da0a/48'/0'/0'/1']xpub6DkFAXWQ2dHxnMKoSBogHrw1rgNJKR4umdbnNVNTYeCGcduxWnN\
+         UHgGptqEQWPKRmeW4Zn4FHSbLMBKEWYaMDYu47Ytg6DdFnPNt8hwn5mE",
+        "[3f635a63/48'/0'/0'/1']xpub6FHZCoNb3tg3mxjcXsQx1xLpNmod6woECf2fB4nQbe9NXbvha2u\
+         cpDpnGbTFF68KUMUr1hNQ9E5jVEvpT2kUkVmFVDrJawcbgXzDpJc2hkF",
+        "[b8688df1/48'/0'/0'/1']xpub6FQya7zGhR92giSkXpPgPHpq85nUnqabbbNuJiae1zndR3B6Nq2\
+         QCoSWBkdLF7bkifSYSNvyTfhg4KBvKyJ94HXuEaeWZsabMnTyJiPz21N"
+    ];
+
+    fn multisig(multisig_type: MultisigType, keys: &[&str]) -> Multisig {
+        let cosigners = keys.iter().map(|key| Cosigner::parse(*key).unwrap()).collect();
+        Multisig::new(2, multisig_type, cosigners).unwrap()
+    }
+
+    #[test]
+    fn test_cosigner_parse() {
+        let cosigner = Cosigner::parse(P2WSH_COSIGNERS[0]).unwrap();
+        assert_eq!(cosigner.origin.fingerprint.to_hex().as_slice(), "73c5da0a");
+        assert_eq!(cosigner.origin.path.encode().as_slice(), "m/48'/0'/0'/2'");
+        assert_eq!(cosigner.encode().as_slice(), P2WSH_COSIGNERS[0]);
+
+        // The origin is required.
+        assert!(Cosigner::parse(P2WSH_COSIGNERS[0].slice_from(23)).is_none());
+        assert!(Cosigner::parse("[73c5da0a/48'/0'/0'/2']xpub").is_none());
+    }
+
+    #[test]
+    fn test_p2wsh() {
+        let multisig = multisig(MultisigType::P2WSH, P2WSH_COSIGNERS.as_slice());
+        assert_eq!(multisig.address(0, 0).unwrap().encode().as_slice(),
+                   "bc1qea2gkgeszr7wm66x2ejkgdxm9nhg9462sszn75zmkhazev0t02vs70kkll");
+        assert_eq!(multisig.address(0, 1).unwrap().encode().as_slice(),
+                   "bc1qa0sj5tjwunnxyhmkgd85548gwqv2r0tc6vjs9c3yy2j7gjea72hs9dvptd");
+        assert_eq!(multisig.address(1, 0).unwrap().encode().as_slice(),
+                   "bc1qc6p3lpt2e2wv0vukgyykx3ca8fhy6pvlfg7sgv8e3qej473jjmxs29860y");
+        assert_eq!(multisig.witness_script(0, 0).unwrap().to_hex().as_slice(),
+                   "52210254689c4a585b8f3d9ddea8e9e68d0e8f49f75709fa6e7d075657573815958a81\
+                    21028e818df63d6f2dce9308d7455fd4c85cbb39d703608b8fbed647d75e262322d8\
+                    2103dc1953c2756c7c58d4f48ca1bbba767f414fd236bf4d662b67721ac626c514e053ae");
+        assert!(multisig.redeem_script(0, 0).is_none());
+
+        let descriptor = multisig.to_descriptor(0).encode();
+        assert!(descriptor.as_slice().starts_with("wsh(sortedmulti(2,[73c5da0a/48'/0'/0'/2']"));
+        assert!(descriptor.as_slice().ends_with("/0/*))#9awrh0v0"));
+    }
+
+    #[test]
+    fn test_p2sh_p2wsh() {
+        let multisig = multisig(MultisigType::P2SHP2WSH, P2SH_P2WSH_COSIGNERS.as_slice());
+        assert_eq!(multisig.address(0, 0).unwrap().encode().as_slice(), "36ZrDZ9nkw5G83zYBmzQneVjErgkfN6RDD");
+        assert_eq!(multisig.address(0, 1).unwrap().encode().as_slice(), "3HpstT3q6fBTEfuQvndkU2Z9eUyyYhz5BU");
+        assert_eq!(multisig.address(1, 0).unwrap().encode().as_slice(), "3EZGPrkM4bZDYuNSTmBZXDEaEConRxFFgn");
+        assert!(multisig.to_descriptor(0).encode().as_slice().ends_with("#xc25p0n9"));
+    }
+
+    #[test]
+    fn test_account_path() {
+        assert_eq!(MultisigType::P2WSH.account_path(0).encode().as_slice(), "m/48'/0'/0'/2'");
+        assert_eq!(MultisigType::P2SHP2WSH.account_path(3).encode().as_slice(), "m/48'/0'/3'/1'");
+    }
+
+    #[test]
+    fn test_new_invalid() {
+        let cosigners: Vec<Cosigner> = P2WSH_COSIGNERS.iter().map(|key| Cosigner::parse(*key).unwrap()).collect();
+        assert!(Multisig::new(0, MultisigType::P2WSH, cosigners.clone()).is_none());
+        assert!(Multisig::new(4, MultisigType::P2WSH, cosigners.clone()).is_none());
+
+        let mut duplicated = cosigners.clone();
+        duplicated.push(cosigners[0].clone());
+        assert!(Multisig::new(2, MultisigType::P2WSH, duplicated).is_none());
+    }
+}
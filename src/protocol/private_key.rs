@@ -1,18 +1,28 @@
 //! Bitcoin private key representation.
 
-use openssl;
+use std::io::{IoResult, IoError, OtherIoError};
 
 use util::wif;
+use util::secret::{SecretBytes, wipe};
+use util::entropy::{EntropyPool, SourceRecord};
 use protocol::public_key::PublicKey;
 use protocol::address::Address;
 
 /// Length of a raw Bitcoin private key.
 pub static LENGTH: uint = 32u;
 
+/// The fewest dice rolls `from_dice_rolls()` accepts. 100 rolls of a
+/// six-sided die hold about 258 bits of entropy, and 99 would only hold 255.
+pub static MIN_DICE_ROLLS: uint = 100;
+
 // This byte must be at the start of any Bitcoin private key that's in Wallet
 // Import Format (WIF).
 static VERSION_BYTE: u8 = 0x80;
 
+// The byte after the key in WIF that marks it as having a compressed public
+// key.
+static COMPRESSED_FLAG: u8 = 0x01;
+
 // Bitcoin keys must be less than or equal to this value, as dictated by the
 // secp256k1 curve it uses.
 static MAX: &'static [u8] = &[
@@ -32,34 +42,99 @@ static ZERO: &'static [u8] = &[
 
 /// Represents a raw Bitcoin private key, consisting of 32 bytes of data which
 /// must be greater than `ZERO` and no greater than `MAX`, as defined above.
+/// The data is wiped from memory when the key is dropped, and is never shown
+/// when the key is formatted.
 #[deriving(Clone, PartialEq, Show)]
-pub struct PrivateKey(Vec<u8>);
+pub struct PrivateKey(SecretBytes);
 
 impl PrivateKey {
     /// Creates a PrivateKey from raw data. Returns None if the data is not a
     /// valid Bitcoin private key.
     pub fn new(data: &[u8]) -> Option<PrivateKey> {
         if PrivateKey::is_valid(data) {
-            Some(PrivateKey(data.to_vec()))
+            Some(PrivateKey(SecretBytes::from_slice(data)))
         } else {
             None
         }
     }
 
     /// Generates a random Bitcoin private key securely, using openssl's random
-    /// bytes generator.
-    pub fn generate() -> PrivateKey {
+    /// bytes generator. Returns an error if its output keeps failing the
+    /// entropy health tests.
+    pub fn generate() -> IoResult<PrivateKey> {
         loop {
             // Just generate 32 random bytes. The result is almost certainly a
             // valid private key. Just in case it isn't, keep looping until we
             // get a valid one.
-            let key = openssl::crypto::rand::rand_bytes(LENGTH);
-            if PrivateKey::is_valid(key.as_slice()) {
-                return PrivateKey(key)
+            match PrivateKey::generate_from(&mut EntropyPool::system()) {
+                Ok((key, _)) => return Ok(key),
+                Err(ref e) if e.desc == "invalid private key" => continue,
+                Err(e) => return Err(e)
             }
         }
     }
 
+    /// Generates a private key from the entropy sources in the given pool,
+    /// and returns it with the record of which sources went into it. Fails if
+    /// the pool can't gather enough entropy that passes the health tests, or
+    /// (with a negligible chance) if the entropy isn't a valid private key.
+    pub fn generate_from(pool: &mut EntropyPool) -> IoResult<(PrivateKey, Vec<SourceRecord>)> {
+        let entropy = try!(pool.gather());
+
+        match PrivateKey::new(entropy.get_data()) {
+            Some(key) => Ok((key, entropy.get_sources().to_vec())),
+            None => Err(IoError {
+                kind: OtherIoError,
+                desc: "invalid private key",
+                detail: Some(format!("The entropy isn't a valid private key; try again with different entropy"))
+            })
+        }
+    }
+
+    /// Converts rolls of a six-sided die into a private key, without using
+    /// any random number generator. Each roll is given as a base-6 digit: the
+    /// number rolled minus one, so 1 becomes 0 and 6 becomes 5. Returns None
+    /// if there are fewer than `MIN_DICE_ROLLS` rolls, if a digit isn't from
+    /// 0 to 5, or if the result isn't a valid private key.
+    ///
+    /// The conversion is simple enough to check by hand, or with `bc`:
+    ///
+    /// 1. Subtract one from each roll and write the results down in order, as
+    ///    one base-6 number with the first roll as its most significant digit.
+    /// 2. Convert that number to hexadecimal.
+    /// 3. Keep only its last 64 hex digits (i.e. reduce it modulo 2^256),
+    ///    padding it with zeros on the left if it's shorter.
+    /// 4. The result is the private key, which must be greater than zero and
+    ///    no greater than `MAX`.
+    ///
+    /// With 100 rolls, reducing modulo 2^256 makes some keys slightly more
+    /// likely than others, but no key is more likely than 1 in 6^99 (about
+    /// 2^255.9).
+    pub fn from_dice_rolls(rolls: &[u8]) -> Option<PrivateKey> {
+        if rolls.len() < MIN_DICE_ROLLS { return None; }
+
+        // Multiply by 6 and add each digit in turn, in 32 big-endian bytes.
+        // Whatever carries out of the top byte is dropped, which is the
+        // reduction modulo 2^256.
+        let mut data = Vec::from_elem(LENGTH, 0u8);
+        for &digit in rolls.iter() {
+            if digit > 5 {
+                wipe(data.as_mut_slice());
+                return None;
+            }
+
+            let mut carry = digit as uint;
+            for byte in data.iter_mut().rev() {
+                let value = (*byte as uint) * 6 + carry;
+                *byte = value as u8;
+                carry = value >> 8;
+            }
+        }
+
+        let data = SecretBytes::new(data);
+        PrivateKey::new(data.as_slice())
+    }
+
     // Checks if the given private key data is valid.
     fn is_valid(data: &[u8]) -> bool {
         data.len() == LENGTH &&
@@ -78,7 +153,7 @@ impl PrivateKey {
         let key = wif::decode(data, VERSION_BYTE);
 
         if key.is_some() {
-            let key = key.unwrap();
+            let key = SecretBytes::new(key.unwrap());
             if PrivateKey::is_valid(key.as_slice()) {
                 return Some(PrivateKey(key));
             }
@@ -95,8 +170,19 @@ impl PrivateKey {
 
     /// Converts the private key to Wallet Import Format (WIF), as raw bytes.
     /// See from_wif() for details on the format.
-    pub fn to_wif(&self) -> Vec<u8> {
-        wif::encode(self.get_data().as_slice(), VERSION_BYTE)
+    pub fn to_wif(&self) -> SecretBytes {
+        SecretBytes::new(wif::encode(self.get_data().as_slice(), VERSION_BYTE))
+    }
+
+    /// Converts the private key to WIF with the compression flag, a 0x01 byte
+    /// after the key, as raw bytes. Other wallets take this to mean the key's
+    /// addresses use the compressed public key, as SegWit and taproot ones
+    /// do.
+    pub fn to_compressed_wif(&self) -> SecretBytes {
+        let mut data = SecretBytes::with_capacity(LENGTH + 1);
+        data.push_all(self.get_data());
+        data.push_all(&[COMPRESSED_FLAG]);
+        SecretBytes::new(wif::encode(data.as_slice(), VERSION_BYTE))
     }
 
     /// Derives the public key from the given private key.
@@ -108,6 +194,27 @@ impl PrivateKey {
     pub fn to_address(&self) -> Address {
         Address::from_private_key(self)
     }
+
+    /// Derives the P2PKH address of the compressed public key from the given
+    /// private key.
+    pub fn to_compressed_address(&self) -> Address {
+        self.to_public_key().to_compressed_address()
+    }
+
+    /// Derives the P2SH-wrapped P2WPKH address from the given private key.
+    pub fn to_p2sh_p2wpkh_address(&self) -> Address {
+        Address::p2sh_p2wpkh_from_private_key(self)
+    }
+
+    /// Derives the native P2WPKH address from the given private key.
+    pub fn to_p2wpkh_address(&self) -> Address {
+        Address::p2wpkh_from_private_key(self)
+    }
+
+    /// Derives the taproot address from the given private key.
+    pub fn to_p2tr_address(&self) -> Address {
+        Address::p2tr_from_private_key(self)
+    }
 }
 
 #[cfg(test)]
@@ -115,6 +222,7 @@ mod tests {
     use serialize::hex::FromHex;
 
     use util::base58;
+    use util::entropy::{EntropyPool, Dice};
 
     use super::{LENGTH, ZERO, MAX};
     use super::PrivateKey;
@@ -128,6 +236,48 @@ mod tests {
         assert_eq!(private_key.unwrap().get_data(), data.as_slice());
     }
 
+    #[test]
+    fn test_generate_from() {
+        let rolls = "32461151351521144121541512665155412152342515356215\
+                     56231561515246543454332262153543643511542324416155";
+        let mut pool = EntropyPool::new();
+        pool.add(box Dice::parse(rolls, 6).unwrap());
+
+        let (private_key, sources) = PrivateKey::generate_from(&mut pool).unwrap();
+        let expected = "fb579901ad6b3bb99c4c1e35da95d94a3a0f8e6c6078cb29035863c9ffaa44f3";
+        assert_eq!(private_key.get_data(), expected.from_hex().unwrap().as_slice());
+        assert_eq!(sources.len(), 1);
+
+        // Too few rolls.
+        let mut pool = EntropyPool::new();
+        pool.add(box Dice::parse("1234", 6).unwrap());
+        assert!(PrivateKey::generate_from(&mut pool).is_err());
+    }
+
+    #[test]
+    fn test_from_dice_rolls() {
+        let rolls = "32461151351521144121541512665155412152342515356215\
+                     56231561515246543454332262153543643511542324416155";
+        let digits: Vec<u8> = rolls.chars().map(|ch| ch.to_digit(10).unwrap() as u8 - 1).collect();
+        let private_key = PrivateKey::from_dice_rolls(digits.as_slice()).unwrap();
+        let expected = "233de8ccf4d5f60f4d4f89330963a80162d2e897de5ea3560b17b8d87b381bb4";
+        assert_eq!(private_key.get_data(), expected.from_hex().unwrap().as_slice());
+
+        // 6^100 - 1, which is bigger than 2^256.
+        let private_key = PrivateKey::from_dice_rolls(Vec::from_elem(100, 5u8).as_slice()).unwrap();
+        let expected = "a4653ca673768565b41f775d6947d55cf3813d0fffffffffffffffffffffffff";
+        assert_eq!(private_key.get_data(), expected.from_hex().unwrap().as_slice());
+
+        // All ones gives zero, which isn't a valid key.
+        assert!(PrivateKey::from_dice_rolls(Vec::from_elem(100, 0u8).as_slice()).is_none());
+        // Too few rolls.
+        assert!(PrivateKey::from_dice_rolls(digits.slice_to(99)).is_none());
+        // Not a base-6 digit.
+        let mut invalid = digits.clone();
+        *invalid.index_mut(&50) = 6;
+        assert!(PrivateKey::from_dice_rolls(invalid.as_slice()).is_none());
+    }
+
     #[test]
     fn test_new_max() {
         let private_key = PrivateKey::new(MAX);
@@ -165,10 +315,10 @@ mod tests {
 
     #[test]
     fn test_generate() {
-        let private_key = PrivateKey::generate();
+        let private_key = PrivateKey::generate().unwrap();
         assert!(private_key.get_data().len() == LENGTH);
 
-        let another_key = PrivateKey::generate();
+        let another_key = PrivateKey::generate().unwrap();
         assert!(private_key.get_data() != another_key.get_data());
     }
 
@@ -182,6 +332,23 @@ mod tests {
         assert_eq!(wif_base58.as_slice(), "5KPqe3y95higsGQaWN6TQPtv2BQ2X1SqL87AmVAuiz811uCQRYQ");
     }
 
+    #[test]
+    fn test_to_compressed_wif() {
+        let data = "0000000000000000000000000000000000000000000000000000000000000001";
+        let private_key = PrivateKey::new(data.from_hex().unwrap().as_slice()).unwrap();
+        let wif_base58 = base58::encode(private_key.to_compressed_wif().as_slice());
+        assert_eq!(wif_base58.as_slice(), "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn");
+    }
+
+    #[test]
+    fn test_show_hides_key() {
+        let data = "CFE1B4C8DDA7EBF5FCACC4086BD9530F1C2201AE5A7D1DEF090D911CF28E5C5F";
+        let private_key = PrivateKey::new(data.from_hex().unwrap().as_slice()).unwrap();
+        let shown = format!("{}", private_key);
+        assert!(!shown.as_slice().contains("cfe1"));
+        assert!(!shown.as_slice().contains("207"));
+    }
+
     #[test]
     fn test_to_public_key() {
         let data = "F91BCBB19F3A8A03204B70B08DB2950716C565E362912C5B368CC171FF578B9F";
@@ -203,5 +370,15 @@ mod tests {
         let expected = base58::decode("14ydpwhvtVBMjt5NrechP46UKLSY7jYn7q").unwrap();
         assert_eq!(address.get_data(), expected.as_slice());
     }
+
+    #[test]
+    fn test_to_p2sh_p2wpkh_address() {
+        let data = "CBBEC41B016517C3DA8E2F88BDACB293802CECF1AE2C47A7CB5D4BDA28353B5B";
+        let data = data.from_hex().unwrap();
+        let private_key = PrivateKey::new(data.as_slice()).unwrap();
+        let address = private_key.to_p2sh_p2wpkh_address();
+        let expected = base58::decode("32eF994S6syKv4FaLaikazrPYmW7C7YkWp").unwrap();
+        assert_eq!(address.get_data(), expected.as_slice());
+    }
 }
 
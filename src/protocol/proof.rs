@@ -0,0 +1,322 @@
+//! Proofs that a wallet file holds the private keys for its addresses, which
+//! can be checked without the passphrase. Every key signs a message made of
+//! a challenge (chosen by whoever asked for the proof, so that old proofs
+//! can't be reused), a hash of the wallet file, and the key's address.
+//!
+//! A proof file uses the same format as a wallet file:
+//!
+//!     !challenge: <the challenge, in hex>
+//!     !wallet_sha256: <SHA-256 hash of the wallet file>
+//!     <address>:
+//!       <public key>
+//!       <signature>
+//!       <witness script, for multisig addresses>
+//!
+//! Taproot addresses are signed with a BIP 340 Schnorr signature by the
+//! tweaked key, and the public key given is the internal key. All other
+//! addresses are signed with a DER-encoded ECDSA signature. The public key is
+//! uncompressed for single-key addresses (even P2PKH ones made from the
+//! compressed key) and compressed for multisig addresses, as it appears in the
+//! witness script.
+
+use serialize::hex::{ToHex, FromHex};
+use std::io::{IoResult, IoError, OtherIoError, MemReader};
+
+use util::bytes;
+use util::ecdsa;
+use util::hash;
+use util::schnorr;
+use protocol::address::{Address, AddressType};
+use protocol::private_key::PrivateKey;
+use protocol::public_key::PublicKey;
+use protocol::script;
+use protocol::taproot;
+use wallet_parser;
+
+// The tag of the hash that's signed.
+static MESSAGE_TAG: &'static str = "Cykas/proof";
+
+// The special keys in a proof file.
+static CHALLENGE_KEY: &'static str = "!challenge";
+static WALLET_HASH_KEY: &'static str = "!wallet_sha256";
+
+// The length of a compressed public key.
+static COMPRESSED_LENGTH: uint = 33;
+
+/// A signature by the private key of one address.
+#[deriving(Clone, PartialEq, Show)]
+pub struct KeyProof {
+    pub address: Address,
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+    /// The witness script of a multisig address, which the public key has
+    /// to be in.
+    pub witness_script: Option<Vec<u8>>
+}
+
+/// A proof for some or all of the addresses in a wallet file.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Proof {
+    pub challenge: String,
+    pub wallet_hash: Vec<u8>,
+    pub keys: Vec<KeyProof>
+}
+
+impl KeyProof {
+    /// Signs for an address with its private key. For a multisig address,
+    /// the private key is our key in the given witness script.
+    pub fn sign(challenge: &str, wallet_hash: &[u8], address: &Address, private_key: &PrivateKey,
+                witness_script: Option<Vec<u8>>) -> KeyProof {
+        let message = message(challenge, wallet_hash, address);
+
+        let (public_key, signature) = if witness_script.is_some() {
+            (private_key.to_public_key().to_compressed(),
+             ecdsa::sign(message.as_slice(), private_key.get_data()))
+        } else if address.get_type() == AddressType::P2TR {
+            // A key without a script tree always has a valid tweak.
+            let tweaked = taproot::tweak_private_key(private_key, None).unwrap();
+            (schnorr::x_only_public_key(private_key.get_data()),
+             schnorr::sign(message.as_slice(), tweaked.get_data(), &[0u8, ..32]))
+        } else {
+            (private_key.to_public_key().get_data().to_vec(),
+             ecdsa::sign(message.as_slice(), private_key.get_data()))
+        };
+
+        KeyProof {
+            address: address.clone(),
+            public_key: public_key,
+            signature: signature,
+            witness_script: witness_script
+        }
+    }
+
+    /// Checks that the public key belongs to the address and that the
+    /// signature is valid for the given challenge and wallet hash.
+    pub fn verify(&self, challenge: &str, wallet_hash: &[u8]) -> bool {
+        let message = message(challenge, wallet_hash, &self.address);
+
+        match self.witness_script {
+            Some(ref witness_script) => {
+                let script_hash = hash::sha256(witness_script.as_slice());
+                let address = match self.address.get_type() {
+                    AddressType::P2WSH => Address::p2wsh_from_witness_script(witness_script.as_slice()),
+                    AddressType::P2SH => {
+                        Address::from_redeem_script(script::p2wsh_script_pubkey(script_hash.as_slice()).as_slice())
+                    }
+                    _ => return false
+                };
+
+                address == self.address &&
+                self.public_key.len() == COMPRESSED_LENGTH &&
+                contains(witness_script.as_slice(), script::push_data(self.public_key.as_slice()).as_slice()) &&
+                ecdsa::verify(message.as_slice(), self.public_key.as_slice(), self.signature.as_slice())
+            }
+            None if self.address.get_type() == AddressType::P2TR => {
+                match taproot::tweak_public_key(self.public_key.as_slice(), None) {
+                    Some(output_key) => {
+                        Address::p2tr_from_output_key(output_key.as_slice()) == self.address &&
+                        schnorr::verify(message.as_slice(), output_key.as_slice(), self.signature.as_slice())
+                    }
+                    None => false
+                }
+            }
+            None => {
+                let public_key = match PublicKey::new(self.public_key.as_slice()) {
+                    Some(public_key) => public_key,
+                    None => return false
+                };
+                let address = match self.address.get_type() {
+                    AddressType::P2PKH if public_key.to_compressed_address() == self.address => {
+                        public_key.to_compressed_address()
+                    }
+                    AddressType::P2PKH => public_key.to_address(),
+                    AddressType::P2SH => public_key.to_p2sh_p2wpkh_address(),
+                    AddressType::P2WPKH => public_key.to_p2wpkh_address(),
+                    _ => return false
+                };
+
+                address == self.address &&
+                ecdsa::verify(message.as_slice(), self.public_key.as_slice(), self.signature.as_slice())
+            }
+        }
+    }
+}
+
+impl Proof {
+    /// Finds the proof for the given address.
+    pub fn find(&self, address: &Address) -> Option<&KeyProof> {
+        self.keys.iter().find(|key| key.address == *address)
+    }
+
+    /// Writes the proof out in the format described above.
+    pub fn encode(&self) -> String {
+        let mut result = String::new();
+        result.push_str("# Proof that a Cykas wallet file holds the private keys for its addresses.\n");
+        result.push_str(format!("{}: {}\n", CHALLENGE_KEY, self.challenge.as_bytes().to_hex()).as_slice());
+        result.push_str(format!("{}: {}\n", WALLET_HASH_KEY, self.wallet_hash.to_hex()).as_slice());
+
+        for key in self.keys.iter() {
+            result.push_str(format!("\n{}:\n", key.address.encode()).as_slice());
+            result.push_str(format!("  {}\n", key.public_key.to_hex()).as_slice());
+            result.push_str(format!("  {}\n", key.signature.to_hex()).as_slice());
+            match key.witness_script {
+                Some(ref witness_script) => {
+                    result.push_str(format!("  {}\n", witness_script.to_hex()).as_slice());
+                }
+                None => {}
+            }
+        }
+        result
+    }
+
+    /// Parses a proof in the format described above, from the file with the
+    /// given name, which is used in error messages.
+    pub fn parse(text: &str, name: &str) -> IoResult<Proof> {
+        let mut reader = MemReader::new(text.as_bytes().to_vec());
+        let parsed = try!(wallet_parser::parse(&mut reader, name));
+
+        let mut challenge = None;
+        let mut wallet_hash = None;
+        let mut keys = Vec::new();
+
+        for (key, values) in parsed.into_iter() {
+            if key.as_slice() == CHALLENGE_KEY {
+                challenge = values.concat().as_slice().from_hex().ok().and_then(|data| String::from_utf8(data).ok());
+                continue;
+            } else if key.as_slice() == WALLET_HASH_KEY {
+                wallet_hash = values.concat().as_slice().from_hex().ok();
+                continue;
+            }
+
+            let address = Address::decode(key.as_slice());
+            let values: Vec<Option<Vec<u8>>> = values.iter().map(|value| value.as_slice().from_hex().ok()).collect();
+
+            let key_proof = match (address, values.as_slice()) {
+                (Some(address), [Some(ref public_key), Some(ref signature)]) => {
+                    Some(KeyProof { address: address, public_key: public_key.clone(),
+                                    signature: signature.clone(), witness_script: None })
+                }
+                (Some(address), [Some(ref public_key), Some(ref signature), Some(ref witness_script)]) => {
+                    Some(KeyProof { address: address, public_key: public_key.clone(),
+                                    signature: signature.clone(), witness_script: Some(witness_script.clone()) })
+                }
+                _ => None
+            };
+
+            match key_proof {
+                Some(key_proof) => keys.push(key_proof),
+                None => {
+                    return Err(IoError {
+                        kind: OtherIoError,
+                        desc: "invalid key proof",
+                        detail: Some(format!("The proof for '{}' is invalid", key))
+                    });
+                }
+            }
+        }
+
+        match (challenge, wallet_hash) {
+            (Some(challenge), Some(wallet_hash)) => {
+                Ok(Proof { challenge: challenge, wallet_hash: wallet_hash, keys: keys })
+            }
+            _ => Err(IoError {
+                kind: OtherIoError,
+                desc: "invalid proof",
+                detail: Some(format!("'{}' or '{}' is missing or invalid", CHALLENGE_KEY, WALLET_HASH_KEY))
+            })
+        }
+    }
+}
+
+/// Builds the 32-byte message that's signed for an address.
+pub fn message(challenge: &str, wallet_hash: &[u8], address: &Address) -> Vec<u8> {
+    let mut data = Vec::new();
+    bytes::write_var_bytes(&mut data, challenge.as_bytes());
+    bytes::write_var_bytes(&mut data, wallet_hash);
+    bytes::write_var_bytes(&mut data, address.encode().as_bytes());
+    hash::tagged_hash(MESSAGE_TAG, data.as_slice())
+}
+
+// Checks whether `needle` appears anywhere in `haystack`.
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::FromHex;
+
+    use protocol::address::{Address, AddressType};
+    use protocol::private_key::PrivateKey;
+    use protocol::script;
+
+    use super::{KeyProof, Proof};
+
+    static CHALLENGE: &'static str = "audit 2014-12-01";
+
+    fn private_key(n: u8) -> PrivateKey {
+        let mut data = Vec::from_elem(32, 0u8);
+        *data.index_mut(&31) = n;
+        PrivateKey::new(data.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let wallet_hash = Vec::from_elem(32, 0xabu8);
+        let key = private_key(1);
+        let addresses = [key.to_address(), key.to_compressed_address(), key.to_p2sh_p2wpkh_address(),
+                         key.to_p2wpkh_address(), key.to_p2tr_address()];
+
+        for address in addresses.iter() {
+            let proof = KeyProof::sign(CHALLENGE, wallet_hash.as_slice(), address, &key, None);
+            assert!(proof.verify(CHALLENGE, wallet_hash.as_slice()));
+            assert!(!proof.verify("another challenge", wallet_hash.as_slice()));
+            assert!(!proof.verify(CHALLENGE, Vec::from_elem(32, 0u8).as_slice()));
+
+            // The key has to belong to the address.
+            let mut wrong_key = proof.clone();
+            wrong_key.address = private_key(2).to_address();
+            assert!(!wrong_key.verify(CHALLENGE, wallet_hash.as_slice()));
+        }
+    }
+
+    #[test]
+    fn test_sign_verify_multisig() {
+        let wallet_hash = Vec::from_elem(32, 0xabu8);
+        let keys = [private_key(1), private_key(2)];
+        let public_keys: Vec<Vec<u8>> = keys.iter().map(|key| key.to_public_key().to_compressed()).collect();
+        let witness_script = script::multisig_script(2, public_keys.as_slice());
+        let address = Address::p2wsh_from_witness_script(witness_script.as_slice());
+        assert_eq!(address.get_type(), AddressType::P2WSH);
+
+        let proof = KeyProof::sign(CHALLENGE, wallet_hash.as_slice(), &address, &keys[1], Some(witness_script.clone()));
+        assert!(proof.verify(CHALLENGE, wallet_hash.as_slice()));
+
+        // A key that isn't in the witness script.
+        let outsider = KeyProof::sign(CHALLENGE, wallet_hash.as_slice(), &address, &private_key(3),
+                                      Some(witness_script.clone()));
+        assert!(!outsider.verify(CHALLENGE, wallet_hash.as_slice()));
+    }
+
+    #[test]
+    fn test_encode_parse() {
+        let wallet_hash = "5d5b09f6dcb2d53a5fffc60c4ac0d55fabdf556069d6631545f42aa6e3500f2e".from_hex().unwrap();
+        let key = private_key(1);
+        let proof = Proof {
+            challenge: CHALLENGE.to_string(),
+            wallet_hash: wallet_hash.clone(),
+            keys: vec![
+                KeyProof::sign(CHALLENGE, wallet_hash.as_slice(), &key.to_address(), &key, None),
+                KeyProof::sign(CHALLENGE, wallet_hash.as_slice(), &key.to_p2tr_address(), &key, None)
+            ]
+        };
+
+        let parsed = Proof::parse(proof.encode().as_slice(), "proof").unwrap();
+        assert_eq!(parsed, proof);
+        assert!(parsed.find(&key.to_p2tr_address()).unwrap().verify(CHALLENGE, wallet_hash.as_slice()));
+        assert!(parsed.find(&key.to_p2wpkh_address()).is_none());
+
+        assert!(Proof::parse("!challenge: 00", "proof").is_err());
+        assert!(Proof::parse("!challenge: 00 !wallet_sha256: 00 1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH: 00", "proof").is_err());
+    }
+}
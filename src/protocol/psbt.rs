@@ -0,0 +1,355 @@
+//! Partially signed Bitcoin transactions (BIP 174), which let several
+//! wallets, like the cosigners of a multisig wallet, each add what they know
+//! to a transaction before it's finalized and broadcast.
+//!
+//! Only version 0 PSBTs are supported. Every key-value pair is kept, even the
+//! ones Cykas doesn't understand, so that passing a PSBT through Cykas never
+//! loses anything another wallet added.
+
+use serialize::base64::{ToBase64, FromBase64, STANDARD};
+
+use util::bytes;
+use util::bytes::Reader;
+use protocol::transaction::{Transaction, TxOut};
+
+// Every PSBT starts with "psbt" followed by 0xff.
+static MAGIC: &'static [u8] = b"psbt\xff";
+
+// The key type of the unsigned transaction in the global map.
+static GLOBAL_UNSIGNED_TX: u8 = 0x00;
+
+// Key types in the map of each input.
+static IN_NON_WITNESS_UTXO: u8 = 0x00;
+static IN_WITNESS_UTXO: u8 = 0x01;
+static IN_PARTIAL_SIG: u8 = 0x02;
+static IN_SIGHASH_TYPE: u8 = 0x03;
+static IN_REDEEM_SCRIPT: u8 = 0x04;
+static IN_WITNESS_SCRIPT: u8 = 0x05;
+static IN_FINAL_SCRIPTSIG: u8 = 0x07;
+static IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+
+/// The key-value pairs of one section of a PSBT, in the order they were
+/// read or added. Each key starts with its type byte.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Map {
+    pairs: Vec<(Vec<u8>, Vec<u8>)>
+}
+
+/// A partially signed transaction: the unsigned transaction, and a map of
+/// extra data for the whole transaction, for each input and for each output.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Psbt {
+    pub unsigned_tx: Transaction,
+    /// The global map, without the unsigned transaction.
+    pub global: Map,
+    pub inputs: Vec<Map>,
+    pub outputs: Vec<Map>
+}
+
+impl Map {
+    /// Creates an empty map.
+    pub fn new() -> Map {
+        Map { pairs: Vec::new() }
+    }
+
+    /// Gets the value of the given key (including its type byte).
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.pairs.iter()
+            .find(|&&(ref k, _)| k.as_slice() == key)
+            .map(|&(_, ref value)| value.as_slice())
+    }
+
+    /// Gets the key data (the key after the type byte) and value of every
+    /// pair with the given key type.
+    pub fn get_all(&self, key_type: u8) -> Vec<(&[u8], &[u8])> {
+        self.pairs.iter()
+            .filter(|&&(ref key, _)| key[0] == key_type)
+            .map(|&(ref key, ref value)| (key.slice_from(1), value.as_slice()))
+            .collect()
+    }
+
+    /// Sets the value of the given key, replacing any value it already has.
+    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        match self.pairs.iter().position(|&(ref k, _)| *k == key) {
+            Some(index) => {
+                let &(_, ref mut old_value) = self.pairs.index_mut(&index);
+                *old_value = value;
+            }
+            None => self.pairs.push((key, value))
+        }
+    }
+
+    // Reads pairs up to the zero byte that ends a map. Returns None if the
+    // data ends early, a key is empty, or a key appears twice.
+    fn read(reader: &mut Reader) -> Option<Map> {
+        let mut map = Map::new();
+        loop {
+            let key = match reader.read_var_bytes() {
+                Some(key) if key.is_empty() => return Some(map),
+                Some(key) => key,
+                None => return None
+            };
+            let value = match reader.read_var_bytes() { Some(v) => v, None => return None };
+
+            if map.get(key).is_some() { return None; }
+            map.pairs.push((key.to_vec(), value.to_vec()));
+        }
+    }
+
+    fn write(&self, output: &mut Vec<u8>) {
+        for &(ref key, ref value) in self.pairs.iter() {
+            bytes::write_var_bytes(output, key.as_slice());
+            bytes::write_var_bytes(output, value.as_slice());
+        }
+        output.push(0x00);
+    }
+}
+
+impl Psbt {
+    /// Creates a PSBT with empty maps for an unsigned transaction. Returns
+    /// None if any input has a scriptSig or witness.
+    pub fn new(unsigned_tx: Transaction) -> Option<Psbt> {
+        let is_unsigned = unsigned_tx.inputs.iter().all(|input| {
+            input.script_sig.is_empty() && input.witness.is_empty()
+        });
+        if !is_unsigned { return None; }
+
+        let inputs = Vec::from_fn(unsigned_tx.inputs.len(), |_| Map::new());
+        let outputs = Vec::from_fn(unsigned_tx.outputs.len(), |_| Map::new());
+        Some(Psbt { unsigned_tx: unsigned_tx, global: Map::new(), inputs: inputs, outputs: outputs })
+    }
+
+    /// Parses a serialized PSBT. Returns None if it's invalid.
+    pub fn parse(data: &[u8]) -> Option<Psbt> {
+        let mut reader = Reader::new(data);
+        if reader.read_bytes(MAGIC.len()) != Some(MAGIC) { return None; }
+
+        let mut global = match Map::read(&mut reader) { Some(map) => map, None => return None };
+
+        let unsigned_tx = match global.get(&[GLOBAL_UNSIGNED_TX]) {
+            Some(data) => Transaction::parse(data),
+            None => None
+        };
+        let mut psbt = match unsigned_tx.and_then(|tx| Psbt::new(tx)) {
+            Some(psbt) => psbt,
+            None => return None
+        };
+        global.pairs.retain(|&(ref key, _)| key.as_slice() != [GLOBAL_UNSIGNED_TX].as_slice());
+        psbt.global = global;
+
+        for input in psbt.inputs.iter_mut() {
+            *input = match Map::read(&mut reader) { Some(map) => map, None => return None };
+        }
+        for output in psbt.outputs.iter_mut() {
+            *output = match Map::read(&mut reader) { Some(map) => map, None => return None };
+        }
+
+        if reader.is_empty() { Some(psbt) } else { None }
+    }
+
+    /// Decodes a PSBT from base64, the way it's usually passed around.
+    /// Returns None if it's invalid.
+    pub fn decode(string: &str) -> Option<Psbt> {
+        match string.trim().from_base64() {
+            Ok(data) => Psbt::parse(data.as_slice()),
+            Err(_) => None
+        }
+    }
+
+    /// Serializes the PSBT.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut result = MAGIC.to_vec();
+
+        // The unsigned transaction always comes first.
+        bytes::write_var_bytes(&mut result, &[GLOBAL_UNSIGNED_TX]);
+        bytes::write_var_bytes(&mut result, self.unsigned_tx.serialize_without_witness().as_slice());
+        self.global.write(&mut result);
+
+        for input in self.inputs.iter() {
+            input.write(&mut result);
+        }
+        for output in self.outputs.iter() {
+            output.write(&mut result);
+        }
+        result
+    }
+
+    /// Serializes the PSBT and encodes it in base64.
+    pub fn encode(&self) -> String {
+        self.serialize().as_slice().to_base64(STANDARD)
+    }
+
+    /// Gets the output that the input at `input_index` spends, from its
+    /// witness UTXO or else its non-witness UTXO (the whole previous
+    /// transaction, which has to match the input's previous txid). Returns
+    /// None if neither is there or valid.
+    pub fn spent_output(&self, input_index: uint) -> Option<TxOut> {
+        let input = &self.inputs[input_index];
+
+        match input.get(&[IN_WITNESS_UTXO]) {
+            Some(data) => return TxOut::parse(data),
+            None => {}
+        }
+
+        let tx_input = &self.unsigned_tx.inputs[input_index];
+        input.get(&[IN_NON_WITNESS_UTXO])
+            .and_then(|data| Transaction::parse(data))
+            .and_then(|previous_tx| {
+                if previous_tx.txid() != tx_input.previous_txid { return None; }
+                previous_tx.outputs.get(tx_input.previous_index as uint).map(|output| output.clone())
+            })
+    }
+
+    /// Sets the witness UTXO of the input at `input_index`.
+    pub fn set_witness_utxo(&mut self, input_index: uint, output: &TxOut) {
+        self.inputs.index_mut(&input_index).insert(vec![IN_WITNESS_UTXO], output.serialize());
+    }
+
+    /// Gets the sighash type the input at `input_index` should be signed
+    /// with, if one is given. Returns None if there isn't one, or if it's
+    /// invalid.
+    pub fn sighash_type(&self, input_index: uint) -> Option<u32> {
+        self.inputs[input_index].get(&[IN_SIGHASH_TYPE]).and_then(|data| {
+            if data.len() != 4 { return None; }
+            let mut reader = Reader::new(data);
+            reader.read_u32()
+        })
+    }
+
+    /// Gets the redeem script of the input at `input_index`, if it's given.
+    pub fn redeem_script(&self, input_index: uint) -> Option<&[u8]> {
+        self.inputs[input_index].get(&[IN_REDEEM_SCRIPT])
+    }
+
+    /// Sets the redeem script of the input at `input_index`.
+    pub fn set_redeem_script(&mut self, input_index: uint, redeem_script: &[u8]) {
+        self.inputs.index_mut(&input_index).insert(vec![IN_REDEEM_SCRIPT], redeem_script.to_vec());
+    }
+
+    /// Gets the witness script of the input at `input_index`, if it's given.
+    pub fn witness_script(&self, input_index: uint) -> Option<&[u8]> {
+        self.inputs[input_index].get(&[IN_WITNESS_SCRIPT])
+    }
+
+    /// Sets the witness script of the input at `input_index`.
+    pub fn set_witness_script(&mut self, input_index: uint, witness_script: &[u8]) {
+        self.inputs.index_mut(&input_index).insert(vec![IN_WITNESS_SCRIPT], witness_script.to_vec());
+    }
+
+    /// Gets the partial signatures of the input at `input_index`, as pairs
+    /// of public key and signature (with the sighash type appended).
+    pub fn partial_signatures(&self, input_index: uint) -> Vec<(&[u8], &[u8])> {
+        self.inputs[input_index].get_all(IN_PARTIAL_SIG)
+    }
+
+    /// Adds the signature of the given public key to the input at
+    /// `input_index`, replacing any signature that key already made.
+    pub fn add_partial_signature(&mut self, input_index: uint, public_key: &[u8], signature: &[u8]) {
+        let mut key = vec![IN_PARTIAL_SIG];
+        key.push_all(public_key);
+        self.inputs.index_mut(&input_index).insert(key, signature.to_vec());
+    }
+
+    /// Checks whether the input at `input_index` has already been finalized,
+    /// in which case it doesn't need any more signatures.
+    pub fn is_finalized(&self, input_index: uint) -> bool {
+        let input = &self.inputs[input_index];
+        input.get(&[IN_FINAL_SCRIPTSIG]).is_some() || input.get(&[IN_FINAL_SCRIPTWITNESS]).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::{ToHex, FromHex};
+
+    use protocol::transaction::{Transaction, TxOut};
+
+    use super::Psbt;
+
+    // The BIP 174 test vector with one P2PKH input, which has a non-witness
+    // UTXO, and two outputs with empty maps.
+    static P2PKH_PSBT: &'static str =
+        "cHNidP8BAHUCAAAAASaBcTce3/KF6Tet7qSze3gADAVmy7OtZGQXE8pCFxv2AAAAAAD+////AtPf9QUAAAAAGXapFNDF\
+         mQPFusKGh2DpD9UhpGZap2UgiKwA4fUFAAAAABepFDVF5uM7gyxHBQ8k0+65PJwDlIvHh7MuEwAAAQD9pQEBAAAAAAEC\
+         iaPHHqtNIOA3G7ukzGmPopXJRjr6Ljl/hTPMti+VZ+UBAAAAFxYAFL4Y0VKpsBIDna89p95PUzSe7LmF/////4b4qkOn\
+         Hf8USIk6UwpyN+9rRgi7st0tAXHmOuxqSJC0AQAAABcWABT+Pp7xp0XpdNkCxDVZQ6vLNL1TU/////8CAMLrCwAAAAAZ\
+         dqkUhc/xCX/Z4Ai7NK9wnGIZeziXikiIrHL++E4sAAAAF6kUM5cluiHv1irHU6m80GfWx6ajnQWHAkcwRAIgJxK+IuAn\
+         DzlPVoMR3HyppolwuAJf3TskAinwf4pfOiQCIAGLONfc0xTnNMkna9b7QPZzMlvEuqFEyADS8vAtsnZcASED0uFWdJQb\
+         rUqZY3LLh+GFbTZSYG2YVi/jnF6efkE/IQUCSDBFAiEA0SuFLYXc2WHS9fSrZgZU327tzHlMDDPOXMMJ/7X85Y0CIGcz\
+         io4OFyXBl/saiK9Z9R5E5CVbIBZ8hoQDHAXR8lkqASECI7cr7vCWXRC+B3jv7NYfysb3mk6haTkzgHNEZPhPKrMAAAAA\
+         AAAA";
+
+    // An unsigned transaction with one input and two outputs, from the
+    // BIP 143 examples.
+    static UNSIGNED_TX: &'static str =
+        "010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1c\
+         a29787b96e0100000000ffffffff0200e9a435000000001976a914389ffce9cd\
+         9ae88dcc0631e88a821ffdbe9bfe2688acc0832f05000000001976a9147480a3\
+         3f950689af511e6e84c138dbbd3c3ee41588ac00000000";
+
+    #[test]
+    fn test_decode() {
+        let psbt = Psbt::decode(P2PKH_PSBT).unwrap();
+        assert_eq!(psbt.inputs.len(), 1);
+        assert_eq!(psbt.outputs.len(), 2);
+        assert_eq!(psbt.unsigned_tx.outputs[0].value, 99999699);
+
+        // The spent output comes from the previous transaction, which has
+        // witness data.
+        let spent = psbt.spent_output(0).unwrap();
+        assert_eq!(spent.value, 200000000);
+        assert_eq!(spent.script_pubkey.to_hex().as_slice(),
+                   "76a91485cff1097fd9e008bb34af709c62197b38978a4888ac");
+
+        assert!(psbt.sighash_type(0).is_none());
+        assert!(psbt.partial_signatures(0).is_empty());
+        assert!(!psbt.is_finalized(0));
+        assert_eq!(psbt.encode().as_slice(), P2PKH_PSBT);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let tx = Transaction::parse(UNSIGNED_TX.from_hex().unwrap().as_slice()).unwrap();
+        let mut psbt = Psbt::new(tx).unwrap();
+
+        let spent = TxOut { value: 987654321, script_pubkey: "a9149993a429037b5d912407a71c252019287b8d27a587".from_hex().unwrap() };
+        psbt.set_witness_utxo(0, &spent);
+        psbt.set_witness_script(0, &[0x51]);
+        psbt.add_partial_signature(0, &[0x02, 0x01], &[0x30, 0x01]);
+        psbt.add_partial_signature(0, &[0x02, 0x01], &[0x30, 0x02]);
+        psbt.inputs.index_mut(&0).insert(vec![0xfc, 0x00], vec![0xab]);
+
+        let decoded = Psbt::decode(psbt.encode().as_slice()).unwrap();
+        assert_eq!(decoded, psbt);
+        assert_eq!(decoded.spent_output(0), Some(spent));
+        assert_eq!(decoded.witness_script(0), Some([0x51u8].as_slice()));
+        assert!(decoded.redeem_script(0).is_none());
+        assert_eq!(decoded.partial_signatures(0), vec![([0x02u8, 0x01].as_slice(), [0x30u8, 0x02].as_slice())]);
+        assert_eq!(decoded.inputs[0].get(&[0xfc, 0x00]), Some([0xabu8].as_slice()));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let data = Psbt::decode(P2PKH_PSBT).unwrap().serialize();
+
+        let mut bad_magic = data.clone();
+        *bad_magic.index_mut(&4) = 0x00;
+        assert!(Psbt::parse(bad_magic.as_slice()).is_none());
+        assert!(Psbt::parse(data.slice_to(data.len() - 1)).is_none());
+
+        let mut trailing = data.clone();
+        trailing.push(0x00);
+        assert!(Psbt::parse(trailing.as_slice()).is_none());
+
+        // A global map with the same key twice.
+        let mut duplicate = data.slice_to(5).to_vec();
+        duplicate.push_all(&[0x01, 0xfc, 0x00, 0x01, 0xfc, 0x00]);
+        duplicate.push_all(data.slice_from(5));
+        assert!(Psbt::parse(duplicate.as_slice()).is_none());
+
+        // The transaction in a PSBT can't have any signatures.
+        let mut tx = Transaction::parse(UNSIGNED_TX.from_hex().unwrap().as_slice()).unwrap();
+        tx.inputs.index_mut(&0).script_sig = vec![0x00];
+        assert!(Psbt::new(tx).is_none());
+    }
+}
@@ -10,6 +10,11 @@ static LENGTH: uint = 65u;
 // Initial byte of a public key, signifying it's in uncompressed format.
 static FORMAT_BYTE: u8 = 0x04;
 
+// Initial bytes of a compressed public key, signifying whether the Y
+// coordinate is even or odd.
+static COMPRESSED_EVEN_BYTE: u8 = 0x02;
+static COMPRESSED_ODD_BYTE: u8 = 0x03;
+
 /// Represents a raw Bitcoin public key. The bytes of a public key are laid out
 /// like this:
 ///
@@ -48,10 +53,45 @@ impl PublicKey {
         data.as_slice()
     }
 
+    /// Converts the public key to compressed format, which is just the X
+    /// coordinate preceded by a byte saying whether the Y coordinate is even
+    /// (0x02) or odd (0x03). Segregated witness outputs only accept public keys
+    /// in this format.
+    pub fn to_compressed(&self) -> Vec<u8> {
+        let data = self.get_data();
+        let y_is_odd = data[LENGTH - 1] & 1 == 1;
+
+        let mut result = Vec::with_capacity(33);
+        result.push(if y_is_odd { COMPRESSED_ODD_BYTE } else { COMPRESSED_EVEN_BYTE });
+        result.push_all(data.slice(1, 33));
+        result
+    }
+
     /// Derives the address from the public key.
     pub fn to_address(&self) -> Address {
         Address::from_public_key(self)
     }
+
+    /// Derives the P2PKH address of the compressed form of the public key.
+    pub fn to_compressed_address(&self) -> Address {
+        Address::from_compressed_public_key(self)
+    }
+
+    /// Derives the P2SH-wrapped P2WPKH address from the public key.
+    pub fn to_p2sh_p2wpkh_address(&self) -> Address {
+        Address::p2sh_p2wpkh_from_public_key(self)
+    }
+
+    /// Derives the native P2WPKH address from the public key.
+    pub fn to_p2wpkh_address(&self) -> Address {
+        Address::p2wpkh_from_public_key(self)
+    }
+
+    /// Derives the taproot address from the public key, using it as the
+    /// internal key with no script tree.
+    pub fn to_p2tr_address(&self) -> Address {
+        Address::p2tr_from_public_key(self)
+    }
 }
 
 #[cfg(test)]
@@ -102,6 +142,28 @@ mod tests {
         assert_eq!(public_key.get_data(), expected.as_slice());
     }
 
+    #[test]
+    fn test_to_compressed() {
+        let data = "0423111FB83A08B04A546F94BC6845E07BCD5105E4738631DCDCE8E8656A9F3405\
+                      9FC7368BE3FFB812E0C0BCB4C671CE7EE61B277BC4C1ED0240E6A346E5BBBFC0";
+        let data = data.from_hex().unwrap();
+        let public_key = PublicKey::new(data.as_slice()).unwrap();
+        let expected = "0223111FB83A08B04A546F94BC6845E07BCD5105E4738631DCDCE8E8656A9F3405";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(public_key.to_compressed(), expected);
+    }
+
+    #[test]
+    fn test_to_compressed_odd() {
+        let data = "04F9C985FBFD543097E0870B36C98CE627BBC4EAD4668040214D53E96DB341A2A0\
+                      A55DBBFF18F4422E90E038BECECA97461C4076FA33408D568154A66AC8FA702F";
+        let data = data.from_hex().unwrap();
+        let public_key = PublicKey::new(data.as_slice()).unwrap();
+        let expected = "03F9C985FBFD543097E0870B36C98CE627BBC4EAD4668040214D53E96DB341A2A0";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(public_key.to_compressed(), expected);
+    }
+
     #[test]
     fn test_to_address() {
         let data = "0423111FB83A08B04A546F94BC6845E07BCD5105E4738631DCDCE8E8656A9F3405\
@@ -0,0 +1,228 @@
+//! Construction of the Bitcoin scripts needed to spend from the kinds of
+//! addresses Cykas generates.
+
+use util::hash;
+use protocol::public_key::PublicKey;
+
+// Script opcodes used below.
+static OP_0: u8 = 0x00;
+static OP_1: u8 = 0x51;
+static OP_DUP: u8 = 0x76;
+static OP_EQUAL: u8 = 0x87;
+static OP_EQUALVERIFY: u8 = 0x88;
+static OP_HASH160: u8 = 0xa9;
+static OP_CHECKSIG: u8 = 0xac;
+static OP_CHECKMULTISIG: u8 = 0xae;
+
+/// The most public keys a multisig script can have. The number of keys is
+/// pushed with a single opcode, and 15 compressed keys are as many as fit in
+/// a P2SH redeem script.
+pub static MAX_MULTISIG_KEYS: uint = 15;
+
+/// Returns a script that pushes the given data onto the stack. Only handles
+/// data short enough to use a single-byte push opcode, which covers every
+/// signature, public key, and script Cykas deals with.
+pub fn push_data(data: &[u8]) -> Vec<u8> {
+    assert!(data.len() <= 75);
+
+    let mut result = Vec::with_capacity(1 + data.len());
+    result.push(data.len() as u8);
+    result.push_all(data);
+    result
+}
+
+/// Returns the scriptPubKey of a pay-to-public-key-hash output:
+///
+///     OP_DUP OP_HASH160 <20-byte hash> OP_EQUALVERIFY OP_CHECKSIG
+pub fn p2pkh_script_pubkey(public_key_hash: &[u8]) -> Vec<u8> {
+    assert_eq!(public_key_hash.len(), 20);
+
+    let mut result = vec![OP_DUP, OP_HASH160];
+    result.push_all(push_data(public_key_hash).as_slice());
+    result.push(OP_EQUALVERIFY);
+    result.push(OP_CHECKSIG);
+    result
+}
+
+/// Returns the scriptPubKey of a pay-to-script-hash output:
+///
+///     OP_HASH160 <20-byte hash> OP_EQUAL
+pub fn p2sh_script_pubkey(script_hash: &[u8]) -> Vec<u8> {
+    assert_eq!(script_hash.len(), 20);
+
+    let mut result = vec![OP_HASH160];
+    result.push_all(push_data(script_hash).as_slice());
+    result.push(OP_EQUAL);
+    result
+}
+
+/// Returns the scriptPubKey of a native pay-to-witness-public-key-hash output,
+/// which is a version 0 witness program:
+///
+///     OP_0 <20-byte hash of the compressed public key>
+pub fn p2wpkh_script_pubkey(public_key_hash: &[u8]) -> Vec<u8> {
+    assert_eq!(public_key_hash.len(), 20);
+
+    let mut result = vec![OP_0];
+    result.push_all(push_data(public_key_hash).as_slice());
+    result
+}
+
+/// Returns the scriptPubKey of a native pay-to-witness-script-hash output,
+/// which is a version 0 witness program:
+///
+///     OP_0 <32-byte SHA-256 hash of the witness script>
+pub fn p2wsh_script_pubkey(script_hash: &[u8]) -> Vec<u8> {
+    assert_eq!(script_hash.len(), 32);
+
+    let mut result = vec![OP_0];
+    result.push_all(push_data(script_hash).as_slice());
+    result
+}
+
+/// Returns the scriptPubKey of a pay-to-taproot output, which is a version 1
+/// witness program:
+///
+///     OP_1 <32-byte x-only output key>
+pub fn p2tr_script_pubkey(output_key: &[u8]) -> Vec<u8> {
+    assert_eq!(output_key.len(), 32);
+
+    let mut result = vec![OP_1];
+    result.push_all(push_data(output_key).as_slice());
+    result
+}
+
+/// Returns a script that can be spent with a signature from the given public
+/// key, in whatever format it's given:
+///
+///     <public key> OP_CHECKSIG
+pub fn p2pk_script(public_key: &[u8]) -> Vec<u8> {
+    let mut result = push_data(public_key);
+    result.push(OP_CHECKSIG);
+    result
+}
+
+/// Returns a script that can be spent with signatures from `threshold` of the
+/// given public keys, in the same order as the keys:
+///
+///     OP_m <public key>... OP_n OP_CHECKMULTISIG
+pub fn multisig_script(threshold: uint, public_keys: &[Vec<u8>]) -> Vec<u8> {
+    assert!(threshold >= 1 && threshold <= public_keys.len());
+    assert!(public_keys.len() <= MAX_MULTISIG_KEYS);
+
+    let mut result = vec![small_number(threshold)];
+    for public_key in public_keys.iter() {
+        result.push_all(push_data(public_key.as_slice()).as_slice());
+    }
+    result.push(small_number(public_keys.len()));
+    result.push(OP_CHECKMULTISIG);
+    result
+}
+
+// Returns the opcode that pushes a number from 1 to 16.
+fn small_number(n: uint) -> u8 {
+    assert!(n >= 1 && n <= 16);
+    OP_1 - 1 + n as u8
+}
+
+/// Returns the version 0 witness program for the given public key, which is
+/// what gets hashed into a P2SH-P2WPKH address:
+///
+///     OP_0 <20-byte hash of the compressed public key>
+pub fn p2wpkh_redeem_script(public_key: &PublicKey) -> Vec<u8> {
+    let public_key_hash = hash::hash160(public_key.to_compressed().as_slice());
+    p2wpkh_script_pubkey(public_key_hash.as_slice())
+}
+
+/// Returns the scriptSig of an input spending a P2SH-P2WPKH output. It only
+/// pushes the redeem script; the signature goes in the witness.
+pub fn p2sh_p2wpkh_script_sig(public_key: &PublicKey) -> Vec<u8> {
+    push_data(p2wpkh_redeem_script(public_key).as_slice())
+}
+
+/// Returns the witness stack of an input spending a P2WPKH (or P2SH-P2WPKH)
+/// output, given a DER-encoded signature with the sighash type appended.
+pub fn p2wpkh_witness(signature: &[u8], public_key: &PublicKey) -> Vec<Vec<u8>> {
+    vec![signature.to_vec(), public_key.to_compressed()]
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::FromHex;
+
+    use util::hash;
+    use protocol::private_key::PrivateKey;
+
+    use super::{p2pkh_script_pubkey, p2sh_script_pubkey, p2tr_script_pubkey, p2wsh_script_pubkey};
+    use super::{p2pk_script, multisig_script};
+    use super::{p2wpkh_redeem_script, p2sh_p2wpkh_script_sig, p2wpkh_witness};
+
+    #[test]
+    fn test_p2pkh_script_pubkey() {
+        let hash = "9C1998F823D6E9BB23AA7F2444FC5391E24E68E1".from_hex().unwrap();
+        let expected = "76A9149C1998F823D6E9BB23AA7F2444FC5391E24E68E188AC".from_hex().unwrap();
+        assert_eq!(p2pkh_script_pubkey(hash.as_slice()), expected);
+    }
+
+    #[test]
+    fn test_p2sh_script_pubkey() {
+        let hash = "9C1998F823D6E9BB23AA7F2444FC5391E24E68E1".from_hex().unwrap();
+        let expected = "A9149C1998F823D6E9BB23AA7F2444FC5391E24E68E187".from_hex().unwrap();
+        assert_eq!(p2sh_script_pubkey(hash.as_slice()), expected);
+    }
+
+    #[test]
+    fn test_p2tr_script_pubkey() {
+        let key = "53A1F6E454DF1AA2776A2814A721372D6258050DE330B3C6D10EE8F4E0DDA343".from_hex().unwrap();
+        let expected = "512053A1F6E454DF1AA2776A2814A721372D6258050DE330B3C6D10EE8F4E0DDA343";
+        assert_eq!(p2tr_script_pubkey(key.as_slice()), expected.from_hex().unwrap());
+    }
+
+    #[test]
+    fn test_p2wsh_script_pubkey() {
+        // From the BIP 173 examples.
+        let public_key = "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798".from_hex().unwrap();
+        let script = p2pk_script(public_key.as_slice());
+        let script_hash = "1863143C14C5166804BD19203356DA136C985678CD4D27A1B8C6329604903262".from_hex().unwrap();
+        assert_eq!(hash::sha256(script.as_slice()), script_hash);
+        assert_eq!(p2wsh_script_pubkey(script_hash.as_slice()),
+                   "00201863143C14C5166804BD19203356DA136C985678CD4D27A1B8C6329604903262".from_hex().unwrap());
+    }
+
+    #[test]
+    fn test_multisig_script() {
+        let keys = vec![
+            "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9".from_hex().unwrap(),
+            "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798".from_hex().unwrap(),
+            "02C6047F9441ED7D6D3045406E95C07CD85C778E4B8CEF3CA7ABAC09B95C709EE5".from_hex().unwrap()
+        ];
+        let expected = "522102F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9\
+                        210279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798\
+                        2102C6047F9441ED7D6D3045406E95C07CD85C778E4B8CEF3CA7ABAC09B95C709EE553AE";
+        assert_eq!(multisig_script(2, keys.as_slice()), expected.from_hex().unwrap());
+    }
+
+    #[test]
+    fn test_p2wpkh_redeem_script() {
+        let data = "CBBEC41B016517C3DA8E2F88BDACB293802CECF1AE2C47A7CB5D4BDA28353B5B";
+        let data = data.from_hex().unwrap();
+        let public_key = PrivateKey::new(data.as_slice()).unwrap().to_public_key();
+        let expected = "00149C1998F823D6E9BB23AA7F2444FC5391E24E68E1".from_hex().unwrap();
+        assert_eq!(p2wpkh_redeem_script(&public_key), expected);
+
+        let expected = "1600149C1998F823D6E9BB23AA7F2444FC5391E24E68E1".from_hex().unwrap();
+        assert_eq!(p2sh_p2wpkh_script_sig(&public_key), expected);
+    }
+
+    #[test]
+    fn test_p2wpkh_witness() {
+        let data = "CBBEC41B016517C3DA8E2F88BDACB293802CECF1AE2C47A7CB5D4BDA28353B5B";
+        let data = data.from_hex().unwrap();
+        let public_key = PrivateKey::new(data.as_slice()).unwrap().to_public_key();
+        let signature = "3006020101020101".from_hex().unwrap();
+        let witness = p2wpkh_witness(signature.as_slice(), &public_key);
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness[0], signature);
+        assert_eq!(witness[1], public_key.to_compressed());
+    }
+}
@@ -0,0 +1,177 @@
+//! Signature hashing (BIP 143) and signing for version 0 SegWit inputs.
+//!
+//! Cykas uses these to sign for P2WSH multisig outputs, where the script code
+//! is the witness script.
+
+use util::bytes;
+use util::ecdsa;
+use util::hash;
+use protocol::private_key::PrivateKey;
+use protocol::transaction::Transaction;
+use protocol::taproot::{SIGHASH_ALL, SIGHASH_SINGLE, SIGHASH_ANYONECANPAY};
+
+/// Computes the BIP 143 signature hash for the input at `input_index`, which
+/// spends an output worth `value` satoshis. The script code is the witness
+/// script for P2WSH inputs. Returns None if the input index or hash type is
+/// invalid. (SIGHASH_DEFAULT is only valid for taproot.)
+pub fn sighash(tx: &Transaction, input_index: uint, script_code: &[u8], value: u64,
+               hash_type: u8) -> Option<Vec<u8>> {
+    if input_index >= tx.inputs.len() { return None; }
+
+    let valid_hash_type = (hash_type >= 0x01 && hash_type <= 0x03) ||
+                          (hash_type >= 0x81 && hash_type <= 0x83);
+    if !valid_hash_type { return None; }
+
+    let output_type = hash_type & 0x03;
+    let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+
+    // The hashes of the parts of the transaction that aren't signed are all
+    // zeros.
+    let zero_hash = Vec::from_elem(32, 0u8);
+
+    let hash_prevouts = if !anyone_can_pay {
+        let mut prevouts = Vec::new();
+        for input in tx.inputs.iter() {
+            prevouts.push_all(input.previous_txid.as_slice());
+            bytes::write_u32(&mut prevouts, input.previous_index);
+        }
+        hash::double_sha256(prevouts.as_slice())
+    } else {
+        zero_hash.clone()
+    };
+
+    let hash_sequence = if !anyone_can_pay && output_type == SIGHASH_ALL {
+        let mut sequences = Vec::new();
+        for input in tx.inputs.iter() {
+            bytes::write_u32(&mut sequences, input.sequence);
+        }
+        hash::double_sha256(sequences.as_slice())
+    } else {
+        zero_hash.clone()
+    };
+
+    let hash_outputs = if output_type == SIGHASH_ALL {
+        let mut outputs = Vec::new();
+        for output in tx.outputs.iter() {
+            outputs.push_all(output.serialize().as_slice());
+        }
+        hash::double_sha256(outputs.as_slice())
+    } else if output_type == SIGHASH_SINGLE && input_index < tx.outputs.len() {
+        hash::double_sha256(tx.outputs[input_index].serialize().as_slice())
+    } else {
+        // SIGHASH_NONE, or SIGHASH_SINGLE with no corresponding output.
+        zero_hash
+    };
+
+    let input = &tx.inputs[input_index];
+    let mut message = Vec::new();
+    bytes::write_u32(&mut message, tx.version);
+    message.push_all(hash_prevouts.as_slice());
+    message.push_all(hash_sequence.as_slice());
+    message.push_all(input.previous_txid.as_slice());
+    bytes::write_u32(&mut message, input.previous_index);
+    bytes::write_var_bytes(&mut message, script_code);
+    bytes::write_u64(&mut message, value);
+    bytes::write_u32(&mut message, input.sequence);
+    message.push_all(hash_outputs.as_slice());
+    bytes::write_u32(&mut message, tx.lock_time);
+    bytes::write_u32(&mut message, hash_type as u32);
+
+    Some(hash::double_sha256(message.as_slice()))
+}
+
+/// Signs the input at `input_index`, returning a DER-encoded ECDSA signature
+/// with the hash type appended, ready to go in the witness. Returns None
+/// under the same conditions as `sighash()`.
+pub fn sign(tx: &Transaction, input_index: uint, script_code: &[u8], value: u64,
+            private_key: &PrivateKey, hash_type: u8) -> Option<Vec<u8>> {
+    let sighash = match sighash(tx, input_index, script_code, value, hash_type) {
+        Some(sighash) => sighash,
+        None => return None
+    };
+
+    let mut signature = ecdsa::sign(sighash.as_slice(), private_key.get_data());
+    signature.push(hash_type);
+    Some(signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::{ToHex, FromHex};
+
+    use protocol::private_key::PrivateKey;
+    use protocol::transaction::Transaction;
+
+    use super::{sighash, sign};
+
+    // The native P2WPKH example from BIP 143.
+    static P2WPKH_TX: &'static str =
+        "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541d\
+         b4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b\
+         1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb20600000000\
+         1976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d0000\
+         00001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000";
+
+    // The P2SH-P2WSH 6-of-6 multisig example from BIP 143.
+    static MULTISIG_TX: &'static str =
+        "010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1c\
+         a29787b96e0100000000ffffffff0200e9a435000000001976a914389ffce9cd\
+         9ae88dcc0631e88a821ffdbe9bfe2688acc0832f05000000001976a9147480a3\
+         3f950689af511e6e84c138dbbd3c3ee41588ac00000000";
+
+    static MULTISIG_WITNESS_SCRIPT: &'static str =
+        "56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e\
+         8c3ba32103b28f0c28bfab54554ae8c658ac5c3e0ce6e79ad336331f78c428dd\
+         43eea8449b21034b8113d703413d57761b8b9781957b8c0ac1dfe69f492580ca\
+         4195f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58c5d1f47de7\
+         4683123987e967a8f42103a6d48b1131e94ba04d9737d61acdaa1322008af960\
+         2b3b14862c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe67\
+         3a9f01d9f0c19617681024306b56ae";
+
+    #[test]
+    fn test_sign_p2wpkh() {
+        let tx = Transaction::parse(P2WPKH_TX.from_hex().unwrap().as_slice()).unwrap();
+        // For P2WPKH, the script code is the P2PKH script of the key hash.
+        let script_code = "76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac".from_hex().unwrap();
+
+        let actual = sighash(&tx, 1, script_code.as_slice(), 600000000, 0x01).unwrap();
+        assert_eq!(actual.to_hex().as_slice(),
+                   "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670");
+
+        let data = "619c335025c7f4012e556c2a58b2506e30b8511b53ade95ea316fd8c3286feb9";
+        let private_key = PrivateKey::new(data.from_hex().unwrap().as_slice()).unwrap();
+        let signature = sign(&tx, 1, script_code.as_slice(), 600000000, &private_key, 0x01).unwrap();
+        assert_eq!(signature.to_hex().as_slice(),
+                   "304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a\
+                    0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee01");
+    }
+
+    #[test]
+    fn test_sighash_types() {
+        let tx = Transaction::parse(MULTISIG_TX.from_hex().unwrap().as_slice()).unwrap();
+        let witness_script = MULTISIG_WITNESS_SCRIPT.from_hex().unwrap();
+
+        let expected = [
+            (0x01, "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c"),
+            (0x02, "e9733bc60ea13c95c6527066bb975a2ff29a925e80aa14c213f686cbae5d2f36"),
+            (0x03, "1e1f1c303dc025bd664acb72e583e933fae4cff9148bf78c157d1e8f78530aea"),
+            (0x81, "2a67f03e63a6a422125878b40b82da593be8d4efaafe88ee528af6e5a9955c6e"),
+            (0x82, "781ba15f3779d5542ce8ecb5c18716733a5ee42a6f51488ec96154934e2c890a"),
+            (0x83, "511e8e52ed574121fc1b654970395502128263f62662e076dc6baf05c2e6a99b")
+        ];
+
+        for &(hash_type, expected_sighash) in expected.iter() {
+            let actual = sighash(&tx, 0, witness_script.as_slice(), 987654321, hash_type).unwrap();
+            assert_eq!(actual.to_hex().as_slice(), expected_sighash);
+        }
+    }
+
+    #[test]
+    fn test_sighash_invalid() {
+        let tx = Transaction::parse(MULTISIG_TX.from_hex().unwrap().as_slice()).unwrap();
+        let witness_script = MULTISIG_WITNESS_SCRIPT.from_hex().unwrap();
+        assert!(sighash(&tx, 0, witness_script.as_slice(), 987654321, 0x00).is_none());
+        assert!(sighash(&tx, 0, witness_script.as_slice(), 987654321, 0x04).is_none());
+        assert!(sighash(&tx, 1, witness_script.as_slice(), 987654321, 0x01).is_none());
+    }
+}
@@ -0,0 +1,269 @@
+//! Shares of a wallet's master key, made with Shamir's secret sharing so that
+//! any `threshold` of `count` shares give the key back, and fewer give
+//! nothing away. It's the same idea as SLIP-39, in a simpler format: SLIP-39
+//! shares the seed that a master key is made from, and an HD wallet file only
+//! has the master key.
+//!
+//! A share is written down as words from the BIP 39 word list, which encode:
+//!
+//! - a random 16-bit identifier, the same in every share of a split,
+//! - the threshold and the share's index, less 1, in 4 bits each,
+//! - the length of the secret, in a byte,
+//! - the share's value, and
+//! - a checksum: the first bits of the SHA-256 hash of everything before it,
+//!   at least 16 of them and as many more as fill up the last word.
+//!
+//! What's shared isn't just the secret but the secret followed by the first
+//! 4 bytes of its SHA-256 hash, so that combining a wrong share, or shares of
+//! different splits, is caught rather than giving the wrong secret.
+
+use std::fmt;
+use openssl;
+
+use protocol::mnemonic::{wordlist, BITS_PER_WORD};
+use util::bytes::{read_bits, write_bits};
+use util::hash;
+use util::secret;
+use util::secret::SecretBytes;
+use util::shamir;
+
+/// The most shares a secret can be split into, since a share's index takes 4
+/// bits.
+pub static MAX_SHARES: uint = 16;
+
+/// The longest secret that can be split, since its length takes a byte.
+pub static MAX_SECRET_LENGTH: uint = 255;
+
+// The bytes before the share's value: the identifier, the threshold and
+// index, and the secret's length.
+static HEADER_LENGTH: uint = 4;
+
+// The length of the hash of the secret that's shared with it.
+static DIGEST_LENGTH: uint = 4;
+
+// The fewest bits of checksum at the end of a share.
+static MIN_CHECKSUM_BITS: uint = 16;
+
+/// A share of a secret. Its value is wiped from memory when it's dropped, and
+/// never shown when it's formatted.
+pub struct Share {
+    identifier: u16,
+    threshold: uint,
+    index: uint,
+    value: SecretBytes
+}
+
+impl Share {
+    /// Splits a secret into `count` shares, any `threshold` of which give it
+    /// back. Fails if `threshold` is 0 or more than `count`, `count` is more
+    /// than `MAX_SHARES`, or the secret is empty or longer than
+    /// `MAX_SECRET_LENGTH`.
+    pub fn split(secret: &[u8], threshold: uint, count: uint) -> Vec<Share> {
+        assert!(count <= MAX_SHARES);
+        assert!(secret.len() > 0 && secret.len() <= MAX_SECRET_LENGTH);
+
+        let mut digested = SecretBytes::with_capacity(secret.len() + DIGEST_LENGTH);
+        digested.push_all(secret);
+        digested.push_all(hash::sha256(secret).slice_to(DIGEST_LENGTH));
+
+        let identifier = openssl::crypto::rand::rand_bytes(2);
+        let identifier = (identifier[0] as u16 << 8) | identifier[1] as u16;
+
+        shamir::split(digested.as_slice(), threshold, count).into_iter().enumerate().map(|(i, value)| {
+            Share { identifier: identifier, threshold: threshold, index: i + 1, value: value }
+        }).collect()
+    }
+
+    /// Combines shares back into the secret. Only the first `threshold` of
+    /// them are used. Returns an error if there aren't enough shares, they're
+    /// from different splits, the same share is given twice, or the secret
+    /// they give doesn't match its hash.
+    pub fn combine(shares: &[Share]) -> Result<SecretBytes, &'static str> {
+        if shares.is_empty() { return Err("no shares were given"); }
+        let first = &shares[0];
+
+        for (i, share) in shares.iter().enumerate() {
+            if share.identifier != first.identifier || share.threshold != first.threshold ||
+               share.value.len() != first.value.len() {
+                return Err("the shares are from different splits");
+            }
+            if shares.slice_to(i).iter().any(|other| other.index == share.index) {
+                return Err("the same share was given twice");
+            }
+        }
+        if shares.len() < first.threshold { return Err("not enough shares were given"); }
+
+        let values: Vec<(u8, &[u8])> = shares.slice_to(first.threshold).iter().map(|share| {
+            (share.index as u8, share.value.as_slice())
+        }).collect();
+        let digested = shamir::combine(values.as_slice()).unwrap();
+
+        let (secret, digest) = digested.as_slice().split_at(digested.len() - DIGEST_LENGTH);
+        if hash::sha256(secret).slice_to(DIGEST_LENGTH) != digest {
+            return Err("the shares don't give a valid secret, so one of them is wrong");
+        }
+        Ok(SecretBytes::from_slice(secret))
+    }
+
+    /// Parses a share from its words, separated by whitespace, in any case.
+    /// Returns None if a word isn't in the word list, the share is the wrong
+    /// length, or the checksum doesn't match.
+    pub fn parse(phrase: &str) -> Option<Share> {
+        let words = wordlist();
+        let mut indexes = Vec::new();
+        for word in phrase.words() {
+            let word: String = word.chars().map(|ch| ch.to_lowercase()).collect();
+            match words.iter().position(|w| *w == word.as_slice()) {
+                Some(index) => indexes.push(index),
+                None => return None
+            }
+        }
+
+        let bit_count = indexes.len() * BITS_PER_WORD;
+        if bit_count < (HEADER_LENGTH + DIGEST_LENGTH) * 8 + MIN_CHECKSUM_BITS { return None; }
+
+        let mut data = Vec::from_elem((bit_count + 7) / 8, 0u8);
+        for (i, &index) in indexes.iter().enumerate() {
+            write_bits(data.as_mut_slice(), i * BITS_PER_WORD, BITS_PER_WORD, index);
+        }
+
+        let secret_length = data[3] as uint;
+        let length = HEADER_LENGTH + secret_length + DIGEST_LENGTH;
+        let share = if secret_length > 0 && length * 8 + checksum_bits(length) == bit_count &&
+                       checksum(data.slice_to(length), bit_count) == read_bits(data.as_slice(), length * 8, bit_count - length * 8) {
+            Some(Share {
+                identifier: (data[0] as u16 << 8) | data[1] as u16,
+                threshold: (data[2] >> 4) as uint + 1,
+                index: (data[2] & 0x0F) as uint + 1,
+                value: SecretBytes::from_slice(data.slice(HEADER_LENGTH, length))
+            })
+        } else {
+            None
+        };
+
+        secret::wipe(data.as_mut_slice());
+        share
+    }
+
+    /// Returns the share's words, joined by spaces.
+    pub fn to_phrase(&self) -> String {
+        let length = HEADER_LENGTH + self.value.len();
+        let bit_count = length * 8 + checksum_bits(length);
+
+        let mut data = Vec::with_capacity(length + 4);
+        data.push((self.identifier >> 8) as u8);
+        data.push(self.identifier as u8);
+        data.push(((self.threshold - 1) << 4 | (self.index - 1)) as u8);
+        data.push((self.value.len() - DIGEST_LENGTH) as u8);
+        data.push_all(self.value.as_slice());
+        let checksum = hash::sha256(data.as_slice());
+        data.push_all(checksum.slice_to(4));
+
+        let words = wordlist();
+        let phrase: Vec<&str> = range(0, bit_count / BITS_PER_WORD).map(|i| {
+            words[read_bits(data.as_slice(), i * BITS_PER_WORD, BITS_PER_WORD)]
+        }).collect();
+
+        secret::wipe(data.as_mut_slice());
+        phrase.connect(" ")
+    }
+
+    /// Gets the identifier that all the shares of a split have.
+    pub fn get_identifier(&self) -> u16 {
+        self.identifier
+    }
+
+    /// Gets the number of shares that give the secret back.
+    pub fn get_threshold(&self) -> uint {
+        self.threshold
+    }
+
+    /// Gets the share's index, from 1 up to the number of shares.
+    pub fn get_index(&self) -> uint {
+        self.index
+    }
+}
+
+impl fmt::Show for Share {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Share({:04x}, {} of {}, [value hidden])", self.identifier, self.index, self.threshold)
+    }
+}
+
+// Finds how many bits of checksum follow `length` bytes of data: at least
+// `MIN_CHECKSUM_BITS`, and as many more as fill up the last word.
+fn checksum_bits(length: uint) -> uint {
+    let bits = length * 8 + MIN_CHECKSUM_BITS;
+    MIN_CHECKSUM_BITS + (BITS_PER_WORD - bits % BITS_PER_WORD) % BITS_PER_WORD
+}
+
+// Gets the checksum of a share's data, as a number, for a share of
+// `bit_count` bits in all.
+fn checksum(data: &[u8], bit_count: uint) -> uint {
+    read_bits(hash::sha256(data).as_slice(), 0, bit_count - data.len() * 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Share;
+
+    #[test]
+    fn test_split_and_combine() {
+        let secret: Vec<u8> = range(0, 64u8).collect();
+        let shares = Share::split(secret.as_slice(), 3, 5);
+        assert_eq!(shares.len(), 5);
+        for (i, share) in shares.iter().enumerate() {
+            assert_eq!(share.get_identifier(), shares[0].get_identifier());
+            assert_eq!(share.get_threshold(), 3);
+            assert_eq!(share.get_index(), i + 1);
+        }
+
+        // A 64-byte secret takes 54 words.
+        let phrases: Vec<String> = shares.iter().map(|share| share.to_phrase()).collect();
+        assert_eq!(phrases[0].as_slice().words().count(), 54);
+
+        let parsed: Vec<Share> = [4u, 0, 2].iter().map(|&i| {
+            let upper: String = phrases[i].as_slice().chars().map(|ch| ch.to_uppercase()).collect();
+            Share::parse(upper.as_slice()).unwrap()
+        }).collect();
+        assert_eq!(Share::combine(parsed.as_slice()).unwrap().as_slice(), secret.as_slice());
+
+        // Extra shares are ignored.
+        assert_eq!(Share::combine(shares.as_slice()).unwrap().as_slice(), secret.as_slice());
+    }
+
+    #[test]
+    fn test_combine_invalid() {
+        let secret = b"0123456789abcdef";
+        let shares = Share::split(secret, 2, 3);
+        let others = Share::split(secret, 2, 3);
+
+        assert!(Share::combine(&[]).is_err());
+        assert!(Share::combine(shares.slice_to(1)).is_err());
+
+        let parse = |share: &Share| Share::parse(share.to_phrase().as_slice()).unwrap();
+        assert!(Share::combine(&[parse(&shares[0]), parse(&shares[0])]).is_err());
+        // The other split will almost certainly have a different identifier.
+        if others[0].get_identifier() != shares[0].get_identifier() {
+            assert!(Share::combine(&[parse(&shares[0]), parse(&others[1])]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let shares = Share::split(b"0123456789abcdef", 2, 3);
+        let phrase = shares[0].to_phrase();
+        let words: Vec<&str> = phrase.as_slice().words().collect();
+        assert!(Share::parse(phrase.as_slice()).is_some());
+
+        // Two words swapped, a word missing and a word that isn't in the list.
+        let mut swapped = words.clone();
+        swapped.as_mut_slice().swap(0, 1);
+        if swapped != words {
+            assert!(Share::parse(swapped.connect(" ").as_slice()).is_none());
+        }
+        assert!(Share::parse(words.slice_from(1).connect(" ").as_slice()).is_none());
+        assert!(Share::parse(format!("{} cykas", words.slice_from(1).connect(" ")).as_slice()).is_none());
+        assert!(Share::parse("").is_none());
+    }
+}
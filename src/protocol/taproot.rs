@@ -0,0 +1,330 @@
+//! Taproot (BIP 341) key tweaking, signature hashing, and key path spending.
+//!
+//! Cykas only creates taproot outputs with no script tree (as in BIP 86), but
+//! the functions here accept an optional script tree merkle root so that they
+//! can be checked against the BIP 341 test vectors.
+
+use util::bytes;
+use util::hash;
+use util::schnorr;
+use util::secp256k1;
+use util::secp256k1::{Point, Scalar};
+use util::secret;
+use protocol::private_key::PrivateKey;
+use protocol::transaction::{Transaction, TxOut};
+
+/// Signs all inputs and outputs. Uses a 64-byte signature with no sighash
+/// byte appended.
+pub static SIGHASH_DEFAULT: u8 = 0x00;
+/// Signs all inputs and outputs.
+pub static SIGHASH_ALL: u8 = 0x01;
+/// Signs all inputs and none of the outputs.
+pub static SIGHASH_NONE: u8 = 0x02;
+/// Signs all inputs and only the output with the same index as this input.
+pub static SIGHASH_SINGLE: u8 = 0x03;
+/// Combined with one of the above, signs only this input.
+pub static SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+// Epoch byte that starts every taproot signature message.
+static SIGHASH_EPOCH: u8 = 0x00;
+
+/// Computes the tweak `t = hash_TapTweak(P || merkle_root)` for a 32-byte
+/// x-only internal key.
+pub fn tweak(internal_key: &[u8], merkle_root: Option<&[u8]>) -> Vec<u8> {
+    let mut data = internal_key.to_vec();
+    match merkle_root {
+        Some(root) => data.push_all(root),
+        None => {}
+    }
+    hash::tagged_hash("TapTweak", data.as_slice())
+}
+
+/// Tweaks a 32-byte x-only internal public key, returning the x-only output
+/// key `Q = P + tG` that goes in the scriptPubKey. Returns None if the
+/// internal key is invalid or (with negligible probability) the tweak is.
+pub fn tweak_public_key(internal_key: &[u8], merkle_root: Option<&[u8]>) -> Option<Vec<u8>> {
+    let internal_point = match Point::lift_x(internal_key) {
+        Some(point) => point,
+        None => return None
+    };
+
+    let t = secp256k1::from_bytes(tweak(internal_key, merkle_root).as_slice());
+    if t >= secp256k1::curve_order() { return None; }
+
+    let output_point = internal_point.add(&Point::generator().mul(&t));
+    if output_point.is_infinity() { return None; }
+
+    Some(output_point.x_bytes())
+}
+
+/// Tweaks a private key so that it can sign for the output key that
+/// `tweak_public_key()` derives from its public key.
+pub fn tweak_private_key(private_key: &PrivateKey, merkle_root: Option<&[u8]>) -> Option<PrivateKey> {
+    // The internal key always has an even Y coordinate, so negate the private
+    // key if its public key doesn't.
+    let d0 = Scalar::from_bytes(private_key.get_data()).unwrap();
+    let internal_point = Point::generator().mul_secret(&d0);
+    let d = d0.negate_if(!internal_point.has_even_y());
+
+    let t = match Scalar::from_bytes(tweak(internal_point.x_bytes().as_slice(), merkle_root).as_slice()) {
+        Some(t) => t,
+        None => return None
+    };
+
+    let mut tweaked = d.add(&t).to_bytes();
+    let result = PrivateKey::new(tweaked.as_slice());
+    secret::wipe(tweaked.as_mut_slice());
+    result
+}
+
+/// Computes the BIP 341 signature hash for a key path spend of the input at
+/// `input_index`. `spent_outputs` must contain the output being spent by
+/// every input of the transaction, in order. Returns None if the hash type is
+/// invalid, or if it's SIGHASH_SINGLE and there is no corresponding output.
+pub fn sighash(tx: &Transaction, input_index: uint, spent_outputs: &[TxOut],
+               hash_type: u8) -> Option<Vec<u8>> {
+    if input_index >= tx.inputs.len() || spent_outputs.len() != tx.inputs.len() {
+        return None;
+    }
+
+    let valid_hash_type = hash_type <= 0x03 || (hash_type >= 0x81 && hash_type <= 0x83);
+    if !valid_hash_type { return None; }
+
+    let output_type = if hash_type == SIGHASH_DEFAULT { SIGHASH_ALL } else { hash_type & 0x03 };
+    let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+
+    let mut message = vec![SIGHASH_EPOCH, hash_type];
+    bytes::write_u32(&mut message, tx.version);
+    bytes::write_u32(&mut message, tx.lock_time);
+
+    if !anyone_can_pay {
+        let mut prevouts = Vec::new();
+        let mut amounts = Vec::new();
+        let mut script_pubkeys = Vec::new();
+        let mut sequences = Vec::new();
+
+        for (input, spent) in tx.inputs.iter().zip(spent_outputs.iter()) {
+            prevouts.push_all(input.previous_txid.as_slice());
+            bytes::write_u32(&mut prevouts, input.previous_index);
+            bytes::write_u64(&mut amounts, spent.value);
+            bytes::write_var_bytes(&mut script_pubkeys, spent.script_pubkey.as_slice());
+            bytes::write_u32(&mut sequences, input.sequence);
+        }
+
+        message.push_all(hash::sha256(prevouts.as_slice()).as_slice());
+        message.push_all(hash::sha256(amounts.as_slice()).as_slice());
+        message.push_all(hash::sha256(script_pubkeys.as_slice()).as_slice());
+        message.push_all(hash::sha256(sequences.as_slice()).as_slice());
+    }
+
+    if output_type == SIGHASH_ALL {
+        let mut outputs = Vec::new();
+        for output in tx.outputs.iter() {
+            outputs.push_all(output.serialize().as_slice());
+        }
+        message.push_all(hash::sha256(outputs.as_slice()).as_slice());
+    }
+
+    // The spend type: no annex, and a key path spend.
+    message.push(0x00);
+
+    if anyone_can_pay {
+        let input = &tx.inputs[input_index];
+        let spent = &spent_outputs[input_index];
+        message.push_all(input.previous_txid.as_slice());
+        bytes::write_u32(&mut message, input.previous_index);
+        message.push_all(spent.serialize().as_slice());
+        bytes::write_u32(&mut message, input.sequence);
+    } else {
+        bytes::write_u32(&mut message, input_index as u32);
+    }
+
+    if output_type == SIGHASH_SINGLE {
+        if input_index >= tx.outputs.len() { return None; }
+        let output = tx.outputs[input_index].serialize();
+        message.push_all(hash::sha256(output.as_slice()).as_slice());
+    }
+
+    Some(hash::tagged_hash("TapSighash", message.as_slice()))
+}
+
+/// Signs the input at `input_index` for a key path spend, returning the
+/// signature that makes up the input's entire witness. The private key is the
+/// untweaked internal key. `aux_rand` is 32 bytes of auxiliary randomness for
+/// the Schnorr signature. Returns None under the same conditions as
+/// `sighash()`.
+pub fn sign_key_path(tx: &Transaction, input_index: uint, spent_outputs: &[TxOut],
+                     private_key: &PrivateKey, merkle_root: Option<&[u8]>,
+                     hash_type: u8, aux_rand: &[u8]) -> Option<Vec<u8>> {
+    let sighash = match sighash(tx, input_index, spent_outputs, hash_type) {
+        Some(sighash) => sighash,
+        None => return None
+    };
+
+    let tweaked_key = match tweak_private_key(private_key, merkle_root) {
+        Some(key) => key,
+        None => return None
+    };
+
+    let mut signature = schnorr::sign(sighash.as_slice(), tweaked_key.get_data(), aux_rand);
+
+    // The sighash type is only appended when it isn't the default.
+    if hash_type != SIGHASH_DEFAULT {
+        signature.push(hash_type);
+    }
+
+    Some(signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::{ToHex, FromHex};
+
+    use protocol::private_key::PrivateKey;
+    use protocol::transaction::{Transaction, TxOut};
+
+    use super::{tweak, tweak_public_key, tweak_private_key, sighash, sign_key_path};
+
+    // The unsigned transaction and spent outputs from the BIP 341 key path
+    // spending test vectors.
+    static UNSIGNED_TX: &'static str =
+        "02000000097de20cbff686da83a54981d2b9bab3586f4ca7e48f57f5b5596311\
+         5f3b334e9c010000000000000000d7b7cab57b1393ace2d064f4d4a2cb8af6de\
+         f61273e127517d44759b6dafdd990000000000fffffffff8e1f5833843336892\
+         28c5d28eac13366be082dc57441760d957275419a418420000000000ffffffff\
+         f0689180aa63b30cb162a73c6d2a38b7eeda2a83ece74310fda0843ad604853b\
+         0100000000feffffffaa5202bdf6d8ccd2ee0f0202afbbb7461d9264a25e5bfd\
+         3c5a52ee1239e0ba6c0000000000feffffff956149bdc66faa968eb2be2d2faa\
+         29718acbfe3941215893a2a3446d32acd050000000000000000000e664b9773b\
+         88c09c32cb70a2a3e4da0ced63b7ba3b22f848531bbb1d5d5f4c940100000000\
+         00000000e9aa6b8e6c9de67619e6a3924ae25696bb7b694bb677a632a74ef7ea\
+         dfd4eabf0000000000ffffffffa778eb6a263dc090464cd125c466b5a9966772\
+         0b1c110468831d058aa1b82af10100000000ffffffff0200ca9a3b0000000019\
+         76a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac807840cb000000\
+         0020ac9a87f5594be208f8532db38cff670c450ed2fea8fcdefcc9a663f78bab\
+         962b0065cd1d";
+
+    static SPENT_OUTPUTS: &'static [(u64, &'static str)] = &[
+        (420000000, "512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343"),
+        (462000000, "5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3"),
+        (294000000, "76a914751e76e8199196d454941c45d1b3a323f1433bd688ac"),
+        (504000000, "5120e4d810fd50586274face62b8a807eb9719cef49c04177cc6b76a9a4251d5450e"),
+        (630000000, "512091b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605"),
+        (378000000, "00147dd65592d0ab2fe0d0257d571abf032cd9db93dc"),
+        (672000000, "512075169f4001aa68f15bbed28b218df1d0a62cbbcf1188c6665110c293c907b831"),
+        (546000000, "5120712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5"),
+        (588000000, "512077e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220")
+    ];
+
+    fn test_transaction() -> (Transaction, Vec<TxOut>) {
+        let tx = Transaction::parse(UNSIGNED_TX.from_hex().unwrap().as_slice()).unwrap();
+        let spent_outputs = SPENT_OUTPUTS.iter().map(|&(value, script_pubkey)| {
+            TxOut { value: value, script_pubkey: script_pubkey.from_hex().unwrap() }
+        }).collect();
+        (tx, spent_outputs)
+    }
+
+    // Checks one of the inputs from the BIP 341 key path spending test
+    // vectors, signed with all-zero auxiliary randomness.
+    fn check_input(input_index: uint, internal_private_key: &str, merkle_root: Option<&str>,
+                   hash_type: u8, expected_sighash: &str, expected_signature: &str) {
+        let (tx, spent_outputs) = test_transaction();
+        let private_key = PrivateKey::new(internal_private_key.from_hex().unwrap().as_slice()).unwrap();
+        let merkle_root = merkle_root.map(|root| root.from_hex().unwrap());
+        let merkle_root = merkle_root.as_ref().map(|root| root.as_slice());
+        let aux_rand = [0u8, ..32];
+
+        let actual_sighash = sighash(&tx, input_index, spent_outputs.as_slice(), hash_type).unwrap();
+        assert_eq!(actual_sighash.to_hex().as_slice(), expected_sighash);
+
+        let signature = sign_key_path(&tx, input_index, spent_outputs.as_slice(), &private_key,
+                                      merkle_root, hash_type, aux_rand.as_slice()).unwrap();
+        assert_eq!(signature.to_hex().as_slice(), expected_signature);
+    }
+
+    #[test]
+    fn test_tweak() {
+        let internal_key = "d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d";
+        let internal_key = internal_key.from_hex().unwrap();
+        assert_eq!(tweak(internal_key.as_slice(), None).to_hex().as_slice(),
+                   "b86e7be8f39bab32a6f2c0443abbc210f0edac0e2c53d501b36b64437d9c6c70");
+        assert_eq!(tweak_public_key(internal_key.as_slice(), None).unwrap().to_hex().as_slice(),
+                   "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343");
+    }
+
+    #[test]
+    fn test_tweak_with_merkle_root() {
+        let internal_key = "187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27";
+        let internal_key = internal_key.from_hex().unwrap();
+        let merkle_root = "5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21";
+        let merkle_root = merkle_root.from_hex().unwrap();
+        let output_key = tweak_public_key(internal_key.as_slice(), Some(merkle_root.as_slice()));
+        assert_eq!(output_key.unwrap().to_hex().as_slice(),
+                   "147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3");
+    }
+
+    #[test]
+    fn test_tweak_private_key() {
+        let data = "6b973d88838f27366ed61c9ad6367663045cb456e28335c109e30717ae0c6baa";
+        let private_key = PrivateKey::new(data.from_hex().unwrap().as_slice()).unwrap();
+        let tweaked = tweak_private_key(&private_key, None).unwrap();
+        assert_eq!(tweaked.get_data().to_hex().as_slice(),
+                   "2405b971772ad26915c8dcdf10f238753a9b837e5f8e6a86fd7c0cce5b7296d9");
+    }
+
+    #[test]
+    fn test_sign_single() {
+        check_input(0, "6b973d88838f27366ed61c9ad6367663045cb456e28335c109e30717ae0c6baa",
+                    None, 0x03,
+                    "2514a6272f85cfa0f45eb907fcb0d121b808ed37c6ea160a5a9046ed5526d555",
+                    "ed7c1647cb97379e76892be0cacff57ec4a7102aa24296ca39af7541246d8ff1\
+                     4d38958d4cc1e2e478e4d4a764bbfd835b16d4e314b72937b29833060b87276c03");
+    }
+
+    #[test]
+    fn test_sign_single_anyone_can_pay() {
+        check_input(1, "1e4da49f6aaf4e5cd175fe08a32bb5cb4863d963921255f33d3bc31e1343907f",
+                    Some("5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21"), 0x83,
+                    "325a644af47e8a5a2591cda0ab0723978537318f10e6a63d4eed783b96a71a4d",
+                    "052aedffc554b41f52b521071793a6b88d6dbca9dba94cf34c83696de0c1ec35\
+                     ca9c5ed4ab28059bd606a4f3a657eec0bb96661d42921b5f50a95ad33675b54f83");
+    }
+
+    #[test]
+    fn test_sign_all() {
+        check_input(3, "d3c7af07da2d54f7a7735d3d0fc4f0a73164db638b2f2f7c43f711f6d4aa7e64",
+                    Some("c525714a7f49c28aedbbba78c005931a81c234b2f6c99a73e4d06082adc8bf2b"), 0x01,
+                    "bf013ea93474aa67815b1b6cc441d23b64fa310911d991e713cd34c7f5d46669",
+                    "ff45f742a876139946a149ab4d9185574b98dc919d2eb6754f8abaa59d18b025\
+                     637a3aa043b91817739554f4ed2026cf8022dbd83e351ce1fabc272841d2510a01");
+    }
+
+    #[test]
+    fn test_sign_default() {
+        check_input(4, "f36bb07a11e469ce941d16b63b11b9b9120a84d9d87cff2c84a8d4affb438f4e",
+                    Some("ccbd66c6f7e8fdab47b3a486f59d28262be857f30d4773f2d5ea47f7761ce0e2"), 0x00,
+                    "4f900a0bae3f1446fd48490c2958b5a023228f01661cda3496a11da502a7f7ef",
+                    "b4010dd48a617db09926f729e79c33ae0b4e94b79f04a1ae93ede6315eb3669d\
+                     e185a17d2b0ac9ee09fd4c64b678a0b61a0a86fa888a273c8511be83bfd6810f");
+    }
+
+    #[test]
+    fn test_sign_none() {
+        check_input(6, "415cfe9c15d9cea27d8104d5517c06e9de48e2f986b695e4f5ffebf230e725d8",
+                    Some("2f6b2c5397b6d68ca18e09a3f05161668ffe93a988582d55c6f07bd5b3329def"), 0x02,
+                    "15f25c298eb5cdc7eb1d638dd2d45c97c4c59dcaec6679cfc16ad84f30876b85",
+                    "a3785919a2ce3c4ce26f298c3d51619bc474ae24014bcdd31328cd8cfbab2eff\
+                     3395fa0a16fe5f486d12f22a9cedded5ae74feb4bbe5351346508c5405bcfee002");
+    }
+
+    #[test]
+    fn test_sighash_invalid() {
+        let (tx, spent_outputs) = test_transaction();
+        // Invalid hash type.
+        assert!(sighash(&tx, 0, spent_outputs.as_slice(), 0x04).is_none());
+        // SIGHASH_SINGLE with no matching output.
+        assert!(sighash(&tx, 2, spent_outputs.as_slice(), 0x03).is_none());
+        // Missing spent outputs.
+        assert!(sighash(&tx, 0, spent_outputs.slice_to(8), 0x00).is_none());
+    }
+}
@@ -0,0 +1,265 @@
+//! Bitcoin transaction representation and serialization.
+
+use util::bytes;
+use util::bytes::Reader;
+use util::hash;
+
+// The marker and flag bytes that follow the version in a transaction
+// serialized with witness data (BIP 144).
+static WITNESS_MARKER: u8 = 0x00;
+static WITNESS_FLAG: u8 = 0x01;
+
+/// An input of a transaction, which spends an output of a previous one.
+#[deriving(Clone, PartialEq, Show)]
+pub struct TxIn {
+    /// The ID of the transaction being spent, in the byte order it's
+    /// serialized in (which is the reverse of how it's usually displayed).
+    pub previous_txid: Vec<u8>,
+    /// The index of the output being spent.
+    pub previous_index: u32,
+    pub script_sig: Vec<u8>,
+    pub sequence: u32,
+    /// The witness stack. Empty for inputs that don't spend SegWit outputs.
+    pub witness: Vec<Vec<u8>>
+}
+
+/// An output of a transaction.
+#[deriving(Clone, PartialEq, Show)]
+pub struct TxOut {
+    /// The amount, in satoshis.
+    pub value: u64,
+    pub script_pubkey: Vec<u8>
+}
+
+/// A Bitcoin transaction.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Transaction {
+    pub version: u32,
+    pub inputs: Vec<TxIn>,
+    pub outputs: Vec<TxOut>,
+    pub lock_time: u32
+}
+
+impl TxOut {
+    /// Parses a serialized output. Returns None if the data is invalid.
+    pub fn parse(data: &[u8]) -> Option<TxOut> {
+        let mut reader = Reader::new(data);
+        let output = TxOut::read(&mut reader);
+        if reader.is_empty() { output } else { None }
+    }
+
+    /// Serializes the output: the value followed by the scriptPubKey.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        self.write(&mut result);
+        result
+    }
+
+    fn read(reader: &mut Reader) -> Option<TxOut> {
+        let value = match reader.read_u64() { Some(n) => n, None => return None };
+        let script_pubkey = match reader.read_var_bytes() { Some(s) => s, None => return None };
+        Some(TxOut { value: value, script_pubkey: script_pubkey.to_vec() })
+    }
+
+    fn write(&self, output: &mut Vec<u8>) {
+        bytes::write_u64(output, self.value);
+        bytes::write_var_bytes(output, self.script_pubkey.as_slice());
+    }
+}
+
+impl Transaction {
+    /// Parses a serialized transaction, with or without witness data. Returns
+    /// None if the data is invalid.
+    pub fn parse(data: &[u8]) -> Option<Transaction> {
+        let mut reader = Reader::new(data);
+
+        let version = match reader.read_u32() { Some(n) => n, None => return None };
+
+        // A transaction with witness data has a zero byte where the input
+        // count would be, since a transaction can't have zero inputs.
+        let has_witness = reader.peek() == Some(WITNESS_MARKER);
+        if has_witness {
+            reader.read_u8();
+            if reader.read_u8() != Some(WITNESS_FLAG) { return None; }
+        }
+
+        let input_count = match reader.read_compact_size() { Some(n) => n, None => return None };
+        let mut inputs = Vec::new();
+        for _ in range(0, input_count) {
+            let previous_txid = match reader.read_bytes(32) { Some(t) => t, None => return None };
+            let previous_index = match reader.read_u32() { Some(n) => n, None => return None };
+            let script_sig = match reader.read_var_bytes() { Some(s) => s, None => return None };
+            let sequence = match reader.read_u32() { Some(n) => n, None => return None };
+
+            inputs.push(TxIn {
+                previous_txid: previous_txid.to_vec(),
+                previous_index: previous_index,
+                script_sig: script_sig.to_vec(),
+                sequence: sequence,
+                witness: Vec::new()
+            });
+        }
+
+        let output_count = match reader.read_compact_size() { Some(n) => n, None => return None };
+        let mut outputs = Vec::new();
+        for _ in range(0, output_count) {
+            match TxOut::read(&mut reader) {
+                Some(output) => outputs.push(output),
+                None => return None
+            }
+        }
+
+        if has_witness {
+            for input in inputs.iter_mut() {
+                let item_count = match reader.read_compact_size() { Some(n) => n, None => return None };
+                for _ in range(0, item_count) {
+                    match reader.read_var_bytes() {
+                        Some(item) => input.witness.push(item.to_vec()),
+                        None => return None
+                    }
+                }
+            }
+        }
+
+        let lock_time = match reader.read_u32() { Some(n) => n, None => return None };
+
+        if !reader.is_empty() { return None; }
+
+        Some(Transaction {
+            version: version,
+            inputs: inputs,
+            outputs: outputs,
+            lock_time: lock_time
+        })
+    }
+
+    /// Serializes the transaction, including witness data if any input has
+    /// some.
+    pub fn serialize(&self) -> Vec<u8> {
+        let has_witness = self.inputs.iter().any(|input| !input.witness.is_empty());
+        self.serialize_with(has_witness)
+    }
+
+    /// Serializes the transaction in the original format without witness
+    /// data. This is what the transaction ID is a hash of.
+    pub fn serialize_without_witness(&self) -> Vec<u8> {
+        self.serialize_with(false)
+    }
+
+    /// Computes the transaction ID, in serialized byte order.
+    pub fn txid(&self) -> Vec<u8> {
+        hash::double_sha256(self.serialize_without_witness().as_slice())
+    }
+
+    fn serialize_with(&self, include_witness: bool) -> Vec<u8> {
+        let mut result = Vec::new();
+
+        bytes::write_u32(&mut result, self.version);
+
+        if include_witness {
+            result.push(WITNESS_MARKER);
+            result.push(WITNESS_FLAG);
+        }
+
+        bytes::write_compact_size(&mut result, self.inputs.len() as u64);
+        for input in self.inputs.iter() {
+            result.push_all(input.previous_txid.as_slice());
+            bytes::write_u32(&mut result, input.previous_index);
+            bytes::write_var_bytes(&mut result, input.script_sig.as_slice());
+            bytes::write_u32(&mut result, input.sequence);
+        }
+
+        bytes::write_compact_size(&mut result, self.outputs.len() as u64);
+        for output in self.outputs.iter() {
+            output.write(&mut result);
+        }
+
+        if include_witness {
+            for input in self.inputs.iter() {
+                bytes::write_compact_size(&mut result, input.witness.len() as u64);
+                for item in input.witness.iter() {
+                    bytes::write_var_bytes(&mut result, item.as_slice());
+                }
+            }
+        }
+
+        bytes::write_u32(&mut result, self.lock_time);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::{ToHex, FromHex};
+
+    use super::{Transaction, TxOut};
+
+    // The first (unsigned) transaction from the BIP 341 key path spending test
+    // vectors. It has nine inputs and two outputs.
+    static BIP341_UNSIGNED_TX: &'static str =
+        "02000000097de20cbff686da83a54981d2b9bab3586f4ca7e48f57f5b5596311\
+         5f3b334e9c010000000000000000d7b7cab57b1393ace2d064f4d4a2cb8af6de\
+         f61273e127517d44759b6dafdd990000000000fffffffff8e1f5833843336892\
+         28c5d28eac13366be082dc57441760d957275419a418420000000000ffffffff\
+         f0689180aa63b30cb162a73c6d2a38b7eeda2a83ece74310fda0843ad604853b\
+         0100000000feffffffaa5202bdf6d8ccd2ee0f0202afbbb7461d9264a25e5bfd\
+         3c5a52ee1239e0ba6c0000000000feffffff956149bdc66faa968eb2be2d2faa\
+         29718acbfe3941215893a2a3446d32acd050000000000000000000e664b9773b\
+         88c09c32cb70a2a3e4da0ced63b7ba3b22f848531bbb1d5d5f4c940100000000\
+         00000000e9aa6b8e6c9de67619e6a3924ae25696bb7b694bb677a632a74ef7ea\
+         dfd4eabf0000000000ffffffffa778eb6a263dc090464cd125c466b5a9966772\
+         0b1c110468831d058aa1b82af10100000000ffffffff0200ca9a3b0000000019\
+         76a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac807840cb000000\
+         0020ac9a87f5594be208f8532db38cff670c450ed2fea8fcdefcc9a663f78bab\
+         962b0065cd1d";
+
+    #[test]
+    fn test_parse_serialize() {
+        let data = BIP341_UNSIGNED_TX.from_hex().unwrap();
+        let tx = Transaction::parse(data.as_slice()).unwrap();
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.inputs.len(), 9);
+        assert_eq!(tx.outputs.len(), 2);
+        assert_eq!(tx.lock_time, 0x1dcd6500);
+        assert_eq!(tx.inputs[1].previous_index, 0);
+        assert_eq!(tx.inputs[1].sequence, 0xffffffff);
+        assert_eq!(tx.outputs[0].value, 1000000000);
+        assert_eq!(tx.serialize(), data);
+    }
+
+    #[test]
+    fn test_parse_serialize_witness() {
+        let data = BIP341_UNSIGNED_TX.from_hex().unwrap();
+        let mut tx = Transaction::parse(data.as_slice()).unwrap();
+        for input in tx.inputs.iter_mut() {
+            input.witness = vec![vec![0x01, 0x02], vec![]];
+        }
+
+        let serialized = tx.serialize();
+        assert_eq!(serialized.slice(4, 6), [0x00u8, 0x01].as_slice());
+        assert_eq!(Transaction::parse(serialized.as_slice()), Some(tx.clone()));
+        assert_eq!(tx.serialize_without_witness(), data);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let data = BIP341_UNSIGNED_TX.from_hex().unwrap();
+        assert!(Transaction::parse(data.slice_to(data.len() - 1)).is_none());
+
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert!(Transaction::parse(trailing.as_slice()).is_none());
+    }
+
+    #[test]
+    fn test_tx_out() {
+        let data = "00CA9A3B000000001976A91406AFD46BCDFD22EF94AC122AA11F241244A37ECC88AC";
+        let data = data.from_hex().unwrap();
+        let output = TxOut::parse(data.as_slice()).unwrap();
+        assert_eq!(output.value, 1000000000);
+        assert_eq!(output.script_pubkey.to_hex().as_slice(),
+                   "76a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac");
+        assert_eq!(output.serialize(), data);
+    }
+}
@@ -0,0 +1,292 @@
+//! Bech32 and bech32m encoding and decoding, as used by native SegWit
+//! addresses (BIP 173 and BIP 350).
+
+// The bech32 alphabet, mapping 5-bit values to characters.
+static CHARSET: &'static str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+// Generator coefficients of the BCH code used for the checksum.
+static GENERATOR: [u32, ..5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+// The constants the checksum polymod must equal for each variant.
+static BECH32_CONST: u32 = 1;
+static BECH32M_CONST: u32 = 0x2bc830a3;
+
+// The length of the checksum, in 5-bit characters.
+static CHECKSUM_LENGTH: uint = 6;
+
+// Strings longer than this are rejected, as specified by BIP 173.
+static MAX_LENGTH: uint = 90;
+
+/// The two checksum variants. Witness version 0 addresses use bech32, and all
+/// later versions (including taproot) use bech32m.
+#[deriving(Clone, Copy, PartialEq, Show)]
+pub enum Variant {
+    Bech32,
+    Bech32m
+}
+
+impl Variant {
+    fn constant(&self) -> u32 {
+        match *self {
+            Variant::Bech32 => BECH32_CONST,
+            Variant::Bech32m => BECH32M_CONST
+        }
+    }
+}
+
+/// Encodes a human-readable part and a slice of 5-bit values as a bech32 or
+/// bech32m string.
+pub fn encode(hrp: &str, data: &[u8], variant: Variant) -> String {
+    let checksum = create_checksum(hrp, data, variant);
+
+    let mut result = String::with_capacity(hrp.len() + 1 + data.len() + CHECKSUM_LENGTH);
+    result.push_str(hrp);
+    result.push('1');
+    for value in data.iter().chain(checksum.iter()) {
+        result.push(CHARSET.char_at(*value as uint));
+    }
+    result
+}
+
+/// Decodes a bech32 or bech32m string into its human-readable part, its 5-bit
+/// data values (without the checksum), and the checksum variant it used.
+/// Returns None if the string is malformed or the checksum is wrong.
+pub fn decode(string: &str) -> Option<(String, Vec<u8>, Variant)> {
+    if string.len() > MAX_LENGTH { return None; }
+
+    // Mixed case isn't allowed, but all-uppercase is.
+    let has_lower = string.chars().any(|ch| ch.is_lowercase());
+    let has_upper = string.chars().any(|ch| ch.is_uppercase());
+    if has_lower && has_upper { return None; }
+    let string: String = string.chars().map(|ch| ch.to_lowercase()).collect();
+
+    // The separator is the last '1' character, since '1' can appear in the
+    // human-readable part but not in the data part.
+    let separator = match string.as_slice().rfind('1') {
+        Some(pos) => pos,
+        None => return None
+    };
+    if separator == 0 || separator + 1 + CHECKSUM_LENGTH > string.len() {
+        return None;
+    }
+
+    let hrp = string.as_slice().slice_to(separator);
+    if hrp.chars().any(|ch| (ch as u32) < 33 || (ch as u32) > 126) {
+        return None;
+    }
+
+    let mut values = Vec::with_capacity(string.len() - separator - 1);
+    for ch in string.as_slice().slice_from(separator + 1).chars() {
+        match CHARSET.chars().position(|c| c == ch) {
+            Some(value) => values.push(value as u8),
+            None => return None
+        }
+    }
+
+    let checksum = polymod(hrp_expand(hrp).iter().chain(values.iter()));
+    let variant = if checksum == BECH32_CONST {
+        Variant::Bech32
+    } else if checksum == BECH32M_CONST {
+        Variant::Bech32m
+    } else {
+        return None;
+    };
+
+    let data_length = values.len() - CHECKSUM_LENGTH;
+    values.truncate(data_length);
+
+    Some((hrp.to_string(), values, variant))
+}
+
+/// Encodes a SegWit address with the given human-readable part ("bc" for
+/// mainnet), witness version, and witness program. The checksum variant is
+/// chosen based on the witness version.
+pub fn encode_segwit_address(hrp: &str, version: u8, program: &[u8]) -> String {
+    let variant = if version == 0 { Variant::Bech32 } else { Variant::Bech32m };
+
+    let mut data = vec![version];
+    data.push_all(convert_bits(program, 8, 5, true).unwrap().as_slice());
+
+    encode(hrp, data.as_slice(), variant)
+}
+
+/// Decodes a SegWit address with the expected human-readable part, returning
+/// the witness version and witness program. Returns None if the address is
+/// invalid, including if it uses the wrong checksum variant for its version.
+pub fn decode_segwit_address(hrp: &str, address: &str) -> Option<(u8, Vec<u8>)> {
+    let (decoded_hrp, data, variant) = match decode(address) {
+        Some(decoded) => decoded,
+        None => return None
+    };
+
+    if decoded_hrp.as_slice() != hrp || data.is_empty() { return None; }
+
+    let version = data[0];
+    if version > 16 { return None; }
+
+    let expected_variant = if version == 0 { Variant::Bech32 } else { Variant::Bech32m };
+    if variant != expected_variant { return None; }
+
+    let program = match convert_bits(data.slice_from(1), 5, 8, false) {
+        Some(program) => program,
+        None => return None
+    };
+
+    if program.len() < 2 || program.len() > 40 { return None; }
+    if version == 0 && program.len() != 20 && program.len() != 32 { return None; }
+
+    Some((version, program))
+}
+
+/// Regroups a slice of `from`-bit values into `to`-bit values. When `pad` is
+/// true, leftover bits are padded with zeroes; otherwise they must be zero
+/// and fewer than `from` bits, or None is returned.
+pub fn convert_bits(data: &[u8], from: uint, to: uint, pad: bool) -> Option<Vec<u8>> {
+    let mut accumulator = 0u32;
+    let mut bits = 0u;
+    let max_value = (1u32 << to) - 1;
+    let max_accumulator = (1u32 << (from + to - 1)) - 1;
+    let mut result = Vec::new();
+
+    for value in data.iter() {
+        if (*value as u32) >> from != 0 { return None; }
+        accumulator = ((accumulator << from) | (*value as u32)) & max_accumulator;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            result.push(((accumulator >> bits) & max_value) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            result.push(((accumulator << (to - bits)) & max_value) as u8);
+        }
+    } else if bits >= from || ((accumulator << (to - bits)) & max_value) != 0 {
+        return None;
+    }
+
+    Some(result)
+}
+
+// Computes the 6-value checksum for the given human-readable part and data.
+fn create_checksum(hrp: &str, data: &[u8], variant: Variant) -> Vec<u8> {
+    let zeroes = [0u8, ..6];
+    let expanded = hrp_expand(hrp);
+    let checksum_input = expanded.iter().chain(data.iter()).chain(zeroes.iter());
+    let polymod = polymod(checksum_input) ^ variant.constant();
+
+    range(0, CHECKSUM_LENGTH).map(|i| {
+        ((polymod >> (5 * (5 - i))) & 31) as u8
+    }).collect()
+}
+
+// Expands the human-readable part into values for the checksum computation.
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut result = Vec::with_capacity(hrp.len() * 2 + 1);
+    for byte in hrp.bytes() {
+        result.push(byte >> 5);
+    }
+    result.push(0);
+    for byte in hrp.bytes() {
+        result.push(byte & 31);
+    }
+    result
+}
+
+// The BCH checksum function from BIP 173.
+fn polymod<'a, I: Iterator<&'a u8>>(mut values: I) -> u32 {
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ (*value as u32);
+        for i in range(0, 5u) {
+            if (top >> i) & 1 == 1 {
+                checksum ^= GENERATOR[i];
+            }
+        }
+    }
+    checksum
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::{ToHex, FromHex};
+
+    use super::{Variant, encode, decode, encode_segwit_address, decode_segwit_address};
+
+    #[test]
+    fn test_decode_valid() {
+        let (hrp, data, variant) = decode("A12UEL5L").unwrap();
+        assert_eq!(hrp.as_slice(), "a");
+        assert!(data.is_empty());
+        assert_eq!(variant, Variant::Bech32);
+
+        let (hrp, _, variant) = decode("a1lqfn3a").unwrap();
+        assert_eq!(hrp.as_slice(), "a");
+        assert_eq!(variant, Variant::Bech32m);
+
+        let (hrp, _, variant) = decode("abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx").unwrap();
+        assert_eq!(hrp.as_slice(), "abcdef");
+        assert_eq!(variant, Variant::Bech32m);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // Invalid checksum.
+        assert!(decode("a1lqfn3q").is_none());
+        // Mixed case.
+        assert!(decode("A1lqfn3a").is_none());
+        // Empty human-readable part.
+        assert!(decode("1qzzfhee").is_none());
+        // No separator.
+        assert!(decode("pzry9x0s0muk").is_none());
+        // Invalid data character.
+        assert!(decode("x1b4n0q5v").is_none());
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("a", &[], Variant::Bech32).as_slice(), "a12uel5l");
+        assert_eq!(encode("a", &[], Variant::Bech32m).as_slice(), "a1lqfn3a");
+    }
+
+    #[test]
+    fn test_encode_segwit_address() {
+        let program = "751E76E8199196D454941C45D1B3A323F1433BD6".from_hex().unwrap();
+        let address = encode_segwit_address("bc", 0, program.as_slice());
+        assert_eq!(address.as_slice(), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+
+        let program = "79BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798";
+        let program = program.from_hex().unwrap();
+        let address = encode_segwit_address("bc", 1, program.as_slice());
+        assert_eq!(address.as_slice(), "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0");
+    }
+
+    #[test]
+    fn test_decode_segwit_address() {
+        let (version, program) =
+            decode_segwit_address("bc", "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4").unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(program.to_hex().as_slice(), "751e76e8199196d454941c45d1b3a323f1433bd6");
+
+        let (version, program) = decode_segwit_address(
+            "bc", "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0").unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(program.to_hex().as_slice(),
+                   "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+    }
+
+    #[test]
+    fn test_decode_segwit_address_invalid() {
+        // Wrong human-readable part.
+        assert!(decode_segwit_address("bc", "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").is_none());
+        // Version 1 with a bech32 (instead of bech32m) checksum.
+        assert!(decode_segwit_address(
+            "bc", "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd").is_none());
+        // Version 0 with a bech32m (instead of bech32) checksum.
+        assert!(decode_segwit_address("bc", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh").is_none());
+        // Invalid program length for version 0.
+        assert!(decode_segwit_address("bc", "BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P").is_none());
+    }
+}
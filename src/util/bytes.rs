@@ -0,0 +1,192 @@
+//! Helpers for reading and writing the little-endian integers and
+//! variable-length fields that Bitcoin's binary formats are made of, and the
+//! bit fields that word lists encode data in.
+
+/// Appends a 32-bit unsigned integer in little-endian order.
+pub fn write_u32(output: &mut Vec<u8>, n: u32) {
+    for i in range(0, 4u) {
+        output.push((n >> (8 * i)) as u8);
+    }
+}
+
+/// Appends a 64-bit unsigned integer in little-endian order.
+pub fn write_u64(output: &mut Vec<u8>, n: u64) {
+    for i in range(0, 8u) {
+        output.push((n >> (8 * i)) as u8);
+    }
+}
+
+/// Appends a "CompactSize" unsigned integer, which takes 1, 3, 5 or 9 bytes
+/// depending on how big it is.
+pub fn write_compact_size(output: &mut Vec<u8>, n: u64) {
+    if n < 0xfd {
+        output.push(n as u8);
+    } else if n <= 0xffff {
+        output.push(0xfd);
+        output.push(n as u8);
+        output.push((n >> 8) as u8);
+    } else if n <= 0xffffffff {
+        output.push(0xfe);
+        write_u32(output, n as u32);
+    } else {
+        output.push(0xff);
+        write_u64(output, n);
+    }
+}
+
+/// Appends a byte string preceded by its length as a CompactSize.
+pub fn write_var_bytes(output: &mut Vec<u8>, data: &[u8]) {
+    write_compact_size(output, data.len() as u64);
+    output.push_all(data);
+}
+
+/// Reads the fields of a binary format from a slice of bytes, front to back.
+/// Every read returns None if there isn't enough data left.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    position: uint
+}
+
+impl<'a> Reader<'a> {
+    /// Creates a Reader positioned at the start of the given data.
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data: data, position: 0 }
+    }
+
+    /// Checks whether all of the data has been read.
+    pub fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    /// Returns the next byte without consuming it.
+    pub fn peek(&self) -> Option<u8> {
+        if self.is_empty() { None } else { Some(self.data[self.position]) }
+    }
+
+    /// Reads the next `n` bytes.
+    pub fn read_bytes(&mut self, n: uint) -> Option<&'a [u8]> {
+        if self.data.len() - self.position < n { return None; }
+
+        let result = self.data.slice(self.position, self.position + n);
+        self.position += n;
+        Some(result)
+    }
+
+    /// Reads a single byte.
+    pub fn read_u8(&mut self) -> Option<u8> {
+        self.read_bytes(1).map(|bytes| bytes[0])
+    }
+
+    /// Reads a 32-bit unsigned little-endian integer.
+    pub fn read_u32(&mut self) -> Option<u32> {
+        self.read_bytes(4).map(|bytes| {
+            bytes.iter().rev().fold(0u32, |n, byte| (n << 8) | (*byte as u32))
+        })
+    }
+
+    /// Reads a 64-bit unsigned little-endian integer.
+    pub fn read_u64(&mut self) -> Option<u64> {
+        self.read_bytes(8).map(|bytes| {
+            bytes.iter().rev().fold(0u64, |n, byte| (n << 8) | (*byte as u64))
+        })
+    }
+
+    /// Reads a CompactSize unsigned integer.
+    pub fn read_compact_size(&mut self) -> Option<u64> {
+        match self.read_u8() {
+            Some(0xfd) => {
+                self.read_bytes(2).map(|bytes| (bytes[0] as u64) | ((bytes[1] as u64) << 8))
+            }
+            Some(0xfe) => self.read_u32().map(|n| n as u64),
+            Some(0xff) => self.read_u64(),
+            Some(n) => Some(n as u64),
+            None => None
+        }
+    }
+
+    /// Reads a byte string preceded by its length as a CompactSize.
+    pub fn read_var_bytes(&mut self) -> Option<&'a [u8]> {
+        match self.read_compact_size() {
+            Some(length) => self.read_bytes(length as uint),
+            None => None
+        }
+    }
+}
+
+/// Reads `count` bits starting at bit `start` of the data, most significant
+/// bit first.
+pub fn read_bits(data: &[u8], start: uint, count: uint) -> uint {
+    let mut result = 0u;
+    for bit in range(start, start + count) {
+        let value = (data[bit / 8] >> (7 - bit % 8)) & 1;
+        result = (result << 1) | (value as uint);
+    }
+    result
+}
+
+/// Writes the lowest `count` bits of `value` starting at bit `start` of the
+/// data, most significant bit first.
+pub fn write_bits(data: &mut [u8], start: uint, count: uint, value: uint) {
+    for i in range(0, count) {
+        let bit = start + i;
+        if (value >> (count - 1 - i)) & 1 == 1 {
+            data[bit / 8] |= 1 << (7 - bit % 8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reader, write_u32, write_u64, write_compact_size, write_var_bytes, read_bits, write_bits};
+
+    #[test]
+    fn test_write_integers() {
+        let mut output = Vec::new();
+        write_u32(&mut output, 0x01020304);
+        write_u64(&mut output, 0x05);
+        assert_eq!(output.as_slice(), [4u8, 3, 2, 1, 5, 0, 0, 0, 0, 0, 0, 0].as_slice());
+    }
+
+    #[test]
+    fn test_write_compact_size() {
+        let mut output = Vec::new();
+        write_compact_size(&mut output, 0xfc);
+        write_compact_size(&mut output, 0xfd);
+        write_compact_size(&mut output, 0x10000);
+        assert_eq!(output.as_slice(), [0xfcu8, 0xfd, 0xfd, 0x00, 0xfe, 0x00, 0x00, 0x01, 0x00].as_slice());
+    }
+
+    #[test]
+    fn test_reader() {
+        let mut output = Vec::new();
+        write_u32(&mut output, 0xdeadbeef);
+        write_u64(&mut output, 0x0123456789abcdef);
+        write_compact_size(&mut output, 300);
+        write_var_bytes(&mut output, b"abc");
+
+        let mut reader = Reader::new(output.as_slice());
+        assert_eq!(reader.read_u32(), Some(0xdeadbeef));
+        assert_eq!(reader.read_u64(), Some(0x0123456789abcdef));
+        assert_eq!(reader.read_compact_size(), Some(300));
+        assert_eq!(reader.peek(), Some(3));
+        assert_eq!(reader.read_var_bytes(), Some(b"abc".as_slice()));
+        assert!(reader.is_empty());
+        assert_eq!(reader.read_u8(), None);
+    }
+
+    #[test]
+    fn test_reader_not_enough_data() {
+        let data = [0xfdu8, 0x01];
+        let mut reader = Reader::new(data.as_slice());
+        assert_eq!(reader.read_compact_size(), None);
+    }
+
+    #[test]
+    fn test_bits() {
+        let mut data = [0u8, ..3];
+        write_bits(data.as_mut_slice(), 3, 11, 0x5A5);
+        assert_eq!(data.as_slice(), [0x16u8, 0x94, 0x00].as_slice());
+        assert_eq!(read_bits(data.as_slice(), 3, 11), 0x5A5);
+        assert_eq!(read_bits(data.as_slice(), 0, 4), 0x1);
+    }
+}
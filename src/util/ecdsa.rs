@@ -1,100 +1,206 @@
 //! Functions that work with elliptic curve keys and signatures.
 
-use libc::{c_int, c_uchar, size_t};
-use std::ptr;
-
-// OpenSSL's numeric code for the particular elliptic curve that Bitcoin uses.
-#[allow(non_upper_case_globals)]
-static NID_secp256k1: int = 714;
-
-#[allow(non_camel_case_types)]
-#[repr(C)]
-struct EC_GROUP;
-
-#[allow(non_camel_case_types)]
-#[repr(C)]
-struct EC_POINT;
-
-#[allow(non_camel_case_types)]
-#[repr(C)]
-struct BIGNUM;
-
-#[allow(non_camel_case_types)]
-#[repr(C)]
-struct BN_CTX;
-
-#[allow(non_camel_case_types)]
-#[allow(dead_code)]
-#[repr(C)]
-enum point_conversion_form_t {
-    POINT_CONVERSION_COMPRESSED = 2,
-    POINT_CONVERSION_UNCOMPRESSED = 4,
-    POINT_CONVERSION_HYBRID = 6
+use num::Zero;
+use num::bigint::BigUint;
+
+use util::hash;
+use util::secp256k1;
+use util::secp256k1::{Point, Scalar};
+
+// The tags of the DER types that make up a signature.
+static DER_SEQUENCE: u8 = 0x30;
+static DER_INTEGER: u8 = 0x02;
+
+/// An implementation of the secp256k1 operations that need a private key.
+/// Cykas uses `NativeBackend`, which is plain Rust. With the `openssl-ec`
+/// feature, `util::openssl_ec::OpensslBackend` does the same through
+/// OpenSSL, and the two are tested against each other.
+pub trait Backend {
+    /// Derives the 65-byte uncompressed public key from a 32-byte private
+    /// key. Returns None if the private key isn't valid, or if the backend
+    /// fails.
+    fn derive_public_key(&self, private_key: &[u8]) -> Option<Vec<u8>>;
 }
 
-#[link(name = "crypto")]
-extern {
-    fn EC_POINT_new(group: *const EC_GROUP) -> *mut EC_POINT;
-    fn EC_POINT_free(point: *mut EC_POINT);
-    fn EC_POINT_mul(group: *const EC_GROUP,
-                    r: *mut EC_POINT,
-                    n: *const BIGNUM,
-                    q: *const EC_POINT,
-                    m: *const BIGNUM,
-                    ctx: *mut BN_CTX) -> c_int;
-    fn EC_POINT_point2oct(group: *const EC_GROUP,
-                          p: *const EC_POINT,
-                          form: point_conversion_form_t,
-                          buf: *mut c_uchar,
-                          len: size_t,
-                          ctx: *mut BN_CTX) -> size_t;
-
-    fn EC_GROUP_new_by_curve_name(nid: c_int) -> *mut EC_GROUP;
-
-    fn BN_new() -> *mut BIGNUM;
-    fn BN_free(a: *mut BIGNUM);
-    fn BN_bin2bn(s: *const c_uchar, len: c_int, ret: *mut BIGNUM) -> *mut BIGNUM;
-
-    fn BN_CTX_new() -> *mut BN_CTX;
-    fn BN_CTX_free(c: *mut BN_CTX);
+/// The pure Rust backend, built on `util::secp256k1`.
+pub struct NativeBackend;
+
+impl Backend for NativeBackend {
+    fn derive_public_key(&self, private_key: &[u8]) -> Option<Vec<u8>> {
+        if !is_valid_private_key(private_key) { return None; }
+
+        let d = Scalar::from_bytes(private_key).unwrap();
+        Some(Point::generator().mul_secret(&d).to_uncompressed())
+    }
+}
+
+/// Checks that a private key is 32 bytes long and, as a number, is between 1
+/// and the curve order minus one.
+pub fn is_valid_private_key(private_key: &[u8]) -> bool {
+    match Scalar::from_bytes(private_key) {
+        Some(d) => !d.is_zero(),
+        None => false
+    }
 }
 
 /// Takes a 32-byte Bitcoin private key, and derives the 65-byte uncompressed
-/// public key from it. Assumes the private key is valid, i.e. is 32 bytes long
-/// and falls within the range defined in `src/protocol/private_key.rs`.
+/// public key from it. Fails if the private key isn't valid, i.e. isn't 32
+/// bytes long or doesn't fall within the range defined in
+/// `src/protocol/private_key.rs`.
 pub fn derive_public_key(private_key: &[u8]) -> Vec<u8> {
-    assert!(private_key.len() == 32u);
-    unsafe {
-        // Convert private key to OpenSSL's bignum type.
-        let priv_key = BN_bin2bn(private_key.as_ptr(), private_key.len() as c_int, BN_new());
+    NativeBackend.derive_public_key(private_key).expect("Invalid private key")
+}
+
+/// Signs a 32-byte message hash with a 32-byte private key, and returns the
+/// DER-encoded signature. The nonce is derived from the key and the message
+/// as in RFC 6979, so signing never depends on a random number generator,
+/// and `s` is always in the lower half of its range, as Bitcoin's
+/// standardness rules require. Assumes the private key is valid.
+pub fn sign(message: &[u8], private_key: &[u8]) -> Vec<u8> {
+    assert_eq!(message.len(), 32);
+    assert_eq!(private_key.len(), 32);
+
+    let d = Scalar::reduce(private_key);
+    let z = Scalar::reduce(message);
+
+    // RFC 6979, section 3.2, with HMAC-SHA256. The message is reduced modulo
+    // n before it goes into the HMAC.
+    let reduced_message = z.to_bytes();
+    let mut v = Vec::from_elem(32, 0x01u8);
+    let mut k = Vec::from_elem(32, 0x00u8);
+    for &separator in [0x00u8, 0x01].iter() {
+        let mut data = v.clone();
+        data.push(separator);
+        data.push_all(private_key);
+        data.push_all(reduced_message.as_slice());
+        k = hash::hmac_sha256(k.as_slice(), data.as_slice());
+        v = hash::hmac_sha256(k.as_slice(), v.as_slice());
+    }
+
+    loop {
+        v = hash::hmac_sha256(k.as_slice(), v.as_slice());
+        match Scalar::from_bytes(v.as_slice()) {
+            Some(ref nonce) if !nonce.is_zero() => {
+                let r_point = Point::generator().mul_secret(nonce);
+                let r = Scalar::reduce(r_point.x_bytes().as_slice());
+                let s = nonce.inverse().mul(&z.add(&r.mul(&d)));
+
+                if !r.is_zero() && !s.is_zero() {
+                    let high = s.is_high();
+                    return encode_der(&r, &s.negate_if(high));
+                }
+            },
+            _ => {}
+        }
+
+        // Try the next nonce. This is astronomically unlikely to happen.
+        let mut data = v.clone();
+        data.push(0x00);
+        k = hash::hmac_sha256(k.as_slice(), data.as_slice());
+        v = hash::hmac_sha256(k.as_slice(), v.as_slice());
+    }
+}
+
+/// Verifies a DER-encoded signature of a 32-byte message hash against a
+/// public key in compressed or uncompressed format. Returns false for
+/// malformed keys and signatures as well as for signatures that don't match.
+pub fn verify(message: &[u8], public_key: &[u8], signature: &[u8]) -> bool {
+    if message.len() != 32 { return false; }
+
+    let public_point = match Point::from_bytes(public_key) {
+        Some(point) => point,
+        None => return false
+    };
+
+    let (r, s) = match decode_der(signature) {
+        Some(values) => values,
+        None => return false
+    };
+
+    let n = secp256k1::curve_order();
+    if r.is_zero() || s.is_zero() || r >= n || s >= n { return false; }
+
+    // R = (z/s)*G + (r/s)*P, and the signature is valid if R's X coordinate
+    // is r.
+    let z = secp256k1::from_bytes(message);
+    let w = secp256k1::mod_inverse(&s, &n);
+    let u1 = (z * w) % n;
+    let u2 = (r * w) % n;
+    let r_point = Point::generator().mul(&u1).add(&public_point.mul(&u2));
+
+    !r_point.is_infinity() &&
+    secp256k1::from_bytes(r_point.x_bytes().as_slice()) % n == r
+}
 
-        // Initialize the secp256k1 elliptic curve used by Bitcoin.
-        let curve = EC_GROUP_new_by_curve_name(NID_secp256k1 as c_int) as *const EC_GROUP;
+// Encodes a signature in DER, as Bitcoin requires: a sequence of the two
+// integers r and s.
+fn encode_der(r: &Scalar, s: &Scalar) -> Vec<u8> {
+    let r = encode_der_integer(r);
+    let s = encode_der_integer(s);
 
-        // Init a context for doing bignum stuff.
-        let ctx = BN_CTX_new();
+    let mut result = vec![DER_SEQUENCE, (r.len() + s.len()) as u8];
+    result.push_all(r.as_slice());
+    result.push_all(s.as_slice());
+    result
+}
 
-        // Use elliptic curve point multiplication to derive the public key.
-        let pub_key = EC_POINT_new(curve);
-        EC_POINT_mul(curve, pub_key, priv_key as *const BIGNUM, ptr::null(), ptr::null(), ctx);
+// Encodes a positive integer in DER, in as few bytes as possible. There has
+// to be a zero byte in front if the first bit would otherwise be set, so
+// that it isn't read as negative.
+fn encode_der_integer(n: &Scalar) -> Vec<u8> {
+    let bytes = n.to_bytes();
+    let mut start = 0;
+    while start < bytes.len() - 1 && bytes[start] == 0 {
+        start += 1;
+    }
 
-        // Convert public key point to the actual key, in uncompressed format.
-        let mut result = Vec::from_elem(65, 0u8);
-        EC_POINT_point2oct(curve, pub_key as *const EC_POINT, point_conversion_form_t::POINT_CONVERSION_UNCOMPRESSED, result.as_mut_ptr(), 65, ctx);
-        *result.index_mut(&0) = 0x04;
+    let mut value = Vec::with_capacity(33);
+    if bytes[start] & 0x80 != 0 { value.push(0); }
+    value.push_all(bytes.slice_from(start));
 
-        // Free the allocated resources.
-        BN_CTX_free(ctx);
-        EC_POINT_free(pub_key);
-        BN_free(priv_key);
+    let mut result = vec![DER_INTEGER, value.len() as u8];
+    result.push_all(value.as_slice());
+    result
+}
 
-        result
+// Decodes a DER-encoded signature into r and s. Returns None unless it's
+// strictly encoded, as BIP 66 requires.
+fn decode_der(signature: &[u8]) -> Option<(BigUint, BigUint)> {
+    if signature.len() < 8 || signature[0] != DER_SEQUENCE ||
+       signature[1] as uint != signature.len() - 2 {
+        return None;
     }
+
+    let mut rest = signature.slice_from(2);
+    let mut values = Vec::with_capacity(2);
+    for _ in range(0, 2u) {
+        if rest.len() < 3 || rest[0] != DER_INTEGER { return None; }
+
+        let length = rest[1] as uint;
+        if length == 0 || length > 33 || rest.len() < 2 + length { return None; }
+
+        let value = rest.slice(2, 2 + length);
+        // Negative numbers aren't allowed, and neither are unnecessary zero
+        // bytes in front.
+        if value[0] & 0x80 != 0 { return None; }
+        if length > 1 && value[0] == 0 && value[1] & 0x80 == 0 { return None; }
+
+        values.push(secp256k1::from_bytes(value));
+        rest = rest.slice_from(2 + length);
+    }
+
+    if !rest.is_empty() { return None; }
+
+    let s = values.pop().unwrap();
+    let r = values.pop().unwrap();
+    Some((r, s))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::derive_public_key;
+    use serialize::hex::FromHex;
+
+    use super::{derive_public_key, is_valid_private_key, sign, verify};
 
     #[test]
     fn test_derive_public_key() {
@@ -110,5 +216,54 @@ mod tests {
 
         assert_eq!(derived_public_key.as_slice(), actual_public_key);
     }
-}
 
+    #[test]
+    fn test_is_valid_private_key() {
+        let one = "0000000000000000000000000000000000000000000000000000000000000001".from_hex().unwrap();
+        let zero = "0000000000000000000000000000000000000000000000000000000000000000".from_hex().unwrap();
+        let order = "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141".from_hex().unwrap();
+        assert!(is_valid_private_key(one.as_slice()));
+        assert!(!is_valid_private_key(zero.as_slice()));
+        assert!(!is_valid_private_key(order.as_slice()));
+        assert!(!is_valid_private_key(one.slice_from(1)));
+    }
+
+    #[test]
+    fn test_sign() {
+        // A well-known RFC 6979 test vector for secp256k1: the private key 1
+        // and the SHA-256 hash of "Satoshi Nakamoto".
+        let message = "A0DC65FFCA799873CBEA0AC274015B9526505DAAAED385155425F7337704883E".from_hex().unwrap();
+        let private_key = "0000000000000000000000000000000000000000000000000000000000000001".from_hex().unwrap();
+        let expected = "3045022100934B1EA10A4B3C1757E2B0C017D0B6143CE3C9A7E6A4A49860D7A6AB210EE3D8\
+                        02202442CE9D2B916064108014783E923EC36B49743E2FFA1C4496F01A512AAFD9E5";
+        assert_eq!(sign(message.as_slice(), private_key.as_slice()), expected.from_hex().unwrap());
+
+        // The signature from BIP 143's native P2WPKH example (without the
+        // sighash type), which Bitcoin Core also made with RFC 6979.
+        let message = "C37AF31116D1B27CAF68AAE9E3AC82F1477929014D5B917657D0EB49478CB670".from_hex().unwrap();
+        let private_key = "619C335025C7F4012E556C2A58B2506E30B8511B53ADE95EA316FD8C3286FEB9".from_hex().unwrap();
+        let expected = "304402203609E17B84F6A7D30C80BFA610B5B4542F32A8A0D5447A12FB1366D7F01CC44A\
+                        0220573A954C4518331561406F90300E8F3358F51928D43C212A8CAED02DE67EEBEE";
+        assert_eq!(sign(message.as_slice(), private_key.as_slice()), expected.from_hex().unwrap());
+    }
+
+    #[test]
+    fn test_verify() {
+        let message = "C37AF31116D1B27CAF68AAE9E3AC82F1477929014D5B917657D0EB49478CB670".from_hex().unwrap();
+        let public_key = "025476C2E83188368DA1FF3E292E7ACAFCDB3566BB0AD253F62FC70F07AEEE6357".from_hex().unwrap();
+        let signature = "304402203609E17B84F6A7D30C80BFA610B5B4542F32A8A0D5447A12FB1366D7F01CC44A\
+                         0220573A954C4518331561406F90300E8F3358F51928D43C212A8CAED02DE67EEBEE";
+        let signature = signature.from_hex().unwrap();
+        assert!(verify(message.as_slice(), public_key.as_slice(), signature.as_slice()));
+
+        let mut wrong_message = message.clone();
+        *wrong_message.index_mut(&0) ^= 1;
+        assert!(!verify(wrong_message.as_slice(), public_key.as_slice(), signature.as_slice()));
+
+        // The same signature with an unnecessary zero byte in front of r.
+        let padded = "30450221003609E17B84F6A7D30C80BFA610B5B4542F32A8A0D5447A12FB1366D7F01CC44A\
+                      0220573A954C4518331561406F90300E8F3358F51928D43C212A8CAED02DE67EEBEE";
+        let padded = padded.from_hex().unwrap();
+        assert!(!verify(message.as_slice(), public_key.as_slice(), padded.as_slice()));
+    }
+}
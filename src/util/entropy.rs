@@ -0,0 +1,576 @@
+//! Gathers the entropy that keys are generated from. Entropy can come from
+//! several sources: OpenSSL's random number generator, dice rolls, coin flips
+//! or a hardware random number generator like `/dev/hwrng`. The sources'
+//! samples are hashed together, so the result is as unpredictable as the
+//! best of them, and a source you don't trust can't make it worse.
+//!
+//! Before they're used, every source's samples go through the two health
+//! tests from NIST SP 800-90B: the repetition count test and (a simplified
+//! version of) the adaptive proportion test. They catch broken sources, like
+//! a stuck device or someone typing the same roll over and over, rather than
+//! subtle bias. Since a good source fails them now and then too, sources that
+//! can be read again are, and only count as broken if they keep failing.
+
+use std::io::{File, IoResult, IoError, OtherIoError};
+use std::num::Float;
+use openssl;
+
+use util::bytes;
+use util::hash;
+use util::secret::SecretBytes;
+
+/// The number of bytes of entropy that `EntropyPool::gather()` returns.
+pub static LENGTH: uint = 32;
+
+/// The least entropy, in bits, that the sources together must provide.
+pub static MIN_ENTROPY_BITS: uint = 128;
+
+// The health tests should only fail on a good source with this probability.
+// It's the false alarm rate that SP 800-90B recommends.
+static FALSE_ALARM_BITS: uint = 20;
+
+// How many times a source that can be read again is read before its samples
+// failing the health tests is an error. A good source fails this many times
+// in a row with a probability of 2^-60.
+static HEALTH_TEST_ATTEMPTS: uint = 3;
+
+// The tag for hashing the sources' samples together.
+static MIXING_TAG: &'static str = "Cykas/entropy";
+
+// The most sides a die can have.
+static MAX_SIDES: uint = 100;
+
+/// Samples read from an entropy source. Each sample is one of `symbols`
+/// values, from 0 to `symbols - 1`, each of which should be equally likely.
+pub struct Samples {
+    pub data: SecretBytes,
+    pub symbols: uint
+}
+
+impl Samples {
+    /// Estimates how many bits of entropy the samples hold, assuming the
+    /// source is unbiased.
+    pub fn entropy_bits(&self) -> uint {
+        (self.data.len() as f64 * bits_per_sample(self.symbols)).floor() as uint
+    }
+}
+
+/// A source of entropy.
+pub trait EntropySource {
+    /// Describes the source for the record of what went into a key, e.g.
+    /// "99 rolls of a 6-sided die".
+    fn describe(&self) -> String;
+
+    /// Reads the source's samples.
+    fn read(&mut self) -> IoResult<Samples>;
+
+    /// Checks whether reading the source again gives new samples, so that
+    /// ones that fail a health test by chance can be replaced. Rolls and
+    /// flips typed in by the user can't be.
+    fn can_read_again(&self) -> bool {
+        false
+    }
+}
+
+/// OpenSSL's cryptographically secure random number generator.
+pub struct SystemRandom {
+    length: uint
+}
+
+impl SystemRandom {
+    /// Reads the given number of random bytes.
+    pub fn new(length: uint) -> SystemRandom {
+        SystemRandom { length: length }
+    }
+}
+
+impl EntropySource for SystemRandom {
+    fn describe(&self) -> String {
+        format!("{} bytes from the system random number generator", self.length)
+    }
+
+    fn read(&mut self) -> IoResult<Samples> {
+        let data = SecretBytes::new(openssl::crypto::rand::rand_bytes(self.length));
+        Ok(Samples { data: data, symbols: 256 })
+    }
+
+    fn can_read_again(&self) -> bool {
+        true
+    }
+}
+
+/// A device that outputs random bytes, like `/dev/hwrng`.
+pub struct Device {
+    path: Path,
+    length: uint
+}
+
+impl Device {
+    /// Reads the given number of bytes from the device at `path`.
+    pub fn new(path: &Path, length: uint) -> Device {
+        Device { path: path.clone(), length: length }
+    }
+}
+
+impl EntropySource for Device {
+    fn describe(&self) -> String {
+        format!("{} bytes from {}", self.length, self.path.display())
+    }
+
+    fn read(&mut self) -> IoResult<Samples> {
+        let mut file = try!(File::open(&self.path));
+        let data = SecretBytes::new(try!(file.read_exact(self.length)));
+        Ok(Samples { data: data, symbols: 256 })
+    }
+
+    fn can_read_again(&self) -> bool {
+        true
+    }
+}
+
+/// Rolls of a die, typed in by the user.
+pub struct Dice {
+    rolls: SecretBytes,
+    sides: uint
+}
+
+impl Dice {
+    /// Parses rolls of a die with the given number of sides, numbered from 1.
+    /// If the die has fewer than 10 sides, every digit is a roll and spaces
+    /// are optional. Otherwise rolls are separated by whitespace. Returns
+    /// None if there's a roll that isn't a number from 1 to `sides`, or if
+    /// `sides` isn't between 2 and 100.
+    pub fn parse(rolls: &str, sides: uint) -> Option<Dice> {
+        if sides < 2 || sides > MAX_SIDES { return None; }
+
+        // The rolls go straight into secret memory, without a copy in between
+        // that would be left behind. There can't be more of them than bytes.
+        let mut data = SecretBytes::with_capacity(rolls.len());
+        if sides < 10 {
+            for ch in rolls.chars().filter(|ch| !ch.is_whitespace()) {
+                if !push_roll(&mut data, ch.to_digit(10), sides) { return None; }
+            }
+        } else {
+            for word in rolls.words() {
+                if !push_roll(&mut data, from_str::<uint>(word), sides) { return None; }
+            }
+        }
+
+        Some(Dice { rolls: data, sides: sides })
+    }
+}
+
+// Adds a roll to the data, numbered from 0. Returns false if it isn't a
+// number from 1 to `sides`.
+fn push_roll(data: &mut SecretBytes, value: Option<uint>, sides: uint) -> bool {
+    match value {
+        Some(value) if value >= 1 && value <= sides => {
+            data.push_all(&[(value - 1) as u8]);
+            true
+        }
+        _ => false
+    }
+}
+
+impl EntropySource for Dice {
+    fn describe(&self) -> String {
+        format!("{} rolls of a {}-sided die", self.rolls.len(), self.sides)
+    }
+
+    fn read(&mut self) -> IoResult<Samples> {
+        Ok(Samples { data: self.rolls.clone(), symbols: self.sides })
+    }
+}
+
+/// Coin flips, typed in by the user.
+pub struct CoinFlips {
+    flips: SecretBytes
+}
+
+impl CoinFlips {
+    /// Parses coin flips written as `H` and `T` (in either case) or as `1`
+    /// and `0`. Whitespace is ignored. Returns None if there's any other
+    /// character.
+    pub fn parse(flips: &str) -> Option<CoinFlips> {
+        let mut data = SecretBytes::with_capacity(flips.len());
+        for ch in flips.chars().filter(|ch| !ch.is_whitespace()) {
+            match ch {
+                'H' | 'h' | '1' => data.push_all(&[1]),
+                'T' | 't' | '0' => data.push_all(&[0]),
+                _ => return None
+            }
+        }
+
+        Some(CoinFlips { flips: data })
+    }
+}
+
+impl EntropySource for CoinFlips {
+    fn describe(&self) -> String {
+        format!("{} coin flips", self.flips.len())
+    }
+
+    fn read(&mut self) -> IoResult<Samples> {
+        Ok(Samples { data: self.flips.clone(), symbols: 2 })
+    }
+}
+
+/// A record of one of the sources that went into some entropy.
+#[deriving(Clone, PartialEq, Show)]
+pub struct SourceRecord {
+    pub description: String,
+    /// The estimated entropy of the source's samples, in bits.
+    pub entropy_bits: uint
+}
+
+/// Entropy gathered from an EntropyPool, along with a record of where it
+/// came from.
+pub struct Entropy {
+    data: SecretBytes,
+    sources: Vec<SourceRecord>
+}
+
+impl Entropy {
+    /// Gets the entropy, which is `LENGTH` bytes long.
+    pub fn get_data(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    /// Gets the record of the sources that were mixed together.
+    pub fn get_sources(&self) -> &[SourceRecord] {
+        self.sources.as_slice()
+    }
+
+    /// Estimates the total entropy in bits. The output of the mixing hash
+    /// can't hold more than `LENGTH * 8` bits, however much went in.
+    pub fn entropy_bits(&self) -> uint {
+        let total = self.sources.iter().fold(0, |total, source| total + source.entropy_bits);
+        if total < LENGTH * 8 { total } else { LENGTH * 8 }
+    }
+}
+
+/// A set of entropy sources to mix together.
+pub struct EntropyPool {
+    sources: Vec<Box<EntropySource + 'static>>
+}
+
+impl EntropyPool {
+    /// Creates a pool with no sources.
+    pub fn new() -> EntropyPool {
+        EntropyPool { sources: Vec::new() }
+    }
+
+    /// Creates a pool with the system random number generator as its only
+    /// source. More sources can be added to it.
+    pub fn system() -> EntropyPool {
+        let mut pool = EntropyPool::new();
+        pool.add(box SystemRandom::new(LENGTH));
+        pool
+    }
+
+    /// Adds a source to the pool.
+    pub fn add(&mut self, source: Box<EntropySource + 'static>) {
+        self.sources.push(source);
+    }
+
+    /// Reads every source, runs the health tests on their samples, and
+    /// hashes them together into `LENGTH` bytes of entropy. A source that
+    /// fails a health test is read again, if it can be, up to
+    /// `HEALTH_TEST_ATTEMPTS` times in all. Fails if a source can't be read or
+    /// keeps failing a health test, or if the sources together have less than
+    /// `MIN_ENTROPY_BITS` bits of entropy.
+    pub fn gather(&mut self) -> IoResult<Entropy> {
+        let mut records = Vec::with_capacity(self.sources.len());
+        let mut all_samples = Vec::with_capacity(self.sources.len());
+
+        for source in self.sources.iter_mut() {
+            let description = source.describe();
+            let attempts = if source.can_read_again() { HEALTH_TEST_ATTEMPTS } else { 1 };
+
+            let mut passed = None;
+            let mut failed_test = "";
+            for _ in range(0, attempts) {
+                let samples = try!(source.read());
+                match health_test(samples.data.as_slice(), samples.symbols) {
+                    Ok(()) => {
+                        passed = Some(samples);
+                        break;
+                    }
+                    Err(test) => failed_test = test
+                }
+            }
+
+            let samples = match passed {
+                Some(samples) => samples,
+                None => {
+                    let times = if attempts > 1 { format!(" {} times in a row", attempts) } else { String::new() };
+                    return Err(IoError {
+                        kind: OtherIoError,
+                        desc: "entropy health test failed",
+                        detail: Some(format!("The {} failed the {} test{}", description, failed_test, times))
+                    });
+                }
+            };
+
+            records.push(SourceRecord { description: description, entropy_bits: samples.entropy_bits() });
+            all_samples.push(samples);
+        }
+
+        let entropy_bits = records.iter().fold(0, |total, record| total + record.entropy_bits);
+        if entropy_bits < MIN_ENTROPY_BITS {
+            return Err(IoError {
+                kind: OtherIoError,
+                desc: "not enough entropy",
+                detail: Some(format!("The entropy sources only have about {} bits of entropy, but at least {} are needed",
+                                     entropy_bits, MIN_ENTROPY_BITS))
+            });
+        }
+
+        let data = mix(records.as_slice(), all_samples.as_slice());
+        Ok(Entropy { data: data, sources: records })
+    }
+}
+
+// Hashes the sources' descriptions and samples together with a BIP 340
+// tagged hash. Each one is length-prefixed, so different sets of samples
+// can't give the same input.
+fn mix(records: &[SourceRecord], all_samples: &[Samples]) -> SecretBytes {
+    // The prefixes take up to 9 bytes each.
+    let mut length = 64;
+    for (record, samples) in records.iter().zip(all_samples.iter()) {
+        length += 18 + record.description.len() + samples.data.len();
+    }
+
+    // This is hash::tagged_hash(), but with the input built in a Vec that's
+    // big enough from the start, so it doesn't leave copies of the samples
+    // behind when it grows, and that's wiped afterwards.
+    let tag_hash = hash::sha256(MIXING_TAG.as_bytes());
+    let mut input = Vec::with_capacity(length);
+    input.push_all(tag_hash.as_slice());
+    input.push_all(tag_hash.as_slice());
+    for (record, samples) in records.iter().zip(all_samples.iter()) {
+        bytes::write_var_bytes(&mut input, record.description.as_bytes());
+        bytes::write_var_bytes(&mut input, samples.data.as_slice());
+    }
+    let input = SecretBytes::new(input);
+
+    SecretBytes::new(hash::sha256(input.as_slice()))
+}
+
+// The entropy of each sample, in bits, if the source is unbiased.
+fn bits_per_sample(symbols: uint) -> f64 {
+    (symbols as f64).log2()
+}
+
+/// Runs the health tests on samples that each have one of `symbols` values.
+/// Returns the name of the test that failed, if one did.
+pub fn health_test(samples: &[u8], symbols: uint) -> Result<(), &'static str> {
+    if !repetition_count_test(samples, symbols) {
+        return Err("repetition count");
+    }
+    if !adaptive_proportion_test(samples, symbols) {
+        return Err("adaptive proportion");
+    }
+    Ok(())
+}
+
+// Checks that no value is repeated too many times in a row. The cutoff is
+// the run length that an unbiased source only gives with probability
+// 2^-FALSE_ALARM_BITS.
+fn repetition_count_test(samples: &[u8], symbols: uint) -> bool {
+    let cutoff = 1 + (FALSE_ALARM_BITS as f64 / bits_per_sample(symbols)).ceil() as uint;
+
+    let mut run = 0u;
+    for i in range(0, samples.len()) {
+        run = if i > 0 && samples[i] == samples[i - 1] { run + 1 } else { 1 };
+        if run >= cutoff { return false; }
+    }
+    true
+}
+
+// Checks that no value turns up too often. SP 800-90B counts how often the
+// first sample of each 512-sample window turns up; the samples here are
+// short, so this takes them as one window and counts the most common value.
+fn adaptive_proportion_test(samples: &[u8], symbols: uint) -> bool {
+    let mut counts = Vec::from_elem(symbols, 0u);
+    for sample in samples.iter() {
+        *counts.index_mut(&(*sample as uint)) += 1;
+    }
+
+    let most_common = counts.iter().fold(0, |max, &count| if count > max { count } else { max });
+    most_common < proportion_cutoff(samples.len(), symbols)
+}
+
+// Finds the smallest count such that, in `n` samples from an unbiased source
+// with `symbols` values, the chance of any value turning up that many times
+// or more is below 2^-FALSE_ALARM_BITS. The binomial probabilities are
+// computed as logarithms, since they can be too small for an f64.
+fn proportion_cutoff(n: uint, symbols: uint) -> uint {
+    let p = 1.0 / symbols as f64;
+    let false_alarm = (2.0f64).powi(-(FALSE_ALARM_BITS as i32));
+
+    let mut log_probabilities = Vec::with_capacity(n + 1);
+    let mut log_choose = 0.0f64;
+    for k in range(0, n + 1) {
+        log_probabilities.push(log_choose + k as f64 * p.ln() + (n - k) as f64 * (1.0 - p).ln());
+        if k < n {
+            log_choose += ((n - k) as f64).ln() - ((k + 1) as f64).ln();
+        }
+    }
+
+    // The chance of some value turning up at least c times is at most
+    // `symbols` times the chance for a particular value.
+    let mut tail = 0.0f64;
+    for c in range(0, n + 1).rev() {
+        tail += log_probabilities[c].exp();
+        if tail * symbols as f64 >= false_alarm {
+            return c + 1;
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::ToHex;
+    use std::io::IoResult;
+
+    use util::secret::SecretBytes;
+
+    use super::{EntropyPool, EntropySource, Samples, Dice, CoinFlips, SourceRecord, HEALTH_TEST_ATTEMPTS,
+                health_test, proportion_cutoff};
+
+    // 100 rolls of a fair die.
+    static ROLLS: &'static str =
+        "32461151351521144121541512665155412152342515356215\
+         56231561515246543454332262153543643511542324416155";
+
+    #[test]
+    fn test_parse_dice() {
+        let dice = Dice::parse("1 6 2\n5", 6).unwrap();
+        assert_eq!(dice.rolls.as_slice(), [0u8, 5, 1, 4].as_slice());
+
+        let dice = Dice::parse("20 1 13", 20).unwrap();
+        assert_eq!(dice.rolls.as_slice(), [19u8, 0, 12].as_slice());
+
+        assert!(Dice::parse("1 2 7", 6).is_none());
+        assert!(Dice::parse("0", 6).is_none());
+        assert!(Dice::parse("1x", 6).is_none());
+        assert!(Dice::parse("21", 20).is_none());
+        assert!(Dice::parse("1", 1).is_none());
+    }
+
+    #[test]
+    fn test_parse_coin_flips() {
+        let flips = CoinFlips::parse("HTht 10").unwrap();
+        assert_eq!(flips.flips.as_slice(), [1u8, 0, 1, 0, 1, 0].as_slice());
+        assert!(CoinFlips::parse("HTX").is_none());
+    }
+
+    #[test]
+    fn test_proportion_cutoff() {
+        assert_eq!(proportion_cutoff(32, 256), 6);
+        assert_eq!(proportion_cutoff(100, 6), 39);
+        assert_eq!(proportion_cutoff(256, 2), 168);
+    }
+
+    #[test]
+    fn test_health_test() {
+        let rolls = Dice::parse(ROLLS, 6).unwrap();
+        assert_eq!(health_test(rolls.rolls.as_slice(), 6), Ok(()));
+
+        // Nine of the same roll in a row.
+        let stuck = Dice::parse("3333333331", 6).unwrap();
+        assert_eq!(health_test(stuck.rolls.as_slice(), 6), Err("repetition count"));
+
+        // No long runs, but only two of the six values.
+        let biased = Dice::parse("12".repeat(50).as_slice(), 6).unwrap();
+        assert_eq!(health_test(biased.rolls.as_slice(), 6), Err("adaptive proportion"));
+
+        assert_eq!(health_test(&[7, 7, 7, 7], 256), Err("repetition count"));
+    }
+
+    #[test]
+    fn test_gather() {
+        let mut pool = EntropyPool::new();
+        pool.add(box Dice::parse(ROLLS, 6).unwrap());
+        let entropy = pool.gather().unwrap();
+
+        assert_eq!(entropy.get_data().to_hex().as_slice(),
+                   "fb579901ad6b3bb99c4c1e35da95d94a3a0f8e6c6078cb29035863c9ffaa44f3");
+        assert_eq!(entropy.get_sources(), [SourceRecord {
+            description: "100 rolls of a 6-sided die".to_string(),
+            entropy_bits: 258
+        }].as_slice());
+        assert_eq!(entropy.entropy_bits(), 256);
+
+        // The same samples always give the same entropy.
+        assert_eq!(pool.gather().unwrap().get_data(), entropy.get_data());
+    }
+
+    #[test]
+    fn test_gather_mixes_sources() {
+        let mut pool = EntropyPool::system();
+        pool.add(box CoinFlips::parse("HTTHHTHT").unwrap());
+        let entropy = pool.gather().unwrap();
+        assert_eq!(entropy.get_sources().len(), 2);
+        assert_eq!(entropy.get_sources()[1].entropy_bits, 8);
+
+        let mut pool = EntropyPool::system();
+        pool.add(box CoinFlips::parse("HTTHHTHT").unwrap());
+        assert!(pool.gather().unwrap().get_data() != entropy.get_data());
+    }
+
+    // A source that reads stuck bytes the first `failures` times, and then
+    // ones that pass the health tests.
+    struct Flaky {
+        failures: uint,
+        reads: uint
+    }
+
+    impl EntropySource for Flaky {
+        fn describe(&self) -> String {
+            "flaky source".to_string()
+        }
+
+        fn read(&mut self) -> IoResult<Samples> {
+            self.reads += 1;
+            let data = if self.reads > self.failures {
+                range(0u8, 32).collect::<Vec<u8>>()
+            } else {
+                Vec::from_elem(32, 7u8)
+            };
+            Ok(Samples { data: SecretBytes::new(data), symbols: 256 })
+        }
+
+        fn can_read_again(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_gather_reads_again() {
+        let mut pool = EntropyPool::new();
+        pool.add(box Flaky { failures: 1, reads: 0 });
+        let entropy = pool.gather().unwrap();
+        assert_eq!(entropy.get_sources()[0].entropy_bits, 256);
+
+        let mut pool = EntropyPool::new();
+        pool.add(box Flaky { failures: HEALTH_TEST_ATTEMPTS, reads: 0 });
+        assert!(pool.gather().is_err());
+
+        // Dice rolls can't be read again, so they only get one try.
+        let mut pool = EntropyPool::new();
+        pool.add(box Dice::parse("3333333331".repeat(10).as_slice(), 6).unwrap());
+        assert!(pool.gather().is_err());
+    }
+
+    #[test]
+    fn test_gather_not_enough_entropy() {
+        let mut pool = EntropyPool::new();
+        pool.add(box Dice::parse("3246115135152114412154", 6).unwrap());
+        assert!(pool.gather().is_err());
+
+        assert!(EntropyPool::new().gather().is_err());
+    }
+}
@@ -0,0 +1,314 @@
+//! The hash functions used throughout the Bitcoin protocol.
+
+use std::num::Int;
+
+use openssl;
+use openssl::crypto::hash::HashType::{SHA256, SHA512, RIPEMD160};
+
+/// Performs a single SHA256 hash of the given data.
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    openssl::crypto::hash::hash(SHA256, data)
+}
+
+/// Performs a double SHA256 hash of the given data. This is what Bitcoin uses
+/// for checksums and transaction IDs.
+pub fn double_sha256(data: &[u8]) -> Vec<u8> {
+    let first_hash = sha256(data);
+    sha256(first_hash.as_slice())
+}
+
+/// Performs a RIPEMD-160 hash of a SHA256 hash of the given data. This is what
+/// Bitcoin uses to turn public keys and scripts into addresses.
+pub fn hash160(data: &[u8]) -> Vec<u8> {
+    let sha = sha256(data);
+    openssl::crypto::hash::hash(RIPEMD160, sha.as_slice())
+}
+
+/// Performs a BIP 340 tagged hash of the given data, i.e. a SHA256 hash of
+/// the data preceded by the SHA256 hash of the tag, twice.
+pub fn tagged_hash(tag: &str, data: &[u8]) -> Vec<u8> {
+    let tag_hash = sha256(tag.as_bytes());
+
+    let mut preimage = Vec::with_capacity(64 + data.len());
+    preimage.push_all(tag_hash.as_slice());
+    preimage.push_all(tag_hash.as_slice());
+    preimage.push_all(data);
+
+    sha256(preimage.as_slice())
+}
+
+/// Computes an HMAC-SHA256 of the given data with the given key. RFC 6979
+/// uses this to generate ECDSA nonces.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut hmac = openssl::crypto::hmac::HMAC(SHA256, key);
+    hmac.update(data);
+    hmac.finalize()
+}
+
+/// Computes an HMAC-SHA512 of the given data with the given key. BIP 32 uses
+/// this to derive child keys.
+pub fn hmac_sha512(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut hmac = openssl::crypto::hmac::HMAC(SHA512, key);
+    hmac.update(data);
+    hmac.finalize()
+}
+
+/// Derives a key of `key_length` bytes from a password and salt with
+/// PBKDF2, using HMAC-SHA512 as the pseudorandom function. (OpenSSL's
+/// bindings only provide PBKDF2 with HMAC-SHA1.) BIP 39 uses this to turn a
+/// mnemonic into a seed.
+pub fn pbkdf2_hmac_sha512(password: &[u8], salt: &[u8], iterations: uint, key_length: uint) -> Vec<u8> {
+    pbkdf2(hmac_sha512, password, salt, iterations, key_length)
+}
+
+/// Derives a key of `key_length` bytes from a password and salt with
+/// PBKDF2, using HMAC-SHA256 as the pseudorandom function. scrypt uses this
+/// on either side of its memory-hard part.
+pub fn pbkdf2_hmac_sha256(password: &[u8], salt: &[u8], iterations: uint, key_length: uint) -> Vec<u8> {
+    pbkdf2(hmac_sha256, password, salt, iterations, key_length)
+}
+
+/// Derives a key of `key_length` bytes from a password and salt with scrypt
+/// (RFC 7914), which needs about `128 * block_size * cost` bytes of memory as
+/// well as a lot of time, so that guessing passwords on custom hardware
+/// doesn't pay off. `cost` must be a power of 2 greater than 1. BIP 38 uses
+/// this to encrypt private keys with a passphrase.
+pub fn scrypt(password: &[u8], salt: &[u8], cost: uint, block_size: uint, parallelism: uint,
+              key_length: uint) -> Vec<u8> {
+    assert!(cost > 1 && cost & (cost - 1) == 0);
+
+    let block_length = 128 * block_size;
+    let mut blocks = pbkdf2_hmac_sha256(password, salt, 1, parallelism * block_length);
+    for block in blocks.as_mut_slice().chunks_mut(block_length) {
+        scrypt_rom_mix(block, cost);
+    }
+    pbkdf2_hmac_sha256(password, blocks.as_slice(), 1, key_length)
+}
+
+// PBKDF2 with the given HMAC as the pseudorandom function.
+fn pbkdf2(hmac: fn(&[u8], &[u8]) -> Vec<u8>, password: &[u8], salt: &[u8], iterations: uint,
+          key_length: uint) -> Vec<u8> {
+    let mut result = Vec::with_capacity(key_length);
+    let mut block_index = 1u32;
+
+    while result.len() < key_length {
+        let mut block_salt = salt.to_vec();
+        for i in range(0, 4u).rev() {
+            block_salt.push((block_index >> (8 * i)) as u8);
+        }
+
+        let mut u = hmac(password, block_salt.as_slice());
+        let mut block = u.clone();
+        for _ in range(1, iterations) {
+            u = hmac(password, u.as_slice());
+            for (b, x) in block.iter_mut().zip(u.iter()) {
+                *b ^= *x;
+            }
+        }
+
+        let needed = key_length - result.len();
+        result.push_all(block.slice_to(if needed < block.len() { needed } else { block.len() }));
+        block_index += 1;
+    }
+
+    result
+}
+
+// scrypt's ROMix: fills a table with `cost` successive mixes of the block,
+// then mixes the block with entries of the table that depend on it.
+fn scrypt_rom_mix(block: &mut [u8], cost: uint) {
+    let word_count = block.len() / 4;
+    let mut x: Vec<u32> = block.chunks(4).map(|word| {
+        word.iter().rev().fold(0u32, |value, &byte| value << 8 | byte as u32)
+    }).collect();
+    let mut scratch = Vec::from_elem(word_count, 0u32);
+
+    let mut table = Vec::with_capacity(cost * word_count);
+    for _ in range(0, cost) {
+        table.push_all(x.as_slice());
+        scrypt_block_mix(x.as_mut_slice(), scratch.as_mut_slice());
+    }
+
+    for _ in range(0, cost) {
+        // The first word of the last 64 bytes picks the entry.
+        let j = x[word_count - 16] as uint & (cost - 1);
+        for (word, &entry) in x.iter_mut().zip(table.slice(j * word_count, (j + 1) * word_count).iter()) {
+            *word ^= entry;
+        }
+        scrypt_block_mix(x.as_mut_slice(), scratch.as_mut_slice());
+    }
+
+    for (i, &word) in x.iter().enumerate() {
+        for j in range(0, 4u) {
+            block[i * 4 + j] = (word >> (8 * j)) as u8;
+        }
+    }
+}
+
+// scrypt's BlockMix: runs Salsa20/8 over each 64 bytes of the block in turn,
+// chained together, and puts the even outputs before the odd ones.
+fn scrypt_block_mix(block: &mut [u32], scratch: &mut [u32]) {
+    let chunk_count = block.len() / 16;
+    let mut x = [0u32, ..16];
+    for (i, &word) in block.slice_from((chunk_count - 1) * 16).iter().enumerate() {
+        x[i] = word;
+    }
+
+    for i in range(0, chunk_count) {
+        for j in range(0, 16u) {
+            x[j] ^= block[i * 16 + j];
+        }
+        salsa20_8(&mut x);
+
+        let position = (i / 2 + (i % 2) * (chunk_count / 2)) * 16;
+        for j in range(0, 16u) {
+            scratch[position + j] = x[j];
+        }
+    }
+
+    for (word, &mixed) in block.iter_mut().zip(scratch.iter()) {
+        *word = mixed;
+    }
+}
+
+// The Salsa20 core with 8 rounds, which scrypt mixes its blocks with.
+fn salsa20_8(block: &mut [u32, ..16]) {
+    let mut x = *block;
+    for _ in range(0, 4u) {
+        // A round on the columns, then one on the rows.
+        salsa20_quarter_round(&mut x, 0, 4, 8, 12);
+        salsa20_quarter_round(&mut x, 5, 9, 13, 1);
+        salsa20_quarter_round(&mut x, 10, 14, 2, 6);
+        salsa20_quarter_round(&mut x, 15, 3, 7, 11);
+        salsa20_quarter_round(&mut x, 0, 1, 2, 3);
+        salsa20_quarter_round(&mut x, 5, 6, 7, 4);
+        salsa20_quarter_round(&mut x, 10, 11, 8, 9);
+        salsa20_quarter_round(&mut x, 15, 12, 13, 14);
+    }
+    for i in range(0, 16u) {
+        block[i] += x[i];
+    }
+}
+
+fn salsa20_quarter_round(x: &mut [u32, ..16], a: uint, b: uint, c: uint, d: uint) {
+    let t = (x[a] + x[d]).rotate_left(7);
+    x[b] ^= t;
+    let t = (x[b] + x[a]).rotate_left(9);
+    x[c] ^= t;
+    let t = (x[c] + x[b]).rotate_left(13);
+    x[d] ^= t;
+    let t = (x[d] + x[c]).rotate_left(18);
+    x[a] ^= t;
+}
+
+/// Computes the CRC-32 checksum used by zlib, PNG and Uniform Resources. It's
+/// only for catching accidental errors, not a cryptographic hash.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data.iter() {
+        crc ^= byte as u32;
+        for _ in range(0, 8u) {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::FromHex;
+
+    use super::{sha256, double_sha256, hash160, tagged_hash, hmac_sha256, hmac_sha512, pbkdf2_hmac_sha512};
+    use super::{pbkdf2_hmac_sha256, scrypt, crc32};
+
+    #[test]
+    fn test_sha256() {
+        let expected = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(sha256(b"abc"), expected);
+    }
+
+    #[test]
+    fn test_double_sha256() {
+        let data = "00010966776006953D5567439E5E39F86A0D273BEE".from_hex().unwrap();
+        let expected = "D61967F63C7DD183914A4AE452C9F6AD5D462CE3D277798075B107615C1A8A30";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(double_sha256(data.as_slice()), expected);
+    }
+
+    #[test]
+    fn test_hash160() {
+        let data = "0279A17659BAC072B06740415E9E1DB4579ABE0E7AB0C50CD0912E2BF82456245A";
+        let data = data.from_hex().unwrap();
+        let expected = "9C1998F823D6E9BB23AA7F2444FC5391E24E68E1".from_hex().unwrap();
+        assert_eq!(hash160(data.as_slice()), expected);
+    }
+
+    #[test]
+    fn test_tagged_hash() {
+        let data = "D6889CB081036E0FAEFA3A35157AD71086B123B2B144B649798B494C300A961D";
+        let data = data.from_hex().unwrap();
+        let expected = "B86E7BE8F39BAB32A6F2C0443ABBC210F0EDAC0E2C53D501B36B64437D9C6C70";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(tagged_hash("TapTweak", data.as_slice()), expected);
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // Test case 2 from RFC 4231.
+        let expected = "5BDCC146BF60754E6A042426089575C75A003F089D2739839DEC58B964EC3843";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(hmac_sha256(b"Jefe", b"what do ya want for nothing?"), expected);
+    }
+
+    #[test]
+    fn test_hmac_sha512() {
+        // Test case 2 from RFC 4231.
+        let expected = "164B7A7BFCF819E2E395FBE73B56E0A387BD64222E831FD610270CD7EA250554\
+                        9758BF75C05A994A6D034F65F8F0E6FDCAEAB1A34D4A6B4B636E070A38BCE737";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(hmac_sha512(b"Jefe", b"what do ya want for nothing?"), expected);
+    }
+
+    #[test]
+    fn test_pbkdf2_hmac_sha512() {
+        let expected = "867F70CF1ADE02CFF3752599A3A53DC4AF34C7A669815AE5D513554E1C8CF252\
+                        C02D470A285A0501BAD999BFE943C08F050235D7D68B1DA55E63F73B60A57FCE";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(pbkdf2_hmac_sha512(b"password", b"salt", 1, 64), expected);
+
+        let expected = "E1D9C16AA681708A45F5C7C4E215CEB66E011A2E9F0040713F18AEFDB866D53C";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(pbkdf2_hmac_sha512(b"password", b"salt", 2, 32), expected);
+    }
+
+    #[test]
+    fn test_pbkdf2_hmac_sha256() {
+        let expected = "120FB6CFFCF8B32C43E7225256C4F837A86548C92CCC35480805987CB70BE17B";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(pbkdf2_hmac_sha256(b"password", b"salt", 1, 32), expected);
+
+        let expected = "AE4D0C95AF6B46D32D0ADFF928F06DD02A303F8EF3C251DFD6E2D85A95474C43";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(pbkdf2_hmac_sha256(b"password", b"salt", 2, 32), expected);
+    }
+
+    #[test]
+    fn test_scrypt() {
+        // From RFC 7914, section 12.
+        let expected = "77D6576238657B203B19CA42C18A0497F16B4844E3074AE8DFDFFA3FEDE21442\
+                        FCD0069DED0948F8326A753A0FC81F17E8D3E0FB2E0D3628CF35E20C38D18906";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(scrypt(b"", b"", 16, 1, 1, 64), expected);
+
+        let expected = "FDBABE1C9D3472007856E7190D01E9FE7C6AD7CBC8237830E77376634B373162\
+                        2EAF30D92E22A3886FF109279D9830DAC727AFB94A83EE6D8360CBDFA2CC0640";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(scrypt(b"password", b"NaCl", 1024, 8, 16, 64), expected);
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+}
@@ -0,0 +1,335 @@
+//! DEFLATE decompression (RFC 1951), and the zlib format that wraps it (RFC
+//! 1950), which is how PNG images are compressed.
+
+// The most bits in a Huffman code.
+static MAX_BITS: uint = 15;
+
+// The base lengths and extra bits of length symbols 257 to 285.
+static LENGTH_BASES: [u16, ..29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+static LENGTH_EXTRA_BITS: [u8, ..29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+];
+
+// The base distances and extra bits of distance symbols 0 to 29.
+static DISTANCE_BASES: [u16, ..30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+];
+static DISTANCE_EXTRA_BITS: [u8, ..30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13
+];
+
+// The order that the code lengths of the code length alphabet are given in.
+static CODE_LENGTH_ORDER: [uint, ..19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// The symbol that ends a block.
+static END_OF_BLOCK: u16 = 256;
+
+/// Decompresses zlib data, checking its header and Adler-32 checksum.
+/// Returns None if it's invalid.
+pub fn zlib_decompress(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 6 { return None; }
+
+    // The compression method must be DEFLATE, and there can't be a preset
+    // dictionary.
+    let (method, flags) = (data[0], data[1]);
+    if method & 0x0F != 8 || (method as uint * 256 + flags as uint) % 31 != 0 || flags & 0x20 != 0 {
+        return None;
+    }
+
+    let mut reader = BitReader { data: data.slice_from(2), position: 0, bit: 0 };
+    let output = match inflate_from(&mut reader) {
+        Some(output) => output,
+        None => return None
+    };
+
+    let rest = reader.data.slice_from(reader.position + if reader.bit > 0 { 1 } else { 0 });
+    if rest.len() < 4 { return None; }
+    let checksum = (rest[0] as u32 << 24) | (rest[1] as u32 << 16) | (rest[2] as u32 << 8) | rest[3] as u32;
+    if checksum == adler32(output.as_slice()) { Some(output) } else { None }
+}
+
+/// Decompresses raw DEFLATE data. Returns None if it's invalid.
+pub fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    inflate_from(&mut BitReader { data: data, position: 0, bit: 0 })
+}
+
+// Reads bits from a byte slice, starting with the least significant bit of
+// each byte, as DEFLATE packs them.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: uint,
+    bit: uint
+}
+
+impl<'a> BitReader<'a> {
+    // Reads `count` bits, with the first one read as the least significant.
+    fn read_bits(&mut self, count: uint) -> Option<u32> {
+        let mut value = 0u32;
+        for i in range(0, count) {
+            if self.position >= self.data.len() { return None; }
+            let bit = (self.data[self.position] >> self.bit) & 1;
+            value |= (bit as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.position += 1;
+            }
+        }
+        Some(value)
+    }
+
+    // Skips to the start of the next byte, and reads `count` whole bytes.
+    fn read_aligned_bytes(&mut self, count: uint) -> Option<&'a [u8]> {
+        if self.bit > 0 {
+            self.bit = 0;
+            self.position += 1;
+        }
+        if self.position + count > self.data.len() { return None; }
+        let bytes = self.data.slice(self.position, self.position + count);
+        self.position += count;
+        Some(bytes)
+    }
+}
+
+// A canonical Huffman code, given by how many codes there are of each length
+// and the symbols in order of their codes.
+struct Huffman {
+    counts: [u16, ..16],
+    symbols: Vec<u16>
+}
+
+impl Huffman {
+    // Builds the code from the length of each symbol's code, where 0 means
+    // the symbol isn't used. Returns None if there are too many codes of
+    // some length for them to be prefix-free.
+    fn new(lengths: &[u8]) -> Option<Huffman> {
+        let mut counts = [0u16, ..16];
+        for &length in lengths.iter() {
+            counts[length as uint] += 1;
+        }
+
+        let mut left = 1i;
+        for length in range(1, MAX_BITS + 1) {
+            left = left * 2 - counts[length] as int;
+            if left < 0 { return None; }
+        }
+
+        // The offset of the first symbol of each length in `symbols`.
+        let mut offsets = [0u16, ..16];
+        for length in range(1, MAX_BITS) {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = Vec::from_elem(lengths.len(), 0u16);
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                *symbols.index_mut(&(offsets[length as uint] as uint)) = symbol as u16;
+                offsets[length as uint] += 1;
+            }
+        }
+
+        counts[0] = 0;
+        Some(Huffman { counts: counts, symbols: symbols })
+    }
+
+    // Reads one symbol. Codes are packed starting from their most
+    // significant bit.
+    fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+        let mut code = 0i;
+        let mut first = 0i;
+        let mut index = 0i;
+        for length in range(1, MAX_BITS + 1) {
+            code |= match reader.read_bits(1) { Some(bit) => bit as int, None => return None };
+            let count = self.counts[length] as int;
+            if code - first < count {
+                return Some(self.symbols[(index + code - first) as uint]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+// Decompresses blocks until the last one.
+fn inflate_from(reader: &mut BitReader) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    loop {
+        let last = match reader.read_bits(1) { Some(bit) => bit == 1, None => return None };
+        let ok = match reader.read_bits(2) {
+            Some(0) => stored_block(reader, &mut output),
+            Some(1) => {
+                let (lengths, distances) = fixed_codes();
+                compressed_block(reader, &mut output, &lengths, &distances)
+            }
+            Some(2) => {
+                match dynamic_codes(reader) {
+                    Some((lengths, distances)) => compressed_block(reader, &mut output, &lengths, &distances),
+                    None => false
+                }
+            }
+            _ => false
+        };
+        if !ok { return None; }
+        if last { return Some(output); }
+    }
+}
+
+// Copies an uncompressed block to the output.
+fn stored_block(reader: &mut BitReader, output: &mut Vec<u8>) -> bool {
+    let header = match reader.read_aligned_bytes(4) { Some(header) => header, None => return false };
+    let length = header[0] as uint | (header[1] as uint << 8);
+    let complement = header[2] as uint | (header[3] as uint << 8);
+    if length != !complement & 0xFFFF { return false; }
+
+    match reader.read_aligned_bytes(length) {
+        Some(bytes) => {
+            output.push_all(bytes);
+            true
+        }
+        None => false
+    }
+}
+
+// The fixed codes that blocks of type 1 use.
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = Vec::from_elem(288, 8u8);
+    for i in range(144, 256u) { *lengths.index_mut(&i) = 9; }
+    for i in range(256, 280u) { *lengths.index_mut(&i) = 7; }
+    let distances = Vec::from_elem(30, 5u8);
+    (Huffman::new(lengths.as_slice()).unwrap(), Huffman::new(distances.as_slice()).unwrap())
+}
+
+// Reads the codes that a block of type 2 is compressed with, which are
+// themselves Huffman coded.
+fn dynamic_codes(reader: &mut BitReader) -> Option<(Huffman, Huffman)> {
+    let (length_count, distance_count, code_length_count) =
+        match (reader.read_bits(5), reader.read_bits(5), reader.read_bits(4)) {
+            (Some(a), Some(b), Some(c)) => (a as uint + 257, b as uint + 1, c as uint + 4),
+            _ => return None
+        };
+    if length_count > 286 || distance_count > 30 { return None; }
+
+    let mut code_lengths = [0u8, ..19];
+    for i in range(0, code_length_count) {
+        code_lengths[CODE_LENGTH_ORDER[i]] = match reader.read_bits(3) { Some(n) => n as u8, None => return None };
+    }
+    let code_length_code = match Huffman::new(&code_lengths) { Some(code) => code, None => return None };
+
+    let mut lengths = Vec::with_capacity(length_count + distance_count);
+    while lengths.len() < length_count + distance_count {
+        let symbol = match code_length_code.decode(reader) { Some(symbol) => symbol, None => return None };
+        let (value, repeat) = match symbol {
+            0...15 => (symbol as u8, 1),
+            16 => {
+                let previous = match lengths.last() { Some(&previous) => previous, None => return None };
+                (previous, 3 + match reader.read_bits(2) { Some(n) => n as uint, None => return None })
+            }
+            17 => (0, 3 + match reader.read_bits(3) { Some(n) => n as uint, None => return None }),
+            _ => (0, 11 + match reader.read_bits(7) { Some(n) => n as uint, None => return None })
+        };
+        if lengths.len() + repeat > length_count + distance_count { return None; }
+        lengths.grow(repeat, value);
+    }
+
+    // There has to be a code for the end of the block.
+    if lengths[END_OF_BLOCK as uint] == 0 { return None; }
+
+    match (Huffman::new(lengths.slice_to(length_count)), Huffman::new(lengths.slice_from(length_count))) {
+        (Some(lengths), Some(distances)) => Some((lengths, distances)),
+        _ => None
+    }
+}
+
+// Decodes the literals and back-references of a compressed block.
+fn compressed_block(reader: &mut BitReader, output: &mut Vec<u8>, lengths: &Huffman, distances: &Huffman) -> bool {
+    loop {
+        let symbol = match lengths.decode(reader) { Some(symbol) => symbol, None => return false };
+        if symbol < END_OF_BLOCK {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == END_OF_BLOCK { return true; }
+
+        let index = (symbol - END_OF_BLOCK - 1) as uint;
+        if index >= LENGTH_BASES.len() { return false; }
+        let length = match reader.read_bits(LENGTH_EXTRA_BITS[index] as uint) {
+            Some(extra) => LENGTH_BASES[index] as uint + extra as uint,
+            None => return false
+        };
+
+        let index = match distances.decode(reader) { Some(symbol) => symbol as uint, None => return false };
+        if index >= DISTANCE_BASES.len() { return false; }
+        let distance = match reader.read_bits(DISTANCE_EXTRA_BITS[index] as uint) {
+            Some(extra) => DISTANCE_BASES[index] as uint + extra as uint,
+            None => return false
+        };
+        if distance > output.len() { return false; }
+
+        // The copy can overlap what it's copying, so it goes a byte at a
+        // time.
+        let start = output.len() - distance;
+        for i in range(0, length) {
+            let byte = output[start + i];
+            output.push(byte);
+        }
+    }
+}
+
+// The Adler-32 checksum that zlib uses.
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::FromHex;
+
+    use super::{zlib_decompress, inflate};
+
+    #[test]
+    fn test_fixed_codes() {
+        let data = "789ccb48cdc9c957c8402701680308b1".from_hex().unwrap();
+        assert_eq!(zlib_decompress(data.as_slice()).unwrap().as_slice(), b"hello hello hello hello");
+    }
+
+    #[test]
+    fn test_dynamic_codes() {
+        let data = "78dab5cbc70180201005d1567e05d4e2c10640490656b250bddb84e779b33a8d58fd764225ea01865e1cf57e32\
+                    a8e984c2f9927360272bb0fe8617c9ee1e508cba2f0ec637cd69ea80cbc74a895f9bc507b2fb3f0d".from_hex().unwrap();
+        let mut expected = Vec::new();
+        for _ in range(0, 3u) {
+            expected.push_all(b"The quick brown fox jumps over the lazy dog. ");
+        }
+        expected.push_all(b"Pack my box with five dozen liquor jugs.");
+        assert_eq!(zlib_decompress(data.as_slice()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_stored_block() {
+        let data = "7801010300fcff616263024d0127".from_hex().unwrap();
+        assert_eq!(zlib_decompress(data.as_slice()).unwrap().as_slice(), b"abc");
+        assert_eq!(inflate(data.slice(2, 10)).unwrap().as_slice(), b"abc");
+    }
+
+    #[test]
+    fn test_invalid() {
+        // A wrong checksum.
+        let data = "789ccb48cdc9c957c8402701680308b2".from_hex().unwrap();
+        assert!(zlib_decompress(data.as_slice()).is_none());
+        // Cut short.
+        let data = "789ccb48cdc9c957c840".from_hex().unwrap();
+        assert!(zlib_decompress(data.as_slice()).is_none());
+    }
+}
@@ -3,4 +3,19 @@
 pub mod ecdsa;
 pub mod base58;
 pub mod wif;
-
+pub mod hash;
+pub mod bytes;
+pub mod bech32;
+pub mod secp256k1;
+pub mod schnorr;
+pub mod secret;
+pub mod terminal;
+pub mod entropy;
+pub mod shamir;
+pub mod qr;
+pub mod ur;
+pub mod inflate;
+pub mod png;
+pub mod qr_scanner;
+#[cfg(feature = "openssl-ec")]
+pub mod openssl_ec;
@@ -0,0 +1,181 @@
+//! Public key derivation through OpenSSL's elliptic curve interface, which is
+//! how Cykas derived public keys before it had its own secp256k1 code. It's
+//! only built with the `openssl-ec` feature, and mostly exists so that the
+//! native backend can be checked against it.
+
+use libc::{c_int, c_uchar, size_t};
+use std::ptr;
+
+use util::ecdsa;
+use util::ecdsa::Backend;
+
+// OpenSSL's numeric code for the particular elliptic curve that Bitcoin uses.
+#[allow(non_upper_case_globals)]
+static NID_secp256k1: c_int = 714;
+
+// The length of an uncompressed public key.
+static PUBLIC_KEY_LENGTH: uint = 65;
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct EC_GROUP;
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct EC_POINT;
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct BIGNUM;
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct BN_CTX;
+
+#[allow(non_camel_case_types)]
+#[allow(dead_code)]
+#[repr(C)]
+enum point_conversion_form_t {
+    POINT_CONVERSION_COMPRESSED = 2,
+    POINT_CONVERSION_UNCOMPRESSED = 4,
+    POINT_CONVERSION_HYBRID = 6
+}
+
+#[link(name = "crypto")]
+extern {
+    fn EC_POINT_new(group: *const EC_GROUP) -> *mut EC_POINT;
+    fn EC_POINT_free(point: *mut EC_POINT);
+    fn EC_POINT_mul(group: *const EC_GROUP,
+                    r: *mut EC_POINT,
+                    n: *const BIGNUM,
+                    q: *const EC_POINT,
+                    m: *const BIGNUM,
+                    ctx: *mut BN_CTX) -> c_int;
+    fn EC_POINT_point2oct(group: *const EC_GROUP,
+                          p: *const EC_POINT,
+                          form: point_conversion_form_t,
+                          buf: *mut c_uchar,
+                          len: size_t,
+                          ctx: *mut BN_CTX) -> size_t;
+
+    fn EC_GROUP_new_by_curve_name(nid: c_int) -> *mut EC_GROUP;
+    fn EC_GROUP_free(group: *mut EC_GROUP);
+
+    fn BN_clear_free(a: *mut BIGNUM);
+    fn BN_bin2bn(s: *const c_uchar, len: c_int, ret: *mut BIGNUM) -> *mut BIGNUM;
+
+    fn BN_CTX_new() -> *mut BN_CTX;
+    fn BN_CTX_free(c: *mut BN_CTX);
+}
+
+/// The OpenSSL backend. Every OpenSSL call is checked, and if any of them
+/// fails, `derive_public_key()` returns None.
+pub struct OpensslBackend;
+
+// The OpenSSL objects used to derive a public key. They're freed when this
+// goes out of scope, however derive_public_key() returns.
+struct Resources {
+    group: *mut EC_GROUP,
+    ctx: *mut BN_CTX,
+    key: *mut BIGNUM,
+    point: *mut EC_POINT
+}
+
+impl Drop for Resources {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.point.is_null() { EC_POINT_free(self.point); }
+            // The private key is cleared before it's freed.
+            if !self.key.is_null() { BN_clear_free(self.key); }
+            if !self.ctx.is_null() { BN_CTX_free(self.ctx); }
+            if !self.group.is_null() { EC_GROUP_free(self.group); }
+        }
+    }
+}
+
+impl Backend for OpensslBackend {
+    fn derive_public_key(&self, private_key: &[u8]) -> Option<Vec<u8>> {
+        if !ecdsa::is_valid_private_key(private_key) { return None; }
+
+        let mut resources = Resources {
+            group: ptr::null_mut(),
+            ctx: ptr::null_mut(),
+            key: ptr::null_mut(),
+            point: ptr::null_mut()
+        };
+
+        unsafe {
+            resources.group = EC_GROUP_new_by_curve_name(NID_secp256k1);
+            if resources.group.is_null() { return None; }
+
+            resources.ctx = BN_CTX_new();
+            if resources.ctx.is_null() { return None; }
+
+            // With a null `ret`, BN_bin2bn() allocates a new bignum.
+            resources.key = BN_bin2bn(private_key.as_ptr(), private_key.len() as c_int, ptr::null_mut());
+            if resources.key.is_null() { return None; }
+
+            resources.point = EC_POINT_new(resources.group as *const EC_GROUP);
+            if resources.point.is_null() { return None; }
+
+            let status = EC_POINT_mul(resources.group as *const EC_GROUP, resources.point,
+                                      resources.key as *const BIGNUM, ptr::null(), ptr::null(),
+                                      resources.ctx);
+            if status != 1 { return None; }
+
+            let mut result = Vec::from_elem(PUBLIC_KEY_LENGTH, 0u8);
+            let length = EC_POINT_point2oct(resources.group as *const EC_GROUP,
+                                            resources.point as *const EC_POINT,
+                                            point_conversion_form_t::POINT_CONVERSION_UNCOMPRESSED,
+                                            result.as_mut_ptr(), PUBLIC_KEY_LENGTH as size_t,
+                                            resources.ctx);
+            if length as uint != PUBLIC_KEY_LENGTH || result[0] != 0x04 { return None; }
+
+            Some(result)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::FromHex;
+
+    use util::hash;
+    use util::ecdsa::{Backend, NativeBackend};
+
+    use super::OpensslBackend;
+
+    #[test]
+    fn test_backends_agree() {
+        let mut private_keys = vec![
+            "0000000000000000000000000000000000000000000000000000000000000001".from_hex().unwrap(),
+            "0000000000000000000000000000000000000000000000000000000000000002".from_hex().unwrap(),
+            "F7476532FE5753EBCBEA26FE02FFF18BF015546F85CAF78AC8D599547F7D3AAC".from_hex().unwrap(),
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364140".from_hex().unwrap()
+        ];
+        // And some keys that look random.
+        for i in range(0, 32u8) {
+            private_keys.push(hash::sha256(&[i]));
+        }
+
+        for private_key in private_keys.iter() {
+            let expected = OpensslBackend.derive_public_key(private_key.as_slice());
+            assert!(expected.is_some());
+            assert_eq!(NativeBackend.derive_public_key(private_key.as_slice()), expected);
+        }
+    }
+
+    #[test]
+    fn test_backends_reject_invalid_keys() {
+        let invalid_keys = vec![
+            "0000000000000000000000000000000000000000000000000000000000000000".from_hex().unwrap(),
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141".from_hex().unwrap(),
+            "01".from_hex().unwrap()
+        ];
+
+        for private_key in invalid_keys.iter() {
+            assert_eq!(OpensslBackend.derive_public_key(private_key.as_slice()), None);
+            assert_eq!(NativeBackend.derive_public_key(private_key.as_slice()), None);
+        }
+    }
+}
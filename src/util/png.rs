@@ -0,0 +1,385 @@
+//! PNG decoding (ISO/IEC 15948), as far as reading QR codes out of camera
+//! images needs: every kind of PNG image is read, and turned into 8-bit
+//! grayscale.
+
+use std::io::{IoResult, IoError, OtherIoError};
+
+use util::hash::crc32;
+use util::inflate;
+
+// Every PNG file starts with this.
+static SIGNATURE: [u8, ..8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+// Images with more pixels than this are rejected, rather than risk running
+// out of memory decoding them.
+static MAX_PIXELS: uint = 64 * 1024 * 1024;
+
+// The color types, which say what samples each pixel has.
+static GRAYSCALE: u8 = 0;
+static TRUECOLOR: u8 = 2;
+static INDEXED: u8 = 3;
+static GRAYSCALE_ALPHA: u8 = 4;
+static TRUECOLOR_ALPHA: u8 = 6;
+
+// The starting column and row, and the spacing, of the pixels in each of
+// the seven passes of an Adam7 interlaced image.
+static ADAM7_PASSES: [(uint, uint, uint, uint), ..7] = [
+    (0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)
+];
+
+/// A grayscale image, with one byte per pixel from 0 (black) to 255
+/// (white), row by row from the top left.
+pub struct Image {
+    width: uint,
+    height: uint,
+    pixels: Vec<u8>
+}
+
+impl Image {
+    /// Creates an Image from its pixels. Fails if there aren't exactly
+    /// `width * height` of them.
+    pub fn new(width: uint, height: uint, pixels: Vec<u8>) -> Image {
+        assert_eq!(pixels.len(), width * height);
+        Image { width: width, height: height, pixels: pixels }
+    }
+
+    /// Gets the width of the image, in pixels.
+    pub fn get_width(&self) -> uint {
+        self.width
+    }
+
+    /// Gets the height of the image, in pixels.
+    pub fn get_height(&self) -> uint {
+        self.height
+    }
+
+    /// Gets the brightness of the pixel in column `x` and row `y`.
+    pub fn get_pixel(&self, x: uint, y: uint) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+}
+
+// What the IHDR chunk says about the image.
+struct Header {
+    width: uint,
+    height: uint,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool
+}
+
+impl Header {
+    fn channels(&self) -> uint {
+        if self.color_type == TRUECOLOR {
+            3
+        } else if self.color_type == GRAYSCALE_ALPHA {
+            2
+        } else if self.color_type == TRUECOLOR_ALPHA {
+            4
+        } else {
+            1
+        }
+    }
+
+    // The number of bytes in a row of the given number of pixels, not
+    // counting its filter type.
+    fn row_length(&self, width: uint) -> uint {
+        (width * self.channels() * self.bit_depth as uint + 7) / 8
+    }
+
+    // The distance between a byte and the corresponding byte of the pixel
+    // before it, for filtering.
+    fn filter_distance(&self) -> uint {
+        let bits = self.channels() * self.bit_depth as uint;
+        if bits < 8 { 1 } else { bits / 8 }
+    }
+}
+
+/// Decodes a PNG image to grayscale. Colors are converted to their
+/// brightness, and transparent pixels are drawn over white. Returns an
+/// OtherIoError if the image is invalid.
+pub fn decode(data: &[u8]) -> IoResult<Image> {
+    if data.len() < SIGNATURE.len() || data.slice_to(SIGNATURE.len()) != SIGNATURE.as_slice() {
+        return Err(invalid("The file isn't a PNG image"));
+    }
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut compressed = Vec::new();
+
+    let mut position = SIGNATURE.len();
+    loop {
+        if position + 12 > data.len() {
+            return Err(invalid("The image ends before its IEND chunk"));
+        }
+        let length = read_u32(data.slice_from(position)) as uint;
+        if length > data.len() - position - 12 {
+            return Err(invalid("A chunk is longer than the rest of the image"));
+        }
+        let chunk_type = data.slice(position + 4, position + 8);
+        let chunk_data = data.slice(position + 8, position + 8 + length);
+        let checksum = read_u32(data.slice_from(position + 8 + length));
+        if checksum != crc32(data.slice(position + 4, position + 8 + length)) {
+            return Err(invalid("A chunk's checksum is wrong"));
+        }
+        position += 12 + length;
+
+        // Other chunks, like gamma and text, don't matter here.
+        if chunk_type == b"IHDR" {
+            header = Some(try!(read_header(chunk_data)));
+        } else if chunk_type == b"PLTE" {
+            palette = chunk_data.to_vec();
+        } else if chunk_type == b"IDAT" {
+            compressed.push_all(chunk_data);
+        } else if chunk_type == b"IEND" {
+            break;
+        }
+    }
+
+    let header = match header {
+        Some(header) => header,
+        None => return Err(invalid("The image has no IHDR chunk"))
+    };
+    if header.color_type == INDEXED && (palette.is_empty() || palette.len() % 3 != 0) {
+        return Err(invalid("The image has no valid palette"));
+    }
+
+    let raw = match inflate::zlib_decompress(compressed.as_slice()) {
+        Some(raw) => raw,
+        None => return Err(invalid("The image data isn't valid zlib data"))
+    };
+
+    let mut pixels = Vec::from_elem(header.width * header.height, 0u8);
+    let passes = if header.interlaced { ADAM7_PASSES.as_slice() } else { [(0, 0, 1, 1)].as_slice() };
+    let mut offset = 0;
+    for &(x0, y0, dx, dy) in passes.iter() {
+        let pass_width = (header.width + dx - 1 - x0) / dx;
+        let pass_height = (header.height + dy - 1 - y0) / dy;
+        if pass_width == 0 || pass_height == 0 { continue; }
+
+        let length = (header.row_length(pass_width) + 1) * pass_height;
+        if offset + length > raw.len() {
+            return Err(invalid("The image data is too short"));
+        }
+        let rows = try!(unfilter(&header, raw.slice(offset, offset + length), pass_width));
+        offset += length;
+
+        let row_length = header.row_length(pass_width);
+        for y in range(0, pass_height) {
+            let row = rows.slice(y * row_length, (y + 1) * row_length);
+            for x in range(0, pass_width) {
+                let gray = try!(to_gray(&header, row, x, palette.as_slice()));
+                *pixels.index_mut(&((y0 + y * dy) * header.width + x0 + x * dx)) = gray;
+            }
+        }
+    }
+
+    Ok(Image::new(header.width, header.height, pixels))
+}
+
+fn invalid(detail: &str) -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: "invalid PNG image",
+        detail: Some(detail.to_string())
+    }
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    (data[0] as u32 << 24) | (data[1] as u32 << 16) | (data[2] as u32 << 8) | data[3] as u32
+}
+
+fn read_header(data: &[u8]) -> IoResult<Header> {
+    if data.len() != 13 { return Err(invalid("The IHDR chunk is the wrong length")); }
+
+    let header = Header {
+        width: read_u32(data) as uint,
+        height: read_u32(data.slice_from(4)) as uint,
+        bit_depth: data[8],
+        color_type: data[9],
+        interlaced: data[12] == 1
+    };
+
+    let color_type = header.color_type;
+    let valid_depth = if color_type == GRAYSCALE {
+        [1, 2, 4, 8, 16].contains(&header.bit_depth)
+    } else if color_type == INDEXED {
+        [1, 2, 4, 8].contains(&header.bit_depth)
+    } else if color_type == TRUECOLOR || color_type == GRAYSCALE_ALPHA || color_type == TRUECOLOR_ALPHA {
+        [8, 16].contains(&header.bit_depth)
+    } else {
+        false
+    };
+    if !valid_depth {
+        return Err(invalid("The image's color type or bit depth is invalid"));
+    }
+    if data[10] != 0 || data[11] != 0 || data[12] > 1 {
+        return Err(invalid("The image uses an unknown compression, filter or interlace method"));
+    }
+    if header.width == 0 || header.height == 0 || header.width > MAX_PIXELS / header.height {
+        return Err(invalid("The image is empty or too big"));
+    }
+
+    Ok(header)
+}
+
+// Undoes the filter on each row of (a pass of) the image, and returns the
+// rows without their filter type bytes.
+fn unfilter(header: &Header, data: &[u8], width: uint) -> IoResult<Vec<u8>> {
+    let row_length = header.row_length(width);
+    let distance = header.filter_distance();
+    let height = data.len() / (row_length + 1);
+    let mut rows: Vec<u8> = Vec::with_capacity(row_length * height);
+
+    for y in range(0, height) {
+        let filter = data[y * (row_length + 1)];
+        let row = data.slice(y * (row_length + 1) + 1, (y + 1) * (row_length + 1));
+        let start = y * row_length;
+
+        for i in range(0, row_length) {
+            // The byte to the left, the byte above, and the byte above that.
+            let left = if i >= distance { rows[start + i - distance] } else { 0 };
+            let up = if y > 0 { rows[start + i - row_length] } else { 0 };
+            let up_left = if y > 0 && i >= distance { rows[start + i - row_length - distance] } else { 0 };
+
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as uint + up as uint) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(invalid("A row has an unknown filter type"))
+            };
+            rows.push(row[i] + predicted);
+        }
+    }
+
+    Ok(rows)
+}
+
+// The Paeth predictor: whichever of the three neighbors is closest to
+// left + up - up_left.
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as int + up as int - up_left as int;
+    let (distance_left, distance_up, distance_up_left) =
+        ((estimate - left as int).abs(), (estimate - up as int).abs(), (estimate - up_left as int).abs());
+    if distance_left <= distance_up && distance_left <= distance_up_left {
+        left
+    } else if distance_up <= distance_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+// Reads the sample in the given channel of the pixel in a row, scaled to 8
+// bits.
+fn sample(header: &Header, row: &[u8], x: uint, channel: uint) -> u8 {
+    let depth = header.bit_depth as uint;
+    let index = x * header.channels() + channel;
+    match depth {
+        8 => row[index],
+        // The high byte is enough.
+        16 => row[index * 2],
+        _ => {
+            let bit = index * depth;
+            let value = (row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1) as u8;
+            if header.color_type == INDEXED { value } else { (value as uint * 255 / ((1 << depth) - 1)) as u8 }
+        }
+    }
+}
+
+// Works out the brightness of a pixel in a row.
+fn to_gray(header: &Header, row: &[u8], x: uint, palette: &[u8]) -> IoResult<u8> {
+    let color_type = header.color_type;
+    let (red, green, blue, alpha) = if color_type == GRAYSCALE {
+        let gray = sample(header, row, x, 0);
+        (gray, gray, gray, 255)
+    } else if color_type == GRAYSCALE_ALPHA {
+        let gray = sample(header, row, x, 0);
+        (gray, gray, gray, sample(header, row, x, 1))
+    } else if color_type == INDEXED {
+        let index = sample(header, row, x, 0) as uint * 3;
+        if index + 3 > palette.len() {
+            return Err(invalid("A pixel's color isn't in the palette"));
+        }
+        (palette[index], palette[index + 1], palette[index + 2], 255)
+    } else if color_type == TRUECOLOR {
+        (sample(header, row, x, 0), sample(header, row, x, 1), sample(header, row, x, 2), 255)
+    } else {
+        (sample(header, row, x, 0), sample(header, row, x, 1), sample(header, row, x, 2), sample(header, row, x, 3))
+    };
+
+    // The ITU-R BT.601 luma weights, then blended with white by the alpha.
+    let luma = (299 * red as uint + 587 * green as uint + 114 * blue as uint) / 1000;
+    Ok(((luma * alpha as uint + 255 * (255 - alpha as uint)) / 255) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::FromHex;
+
+    use super::decode;
+
+    fn decode_hex(hex: &str) -> Vec<u8> {
+        let image = decode(hex.from_hex().unwrap().as_slice()).unwrap();
+        let mut pixels = Vec::new();
+        for y in range(0, image.get_height()) {
+            for x in range(0, image.get_width()) {
+                pixels.push(image.get_pixel(x, y));
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn test_decode_truecolor() {
+        // 3x2, with the Sub and Paeth filters and the data split over two
+        // IDAT chunks.
+        assert_eq!(decode_hex("89504e470d0a1a0a0000000d49484452000000030000000208020000001216f14d0000000467414d41\
+                               0000b18f0bfc61050000000e4944415478da635cea7baaf846dae21fb62c27ece2090000000f49444154\
+                               79f7450efff38ed8b31800688e0a6f5d6e45000000000049454e44ae426082"),
+                   vec![117, 34, 85, 56, 98, 144]);
+    }
+
+    #[test]
+    fn test_decode_grayscale() {
+        // 5x3 with one bit per pixel, and the Up and Average filters.
+        assert_eq!(decode_hex("89504e470d0a1a0a0000000d4948445200000005000000030100000000734df8550000000467414d41\
+                               0000b18f0bfc6105000000074944415478da6312601660180bf7070000000749444154e2000000aa0030\
+                               2f5f84000000000049454e44ae426082"),
+                   vec![0, 0, 0, 255, 0, 0, 0, 0, 255, 255, 0, 0, 255, 0, 0]);
+    }
+
+    #[test]
+    fn test_decode_interlaced_palette() {
+        // 5x5, Adam7 interlaced, with two bits per pixel indexing black,
+        // white, red and blue.
+        assert_eq!(decode_hex("89504e470d0a1a0a0000000d49484452000000050000000502030000018706fee00000000c504c5445\
+                               000000ffffffff00000000ff011d334a0000000467414d410000b18f0bfc6105000000104944415478da\
+                               6370606060f8c0c0c0c8c0f084a18cd310ea0000001049444154817102d30786ba038cdea6003aca05d8\
+                               3f2e63ae0000000049454e44ae426082"),
+                   vec![255, 76, 0, 0, 0, 255, 29, 29, 76, 29, 29, 76, 76, 255, 255, 255, 0, 76, 29, 76, 29, 76, 0, 0, 29]);
+    }
+
+    #[test]
+    fn test_decode_grayscale_alpha() {
+        // 2x2 with 16-bit samples.
+        assert_eq!(decode_hex("89504e470d0a1a0a0000000d4948445200000002000000021004000000882f19ec0000000467414d41\
+                               0000b18f0bfc61050000000d4944415478da6309295daff833dadb827987237f5a0000000e49444154ed\
+                               babd4c531f4ae6010043f3078fa26d72070000000049454e44ae426082"),
+                   vec![137, 80, 251, 119]);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // Not a PNG image.
+        assert!(decode(b"GIF89a").is_err());
+        // The IHDR chunk's checksum is wrong.
+        assert!(decode("89504e470d0a1a0a0000000d49484452000000020000000210040000008\
+                        82f19ed0000000049454e44ae426082".from_hex().unwrap().as_slice()).is_err());
+        // It ends without an IEND chunk.
+        assert!(decode("89504e470d0a1a0a0000000d4948445200000002000000021004000000882f19ec"
+                       .from_hex().unwrap().as_slice()).is_err());
+    }
+}
@@ -0,0 +1,1135 @@
+//! QR code encoding (ISO/IEC 18004), for printing data or showing it on a
+//! screen so it can be read back with a camera. Each code holds a single
+//! segment in byte mode, or in the denser alphanumeric mode when the data
+//! only uses its characters (which include uppercase hex). Codes can be
+//! decoded again too, from their modules as sampled from a picture.
+
+use std::cmp;
+use std::iter::range_step;
+use std::num::Int;
+
+/// How much of a QR code can be damaged and still be read: about 7%, 15%, 25%
+/// or 30% of it. More error correction leaves less room for data.
+#[deriving(Clone, Copy, PartialEq, Show)]
+pub enum ErrorCorrection {
+    Low,
+    Medium,
+    Quartile,
+    High
+}
+
+impl ErrorCorrection {
+    // The index into the tables below.
+    fn index(&self) -> uint {
+        match *self {
+            ErrorCorrection::Low => 0,
+            ErrorCorrection::Medium => 1,
+            ErrorCorrection::Quartile => 2,
+            ErrorCorrection::High => 3
+        }
+    }
+
+    // The two bits that represent the level in the format information.
+    fn format_bits(&self) -> u32 {
+        match *self {
+            ErrorCorrection::Low => 1,
+            ErrorCorrection::Medium => 0,
+            ErrorCorrection::Quartile => 3,
+            ErrorCorrection::High => 2
+        }
+    }
+}
+
+// The error correction levels, for trying each of them.
+static ERROR_CORRECTION_LEVELS: [ErrorCorrection, ..4] =
+    [ErrorCorrection::Low, ErrorCorrection::Medium, ErrorCorrection::Quartile, ErrorCorrection::High];
+
+/// The largest QR code version. A version N code is 4N + 17 modules across.
+pub static MAX_VERSION: uint = 40;
+
+// The characters that alphanumeric mode can encode, in the order of their
+// values.
+static ALPHANUMERIC_CHARSET: &'static str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+// The number of error correction codewords in each block, for each error
+// correction level and version (with a placeholder for version 0).
+static ECC_CODEWORDS_PER_BLOCK: [[u8, ..41], ..4] = [
+    [0, 7, 10, 15, 20, 26, 18, 20, 24, 30, 18, 20, 24, 26, 30, 22, 24, 28, 30, 28, 28,
+     28, 28, 30, 30, 26, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+    [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26,
+     26, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28],
+    [0, 13, 22, 18, 26, 18, 24, 18, 22, 20, 24, 28, 26, 24, 20, 30, 24, 28, 28, 26, 30,
+     28, 30, 30, 30, 30, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+    [0, 17, 28, 22, 16, 22, 28, 26, 26, 24, 28, 24, 28, 22, 24, 24, 30, 28, 28, 26, 28,
+     30, 24, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30]
+];
+
+// The number of error correction blocks the codewords are split into, for
+// each error correction level and version.
+static ERROR_CORRECTION_BLOCKS: [[u8, ..41], ..4] = [
+    [0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 4, 6, 6, 6, 6, 7, 8,
+     8, 9, 9, 10, 12, 12, 12, 13, 14, 15, 16, 17, 18, 19, 19, 20, 21, 22, 24, 25],
+    [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16,
+     17, 17, 18, 20, 21, 23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49],
+    [0, 1, 1, 2, 2, 4, 4, 6, 6, 8, 8, 8, 10, 12, 16, 12, 17, 16, 18, 21, 20,
+     23, 23, 25, 27, 29, 34, 34, 35, 38, 40, 43, 45, 48, 51, 53, 56, 59, 62, 65, 68],
+    [0, 1, 1, 2, 4, 4, 4, 5, 6, 8, 8, 11, 11, 16, 16, 18, 16, 19, 21, 25, 25,
+     25, 34, 30, 32, 35, 37, 40, 42, 45, 48, 51, 54, 57, 60, 63, 66, 70, 74, 77, 81]
+];
+
+// Penalty weights for choosing a mask, from the specification.
+static PENALTY_RUN: uint = 3;
+static PENALTY_BLOCK: uint = 3;
+static PENALTY_FINDER_LIKE: uint = 40;
+static PENALTY_BALANCE: uint = 10;
+
+// The most bits of the format information that can be wrong and still be
+// corrected: the valid format bits all differ in at least seven.
+static MAX_FORMAT_ERRORS: uint = 3;
+
+// Likewise for the version information, where they differ in at least eight.
+static MAX_VERSION_ERRORS: uint = 3;
+
+/// A QR code: a square grid of dark and light modules, not counting the
+/// light border (the quiet zone) that should be left around it.
+pub struct QrCode {
+    version: uint,
+    size: uint,
+    modules: Vec<bool>,
+    // Which modules belong to function patterns rather than data. Only used
+    // while the code is being drawn.
+    is_function: Vec<bool>
+}
+
+impl QrCode {
+    /// Encodes the data in the smallest QR code that will hold it with the
+    /// given error correction level. Returns None if it's too long for even
+    /// a version 40 code.
+    pub fn encode(data: &[u8], error_correction: ErrorCorrection) -> Option<QrCode> {
+        let alphanumeric = is_alphanumeric(data);
+
+        let version = match range(1, MAX_VERSION + 1).find(|&version| {
+            segment_bits(data.len(), alphanumeric, version) <= data_codewords(version, error_correction) * 8
+        }) {
+            Some(version) => version,
+            None => return None
+        };
+
+        let size = version * 4 + 17;
+        let mut qr = QrCode {
+            version: version,
+            size: size,
+            modules: Vec::from_elem(size * size, false),
+            is_function: Vec::from_elem(size * size, false)
+        };
+
+        let codewords = add_error_correction(encode_data(data, alphanumeric, version, error_correction).as_slice(),
+                                             version, error_correction);
+        qr.draw_function_patterns(error_correction);
+        qr.draw_codewords(codewords.as_slice());
+
+        // Masking is its own inverse, so each mask can be tried and undone.
+        let mut best_mask = 0;
+        let mut min_penalty = None;
+        for mask in range(0, 8u) {
+            qr.apply_mask(mask);
+            qr.draw_format_bits(error_correction, mask);
+            let penalty = qr.penalty();
+            if min_penalty.map_or(true, |min_penalty| penalty < min_penalty) {
+                best_mask = mask;
+                min_penalty = Some(penalty);
+            }
+            qr.apply_mask(mask);
+        }
+        qr.apply_mask(best_mask);
+        qr.draw_format_bits(error_correction, best_mask);
+
+        Some(qr)
+    }
+
+    /// Gets the version of the QR code, from 1 to 40.
+    pub fn get_version(&self) -> uint {
+        self.version
+    }
+
+    /// Gets the width and height of the QR code, in modules.
+    pub fn get_size(&self) -> uint {
+        self.size
+    }
+
+    /// Checks whether the module in column `x` and row `y` is dark. Modules
+    /// outside the code are light, like the quiet zone.
+    pub fn is_dark(&self, x: int, y: int) -> bool {
+        let size = self.size as int;
+        0 <= x && x < size && 0 <= y && y < size && self.modules[y as uint * self.size + x as uint]
+    }
+
+    /// Renders the QR code as an SVG path of its dark modules, with its top
+    /// left corner at (x, y) and each module the given size.
+    pub fn to_svg_path(&self, x: f64, y: f64, module_size: f64) -> String {
+        let mut path = String::new();
+        for row in range(0, self.size) {
+            for column in range(0, self.size) {
+                if self.is_dark(column as int, row as int) {
+                    path.push_str(format!("M{:.2},{:.2}h{:.2}v{:.2}h-{:.2}z",
+                                          x + column as f64 * module_size, y + row as f64 * module_size,
+                                          module_size, module_size, module_size).as_slice());
+                }
+            }
+        }
+        format!("<path d=\"{}\" fill=\"#000\"/>", path)
+    }
+
+    /// Renders the QR code as lines of text for printing in a monospace
+    /// font, with two characters per module so that it comes out roughly
+    /// square, and a quiet zone of `border` modules around it.
+    pub fn to_ascii(&self, border: uint) -> Vec<String> {
+        let border = border as int;
+        let size = self.size as int;
+        range(-border, size + border).map(|y| {
+            let mut line = String::new();
+            for x in range(-border, size + border) {
+                line.push_str(if self.is_dark(x, y) { "##" } else { "  " });
+            }
+            line
+        }).collect()
+    }
+
+    /// Renders the QR code as lines of text for a terminal, using Unicode
+    /// half blocks so that each character is one module wide and two
+    /// modules tall, with a quiet zone of `border` modules around it. With
+    /// `invert`, the light modules are drawn instead of the dark ones, which
+    /// is what a terminal with light text on a dark background needs.
+    pub fn to_unicode(&self, border: uint, invert: bool) -> Vec<String> {
+        let border = border as int;
+        let end = self.size as int + border;
+
+        let mut lines = Vec::new();
+        for y in range_step(-border, end, 2) {
+            let mut line = String::new();
+            for x in range(-border, end) {
+                // The bottom half of the last line is past the quiet zone
+                // when it has an odd number of rows.
+                let top = self.is_dark(x, y) != invert;
+                let bottom = y + 1 < end && self.is_dark(x, y + 1) != invert;
+                line.push(match (top, bottom) {
+                    (true, true) => '\u2588',
+                    (true, false) => '\u2580',
+                    (false, true) => '\u2584',
+                    (false, false) => ' '
+                });
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn set_function_module(&mut self, x: uint, y: uint, dark: bool) {
+        let index = y * self.size + x;
+        *self.modules.index_mut(&index) = dark;
+        *self.is_function.index_mut(&index) = true;
+    }
+
+    fn draw_function_patterns(&mut self, error_correction: ErrorCorrection) {
+        let size = self.size;
+
+        // Timing patterns.
+        for i in range(0, size) {
+            self.set_function_module(6, i, i % 2 == 0);
+            self.set_function_module(i, 6, i % 2 == 0);
+        }
+
+        // Finder patterns in three corners, with their light separators.
+        for &(x, y) in [(3, 3), (size - 4, 3), (3, size - 4)].iter() {
+            for dy in range(-4, 5i) {
+                for dx in range(-4, 5i) {
+                    let (module_x, module_y) = (x as int + dx, y as int + dy);
+                    if 0 <= module_x && module_x < size as int && 0 <= module_y && module_y < size as int {
+                        let distance = cmp::max(dx.abs(), dy.abs());
+                        self.set_function_module(module_x as uint, module_y as uint,
+                                                 distance != 2 && distance != 4);
+                    }
+                }
+            }
+        }
+
+        // Alignment patterns, except where they'd overlap the finders.
+        let positions = alignment_pattern_positions(self.version);
+        let last = positions.len() - 1;
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                if (i == 0 && j == 0) || (i == 0 && j == last) || (i == last && j == 0) { continue; }
+                for dy in range(-2, 3i) {
+                    for dx in range(-2, 3i) {
+                        self.set_function_module((x as int + dx) as uint, (y as int + dy) as uint,
+                                                 cmp::max(dx.abs(), dy.abs()) != 1);
+                    }
+                }
+            }
+        }
+
+        // The format bits are reserved with a dummy mask until the real one
+        // is chosen.
+        self.draw_format_bits(error_correction, 0);
+
+        if self.version >= 7 {
+            let bits = version_bits(self.version);
+            for i in range(0, 18u) {
+                let dark = (bits >> i) & 1 == 1;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function_module(a, b, dark);
+                self.set_function_module(b, a, dark);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, error_correction: ErrorCorrection, mask: uint) {
+        let bits = format_bits(error_correction, mask);
+        let bit = |i: uint| (bits >> i) & 1 == 1;
+        let size = self.size;
+
+        // The first copy, around the top left finder.
+        for i in range(0, 6u) {
+            self.set_function_module(8, i, bit(i));
+        }
+        self.set_function_module(8, 7, bit(6));
+        self.set_function_module(8, 8, bit(7));
+        self.set_function_module(7, 8, bit(8));
+        for i in range(9, 15u) {
+            self.set_function_module(14 - i, 8, bit(i));
+        }
+
+        // The second copy, split between the other two finders.
+        for i in range(0, 8u) {
+            self.set_function_module(size - 1 - i, 8, bit(i));
+        }
+        for i in range(8, 15u) {
+            self.set_function_module(8, size - 15 + i, bit(i));
+        }
+
+        // This module is always dark.
+        self.set_function_module(8, size - 8, true);
+    }
+
+    // Draws the codewords into the data area, in two-module-wide columns
+    // that zigzag up and down from the right edge, skipping the vertical
+    // timing pattern.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        loop {
+            if right == 6 { right = 5; }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in range(0, size) {
+                let y = if upward { size - 1 - vertical } else { vertical };
+                for x in [right, right - 1].iter() {
+                    let index = y * size + *x;
+                    if !self.is_function[index] && i < codewords.len() * 8 {
+                        *self.modules.index_mut(&index) = (codewords[i / 8] >> (7 - i % 8)) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 3 { break; }
+            right -= 2;
+        }
+    }
+
+    // Reads the codewords back out of the data area, the same way
+    // draw_codewords puts them in.
+    fn read_codewords(&self) -> Vec<u8> {
+        let size = self.size;
+        let mut codewords = Vec::from_elem(raw_data_modules(self.version) / 8, 0u8);
+        let mut i = 0;
+        let mut right = size - 1;
+        loop {
+            if right == 6 { right = 5; }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in range(0, size) {
+                let y = if upward { size - 1 - vertical } else { vertical };
+                for x in [right, right - 1].iter() {
+                    let index = y * size + *x;
+                    if !self.is_function[index] && i < codewords.len() * 8 {
+                        if self.modules[index] {
+                            *codewords.index_mut(&(i / 8)) |= 1 << (7 - i % 8);
+                        }
+                        i += 1;
+                    }
+                }
+            }
+            if right < 3 { break; }
+            right -= 2;
+        }
+        codewords
+    }
+
+    fn apply_mask(&mut self, mask: uint) {
+        for y in range(0, self.size) {
+            for x in range(0, self.size) {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0
+                };
+                let index = y * self.size + x;
+                if invert && !self.is_function[index] {
+                    *self.modules.index_mut(&index) = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    // Scores how hard the code would be to read, from runs of the same
+    // color, 2x2 blocks, patterns that look like finders, and the balance
+    // of dark and light modules.
+    fn penalty(&self) -> uint {
+        let size = self.size;
+        let mut penalty = 0;
+
+        let finder_like: [bool, ..11] = [true, false, true, true, true, false, true, false, false, false, false];
+        for i in range(0, size) {
+            let row: Vec<bool> = range(0, size).map(|x| self.modules[i * size + x]).collect();
+            let column: Vec<bool> = range(0, size).map(|y| self.modules[y * size + i]).collect();
+
+            for line in [row, column].iter() {
+                let mut run = 1;
+                for j in range(1, size + 1) {
+                    if j < size && line[j] == line[j - 1] {
+                        run += 1;
+                    } else {
+                        if run >= 5 { penalty += PENALTY_RUN + run - 5; }
+                        run = 1;
+                    }
+                }
+
+                for window in line.as_slice().windows(11) {
+                    let forwards = window.iter().zip(finder_like.iter()).all(|(a, b)| a == b);
+                    let backwards = window.iter().zip(finder_like.iter().rev()).all(|(a, b)| a == b);
+                    if forwards || backwards { penalty += PENALTY_FINDER_LIKE; }
+                }
+            }
+        }
+
+        for y in range(0, size - 1) {
+            for x in range(0, size - 1) {
+                let dark = self.modules[y * size + x];
+                if self.modules[y * size + x + 1] == dark && self.modules[(y + 1) * size + x] == dark &&
+                   self.modules[(y + 1) * size + x + 1] == dark {
+                    penalty += PENALTY_BLOCK;
+                }
+            }
+        }
+
+        // Every 5% that the proportion of dark modules is away from 50%.
+        let dark = self.modules.iter().filter(|&&dark| dark).count() as int;
+        let total = (size * size) as int;
+        penalty += ((dark * 2 - total).abs() * 10 / total) as uint * PENALTY_BALANCE;
+
+        penalty
+    }
+}
+
+/// Reads the data out of a QR code, from its modules as they were sampled
+/// from a picture of it: `size` rows of `size` modules, true for the dark
+/// ones. Modules that were read wrong are put right by the error correction,
+/// as long as there aren't too many of them. Returns None if the code can't
+/// be read.
+pub fn decode(size: uint, modules: &[bool]) -> Option<Vec<u8>> {
+    if size < 21 || size > MAX_VERSION * 4 + 17 || size % 4 != 1 || modules.len() != size * size {
+        return None;
+    }
+    let version = (size - 17) / 4;
+    let (error_correction, mask) = match read_format_bits(size, modules) {
+        Some(format) => format,
+        None => return None
+    };
+
+    // The function patterns are drawn on a blank code first, to find out
+    // which modules hold data.
+    let mut qr = QrCode {
+        version: version,
+        size: size,
+        modules: Vec::from_elem(size * size, false),
+        is_function: Vec::from_elem(size * size, false)
+    };
+    qr.draw_function_patterns(error_correction);
+    qr.modules = modules.to_vec();
+    qr.apply_mask(mask);
+
+    match correct_errors(qr.read_codewords().as_slice(), version, error_correction) {
+        Some(data) => parse_segments(data.as_slice(), version),
+        None => None
+    }
+}
+
+/// Finds the version that a code's 18 version bits stand for, as they were
+/// read from a picture of it, allowing for a few to have been read wrong.
+/// Returns None if they're too far from any version's.
+pub fn decode_version_bits(bits: u32) -> Option<uint> {
+    let mut result = None;
+    let mut min_distance = MAX_VERSION_ERRORS + 1;
+    for version in range(7, MAX_VERSION + 1) {
+        let distance = (version_bits(version) ^ bits).count_ones();
+        if distance < min_distance {
+            result = Some(version);
+            min_distance = distance;
+        }
+    }
+    result
+}
+
+// Checks whether every byte of the data can be encoded in alphanumeric mode.
+fn is_alphanumeric(data: &[u8]) -> bool {
+    data.iter().all(|&byte| ALPHANUMERIC_CHARSET.as_bytes().contains(&byte))
+}
+
+// The number of bits in the character count of a segment.
+fn character_count_bits(alphanumeric: bool, version: uint) -> uint {
+    match (alphanumeric, version) {
+        (false, 1...9) => 8,
+        (false, _) => 16,
+        (true, 1...9) => 9,
+        (true, 10...26) => 11,
+        (true, _) => 13
+    }
+}
+
+// The number of bits it takes to encode a segment of the given length.
+fn segment_bits(length: uint, alphanumeric: bool, version: uint) -> uint {
+    let data_bits = if alphanumeric { length / 2 * 11 + length % 2 * 6 } else { length * 8 };
+    4 + character_count_bits(alphanumeric, version) + data_bits
+}
+
+// The number of modules in a QR code of the given version that are left for
+// codewords after the function patterns.
+fn raw_data_modules(version: uint) -> uint {
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignment_count = version / 7 + 2;
+        result -= (25 * alignment_count - 10) * alignment_count - 55;
+        if version >= 7 { result -= 36; }
+    }
+    result
+}
+
+// The number of codewords of data (without error correction) that a QR code
+// of the given version and error correction level holds.
+fn data_codewords(version: uint, error_correction: ErrorCorrection) -> uint {
+    let level = error_correction.index();
+    raw_data_modules(version) / 8 -
+        ECC_CODEWORDS_PER_BLOCK[level][version] as uint * ERROR_CORRECTION_BLOCKS[level][version] as uint
+}
+
+// The centers of the alignment patterns, along both axes.
+fn alignment_pattern_positions(version: uint) -> Vec<uint> {
+    if version == 1 { return Vec::new(); }
+
+    let count = version / 7 + 2;
+    let step = (version * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+    let mut positions: Vec<uint> = range(0, count - 1).map(|i| version * 4 + 10 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+// Encodes the data as a segment and pads it to fill the data codewords.
+fn encode_data(data: &[u8], alphanumeric: bool, version: uint, error_correction: ErrorCorrection) -> Vec<u8> {
+    let mut bits = BitBuffer { bytes: Vec::new(), length: 0 };
+
+    bits.append(if alphanumeric { 0x2 } else { 0x4 }, 4);
+    bits.append(data.len() as u32, character_count_bits(alphanumeric, version));
+    if alphanumeric {
+        let values: Vec<u32> = data.iter().map(|&byte| {
+            ALPHANUMERIC_CHARSET.as_bytes().position_elem(&byte).unwrap() as u32
+        }).collect();
+        for pair in values.as_slice().chunks(2) {
+            match pair {
+                [a, b] => bits.append(a * 45 + b, 11),
+                [a] => bits.append(a, 6),
+                _ => unreachable!()
+            }
+        }
+    } else {
+        for &byte in data.iter() {
+            bits.append(byte as u32, 8);
+        }
+    }
+
+    // A terminator of up to four zero bits, then zeros to the end of the
+    // byte, then alternating pad bytes.
+    let capacity = data_codewords(version, error_correction) * 8;
+    let terminator = cmp::min(capacity - bits.length, 4);
+    bits.append(0, terminator);
+    let padding = (8 - bits.length % 8) % 8;
+    bits.append(0, padding);
+    for &pad in [0xEC, 0x11].iter().cycle().take((capacity - bits.length) / 8) {
+        bits.append(pad, 8);
+    }
+
+    bits.bytes
+}
+
+// Parses the segments in the data codewords of a code, and returns the data
+// they hold. Returns None if they aren't valid, or use a mode that isn't
+// supported.
+fn parse_segments(data: &[u8], version: uint) -> Option<Vec<u8>> {
+    let mut reader = BitReader { bytes: data, position: 0 };
+    let mut result = Vec::new();
+
+    // The data ends at a terminator, or when there's no room left for one.
+    while reader.remaining() >= 4 {
+        let mode = reader.read(4).unwrap();
+        if mode == 0 { break; }
+
+        // An extended channel interpretation just says what character set
+        // the data is in, which is left to whoever reads the data.
+        if mode == 7 {
+            let extra_bits = match reader.read(8) {
+                Some(designator) if designator & 0x80 == 0 => 0,
+                Some(designator) if designator & 0xC0 == 0x80 => 8,
+                Some(designator) if designator & 0xE0 == 0xC0 => 16,
+                _ => return None
+            };
+            if reader.read(extra_bits).is_none() { return None; }
+            continue;
+        }
+
+        let count_bits = match (mode, version) {
+            // Numeric.
+            (1, 1...9) => 10,
+            (1, 10...26) => 12,
+            (1, _) => 14,
+            // Alphanumeric.
+            (2, _) => character_count_bits(true, version),
+            // Byte.
+            (4, _) => character_count_bits(false, version),
+            _ => return None
+        };
+        let mut count = match reader.read(count_bits) {
+            Some(count) => count as uint,
+            None => return None
+        };
+
+        if mode == 1 {
+            // Three digits in ten bits, then two in seven or one in four.
+            while count > 0 {
+                let digits = cmp::min(count, 3);
+                let value = match reader.read(digits * 3 + 1) {
+                    Some(value) if (value as uint) < [1, 10, 100, 1000][digits] => value as uint,
+                    _ => return None
+                };
+                for place in [100, 10, 1].slice_from(3 - digits).iter() {
+                    result.push(b'0' + (value / *place % 10) as u8);
+                }
+                count -= digits;
+            }
+        } else if mode == 2 {
+            // Two characters in eleven bits, then one in six.
+            let charset = ALPHANUMERIC_CHARSET.as_bytes();
+            while count > 0 {
+                let characters = cmp::min(count, 2);
+                let value = match reader.read(characters * 5 + 1) {
+                    Some(value) if (value as uint) < [1, 45, 45 * 45][characters] => value as uint,
+                    _ => return None
+                };
+                if characters == 2 {
+                    result.push(charset[value / 45]);
+                }
+                result.push(charset[value % 45]);
+                count -= characters;
+            }
+        } else {
+            for _ in range(0, count) {
+                match reader.read(8) {
+                    Some(byte) => result.push(byte as u8),
+                    None => return None
+                }
+            }
+        }
+    }
+
+    Some(result)
+}
+
+// Reads bits from bytes, from the most significant bit down.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: uint
+}
+
+impl<'a> BitReader<'a> {
+    fn remaining(&self) -> uint {
+        self.bytes.len() * 8 - self.position
+    }
+
+    // Reads `count` bits as a number, most significant first. Returns None
+    // if there aren't that many left.
+    fn read(&mut self, count: uint) -> Option<u32> {
+        if count > self.remaining() { return None; }
+        let mut value = 0u32;
+        for _ in range(0, count) {
+            let bit = (self.bytes[self.position / 8] >> (7 - self.position % 8)) & 1;
+            value = value << 1 | bit as u32;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+// A sequence of bits, packed into bytes from the most significant bit down.
+struct BitBuffer {
+    bytes: Vec<u8>,
+    length: uint
+}
+
+impl BitBuffer {
+    // Appends the lowest `count` bits of the value, most significant first.
+    fn append(&mut self, value: u32, count: uint) {
+        for i in range(0, count).rev() {
+            if self.length % 8 == 0 { self.bytes.push(0); }
+            let bit = ((value >> i) & 1) as u8;
+            let last = self.bytes.len() - 1;
+            *self.bytes.index_mut(&last) |= bit << (7 - self.length % 8);
+            self.length += 1;
+        }
+    }
+}
+
+// Splits the data codewords into blocks, adds Reed-Solomon error correction
+// codewords to each, and interleaves the blocks.
+fn add_error_correction(data: &[u8], version: uint, error_correction: ErrorCorrection) -> Vec<u8> {
+    let level = error_correction.index();
+    let block_count = ERROR_CORRECTION_BLOCKS[level][version] as uint;
+    let ecc_length = ECC_CODEWORDS_PER_BLOCK[level][version] as uint;
+    let raw_codewords = raw_data_modules(version) / 8;
+    let short_block_count = block_count - raw_codewords % block_count;
+    let short_block_length = raw_codewords / block_count;
+
+    // The short blocks have one fewer data codeword than the long ones.
+    let divisor = reed_solomon_divisor(ecc_length);
+    let mut blocks = Vec::with_capacity(block_count);
+    let mut start = 0;
+    for i in range(0, block_count) {
+        let data_length = short_block_length - ecc_length + if i < short_block_count { 0 } else { 1 };
+        let block_data = data.slice(start, start + data_length);
+        start += data_length;
+        let ecc = reed_solomon_remainder(block_data, divisor.as_slice());
+        blocks.push((block_data, ecc));
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in range(0, short_block_length - ecc_length + 1) {
+        for &(block_data, _) in blocks.iter() {
+            if i < block_data.len() { result.push(block_data[i]); }
+        }
+    }
+    for i in range(0, ecc_length) {
+        for &(_, ref ecc) in blocks.iter() {
+            result.push(ecc[i]);
+        }
+    }
+    result
+}
+
+// Splits the codewords read from a code back into their blocks, corrects the
+// errors in each, and returns the data codewords. Returns None if a block
+// has too many errors to correct.
+fn correct_errors(codewords: &[u8], version: uint, error_correction: ErrorCorrection) -> Option<Vec<u8>> {
+    let level = error_correction.index();
+    let block_count = ERROR_CORRECTION_BLOCKS[level][version] as uint;
+    let ecc_length = ECC_CODEWORDS_PER_BLOCK[level][version] as uint;
+    let short_block_count = block_count - codewords.len() % block_count;
+    let short_data_length = codewords.len() / block_count - ecc_length;
+
+    // Undo the interleaving, where the short blocks have no codeword in the
+    // last round of data codewords.
+    let mut blocks: Vec<Vec<u8>> = Vec::from_fn(block_count, |_| Vec::new());
+    let mut remaining = codewords.iter();
+    for i in range(0, short_data_length + 1) {
+        for (j, block) in blocks.iter_mut().enumerate() {
+            if i < short_data_length || j >= short_block_count {
+                block.push(*remaining.next().unwrap());
+            }
+        }
+    }
+    for _ in range(0, ecc_length) {
+        for block in blocks.iter_mut() {
+            block.push(*remaining.next().unwrap());
+        }
+    }
+
+    let mut data = Vec::with_capacity(codewords.len());
+    for block in blocks.iter_mut() {
+        if !reed_solomon_correct(block.as_mut_slice(), ecc_length) { return None; }
+        data.push_all(block.slice_to(block.len() - ecc_length));
+    }
+    Some(data)
+}
+
+// Multiplies two elements of GF(2^8), modulo x^8 + x^4 + x^3 + x^2 + 1.
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z = 0u8;
+    for i in range(0, 8u).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x1D);
+        z ^= ((y >> i) & 1) * x;
+    }
+    z
+}
+
+// The coefficients of the Reed-Solomon generator polynomial of the given
+// degree, from the highest power down, without the leading 1.
+fn reed_solomon_divisor(degree: uint) -> Vec<u8> {
+    let mut result = Vec::from_elem(degree, 0u8);
+    *result.index_mut(&(degree - 1)) = 1;
+
+    // Multiply by (x - r^i) for each i, where r = 0x02 generates the field.
+    let mut root = 1u8;
+    for _ in range(0, degree) {
+        for j in range(0, degree) {
+            *result.index_mut(&j) = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                *result.index_mut(&j) ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+// The Reed-Solomon error correction codewords for the data.
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let length = divisor.len();
+    let mut result = Vec::from_elem(length, 0u8);
+    for &byte in data.iter() {
+        // Shift the remainder along by one, dividing out the first term.
+        let factor = byte ^ result[0];
+        for j in range(0, length) {
+            let next = if j + 1 < length { result[j + 1] } else { 0 };
+            *result.index_mut(&j) = next ^ gf_multiply(divisor[j], factor);
+        }
+    }
+    result
+}
+
+// The powers of 0x02 in GF(2^8), and their logarithms.
+fn gf_tables() -> ([u8, ..255], [u8, ..256]) {
+    let mut exp = [0u8, ..255];
+    let mut log = [0u8, ..256];
+    let mut x = 1u8;
+    for i in range(0, 255u) {
+        exp[i] = x;
+        log[x as uint] = i as u8;
+        x = gf_multiply(x, 0x02);
+    }
+    (exp, log)
+}
+
+// Evaluates a polynomial over GF(2^8), given its coefficients from the
+// lowest power up.
+fn gf_evaluate(coefficients: &[u8], x: u8) -> u8 {
+    coefficients.iter().rev().fold(0, |result, &coefficient| gf_multiply(result, x) ^ coefficient)
+}
+
+// Corrects the errors in a block of data and error correction codewords, if
+// there are at most half as many as there are error correction codewords.
+// The Berlekamp-Massey algorithm finds the polynomial whose roots say where
+// the errors are, and Forney's algorithm finds what they are. Returns false
+// if there are too many errors to correct.
+fn reed_solomon_correct(block: &mut [u8], ecc_length: uint) -> bool {
+    let (exp, log) = gf_tables();
+    let inverse = |x: u8| exp[(255 - log[x as uint] as uint) % 255];
+
+    // The block evaluated at each root of the generator polynomial, which
+    // is zero for all of them when there are no errors.
+    let mut syndromes = Vec::with_capacity(ecc_length);
+    for i in range(0, ecc_length) {
+        syndromes.push(gf_evaluate_block(block, exp[i]));
+    }
+    if syndromes.iter().all(|&syndrome| syndrome == 0) { return true; }
+
+    let mut locator = Vec::from_elem(ecc_length + 1, 0u8);
+    *locator.index_mut(&0) = 1;
+    let mut previous = locator.clone();
+    let mut previous_discrepancy = 1u8;
+    let mut error_count = 0;
+    let mut shift = 1;
+    for k in range(0, ecc_length) {
+        let mut discrepancy = syndromes[k];
+        for i in range(1, error_count + 1) {
+            discrepancy ^= gf_multiply(locator[i], syndromes[k - i]);
+        }
+        if discrepancy == 0 {
+            shift += 1;
+            continue;
+        }
+
+        let factor = gf_multiply(discrepancy, inverse(previous_discrepancy));
+        let last = locator.clone();
+        for i in range(shift, ecc_length + 1) {
+            *locator.index_mut(&i) ^= gf_multiply(factor, previous[i - shift]);
+        }
+        if error_count * 2 <= k {
+            error_count = k + 1 - error_count;
+            previous = last;
+            previous_discrepancy = discrepancy;
+            shift = 1;
+        } else {
+            shift += 1;
+        }
+    }
+    if error_count * 2 > ecc_length { return false; }
+    let locator = locator.slice_to(error_count + 1);
+
+    // The error evaluator polynomial, and the formal derivative of the
+    // locator, which only has its odd terms.
+    let mut evaluator = Vec::from_elem(ecc_length, 0u8);
+    for i in range(0, ecc_length) {
+        for j in range(0, cmp::min(i, error_count) + 1) {
+            *evaluator.index_mut(&i) ^= gf_multiply(syndromes[i - j], locator[j]);
+        }
+    }
+    let derivative: Vec<u8> = range(1, error_count + 1).map(|i| if i % 2 == 1 { locator[i] } else { 0 }).collect();
+
+    // An error at the codeword for x^p makes 2^-p a root of the locator.
+    let length = block.len();
+    let mut found = 0;
+    for i in range(0, length) {
+        let power = (length - 1 - i) % 255;
+        let x_inverse = exp[(255 - power) % 255];
+        if gf_evaluate(locator, x_inverse) != 0 { continue; }
+
+        let denominator = gf_evaluate(derivative.as_slice(), x_inverse);
+        if denominator == 0 { return false; }
+        let magnitude = gf_multiply(gf_multiply(exp[power], gf_evaluate(evaluator.as_slice(), x_inverse)),
+                                    inverse(denominator));
+        block[i] ^= magnitude;
+        found += 1;
+    }
+
+    // If the roots weren't all in the block, there were too many errors to
+    // tell where they are.
+    if found != error_count { return false; }
+    for i in range(0, ecc_length) {
+        if gf_evaluate_block(block, exp[i]) != 0 { return false; }
+    }
+    true
+}
+
+// Evaluates the polynomial that a block of codewords stands for, with the
+// first codeword as the highest power.
+fn gf_evaluate_block(block: &[u8], x: u8) -> u8 {
+    block.iter().fold(0, |result, &codeword| gf_multiply(result, x) ^ codeword)
+}
+
+// Reads both copies of the format bits, and finds the error correction level
+// and mask of the valid format bits that are closest to either. Returns None
+// if neither is close enough to be sure.
+fn read_format_bits(size: uint, modules: &[bool]) -> Option<(ErrorCorrection, uint)> {
+    let bit = |x: uint, y: uint| if modules[y * size + x] { 1u32 } else { 0 };
+
+    let mut first = bit(8, 7) << 6 | bit(8, 8) << 7 | bit(7, 8) << 8;
+    for i in range(0, 6u) {
+        first |= bit(8, i) << i;
+    }
+    for i in range(9, 15u) {
+        first |= bit(14 - i, 8) << i;
+    }
+    let mut second = 0;
+    for i in range(0, 8u) {
+        second |= bit(size - 1 - i, 8) << i;
+    }
+    for i in range(8, 15u) {
+        second |= bit(8, size - 15 + i) << i;
+    }
+
+    let mut result = None;
+    let mut min_distance = MAX_FORMAT_ERRORS + 1;
+    for &error_correction in ERROR_CORRECTION_LEVELS.iter() {
+        for mask in range(0, 8u) {
+            let bits = format_bits(error_correction, mask);
+            let distance = cmp::min((bits ^ first).count_ones(), (bits ^ second).count_ones());
+            if distance < min_distance {
+                result = Some((error_correction, mask));
+                min_distance = distance;
+            }
+        }
+    }
+    result
+}
+
+// The 15 format bits: the error correction level and mask, with a BCH code,
+// XORed with a fixed pattern so they're never all zero.
+fn format_bits(error_correction: ErrorCorrection, mask: uint) -> u32 {
+    let data = error_correction.format_bits() << 3 | mask as u32;
+    let mut remainder = data;
+    for _ in range(0, 10u) {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+    (data << 10 | remainder) ^ 0x5412
+}
+
+// The 18 version bits that codes of version 7 and up have: the version, with
+// a BCH code.
+fn version_bits(version: uint) -> u32 {
+    let mut remainder = version as u32;
+    for _ in range(0, 12u) {
+        remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1F25);
+    }
+    (version as u32) << 12 | remainder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QrCode, ErrorCorrection, decode, decode_version_bits};
+    use super::{data_codewords, alignment_pattern_positions, encode_data, add_error_correction};
+    use super::{format_bits, version_bits, parse_segments, reed_solomon_correct};
+
+    #[test]
+    fn test_capacities() {
+        // The number of bytes a code holds in byte mode, from the tables in
+        // the specification.
+        let capacities = [
+            (1, ErrorCorrection::Low, 17), (1, ErrorCorrection::Medium, 14),
+            (1, ErrorCorrection::Quartile, 11), (1, ErrorCorrection::High, 7),
+            (10, ErrorCorrection::Medium, 213), (20, ErrorCorrection::Medium, 666),
+            (40, ErrorCorrection::Low, 2953), (40, ErrorCorrection::High, 1273)
+        ];
+        for &(version, error_correction, capacity) in capacities.iter() {
+            let overhead = if version < 10 { 2 } else { 3 };
+            assert_eq!(data_codewords(version, error_correction) - overhead, capacity);
+        }
+    }
+
+    #[test]
+    fn test_alignment_pattern_positions() {
+        assert_eq!(alignment_pattern_positions(1), vec![]);
+        assert_eq!(alignment_pattern_positions(2), vec![6, 18]);
+        assert_eq!(alignment_pattern_positions(7), vec![6, 22, 38]);
+        assert_eq!(alignment_pattern_positions(32), vec![6, 34, 60, 86, 112, 138]);
+        assert_eq!(alignment_pattern_positions(40), vec![6, 30, 58, 86, 114, 142, 170]);
+    }
+
+    #[test]
+    fn test_format_and_version_bits() {
+        assert_eq!(format_bits(ErrorCorrection::Low, 0), 0b111011111000100);
+        assert_eq!(format_bits(ErrorCorrection::Medium, 0), 0b101010000010010);
+        assert_eq!(version_bits(7), 0b000111110010010100);
+        assert_eq!(decode_version_bits(0b000111110010010100), Some(7));
+        assert_eq!(decode_version_bits(0b000111110010010100 ^ 0b100000010000000001), Some(7));
+        assert_eq!(decode_version_bits(0), None);
+    }
+
+    #[test]
+    fn test_codewords() {
+        // "HELLO WORLD" in a 1-Q code, in alphanumeric mode.
+        let data = encode_data(b"HELLO WORLD", true, 1, ErrorCorrection::Quartile);
+        assert_eq!(data, vec![32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236]);
+
+        let codewords = add_error_correction(data.as_slice(), 1, ErrorCorrection::Quartile);
+        assert_eq!(codewords.slice_from(13), [168, 72, 22, 82, 217, 54, 156, 0, 46, 15, 180, 122, 16].as_slice());
+    }
+
+    #[test]
+    fn test_encode() {
+        let qr = QrCode::encode(b"HELLO WORLD", ErrorCorrection::Quartile).unwrap();
+        assert_eq!(qr.get_version(), 1);
+        let expected = [
+            "#######....#..#######",
+            "#.....#.##..#.#.....#",
+            "#.###.#..#.##.#.###.#",
+            "#.###.#.#####.#.###.#",
+            "#.###.#.##.#..#.###.#",
+            "#.....#..#..#.#.....#",
+            "#######.#.#.#.#######",
+            "........##.##........",
+            ".#.####.##..###.##.#.",
+            "#.####.#....####.###.",
+            "..#.#.##...#..##.....",
+            "#.##.#...#.##...##...",
+            "##.########.###.#####",
+            "........#...#..#.#...",
+            "#######..##..##..####",
+            "#.....#.#.#..#..#.###",
+            "#.###.#.##.#..#...###",
+            "#.###.#.#.###...#.#..",
+            "#.###.#..#....#....##",
+            "#.....#.###..###..##.",
+            "#######..#.#.......#."
+        ];
+        for (y, row) in expected.iter().enumerate() {
+            for (x, module) in row.chars().enumerate() {
+                assert_eq!(qr.is_dark(x as int, y as int), module == '#');
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_versions() {
+        // Lowercase text needs byte mode.
+        let qr = QrCode::encode(Vec::from_elem(2953, b'a').as_slice(), ErrorCorrection::Low).unwrap();
+        assert_eq!(qr.get_version(), 40);
+        assert_eq!(qr.get_size(), 177);
+        assert!(QrCode::encode(Vec::from_elem(2954, b'a').as_slice(), ErrorCorrection::Low).is_none());
+
+        // Uppercase hex fits in alphanumeric mode, which holds more.
+        let qr = QrCode::encode(Vec::from_elem(4296, b'A').as_slice(), ErrorCorrection::Low).unwrap();
+        assert_eq!(qr.get_version(), 40);
+    }
+
+    #[test]
+    fn test_decode() {
+        let data: Vec<u8> = range(0, 200u).map(|i| (i * 7) as u8).collect();
+        let inputs = [b"HELLO WORLD", b"ur:crypto-psbt/1-3/lpadaxcsiycynskikeech", data.as_slice()];
+        for input in inputs.iter() {
+            let qr = QrCode::encode(*input, ErrorCorrection::Medium).unwrap();
+            let mut modules = qr.modules.clone();
+            assert_eq!(decode(qr.get_size(), modules.as_slice()), Some(input.to_vec()));
+
+            // A few modules read wrong, including one of the format bits.
+            for &index in [8 * qr.get_size() + 2, qr.get_size() * 12 + 14, modules.len() - 1].iter() {
+                *modules.index_mut(&index) = !modules[index];
+            }
+            assert_eq!(decode(qr.get_size(), modules.as_slice()), Some(input.to_vec()));
+        }
+
+        assert_eq!(decode(21, Vec::from_elem(21 * 21, false).as_slice()), None);
+        assert_eq!(decode(22, Vec::from_elem(22 * 22, false).as_slice()), None);
+    }
+
+    #[test]
+    fn test_parse_segments() {
+        // "01234567" in numeric mode, from the specification.
+        let data = [0x10, 0x20, 0x0C, 0x56, 0x61, 0x80, 0xEC, 0x11, 0xEC, 0x11, 0xEC, 0x11, 0xEC, 0x11, 0xEC, 0x11];
+        assert_eq!(parse_segments(&data, 1), Some(b"01234567".to_vec()));
+
+        let data = encode_data(b"HELLO WORLD", true, 1, ErrorCorrection::Quartile);
+        assert_eq!(parse_segments(data.as_slice(), 1), Some(b"HELLO WORLD".to_vec()));
+    }
+
+    #[test]
+    fn test_reed_solomon_correct() {
+        let codewords = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236,
+                         168, 72, 22, 82, 217, 54, 156, 0, 46, 15, 180, 122, 16];
+
+        // Thirteen error correction codewords can correct six errors.
+        let mut block = codewords;
+        for &(i, value) in [(0, 0), (3, 1), (10, 0xFF), (14, 7), (20, 1), (25, 0)].iter() {
+            block[i] = value;
+        }
+        assert!(reed_solomon_correct(&mut block, 13));
+        assert_eq!(block.as_slice(), codewords.as_slice());
+
+        // But not seven.
+        block[7] = 7;
+        for &(i, value) in [(0, 0), (3, 1), (10, 0xFF), (14, 7), (20, 1), (25, 0)].iter() {
+            block[i] = value;
+        }
+        assert!(!reed_solomon_correct(&mut block, 13));
+    }
+}
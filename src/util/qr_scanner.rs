@@ -0,0 +1,711 @@
+//! Finding and reading QR codes in pictures, like the frames a camera takes
+//! of a screen or a printout. The finder patterns in three corners of a code
+//! give its position, size and angle, and the alignment pattern near the
+//! fourth corner makes up for a picture taken at a slant.
+
+use std::cmp;
+use std::num::Float;
+
+use util::png::Image;
+use util::qr;
+
+// Finder patterns that were found on fewer rows of the picture than this are
+// left out, unless there aren't three that were found more often.
+static MIN_CONFIRMATIONS: uint = 2;
+
+// The most finder patterns, and sets of three of them, that are tried.
+static MAX_CANDIDATES: uint = 10;
+static MAX_ATTEMPTS: uint = 8;
+
+// The area around each pixel that its brightness is compared with, as a
+// fraction of the picture's size, and how much darker than the area's
+// average it has to be to count as dark, in percent.
+static ADAPTIVE_RADIUS_DIVISOR: uint = 16;
+static ADAPTIVE_THRESHOLD: u64 = 90;
+
+// How far from where it should be the alignment pattern is looked for, in
+// modules, looking further away each time it isn't found. Of its 25 modules,
+// this many have to match.
+static ALIGNMENT_ALLOWANCES: [f64, ..3] = [4.0, 8.0, 16.0];
+static MIN_ALIGNMENT_MATCHES: uint = 23;
+
+// A point in the picture, or the distance between two points.
+#[deriving(Clone, Copy, PartialEq, Show)]
+struct Point {
+    x: f64,
+    y: f64
+}
+
+// A finder pattern: its center, the size of a module around it, and how
+// many times it was found.
+#[deriving(Clone, Copy, PartialEq, Show)]
+struct FinderPattern {
+    center: Point,
+    module_size: f64,
+    count: uint
+}
+
+// The picture in black and white.
+struct Bitmap {
+    width: uint,
+    height: uint,
+    dark: Vec<bool>
+}
+
+impl Bitmap {
+    fn contains(&self, x: int, y: int) -> bool {
+        0 <= x && x < self.width as int && 0 <= y && y < self.height as int
+    }
+
+    // Pixels outside the picture are light.
+    fn is_dark(&self, x: int, y: int) -> bool {
+        self.contains(x, y) && self.dark[y as uint * self.width + x as uint]
+    }
+
+    fn is_dark_at(&self, point: Point) -> bool {
+        self.is_dark(point.x.floor() as int, point.y.floor() as int)
+    }
+}
+
+/// Looks for a QR code in the picture, and returns the data in it. Returns
+/// None if there isn't one that can be read.
+pub fn scan(image: &Image) -> Option<Vec<u8>> {
+    // A single threshold works for most pictures, and one that depends on
+    // each pixel's surroundings works for unevenly lit ones.
+    for &adaptive in [false, true].iter() {
+        let bitmap = if adaptive { adaptive_threshold(image) } else { global_threshold(image) };
+        let finder_patterns = find_finder_patterns(&bitmap);
+        for &(top_left, top_right, bottom_left) in arrange_finder_patterns(finder_patterns.as_slice()).iter() {
+            match read_code(&bitmap, &top_left, &top_right, &bottom_left) {
+                Some(data) => return Some(data),
+                None => {}
+            }
+        }
+    }
+    None
+}
+
+// Turns the picture black and white with Otsu's method, which picks the
+// threshold that best splits the pixels into two groups.
+fn global_threshold(image: &Image) -> Bitmap {
+    let (width, height) = (image.get_width(), image.get_height());
+    let mut histogram = [0u, ..256];
+    for y in range(0, height) {
+        for x in range(0, width) {
+            histogram[image.get_pixel(x, y) as uint] += 1;
+        }
+    }
+
+    let total = width * height;
+    let mut sum = 0.0f64;
+    for i in range(0, 256u) {
+        sum += (i * histogram[i]) as f64;
+    }
+
+    let mut threshold = 128;
+    let mut max_variance = 0.0f64;
+    let mut count_below = 0;
+    let mut sum_below = 0.0f64;
+    for i in range(0, 256u) {
+        count_below += histogram[i];
+        if count_below == 0 { continue; }
+        let count_above = total - count_below;
+        if count_above == 0 { break; }
+
+        sum_below += (i * histogram[i]) as f64;
+        let difference = sum_below / count_below as f64 - (sum - sum_below) / count_above as f64;
+        let variance = count_below as f64 * count_above as f64 * difference * difference;
+        if variance > max_variance {
+            max_variance = variance;
+            threshold = i;
+        }
+    }
+
+    let mut dark = Vec::with_capacity(total);
+    for y in range(0, height) {
+        for x in range(0, width) {
+            dark.push(image.get_pixel(x, y) as uint <= threshold);
+        }
+    }
+    Bitmap { width: width, height: height, dark: dark }
+}
+
+// Turns the picture black and white by comparing each pixel with the
+// average of the area around it.
+fn adaptive_threshold(image: &Image) -> Bitmap {
+    let (width, height) = (image.get_width(), image.get_height());
+    let radius = cmp::max(cmp::min(width, height) / ADAPTIVE_RADIUS_DIVISOR, 4);
+
+    // The sum of the pixels above and to the left of each point, so that
+    // the sum of any rectangle takes four lookups.
+    let stride = width + 1;
+    let mut sums = Vec::from_elem(stride * (height + 1), 0u64);
+    for y in range(0, height) {
+        let mut row_sum = 0u64;
+        for x in range(0, width) {
+            row_sum += image.get_pixel(x, y) as u64;
+            let above = sums[y * stride + x + 1];
+            *sums.index_mut(&((y + 1) * stride + x + 1)) = above + row_sum;
+        }
+    }
+
+    let mut dark = Vec::with_capacity(width * height);
+    for y in range(0, height) {
+        let (top, bottom) = (y - cmp::min(y, radius), cmp::min(y + radius + 1, height));
+        for x in range(0, width) {
+            let (left, right) = (x - cmp::min(x, radius), cmp::min(x + radius + 1, width));
+            let count = ((right - left) * (bottom - top)) as u64;
+            let sum = sums[bottom * stride + right] + sums[top * stride + left] -
+                      sums[top * stride + right] - sums[bottom * stride + left];
+            dark.push(image.get_pixel(x, y) as u64 * count * 100 < sum * ADAPTIVE_THRESHOLD);
+        }
+    }
+    Bitmap { width: width, height: height, dark: dark }
+}
+
+// Checks whether runs of dark, light, dark, light and dark pixels are in
+// the 1:1:3:1:1 ratio of a finder pattern, give or take half a module.
+fn is_finder_ratio(counts: &[uint, ..5]) -> bool {
+    let total = counts.iter().fold(0, |sum, &count| sum + count);
+    if total < 7 || counts.iter().any(|&count| count == 0) { return false; }
+
+    let module_size = total as f64 / 7.0;
+    let max_variance = module_size / 2.0;
+    (module_size - counts[0] as f64).abs() < max_variance &&
+        (module_size - counts[1] as f64).abs() < max_variance &&
+        (module_size * 3.0 - counts[2] as f64).abs() < max_variance * 3.0 &&
+        (module_size - counts[3] as f64).abs() < max_variance &&
+        (module_size - counts[4] as f64).abs() < max_variance
+}
+
+// The center of a finder pattern's runs, given where the last run ends.
+fn center_from_end(counts: &[uint, ..5], end: uint) -> f64 {
+    (end - counts[4] - counts[3]) as f64 - counts[2] as f64 / 2.0
+}
+
+// Scans every row of the picture for finder patterns, and checks each one
+// by scanning across it vertically and horizontally.
+fn find_finder_patterns(bitmap: &Bitmap) -> Vec<FinderPattern> {
+    let mut finder_patterns = Vec::new();
+    for y in range(0, bitmap.height) {
+        // The lengths of the last five runs, and which run the current
+        // pixel is in: even ones are dark, odd ones light.
+        let mut counts = [0u, ..5];
+        let mut state = 0;
+        for x in range(0, bitmap.width + 1) {
+            if bitmap.is_dark(x as int, y as int) {
+                if state % 2 == 1 { state += 1; }
+                counts[state] += 1;
+            } else if state % 2 == 1 {
+                counts[state] += 1;
+            } else if state < 4 {
+                state += 1;
+                counts[state] += 1;
+            } else if is_finder_ratio(&counts) && check_finder_pattern(bitmap, &counts, x, y, &mut finder_patterns) {
+                counts = [0u, ..5];
+                state = 0;
+            } else {
+                // The last three runs might be the start of one.
+                counts = [counts[2], counts[3], counts[4], 1, 0];
+                state = 3;
+            }
+        }
+    }
+    finder_patterns
+}
+
+// Checks a possible finder pattern that was found in a row, ending just
+// before column `end`, by scanning across its center vertically and then
+// horizontally again. If it's there, it's added to the finder patterns, or
+// merged with one that was already found.
+fn check_finder_pattern(bitmap: &Bitmap, counts: &[uint, ..5], end: uint, y: uint,
+                        finder_patterns: &mut Vec<FinderPattern>) -> bool {
+    let total = counts.iter().fold(0, |sum, &count| sum + count);
+    let x = center_from_end(counts, end);
+    let y = match cross_check(bitmap, x as int, y as int, 0, 1, counts[2], total) {
+        Some((y, _)) => y,
+        None => return false
+    };
+    let (x, total) = match cross_check(bitmap, x as int, y as int, 1, 0, counts[2], total) {
+        Some(result) => result,
+        None => return false
+    };
+
+    let center = Point { x: x, y: y };
+    let module_size = total as f64 / 7.0;
+    for finder_pattern in finder_patterns.iter_mut() {
+        let previous = *finder_pattern;
+        if (previous.center.x - x).abs() <= module_size && (previous.center.y - y).abs() <= module_size &&
+           (previous.module_size - module_size).abs() <= (previous.module_size / 2.0).max(1.0) {
+            // Average it with the others that were found.
+            let count = previous.count as f64;
+            *finder_pattern = FinderPattern {
+                center: Point {
+                    x: (previous.center.x * count + x) / (count + 1.0),
+                    y: (previous.center.y * count + y) / (count + 1.0)
+                },
+                module_size: (previous.module_size * count + module_size) / (count + 1.0),
+                count: previous.count + 1
+            };
+            return true;
+        }
+    }
+    finder_patterns.push(FinderPattern { center: center, module_size: module_size, count: 1 });
+    true
+}
+
+// Counts the runs of a finder pattern from (x, y) in both directions along
+// (dx, dy), where each of the outer runs is at most `max_count` long. Returns
+// the center along that direction and the total length of the runs, or None
+// if they aren't a finder pattern about as big as `original_total`.
+fn cross_check(bitmap: &Bitmap, x: int, y: int, dx: int, dy: int, max_count: uint,
+               original_total: uint) -> Option<(f64, uint)> {
+    let mut counts = [0u, ..5];
+
+    // Backwards from the center.
+    let (mut i, mut j) = (x, y);
+    while bitmap.is_dark(i, j) {
+        counts[2] += 1;
+        i -= dx;
+        j -= dy;
+    }
+    if !bitmap.contains(i, j) { return None; }
+    while bitmap.contains(i, j) && !bitmap.is_dark(i, j) && counts[1] <= max_count {
+        counts[1] += 1;
+        i -= dx;
+        j -= dy;
+    }
+    if !bitmap.contains(i, j) || counts[1] > max_count { return None; }
+    while bitmap.is_dark(i, j) && counts[0] <= max_count {
+        counts[0] += 1;
+        i -= dx;
+        j -= dy;
+    }
+    if counts[0] > max_count { return None; }
+
+    // Forwards from the center.
+    let (mut i, mut j) = (x + dx, y + dy);
+    while bitmap.is_dark(i, j) {
+        counts[2] += 1;
+        i += dx;
+        j += dy;
+    }
+    if !bitmap.contains(i, j) { return None; }
+    while bitmap.contains(i, j) && !bitmap.is_dark(i, j) && counts[3] < max_count {
+        counts[3] += 1;
+        i += dx;
+        j += dy;
+    }
+    if !bitmap.contains(i, j) || counts[3] >= max_count { return None; }
+    while bitmap.is_dark(i, j) && counts[4] < max_count {
+        counts[4] += 1;
+        i += dx;
+        j += dy;
+    }
+    if counts[4] >= max_count { return None; }
+
+    let total = counts.iter().fold(0, |sum, &count| sum + count);
+    if 5 * (total as int - original_total as int).abs() >= 2 * original_total as int || !is_finder_ratio(&counts) {
+        return None;
+    }
+    let end = if dx != 0 { i } else { j };
+    Some((center_from_end(&counts, end as uint), total))
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    ((a.x - b.x) * (a.x - b.x) + (a.y - b.y) * (a.y - b.y)).sqrt()
+}
+
+// Picks out sets of three finder patterns that could be the corners of a
+// code, as (top left, top right, bottom left), with the ones closest to a
+// right-angled isosceles triangle first.
+fn arrange_finder_patterns(finder_patterns: &[FinderPattern]) -> Vec<(FinderPattern, FinderPattern, FinderPattern)> {
+    let mut candidates: Vec<FinderPattern> = finder_patterns.iter().filter(|finder_pattern| {
+        finder_pattern.count >= MIN_CONFIRMATIONS
+    }).map(|&finder_pattern| finder_pattern).collect();
+    if candidates.len() < 3 {
+        candidates = finder_patterns.to_vec();
+    }
+    candidates.sort_by(|a, b| b.count.cmp(&a.count));
+    candidates.truncate(MAX_CANDIDATES);
+
+    let mut arrangements = Vec::new();
+    let count = candidates.len();
+    for i in range(0, count) {
+        for j in range(i + 1, count) {
+            for k in range(j + 1, count) {
+                let (a, b, c) = (candidates[i], candidates[j], candidates[k]);
+                let min_module_size = a.module_size.min(b.module_size).min(c.module_size);
+                let max_module_size = a.module_size.max(b.module_size).max(c.module_size);
+                if max_module_size > min_module_size * 1.5 { continue; }
+
+                // The top left one is opposite the longest side.
+                let (bc, ac, ab) = (distance(b.center, c.center), distance(a.center, c.center),
+                                    distance(a.center, b.center));
+                let (longest, top_left, p, q) = if bc >= ac && bc >= ab {
+                    (bc, a, b, c)
+                } else if ac >= ab {
+                    (ac, b, a, c)
+                } else {
+                    (ab, c, a, b)
+                };
+                let (first, second) = (distance(top_left.center, p.center), distance(top_left.center, q.center));
+                if first < min_module_size * 7.0 || second < min_module_size * 7.0 { continue; }
+
+                let score = (longest * longest - first * first - second * second).abs() / (longest * longest) +
+                            (first - second).abs() / first.max(second);
+                if score > 0.5 { continue; }
+
+                // Going clockwise from the top left, the top right one comes
+                // first. A mirrored code is read the other way around later.
+                let cross = (p.center.x - top_left.center.x) * (q.center.y - top_left.center.y) -
+                            (p.center.y - top_left.center.y) * (q.center.x - top_left.center.x);
+                let (top_right, bottom_left) = if cross > 0.0 { (p, q) } else { (q, p) };
+                arrangements.push((score, top_left, top_right, bottom_left));
+            }
+        }
+    }
+
+    arrangements.sort_by(|&(a, _, _, _), &(b, _, _, _)| a.partial_cmp(&b).unwrap());
+    arrangements.iter().take(MAX_ATTEMPTS).map(|&(_, top_left, top_right, bottom_left)| {
+        (top_left, top_right, bottom_left)
+    }).collect()
+}
+
+// Measures a finder pattern from edge to edge, along the line through its
+// center towards another one, which is the same whatever angle the code is
+// at. Returns None if its edges aren't where they should be.
+fn finder_pattern_width(bitmap: &Bitmap, finder_pattern: &FinderPattern, toward: &FinderPattern) -> Option<f64> {
+    let length = distance(finder_pattern.center, toward.center);
+    let direction = Point {
+        x: (toward.center.x - finder_pattern.center.x) / length,
+        y: (toward.center.y - finder_pattern.center.y) / length
+    };
+    let limit = (finder_pattern.module_size * 8.0) as uint;
+
+    let mut width = 0;
+    for &sign in [1.0f64, -1.0].iter() {
+        // Through the dark center, the light ring and the dark ring.
+        let mut state = 0u;
+        let mut step = 0;
+        loop {
+            let distance = sign * step as f64;
+            let point = Point { x: finder_pattern.center.x + distance * direction.x,
+                                y: finder_pattern.center.y + distance * direction.y };
+            if bitmap.is_dark_at(point) != (state % 2 == 0) {
+                state += 1;
+                if state == 3 { break; }
+            }
+            step += 1;
+            if step > limit { return None; }
+        }
+        width += step;
+    }
+    Some(width as f64)
+}
+
+// Works out the version of the code that the finder patterns are the
+// corners of, then samples its modules and decodes it. Returns None if it
+// can't be read.
+fn read_code(bitmap: &Bitmap, top_left: &FinderPattern, top_right: &FinderPattern,
+             bottom_left: &FinderPattern) -> Option<Vec<u8>> {
+    let widths = [
+        finder_pattern_width(bitmap, top_left, top_right), finder_pattern_width(bitmap, top_right, top_left),
+        finder_pattern_width(bitmap, top_left, bottom_left), finder_pattern_width(bitmap, bottom_left, top_left)
+    ];
+    if widths.iter().any(|width| width.is_none()) { return None; }
+    // Each width runs to the first pixel past the edge on both sides.
+    let module_size = (widths.iter().fold(0.0, |sum, width| sum + width.unwrap()) - 4.0) / 28.0;
+
+    // The finder patterns' centers are seven modules in from the edges.
+    let span = (distance(top_left.center, top_right.center) +
+                distance(top_left.center, bottom_left.center)) / 2.0 / module_size;
+    let estimate = (span + 7.0 - 17.0) / 4.0;
+
+    // Codes from version 7 up say what version they are, which is more
+    // reliable than measuring them.
+    if estimate >= 5.5 {
+        match read_version(bitmap, top_left, top_right, bottom_left, estimate.round() as uint) {
+            Some(version) => return sample_and_decode(bitmap, top_left, top_right, bottom_left, version, module_size),
+            None => {}
+        }
+    }
+
+    // Otherwise, try the nearest two versions to the estimate.
+    let nearest = estimate.round();
+    let other = if estimate >= nearest { nearest + 1.0 } else { nearest - 1.0 };
+    for &version in [nearest, other].iter() {
+        if version < 1.0 || version > qr::MAX_VERSION as f64 { continue; }
+        match sample_and_decode(bitmap, top_left, top_right, bottom_left, version as uint, module_size) {
+            Some(data) => return Some(data),
+            None => {}
+        }
+    }
+    None
+}
+
+// The distance between the centers of neighboring modules, across and down
+// the code, going by the finder patterns.
+fn module_axes(top_left: &FinderPattern, top_right: &FinderPattern, bottom_left: &FinderPattern,
+               size: uint) -> (Point, Point) {
+    let span = (size - 7) as f64;
+    (Point { x: (top_right.center.x - top_left.center.x) / span, y: (top_right.center.y - top_left.center.y) / span },
+     Point { x: (bottom_left.center.x - top_left.center.x) / span, y: (bottom_left.center.y - top_left.center.y) / span })
+}
+
+// Reads either copy of the version information, next to the top right and
+// bottom left finder patterns. Returns None if neither can be read.
+fn read_version(bitmap: &Bitmap, top_left: &FinderPattern, top_right: &FinderPattern,
+                bottom_left: &FinderPattern, estimate: uint) -> Option<uint> {
+    let (across, down) = module_axes(top_left, top_right, bottom_left, estimate * 4 + 17);
+    let is_dark = |origin: &FinderPattern, x: f64, y: f64| {
+        bitmap.is_dark_at(Point { x: origin.center.x + x * across.x + y * down.x,
+                                  y: origin.center.y + x * across.y + y * down.y })
+    };
+
+    for copy in range(0, 2u) {
+        // Bit i is in column size - 11 + i % 3 and row i / 3 of the top
+        // right copy, which are -7 + i % 3 and -3 + i / 3 modules from the
+        // finder pattern's center, and the bottom left copy is its
+        // transpose.
+        let mut bits = 0u32;
+        for i in range(0, 18u) {
+            let (x, y) = ((i % 3) as f64 - 7.0, (i / 3) as f64 - 3.0);
+            let dark = if copy == 0 { is_dark(top_right, x, y) } else { is_dark(bottom_left, y, x) };
+            if dark { bits |= 1 << i; }
+        }
+        match qr::decode_version_bits(bits) {
+            Some(version) => return Some(version),
+            None => {}
+        }
+    }
+    None
+}
+
+// Looks for the alignment pattern around where it should be, going by the
+// finder patterns, and returns its center. Returns None if it isn't found.
+fn find_alignment_pattern(bitmap: &Bitmap, estimate: Point, across: Point, down: Point,
+                          module_size: f64) -> Option<Point> {
+    let (x0, y0) = (estimate.x as int, estimate.y as int);
+    for allowance in ALIGNMENT_ALLOWANCES.iter() {
+        let radius = (module_size * *allowance).ceil() as int;
+
+        // The average of the best matching points.
+        let mut max_matches = 0;
+        let mut sum = Point { x: 0.0, y: 0.0 };
+        let mut count = 0u;
+        for y in range(cmp::max(y0 - radius, 0), cmp::min(y0 + radius + 1, bitmap.height as int)) {
+            for x in range(cmp::max(x0 - radius, 0), cmp::min(x0 + radius + 1, bitmap.width as int)) {
+                let center = Point { x: x as f64 + 0.5, y: y as f64 + 0.5 };
+                let mut matches = 0;
+                for dy in range(-2, 3i) {
+                    for dx in range(-2, 3i) {
+                        let point = Point { x: center.x + dx as f64 * across.x + dy as f64 * down.x,
+                                            y: center.y + dx as f64 * across.y + dy as f64 * down.y };
+                        if bitmap.is_dark_at(point) == (cmp::max(dx.abs(), dy.abs()) != 1) { matches += 1; }
+                    }
+                }
+
+                if matches > max_matches {
+                    max_matches = matches;
+                    sum = Point { x: 0.0, y: 0.0 };
+                    count = 0;
+                }
+                if matches == max_matches {
+                    sum = Point { x: sum.x + center.x, y: sum.y + center.y };
+                    count += 1;
+                }
+            }
+        }
+
+        if max_matches >= MIN_ALIGNMENT_MATCHES {
+            return Some(Point { x: sum.x / count as f64, y: sum.y / count as f64 });
+        }
+    }
+    None
+}
+
+// Samples the modules of a code of the given version, and decodes them,
+// reading them transposed if that doesn't work in case the picture is
+// mirrored.
+fn sample_and_decode(bitmap: &Bitmap, top_left: &FinderPattern, top_right: &FinderPattern,
+                     bottom_left: &FinderPattern, version: uint, module_size: f64) -> Option<Vec<u8>> {
+    let size = version * 4 + 17;
+    let (across, down) = module_axes(top_left, top_right, bottom_left, size);
+
+    // Without an alignment pattern, the bottom right corner is where it
+    // would be if the picture were taken straight on.
+    let mut bottom_right = Point { x: top_right.center.x + bottom_left.center.x - top_left.center.x,
+                                   y: top_right.center.y + bottom_left.center.y - top_left.center.y };
+    let mut bottom_right_module = size as f64 - 3.5;
+    if version >= 2 {
+        let offset = (size - 10) as f64;
+        let estimate = Point { x: top_left.center.x + offset * (across.x + down.x),
+                               y: top_left.center.y + offset * (across.y + down.y) };
+        match find_alignment_pattern(bitmap, estimate, across, down, module_size) {
+            Some(center) => {
+                bottom_right = center;
+                bottom_right_module = size as f64 - 6.5;
+            }
+            None => {}
+        }
+    }
+
+    let far = size as f64 - 3.5;
+    let transform = Transform::quadrilateral_to_quadrilateral(
+        [Point { x: 3.5, y: 3.5 }, Point { x: far, y: 3.5 },
+         Point { x: bottom_right_module, y: bottom_right_module }, Point { x: 3.5, y: far }],
+        [top_left.center, top_right.center, bottom_right, bottom_left.center]);
+
+    let mut modules = Vec::with_capacity(size * size);
+    for y in range(0, size) {
+        for x in range(0, size) {
+            let point = transform.apply(Point { x: x as f64 + 0.5, y: y as f64 + 0.5 });
+            if !bitmap.contains(point.x.floor() as int, point.y.floor() as int) { return None; }
+            modules.push(bitmap.is_dark_at(point));
+        }
+    }
+
+    match qr::decode(size, modules.as_slice()) {
+        Some(data) => Some(data),
+        None => {
+            let transposed: Vec<bool> = range(0, size * size).map(|i| modules[(i % size) * size + i / size]).collect();
+            qr::decode(size, transposed.as_slice())
+        }
+    }
+}
+
+// A perspective transform from one plane to another, as a 3x3 matrix.
+struct Transform {
+    a11: f64, a21: f64, a31: f64,
+    a12: f64, a22: f64, a32: f64,
+    a13: f64, a23: f64, a33: f64
+}
+
+impl Transform {
+    // The transform that maps one quadrilateral's corners to another's, in
+    // the same order.
+    fn quadrilateral_to_quadrilateral(from: [Point, ..4], to: [Point, ..4]) -> Transform {
+        Transform::square_to_quadrilateral(to).times(&Transform::square_to_quadrilateral(from).adjoint())
+    }
+
+    // The transform that maps the corners of the unit square, clockwise from
+    // (0, 0), to the corners of the quadrilateral.
+    fn square_to_quadrilateral(corners: [Point, ..4]) -> Transform {
+        let [p0, p1, p2, p3] = corners;
+        let dx3 = p0.x - p1.x + p2.x - p3.x;
+        let dy3 = p0.y - p1.y + p2.y - p3.y;
+        if dx3 == 0.0 && dy3 == 0.0 {
+            // It's a parallelogram, so the transform is affine.
+            return Transform {
+                a11: p1.x - p0.x, a21: p2.x - p1.x, a31: p0.x,
+                a12: p1.y - p0.y, a22: p2.y - p1.y, a32: p0.y,
+                a13: 0.0, a23: 0.0, a33: 1.0
+            };
+        }
+
+        let (dx1, dx2, dy1, dy2) = (p1.x - p2.x, p3.x - p2.x, p1.y - p2.y, p3.y - p2.y);
+        let denominator = dx1 * dy2 - dx2 * dy1;
+        let a13 = (dx3 * dy2 - dx2 * dy3) / denominator;
+        let a23 = (dx1 * dy3 - dx3 * dy1) / denominator;
+        Transform {
+            a11: p1.x - p0.x + a13 * p1.x, a21: p3.x - p0.x + a23 * p3.x, a31: p0.x,
+            a12: p1.y - p0.y + a13 * p1.y, a22: p3.y - p0.y + a23 * p3.y, a32: p0.y,
+            a13: a13, a23: a23, a33: 1.0
+        }
+    }
+
+    // The adjugate matrix, which is the inverse transform since scaling
+    // the matrix doesn't change the transform.
+    fn adjoint(&self) -> Transform {
+        Transform {
+            a11: self.a22 * self.a33 - self.a23 * self.a32,
+            a21: self.a23 * self.a31 - self.a21 * self.a33,
+            a31: self.a21 * self.a32 - self.a22 * self.a31,
+            a12: self.a13 * self.a32 - self.a12 * self.a33,
+            a22: self.a11 * self.a33 - self.a13 * self.a31,
+            a32: self.a12 * self.a31 - self.a11 * self.a32,
+            a13: self.a12 * self.a23 - self.a13 * self.a22,
+            a23: self.a13 * self.a21 - self.a11 * self.a23,
+            a33: self.a11 * self.a22 - self.a12 * self.a21
+        }
+    }
+
+    // The transform that applies the other one, then this one.
+    fn times(&self, other: &Transform) -> Transform {
+        Transform {
+            a11: self.a11 * other.a11 + self.a21 * other.a12 + self.a31 * other.a13,
+            a21: self.a11 * other.a21 + self.a21 * other.a22 + self.a31 * other.a23,
+            a31: self.a11 * other.a31 + self.a21 * other.a32 + self.a31 * other.a33,
+            a12: self.a12 * other.a11 + self.a22 * other.a12 + self.a32 * other.a13,
+            a22: self.a12 * other.a21 + self.a22 * other.a22 + self.a32 * other.a23,
+            a32: self.a12 * other.a31 + self.a22 * other.a32 + self.a32 * other.a33,
+            a13: self.a13 * other.a11 + self.a23 * other.a12 + self.a33 * other.a13,
+            a23: self.a13 * other.a21 + self.a23 * other.a22 + self.a33 * other.a23,
+            a33: self.a13 * other.a31 + self.a23 * other.a32 + self.a33 * other.a33
+        }
+    }
+
+    fn apply(&self, point: Point) -> Point {
+        let denominator = self.a13 * point.x + self.a23 * point.y + self.a33;
+        Point {
+            x: (self.a11 * point.x + self.a21 * point.y + self.a31) / denominator,
+            y: (self.a12 * point.x + self.a22 * point.y + self.a32) / denominator
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::Float;
+
+    use util::png::Image;
+    use util::qr::{QrCode, ErrorCorrection};
+    use super::scan;
+
+    // Draws a QR code the way a camera might see it, on a picture half as big
+    // again as the code and its quiet zone: `scale` pixels to a module, turned
+    // by `angle` radians, slanted so that the bottom is `slant` times bigger
+    // than the top, and mirrored if asked.
+    fn picture(data: &[u8], scale: f64, angle: f64, slant: f64, mirrored: bool) -> Image {
+        let qr = QrCode::encode(data, ErrorCorrection::Low).unwrap();
+        let size = qr.get_size() as f64;
+        let full_size = (size + 8.0) * scale;
+        let side = (full_size * 1.5) as uint;
+        let (cos, sin) = (angle.cos(), angle.sin());
+
+        let mut pixels = Vec::with_capacity(side * side);
+        for y in range(0, side) {
+            for x in range(0, side) {
+                let (dx, dy) = (x as f64 + 0.5 - side as f64 / 2.0, y as f64 + 0.5 - side as f64 / 2.0);
+                let (u, v) = (cos * dx + sin * dy, cos * dy - sin * dx);
+                let perspective = 1.0 + slant * v / full_size;
+                let column = (u / perspective / scale + size / 2.0).floor() as int;
+                let row = (v / perspective / scale + size / 2.0).floor() as int;
+                let dark = if mirrored { qr.is_dark(row, column) } else { qr.is_dark(column, row) };
+                pixels.push(if dark { 30 } else { 220 });
+            }
+        }
+        Image::new(side, side, pixels)
+    }
+
+    #[test]
+    fn test_scan() {
+        assert_eq!(scan(&picture(b"HELLO WORLD", 4.0, 0.0, 0.0, false)), Some(b"HELLO WORLD".to_vec()));
+        assert_eq!(scan(&picture(b"cykas", 4.0, 2.0, 0.0, true)), Some(b"cykas".to_vec()));
+
+        // Version 5, with an alignment pattern.
+        let part = "UR:CRYPTO-PSBT/1-3/LPADAXCSIYCYNSKIKEECHDCPHDIEAEADAOAXAAAHAMATAYASBKBDBNBTBABSBEBYBGBWBBBZCMCHCSCF\
+                    CYCWCECACKCTDYNNGWDE";
+        assert_eq!(scan(&picture(part.as_bytes(), 3.0, 0.5, 0.1, false)), Some(part.as_bytes().to_vec()));
+        assert_eq!(scan(&picture(part.as_bytes(), 3.0, -2.5, 0.15, true)), Some(part.as_bytes().to_vec()));
+
+        // Version 9, with version information.
+        let part = format!("UR:CRYPTO-PSBT/{}", "LPADAXCSIYCYNSKIKEECHDCPHDIEAEADAOAXAAAHAMATAYASBKBDBNBTBABSBEBYBGBWBBBZCMCHCS\
+                                                 CFCYCWCECACKCTDYNNGWDE".repeat(3));
+        assert_eq!(scan(&picture(part.as_bytes(), 3.0, 0.5, 0.1, false)), Some(part.as_bytes().to_vec()));
+        assert_eq!(scan(&picture(part.as_bytes(), 3.0, -2.5, 0.15, true)), Some(part.as_bytes().to_vec()));
+
+        assert_eq!(scan(&Image::new(100, 100, Vec::from_elem(10000, 220))), None);
+    }
+}
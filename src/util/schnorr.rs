@@ -0,0 +1,270 @@
+//! BIP 340 Schnorr signatures over secp256k1, as used by taproot.
+//!
+//! Public keys are "x-only": just the 32-byte X coordinate of the point, with
+//! the Y coordinate implicitly even. Signatures are 64 bytes: the X coordinate
+//! of the nonce point `R` followed by the scalar `s`.
+
+use util::hash;
+use util::secp256k1;
+use util::secp256k1::{Point, Scalar};
+
+/// Derives the 32-byte x-only public key from a 32-byte private key. Assumes
+/// the private key is valid.
+pub fn x_only_public_key(private_key: &[u8]) -> Vec<u8> {
+    let d = Scalar::from_bytes(private_key).unwrap();
+    Point::generator().mul_secret(&d).x_bytes()
+}
+
+/// Signs a message with a 32-byte private key, following the signing
+/// algorithm in BIP 340. `aux_rand` is 32 bytes of auxiliary randomness that
+/// gets mixed into the nonce; it protects against side channels but the
+/// signature is secure even if it's all zeroes. Assumes the private key is
+/// valid.
+pub fn sign(message: &[u8], private_key: &[u8], aux_rand: &[u8]) -> Vec<u8> {
+    assert_eq!(private_key.len(), 32);
+    assert_eq!(aux_rand.len(), 32);
+
+    let g = Point::generator();
+
+    // Negate the private key if needed so that its public key has an even Y.
+    let d0 = Scalar::from_bytes(private_key).unwrap();
+    let public_point = g.mul_secret(&d0);
+    let d = d0.negate_if(!public_point.has_even_y());
+    let public_key = public_point.x_bytes();
+
+    // t = d xor hash_aux(aux_rand)
+    let aux_hash = hash::tagged_hash("BIP0340/aux", aux_rand);
+    let d_bytes = d.to_bytes();
+    let t: Vec<u8> = d_bytes.iter().zip(aux_hash.iter()).map(|(a, b)| *a ^ *b).collect();
+
+    // k0 = hash_nonce(t || P || m) mod n
+    let mut nonce_input = t;
+    nonce_input.push_all(public_key.as_slice());
+    nonce_input.push_all(message);
+    let nonce_hash = hash::tagged_hash("BIP0340/nonce", nonce_input.as_slice());
+    let k0 = Scalar::reduce(nonce_hash.as_slice());
+    assert!(!k0.is_zero());
+
+    // Negate the nonce if needed so that R has an even Y.
+    let r_point = g.mul_secret(&k0);
+    let k = k0.negate_if(!r_point.has_even_y());
+    let r = r_point.x_bytes();
+
+    let e = challenge(r.as_slice(), public_key.as_slice(), message);
+    let s = k.add(&e.mul(&d));
+
+    let mut signature = r;
+    signature.push_all(s.to_bytes().as_slice());
+    signature
+}
+
+/// Verifies a BIP 340 signature of a message against a 32-byte x-only public
+/// key. Returns false for malformed keys and signatures as well as for
+/// signatures that don't match.
+pub fn verify(message: &[u8], public_key: &[u8], signature: &[u8]) -> bool {
+    if signature.len() != 64 { return false; }
+
+    let public_point = match Point::lift_x(public_key) {
+        Some(point) => point,
+        None => return false
+    };
+
+    let r = secp256k1::from_bytes(signature.slice(0, 32));
+    let s = secp256k1::from_bytes(signature.slice(32, 64));
+    if r >= secp256k1::field_prime() || s >= secp256k1::curve_order() {
+        return false;
+    }
+
+    let e = challenge(signature.slice(0, 32), public_key, message);
+    let minus_e = secp256k1::from_bytes(e.negate().to_bytes().as_slice());
+
+    // R = s*G - e*P
+    let r_point = Point::generator().mul(&s).add(&public_point.mul(&minus_e));
+
+    !r_point.is_infinity() &&
+    r_point.has_even_y() &&
+    r_point.x_bytes().as_slice() == signature.slice(0, 32)
+}
+
+// Computes the challenge e = hash_challenge(R || P || m) mod n.
+fn challenge(r: &[u8], public_key: &[u8], message: &[u8]) -> Scalar {
+    let mut input = Vec::with_capacity(64 + message.len());
+    input.push_all(r);
+    input.push_all(public_key);
+    input.push_all(message);
+    let e = hash::tagged_hash("BIP0340/challenge", input.as_slice());
+    Scalar::reduce(e.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::FromHex;
+
+    use super::{sign, verify, x_only_public_key};
+
+    // Checks one of the BIP 340 test vectors that includes a private key.
+    fn check_signing_vector(private_key: &str, public_key: &str, aux_rand: &str,
+                            message: &str, signature: &str) {
+        let private_key = private_key.from_hex().unwrap();
+        let public_key = public_key.from_hex().unwrap();
+        let aux_rand = aux_rand.from_hex().unwrap();
+        let message = message.from_hex().unwrap();
+        let signature = signature.from_hex().unwrap();
+
+        assert_eq!(x_only_public_key(private_key.as_slice()), public_key);
+        assert_eq!(sign(message.as_slice(), private_key.as_slice(), aux_rand.as_slice()), signature);
+        assert!(verify(message.as_slice(), public_key.as_slice(), signature.as_slice()));
+    }
+
+    // Checks one of the BIP 340 test vectors that only includes a public key.
+    fn check_verify_vector(public_key: &str, message: &str, signature: &str, expected: bool) {
+        let public_key = public_key.from_hex().unwrap();
+        let message = message.from_hex().unwrap();
+        let signature = signature.from_hex().unwrap();
+        assert_eq!(verify(message.as_slice(), public_key.as_slice(), signature.as_slice()), expected);
+    }
+
+    #[test]
+    fn test_sign_vector_0() {
+        check_signing_vector(
+            "0000000000000000000000000000000000000000000000000000000000000003",
+            "F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA8215\
+             25F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0");
+    }
+
+    #[test]
+    fn test_sign_vector_1() {
+        check_signing_vector(
+            "B7E151628AED2A6ABF7158809CF4F3C762E7160F38B4DA56A784D9045190CFEF",
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "6896BD60EEAE296DB48A229FF71DFE071BDE413E6D43F917DC8DCF8C78DE3341\
+             8906D11AC976ABCCB20B091292BFF4EA897EFCB639EA871CFA95F6DE339E4B0A");
+    }
+
+    #[test]
+    fn test_sign_vector_2() {
+        check_signing_vector(
+            "C90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B14E5C9",
+            "DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EB8",
+            "C87AA53824B4D7AE2EB035A2B5BBBCCC080E76CDC6D1692C4B0B62D798E6D906",
+            "7E2D58D8B3BCDF1ABADEC7829054F90DDA9805AAB56C77333024B9D0A508B75C",
+            "5831AAEED7B44BB74E5EAB94BA9D4294C49BCF2A60728D8B4C200F50DD313C1B\
+             AB745879A5AD954A72C45A91C3A51D3C7ADEA98D82F8481E0E1E03674A6F3FB7");
+    }
+
+    #[test]
+    fn test_sign_vector_3() {
+        check_signing_vector(
+            "0B432B2677937381AEF05BB02A66ECD012773062CF3FA2549E44F58ED2401710",
+            "25D1DFF95105F5253C4022F628A996AD3A0D95FBF21D468A1B33F8C160D8F517",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+            "7EB0509757E246F19449885651611CB965ECC1A187DD51B64FDA1EDC9637D5EC\
+             97582B9CB13DB3933705B32BA982AF5AF25FD78881EBB32771FC5922EFC66EA3");
+    }
+
+    #[test]
+    fn test_sign_variable_length_message() {
+        check_signing_vector(
+            "0340034003400340034003400340034003400340034003400340034003400340",
+            "778CAA53B4393AC467774D09497A87224BF9FAB6F6E68B23086497324D6FD117",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "",
+            "71535DB165ECD9FBBC046E5FFAEA61186BB6AD436732FCCC25291A55895464CF\
+             6069CE26BF03466228F19A3A62DB8A649F2D560FAC652827D1AF0574E427AB63");
+        check_signing_vector(
+            "0340034003400340034003400340034003400340034003400340034003400340",
+            "778CAA53B4393AC467774D09497A87224BF9FAB6F6E68B23086497324D6FD117",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0102030405060708090A0B0C0D0E0F1011",
+            "5130F39A4059B43BC7CAC09A19ECE52B5D8699D1A71E3C52DA9AFDB6B50AC370\
+             C4A482B77BF960F8681540E25B6771ECE1E5A37FD80E5A51897C5566A97EA5A5");
+    }
+
+    #[test]
+    fn test_verify_vector_4() {
+        check_verify_vector(
+            "D69C3509BB99E412E68B0FE8544E72837DFA30746D8BE2AA65975F29D22DC7B9",
+            "4DF3C3F68FCC83B27E9D42C90431A72499F17875C81A599B566C9889B9696703",
+            "00000000000000000000003B78CE563F89A0ED9414F5AA28AD0D96D6795F9C63\
+             76AFB1548AF603B3EB45C9F8207DEE1060CB71C04E80F593060B07D28308D7F4",
+            true);
+    }
+
+    #[test]
+    fn test_verify_public_key_not_on_curve() {
+        check_verify_vector(
+            "EEFDEA4CDB677750A420FEE807EACF21EB9898AE79B9768766E4FAA04A2D4A34",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E177769\
+             69E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B",
+            false);
+    }
+
+    #[test]
+    fn test_verify_invalid_signatures() {
+        let public_key = "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659";
+        let message = "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89";
+
+        // Has R with an odd Y coordinate.
+        check_verify_vector(public_key, message,
+            "FFF97BD5755EEEA420453A14355235D382F6472F8568A18B2F057A1460297556\
+             3CC27944640AC607CD107AE10923D9EF7A73C643E166BE5EBEAFA34B1AC553E2",
+            false);
+
+        // Negated message.
+        check_verify_vector(public_key, message,
+            "1FA62E331EDBC21C394792D2AB1100A7B432B013DF3F6FF4F99FCB33E0E1515F\
+             28890B3EDB6E7189B630448B515CE4F8622A954CFE545735AAEA5134FCCDB2BD",
+            false);
+
+        // Negated s value.
+        check_verify_vector(public_key, message,
+            "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E177769\
+             961764B3AA9B2FFCB6EF947B6887A226E8D7C93E00C5ED0C1834FF0D0C2E6DA6",
+            false);
+
+        // R is the point at infinity.
+        check_verify_vector(public_key, message,
+            "0000000000000000000000000000000000000000000000000000000000000000\
+             123DDA8328AF9C23A94C1FEECFD123BA4FB73476F0D594DCB65C6425BD186051",
+            false);
+        check_verify_vector(public_key, message,
+            "0000000000000000000000000000000000000000000000000000000000000001\
+             7615FBAF5AE28864013C099742DEADB4DBA87F11AC6754F93780D5A1837CF197",
+            false);
+
+        // R's X coordinate is not on the curve.
+        check_verify_vector(public_key, message,
+            "4A298DACAE57395A15D0795DDBFD1DCB564DA82B0F269BC70A74F8220429BA1D\
+             69E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B",
+            false);
+
+        // R's X coordinate is equal to the field size.
+        check_verify_vector(public_key, message,
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC2F\
+             69E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B",
+            false);
+
+        // s is equal to the curve order.
+        check_verify_vector(public_key, message,
+            "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E177769\
+             FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141",
+            false);
+    }
+
+    #[test]
+    fn test_verify_public_key_exceeds_field_size() {
+        check_verify_vector(
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC30",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E177769\
+             69E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B",
+            false);
+    }
+}
@@ -0,0 +1,1012 @@
+//! Arithmetic on secp256k1, the elliptic curve Bitcoin uses, written in plain
+//! Rust. Everything Cykas does with the curve, from deriving public keys to
+//! Schnorr signatures, is built on this.
+//!
+//! The curve is `y^2 = x^3 + 7` over the field of integers modulo `p`.
+//!
+//! Most of this works on BigUint, whose running time depends on the numbers
+//! it's given. That's fine for public data, such as verifying signatures.
+//! Secrets are kept in `Scalar` instead. Its arithmetic modulo `n` and its
+//! conversions to and from bytes take the same time for every value, and
+//! `mul_secret()` multiplies a point by one the same way, on fixed-size field
+//! elements. The points that come out are public.
+
+use std::cmp;
+use num::{Zero, One};
+use num::bigint::{BigUint, ToBigUint};
+use num::Integer;
+
+// The prime `p` that defines the field the curve is over.
+static FIELD_PRIME: &'static [u8] =
+    b"FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC2F";
+
+// The order `n` of the group generated by the generator point.
+static CURVE_ORDER: &'static [u8] =
+    b"FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141";
+
+// The coordinates of the generator point `G`.
+static GENERATOR_X: &'static [u8] =
+    b"79BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798";
+static GENERATOR_Y: &'static [u8] =
+    b"483ADA7726A3C4655DA4FBFC0E1108A8FD17B448A68554199C47D08FFB10D4B8";
+
+/// Returns the prime `p` that defines the field the curve is over.
+pub fn field_prime() -> BigUint {
+    BigUint::parse_bytes(FIELD_PRIME, 16).unwrap()
+}
+
+/// Returns the order `n` of the curve's generator point. Private keys and
+/// signature scalars are integers modulo `n`.
+pub fn curve_order() -> BigUint {
+    BigUint::parse_bytes(CURVE_ORDER, 16).unwrap()
+}
+
+/// Converts a big-endian slice of bytes to a BigUint.
+pub fn from_bytes(data: &[u8]) -> BigUint {
+    let mut n: BigUint = Zero::zero();
+    for byte in data.iter() {
+        n = (n << 8) + byte.to_biguint().unwrap();
+    }
+    n
+}
+
+/// Converts a BigUint to 32 big-endian bytes. Fails if the number doesn't fit.
+pub fn to_bytes(n: &BigUint) -> Vec<u8> {
+    let mut result = Vec::from_elem(32, 0u8);
+    let byte_mask = 0xff_u8.to_biguint().unwrap();
+    let mut n = n.clone();
+    for i in range(0, 32u).rev() {
+        *result.index_mut(&i) = (n & byte_mask).to_u8().unwrap();
+        n = n >> 8;
+    }
+    assert!(n.is_zero());
+    result
+}
+
+/// Computes `base ^ exponent mod modulus`, by square-and-multiply.
+pub fn mod_pow(base: &BigUint, exponent: &BigUint, modulus: &BigUint) -> BigUint {
+    let mut result: BigUint = One::one();
+    for byte in to_bytes(exponent).iter() {
+        for i in range(0, 8u).rev() {
+            result = (result * result) % *modulus;
+            if (*byte >> i) & 1 == 1 {
+                result = (result * *base) % *modulus;
+            }
+        }
+    }
+    result
+}
+
+/// Computes the multiplicative inverse of `a` modulo the prime `modulus`,
+/// using Fermat's little theorem.
+pub fn mod_inverse(a: &BigUint, modulus: &BigUint) -> BigUint {
+    let two = 2u.to_biguint().unwrap();
+    mod_pow(a, &(*modulus - two), modulus)
+}
+
+// Computes `a - b mod modulus`, where `a` and `b` are already reduced.
+fn mod_sub(a: &BigUint, b: &BigUint, modulus: &BigUint) -> BigUint {
+    (*a + *modulus - *b) % *modulus
+}
+
+// Shorthand for making small BigUint constants.
+fn small(n: uint) -> BigUint {
+    n.to_biguint().unwrap()
+}
+
+/// A point on the curve, in affine coordinates.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Point {
+    /// The point at infinity, i.e. the identity element of the group.
+    Infinity,
+    /// A point with the given X and Y coordinates.
+    Affine(BigUint, BigUint)
+}
+
+impl Point {
+    /// Returns the generator point `G`.
+    pub fn generator() -> Point {
+        Point::Affine(BigUint::parse_bytes(GENERATOR_X, 16).unwrap(),
+                      BigUint::parse_bytes(GENERATOR_Y, 16).unwrap())
+    }
+
+    /// Parses a public key in compressed (33-byte) or uncompressed (65-byte)
+    /// format. Returns None if it isn't a valid point on the curve.
+    pub fn from_bytes(data: &[u8]) -> Option<Point> {
+        if data.len() == 33 && (data[0] == 0x02 || data[0] == 0x03) {
+            let point = Point::lift_x(data.slice(1, 33));
+            match point {
+                Some(point) => {
+                    if data[0] == 0x03 { Some(point.negate()) } else { Some(point) }
+                }
+                None => None
+            }
+        } else if data.len() == 65 && data[0] == 0x04 {
+            let x = from_bytes(data.slice(1, 33));
+            let y = from_bytes(data.slice(33, 65));
+            let point = Point::Affine(x, y);
+            if point.is_on_curve() { Some(point) } else { None }
+        } else {
+            None
+        }
+    }
+
+    /// Finds the point with the given 32-byte X coordinate and an even Y
+    /// coordinate, as defined in BIP 340. Returns None if there is no such
+    /// point.
+    pub fn lift_x(x: &[u8]) -> Option<Point> {
+        if x.len() != 32 { return None; }
+
+        let p = field_prime();
+        let x = from_bytes(x);
+        if x >= p { return None; }
+
+        // Since p = 3 mod 4, the square root of a square `c` is `c^((p+1)/4)`.
+        let y_squared = (x * x * x + small(7)) % p;
+        let y = mod_pow(&y_squared, &((p + One::one()) >> 2), &p);
+        if (y * y) % p != y_squared { return None; }
+
+        if y.is_even() {
+            Some(Point::Affine(x, y))
+        } else {
+            Some(Point::Affine(x, p - y))
+        }
+    }
+
+    /// Checks whether the point satisfies the curve equation.
+    pub fn is_on_curve(&self) -> bool {
+        match *self {
+            Point::Infinity => true,
+            Point::Affine(ref x, ref y) => {
+                let p = field_prime();
+                *x < p && *y < p && (*y * *y) % p == (*x * *x * *x + small(7)) % p
+            }
+        }
+    }
+
+    /// Checks whether this is the point at infinity.
+    pub fn is_infinity(&self) -> bool {
+        *self == Point::Infinity
+    }
+
+    /// Returns the point with the same X coordinate and the opposite Y.
+    pub fn negate(&self) -> Point {
+        match *self {
+            Point::Infinity => Point::Infinity,
+            Point::Affine(ref x, ref y) => {
+                let p = field_prime();
+                Point::Affine(x.clone(), (p - *y) % p)
+            }
+        }
+    }
+
+    /// Adds two points.
+    pub fn add(&self, other: &Point) -> Point {
+        let p = field_prime();
+        let sum = jacobian_add(&JacobianPoint::from_affine(self),
+                               &JacobianPoint::from_affine(other), &p);
+        sum.to_affine(&p)
+    }
+
+    /// Multiplies the point by the given scalar, which must be less than
+    /// 2^256. This is how a public key is derived from a private key.
+    pub fn mul(&self, scalar: &BigUint) -> Point {
+        let p = field_prime();
+        let point = JacobianPoint::from_affine(self);
+        let mut result = JacobianPoint::infinity();
+
+        for byte in to_bytes(scalar).iter() {
+            for i in range(0, 8u).rev() {
+                result = jacobian_double(&result, &p);
+                if (*byte >> i) & 1 == 1 {
+                    result = jacobian_add(&result, &point, &p);
+                }
+            }
+        }
+
+        result.to_affine(&p)
+    }
+
+    /// Multiplies the point by a secret scalar, such as a private key or a
+    /// nonce. Unlike `mul()`, this runs in constant time: it's a Montgomery
+    /// ladder over all 256 bits of the scalar, built on fixed-size field
+    /// arithmetic and complete addition formulas, with no branches or memory
+    /// accesses that depend on the scalar.
+    pub fn mul_secret(&self, scalar: &Scalar) -> Point {
+        // The ladder keeps `r1 - r0` equal to the point throughout.
+        let mut r0 = ProjectivePoint::infinity();
+        let mut r1 = ProjectivePoint::from_affine(self);
+
+        for i in range(0, 256u).rev() {
+            let mask = 0u32 - ((scalar.limbs[i / 32] >> (i % 32)) & 1);
+            r0.conditional_swap(&mut r1, mask);
+            r1 = r0.add(&r1);
+            r0 = r0.add(&r0);
+            r0.conditional_swap(&mut r1, mask);
+        }
+
+        r0.to_affine()
+    }
+
+    /// Checks whether the Y coordinate is even. The point at infinity has no
+    /// coordinates, so this returns false for it.
+    pub fn has_even_y(&self) -> bool {
+        match *self {
+            Point::Infinity => false,
+            Point::Affine(_, ref y) => y.is_even()
+        }
+    }
+
+    /// Returns the X coordinate as 32 bytes. This is the "x-only" public key
+    /// format used by BIP 340 and taproot. Fails on the point at infinity.
+    pub fn x_bytes(&self) -> Vec<u8> {
+        match *self {
+            Point::Infinity => panic!("The point at infinity has no X coordinate"),
+            Point::Affine(ref x, _) => to_bytes(x)
+        }
+    }
+
+    /// Encodes the point as a 33-byte compressed public key.
+    pub fn to_compressed(&self) -> Vec<u8> {
+        let mut result = vec![if self.has_even_y() { 0x02 } else { 0x03 }];
+        result.push_all(self.x_bytes().as_slice());
+        result
+    }
+
+    /// Encodes the point as a 65-byte uncompressed public key.
+    pub fn to_uncompressed(&self) -> Vec<u8> {
+        match *self {
+            Point::Infinity => panic!("The point at infinity can't be encoded"),
+            Point::Affine(ref x, ref y) => {
+                let mut result = vec![0x04];
+                result.push_all(to_bytes(x).as_slice());
+                result.push_all(to_bytes(y).as_slice());
+                result
+            }
+        }
+    }
+}
+
+// A point in Jacobian coordinates, where (X, Y, Z) represents the affine point
+// (X/Z^2, Y/Z^3). Working in these coordinates avoids a modular inversion on
+// every addition, which would make scalar multiplication very slow. A Z of
+// zero represents the point at infinity.
+struct JacobianPoint {
+    x: BigUint,
+    y: BigUint,
+    z: BigUint
+}
+
+impl JacobianPoint {
+    fn infinity() -> JacobianPoint {
+        JacobianPoint { x: Zero::zero(), y: One::one(), z: Zero::zero() }
+    }
+
+    fn from_affine(point: &Point) -> JacobianPoint {
+        match *point {
+            Point::Infinity => JacobianPoint::infinity(),
+            Point::Affine(ref x, ref y) => {
+                JacobianPoint { x: x.clone(), y: y.clone(), z: One::one() }
+            }
+        }
+    }
+
+    fn to_affine(&self, p: &BigUint) -> Point {
+        if self.z.is_zero() { return Point::Infinity; }
+
+        let z_inv = mod_inverse(&self.z, p);
+        let z_inv_squared = (z_inv * z_inv) % *p;
+        let x = (self.x * z_inv_squared) % *p;
+        let y = (self.y * z_inv_squared * z_inv) % *p;
+        Point::Affine(x, y)
+    }
+}
+
+// Doubles a point in Jacobian coordinates.
+fn jacobian_double(point: &JacobianPoint, p: &BigUint) -> JacobianPoint {
+    if point.z.is_zero() || point.y.is_zero() {
+        return JacobianPoint::infinity();
+    }
+
+    let y_squared = (point.y * point.y) % *p;
+    let s = (small(4) * point.x * y_squared) % *p;
+    let m = (small(3) * point.x * point.x) % *p;
+
+    let x = mod_sub(&((m * m) % *p), &((small(2) * s) % *p), p);
+    let y = mod_sub(&((m * mod_sub(&s, &x, p)) % *p),
+                    &((small(8) * y_squared * y_squared) % *p), p);
+    let z = (small(2) * point.y * point.z) % *p;
+
+    JacobianPoint { x: x, y: y, z: z }
+}
+
+// Adds two points in Jacobian coordinates.
+fn jacobian_add(a: &JacobianPoint, b: &JacobianPoint, p: &BigUint) -> JacobianPoint {
+    if a.z.is_zero() {
+        return JacobianPoint { x: b.x.clone(), y: b.y.clone(), z: b.z.clone() };
+    }
+    if b.z.is_zero() {
+        return JacobianPoint { x: a.x.clone(), y: a.y.clone(), z: a.z.clone() };
+    }
+
+    let a_z_squared = (a.z * a.z) % *p;
+    let b_z_squared = (b.z * b.z) % *p;
+    let u1 = (a.x * b_z_squared) % *p;
+    let u2 = (b.x * a_z_squared) % *p;
+    let s1 = (a.y * b_z_squared * b.z) % *p;
+    let s2 = (b.y * a_z_squared * a.z) % *p;
+
+    if u1 == u2 {
+        if s1 != s2 {
+            // The points are each other's negation.
+            return JacobianPoint::infinity();
+        }
+        return jacobian_double(a, p);
+    }
+
+    let h = mod_sub(&u2, &u1, p);
+    let r = mod_sub(&s2, &s1, p);
+    let h_squared = (h * h) % *p;
+    let h_cubed = (h_squared * h) % *p;
+    let u1_h_squared = (u1 * h_squared) % *p;
+
+    let x = mod_sub(&mod_sub(&((r * r) % *p), &h_cubed, p),
+                    &((small(2) * u1_h_squared) % *p), p);
+    let y = mod_sub(&((r * mod_sub(&u1_h_squared, &x, p)) % *p),
+                    &((s1 * h_cubed) % *p), p);
+    let z = (h * a.z * b.z) % *p;
+
+    JacobianPoint { x: x, y: y, z: z }
+}
+
+// The prime `p` as eight 32-bit limbs, least significant first.
+static FIELD_PRIME_LIMBS: [u32, ..8] = [
+    0xFFFFFC2F, 0xFFFFFFFE, 0xFFFFFFFF, 0xFFFFFFFF,
+    0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF
+];
+
+// `p` is `2^256 - 2^32 - 977`, so `2^256` is `2^32 + 977` modulo `p`.
+static FIELD_REDUCTION: u64 = 977;
+
+// An integer modulo `p`, as eight 32-bit limbs, least significant first, and
+// always fully reduced. Unlike BigUint, every operation on these does the
+// same work whatever the values are: loops have a fixed length, and choices
+// are made with masks rather than branches.
+#[deriving(Clone)]
+struct FieldElement {
+    limbs: [u32, ..8]
+}
+
+impl FieldElement {
+    fn from_u32(n: u32) -> FieldElement {
+        let mut limbs = [0u32, ..8];
+        limbs[0] = n;
+        FieldElement { limbs: limbs }
+    }
+
+    // Takes a number less than `p`.
+    fn from_biguint(n: &BigUint) -> FieldElement {
+        let bytes = to_bytes(n);
+        let mut limbs = [0u32, ..8];
+        for i in range(0, 8u) {
+            for j in range(0, 4u) {
+                limbs[i] = (limbs[i] << 8) | bytes[28 - 4 * i + j] as u32;
+            }
+        }
+        FieldElement { limbs: limbs }
+    }
+
+    fn to_biguint(&self) -> BigUint {
+        let mut bytes = Vec::with_capacity(32);
+        for i in range(0, 8u).rev() {
+            for j in range(0, 4u).rev() {
+                bytes.push((self.limbs[i] >> (8 * j)) as u8);
+            }
+        }
+        from_bytes(bytes.as_slice())
+    }
+
+    fn is_zero(&self) -> bool {
+        self.limbs.iter().fold(0, |acc, &limb| acc | limb) == 0
+    }
+
+    fn add(&self, other: &FieldElement) -> FieldElement {
+        let mut limbs = [0u32, ..8];
+        let mut carry = 0u64;
+        for i in range(0, 8u) {
+            let t = self.limbs[i] as u64 + other.limbs[i] as u64 + carry;
+            limbs[i] = t as u32;
+            carry = t >> 32;
+        }
+        reduce_once(limbs, carry as u32)
+    }
+
+    fn sub(&self, other: &FieldElement) -> FieldElement {
+        let mut limbs = [0u32, ..8];
+        let mut borrow = 0u64;
+        for i in range(0, 8u) {
+            let t = self.limbs[i] as u64 - other.limbs[i] as u64 - borrow;
+            limbs[i] = t as u32;
+            borrow = t >> 63;
+        }
+
+        // Add `p` back if the subtraction went below zero.
+        let mask = 0u32 - borrow as u32;
+        let mut carry = 0u64;
+        for i in range(0, 8u) {
+            let t = limbs[i] as u64 + (FIELD_PRIME_LIMBS[i] & mask) as u64 + carry;
+            limbs[i] = t as u32;
+            carry = t >> 32;
+        }
+        FieldElement { limbs: limbs }
+    }
+
+    fn mul(&self, other: &FieldElement) -> FieldElement {
+        // The full 512-bit product.
+        let mut product = [0u32, ..16];
+        for i in range(0, 8u) {
+            let mut carry = 0u64;
+            for j in range(0, 8u) {
+                let t = product[i + j] as u64 +
+                        self.limbs[i] as u64 * other.limbs[j] as u64 + carry;
+                product[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            product[i + 8] = carry as u32;
+        }
+
+        // Fold the top half into the bottom one, multiplying it by
+        // `2^32 + 977`. What's left over above 2^256 fits in 33 bits.
+        let mut limbs = [0u32, ..8];
+        let mut carry = 0u64;
+        for i in range(0, 8u) {
+            let mut t = product[i] as u64 + product[i + 8] as u64 * FIELD_REDUCTION + carry;
+            if i > 0 {
+                t += product[i + 7] as u64;
+            }
+            limbs[i] = t as u32;
+            carry = t >> 32;
+        }
+        let top = product[15] as u64 + carry;
+
+        // Folding that in can overflow by one more bit, but only when the
+        // result wraps around to something small, so the second fold can't.
+        let overflow = add_reduction_multiple(&mut limbs, top);
+        add_reduction_multiple(&mut limbs, overflow);
+        reduce_once(limbs, 0)
+    }
+
+    // Computes the multiplicative inverse, as `self^(p - 2)`. The exponent is
+    // public, so it's fine to branch on its bits. Returns zero for zero.
+    fn inverse(&self) -> FieldElement {
+        let exponent = to_bytes(&(field_prime() - small(2)));
+        let mut result = FieldElement::from_u32(1);
+        for byte in exponent.iter() {
+            for i in range(0, 8u).rev() {
+                result = result.mul(&result);
+                if (*byte >> i) & 1 == 1 {
+                    result = result.mul(self);
+                }
+            }
+        }
+        result
+    }
+
+    // Swaps the values of two elements if `mask` is all ones, and leaves them
+    // if it's zero.
+    fn conditional_swap(&mut self, other: &mut FieldElement, mask: u32) {
+        for i in range(0, 8u) {
+            let t = mask & (self.limbs[i] ^ other.limbs[i]);
+            self.limbs[i] ^= t;
+            other.limbs[i] ^= t;
+        }
+    }
+}
+
+// Takes a number below 2p, as eight limbs and a carry bit above them, and
+// subtracts `p` if it's at least `p`.
+fn reduce_once(limbs: [u32, ..8], carry: u32) -> FieldElement {
+    let mut reduced = [0u32, ..8];
+    let mut borrow = 0u64;
+    for i in range(0, 8u) {
+        let t = limbs[i] as u64 - FIELD_PRIME_LIMBS[i] as u64 - borrow;
+        reduced[i] = t as u32;
+        borrow = t >> 63;
+    }
+
+    // Keep the reduced value if there was a carry, or if subtracting `p`
+    // didn't go below zero.
+    let mask = 0u32 - (carry | (borrow as u32 ^ 1));
+    let mut result = [0u32, ..8];
+    for i in range(0, 8u) {
+        result[i] = (reduced[i] & mask) | (limbs[i] & !mask);
+    }
+    FieldElement { limbs: result }
+}
+
+// Adds `multiple * 2^256` to the limbs modulo `p`, i.e. `multiple * (2^32 +
+// 977)`, and returns the carry out of the top limb.
+fn add_reduction_multiple(limbs: &mut [u32, ..8], multiple: u64) -> u64 {
+    let mut carry = 0u64;
+    for i in range(0, 8u) {
+        let mut t = limbs[i] as u64 + carry;
+        if i == 0 {
+            t += multiple * FIELD_REDUCTION;
+        } else if i == 1 {
+            t += multiple;
+        }
+        limbs[i] = t as u32;
+        carry = t >> 32;
+    }
+    carry
+}
+
+// The order `n` as eight 32-bit limbs, least significant first.
+static CURVE_ORDER_LIMBS: [u32, ..8] = [
+    0xD0364141, 0xBFD25E8C, 0xAF48A03B, 0xBAAEDCE6,
+    0xFFFFFFFE, 0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF
+];
+
+// Half of `n`, rounded down.
+static HALF_CURVE_ORDER_LIMBS: [u32, ..8] = [
+    0x681B20A0, 0xDFE92F46, 0x57A4501D, 0x5D576E73,
+    0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF, 0x7FFFFFFF
+];
+
+// `n` is `2^256 - c` for this 129-bit `c`, so `2^256` is `c` modulo `n`.
+static ORDER_COMPLEMENT_LIMBS: [u32, ..5] = [
+    0x2FC9BEBF, 0x402DA173, 0x50B75FC4, 0x45512319, 0x00000001
+];
+
+/// An integer modulo the curve order `n`, such as a private key, a nonce or
+/// a signature scalar. Like the field elements `mul_secret()` uses inside,
+/// these are eight 32-bit limbs, always fully reduced, and every operation
+/// on them does the same work whatever the values are, so secrets should be
+/// kept in these rather than in BigUint.
+#[deriving(Clone)]
+pub struct Scalar {
+    limbs: [u32, ..8]
+}
+
+impl Scalar {
+    /// Reads a scalar from 32 big-endian bytes. Returns None if the data
+    /// isn't 32 bytes long, or if the number isn't less than `n`. Zero is
+    /// allowed, so check `is_zero()` where it isn't.
+    pub fn from_bytes(data: &[u8]) -> Option<Scalar> {
+        if data.len() != 32 { return None; }
+
+        let limbs = limbs_from_bytes(data);
+        if !less_than(&limbs, &CURVE_ORDER_LIMBS) { return None; }
+        Some(Scalar { limbs: limbs })
+    }
+
+    /// Reads a number from 32 big-endian bytes and reduces it modulo `n`, the
+    /// way ECDSA treats a message hash. Fails if the data isn't 32 bytes long.
+    pub fn reduce(data: &[u8]) -> Scalar {
+        assert_eq!(data.len(), 32);
+        reduce_scalar_once(limbs_from_bytes(data), 0)
+    }
+
+    /// Encodes the scalar as 32 big-endian bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(32);
+        for i in range(0, 8u).rev() {
+            for j in range(0, 4u).rev() {
+                bytes.push((self.limbs[i] >> (8 * j)) as u8);
+            }
+        }
+        bytes
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.iter().fold(0, |acc, &limb| acc | limb) == 0
+    }
+
+    /// Checks whether the scalar is more than `n / 2`. A signature's `s` is
+    /// normalised to the lower half.
+    pub fn is_high(&self) -> bool {
+        less_than(&HALF_CURVE_ORDER_LIMBS, &self.limbs)
+    }
+
+    pub fn add(&self, other: &Scalar) -> Scalar {
+        let mut limbs = [0u32, ..8];
+        let mut carry = 0u64;
+        for i in range(0, 8u) {
+            let t = self.limbs[i] as u64 + other.limbs[i] as u64 + carry;
+            limbs[i] = t as u32;
+            carry = t >> 32;
+        }
+        reduce_scalar_once(limbs, carry as u32)
+    }
+
+    pub fn mul(&self, other: &Scalar) -> Scalar {
+        // The full 512-bit product.
+        let mut product = [0u32, ..16];
+        for i in range(0, 8u) {
+            let mut carry = 0u64;
+            for j in range(0, 8u) {
+                let t = product[i + j] as u64 +
+                        self.limbs[i] as u64 * other.limbs[j] as u64 + carry;
+                product[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            product[i + 8] = carry as u32;
+        }
+
+        // Each fold takes the product from 16 limbs down to 14, 12, 10 and
+        // then 9. The fifth leaves it at 9 limbs, but below 2n, with at most
+        // a carry bit in the top limb.
+        let mut limbs = product;
+        let mut len = 16u;
+        for _ in range(0, 5u) {
+            let (folded, folded_len) = fold_order(&limbs, len);
+            limbs = folded;
+            len = folded_len;
+        }
+
+        let mut low = [0u32, ..8];
+        for i in range(0, 8u) {
+            low[i] = limbs[i];
+        }
+        reduce_scalar_once(low, limbs[8])
+    }
+
+    /// Computes `n - self`, which is zero for zero.
+    pub fn negate(&self) -> Scalar {
+        let mut limbs = [0u32, ..8];
+        let mut borrow = 0u64;
+        for i in range(0, 8u) {
+            let t = CURVE_ORDER_LIMBS[i] as u64 - self.limbs[i] as u64 - borrow;
+            limbs[i] = t as u32;
+            borrow = t >> 63;
+        }
+
+        // That gives `n` for zero, so keep it only if the scalar isn't zero.
+        let any = self.limbs.iter().fold(0, |acc, &limb| acc | limb);
+        let mask = 0u32 - ((any | (0u32 - any)) >> 31);
+        for i in range(0, 8u) {
+            limbs[i] &= mask;
+        }
+        Scalar { limbs: limbs }
+    }
+
+    /// Returns the negation of the scalar if `condition` is true, and a copy
+    /// of it otherwise, choosing between them with a mask.
+    pub fn negate_if(&self, condition: bool) -> Scalar {
+        let negated = self.negate();
+        let mask = 0u32 - condition as u32;
+        let mut limbs = [0u32, ..8];
+        for i in range(0, 8u) {
+            limbs[i] = (negated.limbs[i] & mask) | (self.limbs[i] & !mask);
+        }
+        Scalar { limbs: limbs }
+    }
+
+    /// Computes the multiplicative inverse, as `self^(n - 2)`. The exponent
+    /// is public, so it's fine to branch on its bits. Returns zero for zero.
+    pub fn inverse(&self) -> Scalar {
+        let exponent = to_bytes(&(curve_order() - small(2)));
+        let mut result = Scalar { limbs: [1u32, 0, 0, 0, 0, 0, 0, 0] };
+        for byte in exponent.iter() {
+            for i in range(0, 8u).rev() {
+                result = result.mul(&result);
+                if (*byte >> i) & 1 == 1 {
+                    result = result.mul(self);
+                }
+            }
+        }
+        result
+    }
+}
+
+// Reads 32 big-endian bytes into eight limbs, least significant first.
+fn limbs_from_bytes(data: &[u8]) -> [u32, ..8] {
+    let mut limbs = [0u32, ..8];
+    for i in range(0, 8u) {
+        for j in range(0, 4u) {
+            limbs[i] = (limbs[i] << 8) | data[28 - 4 * i + j] as u32;
+        }
+    }
+    limbs
+}
+
+// Compares two numbers by subtracting them and looking at the borrow, going
+// through every limb either way.
+fn less_than(a: &[u32, ..8], b: &[u32, ..8]) -> bool {
+    let mut borrow = 0u64;
+    for i in range(0, 8u) {
+        let t = a[i] as u64 - b[i] as u64 - borrow;
+        borrow = t >> 63;
+    }
+    borrow == 1
+}
+
+// Takes a number below 2n, as eight limbs and a carry bit above them, and
+// subtracts `n` if it's at least `n`.
+fn reduce_scalar_once(limbs: [u32, ..8], carry: u32) -> Scalar {
+    let mut reduced = [0u32, ..8];
+    let mut borrow = 0u64;
+    for i in range(0, 8u) {
+        let t = limbs[i] as u64 - CURVE_ORDER_LIMBS[i] as u64 - borrow;
+        reduced[i] = t as u32;
+        borrow = t >> 63;
+    }
+
+    let mask = 0u32 - (carry | (borrow as u32 ^ 1));
+    let mut result = [0u32, ..8];
+    for i in range(0, 8u) {
+        result[i] = (reduced[i] & mask) | (limbs[i] & !mask);
+    }
+    Scalar { limbs: result }
+}
+
+// Takes a number of `len` limbs, and replaces everything above 2^256 with
+// that part multiplied by `c`, which is the same modulo `n`. Returns the
+// result and its length in limbs, which is enough to hold it whatever the
+// limbs were, so the length only depends on `len`.
+fn fold_order(limbs: &[u32, ..16], len: uint) -> ([u32, ..16], uint) {
+    let high = len - 8;
+    let folded_len = cmp::max(8, high + 5) + 1;
+    let mut result = [0u32, ..16];
+    for i in range(0, 8u) {
+        result[i] = limbs[i];
+    }
+
+    for i in range(0, high) {
+        let mut carry = 0u64;
+        for j in range(0, 5u) {
+            let t = result[i + j] as u64 +
+                    limbs[i + 8] as u64 * ORDER_COMPLEMENT_LIMBS[j] as u64 + carry;
+            result[i + j] = t as u32;
+            carry = t >> 32;
+        }
+        for j in range(i + 5, folded_len) {
+            let t = result[j] as u64 + carry;
+            result[j] = t as u32;
+            carry = t >> 32;
+        }
+    }
+    (result, folded_len)
+}
+
+// A point in homogeneous projective coordinates, where (X, Y, Z) represents
+// the affine point (X/Z, Y/Z), and (0, 1, 0) is the point at infinity. Points
+// are added with the complete formulas from "Complete addition formulas for
+// prime order elliptic curves" by Renes, Costello and Batina (algorithm 7),
+// which work the same way for every pair of points, including doubling and
+// the point at infinity, so there's nothing to branch on.
+#[deriving(Clone)]
+struct ProjectivePoint {
+    x: FieldElement,
+    y: FieldElement,
+    z: FieldElement
+}
+
+impl ProjectivePoint {
+    fn infinity() -> ProjectivePoint {
+        ProjectivePoint {
+            x: FieldElement::from_u32(0),
+            y: FieldElement::from_u32(1),
+            z: FieldElement::from_u32(0)
+        }
+    }
+
+    fn from_affine(point: &Point) -> ProjectivePoint {
+        match *point {
+            Point::Infinity => ProjectivePoint::infinity(),
+            Point::Affine(ref x, ref y) => ProjectivePoint {
+                x: FieldElement::from_biguint(x),
+                y: FieldElement::from_biguint(y),
+                z: FieldElement::from_u32(1)
+            }
+        }
+    }
+
+    fn to_affine(&self) -> Point {
+        if self.z.is_zero() { return Point::Infinity; }
+
+        let z_inv = self.z.inverse();
+        Point::Affine(self.x.mul(&z_inv).to_biguint(), self.y.mul(&z_inv).to_biguint())
+    }
+
+    fn add(&self, other: &ProjectivePoint) -> ProjectivePoint {
+        // Three times the curve's constant term, 7.
+        let b3 = FieldElement::from_u32(21);
+        let (x1, y1, z1) = (&self.x, &self.y, &self.z);
+        let (x2, y2, z2) = (&other.x, &other.y, &other.z);
+
+        let t0 = x1.mul(x2);
+        let t1 = y1.mul(y2);
+        let t2 = z1.mul(z2);
+        let t3 = x1.add(y1).mul(&x2.add(y2)).sub(&t0.add(&t1));
+        let t4 = y1.add(z1).mul(&y2.add(z2)).sub(&t1.add(&t2));
+        let y3 = x1.add(z1).mul(&x2.add(z2)).sub(&t0.add(&t2));
+        let t0 = t0.add(&t0).add(&t0);
+        let t2 = b3.mul(&t2);
+        let z3 = t1.add(&t2);
+        let t1 = t1.sub(&t2);
+        let y3 = b3.mul(&y3);
+
+        ProjectivePoint {
+            x: t3.mul(&t1).sub(&t4.mul(&y3)),
+            y: t1.mul(&z3).add(&y3.mul(&t0)),
+            z: z3.mul(&t4).add(&t0.mul(&t3))
+        }
+    }
+
+    // Swaps two points if `mask` is all ones, and leaves them if it's zero.
+    fn conditional_swap(&mut self, other: &mut ProjectivePoint, mask: u32) {
+        self.x.conditional_swap(&mut other.x, mask);
+        self.y.conditional_swap(&mut other.y, mask);
+        self.z.conditional_swap(&mut other.z, mask);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::{ToHex, FromHex};
+    use num::Zero;
+    use num::bigint::{BigUint, ToBigUint};
+
+    use super::{Point, Scalar, FieldElement, curve_order, from_bytes, to_bytes, mod_inverse, field_prime};
+
+    #[test]
+    fn test_from_bytes_to_bytes() {
+        let data = "00000000000000000000000000000000000000000000000000000000000001FF";
+        let data = data.from_hex().unwrap();
+        let n = from_bytes(data.as_slice());
+        assert_eq!(n, 511u.to_biguint().unwrap());
+        assert_eq!(to_bytes(&n), data);
+    }
+
+    #[test]
+    fn test_mod_inverse() {
+        let p = field_prime();
+        let a = 12345u.to_biguint().unwrap();
+        let a_inv = mod_inverse(&a, &p);
+        assert_eq!((a * a_inv) % p, 1u.to_biguint().unwrap());
+    }
+
+    #[test]
+    fn test_mul_generator() {
+        let private_key = "F7476532FE5753EBCBEA26FE02FFF18BF015546F85CAF78AC8D599547F7D3AAC";
+        let private_key = from_bytes(private_key.from_hex().unwrap().as_slice());
+        let public_key = Point::generator().mul(&private_key);
+        let expected = "04D6630E2F4FB6D62EF5BC5BE850083625C9B584F661AAF7723BD8394DB5F61449\
+                          41F6B5F83442D9391D774C7D7F262CE6C55380E096442305367270B04ACA6B75";
+        let expected = expected.from_hex().unwrap();
+        assert_eq!(public_key.to_uncompressed(), expected);
+        assert!(public_key.is_on_curve());
+    }
+
+    #[test]
+    fn test_mul_order_is_infinity() {
+        let point = Point::generator().mul(&curve_order());
+        assert!(point.is_infinity());
+
+        // As a scalar, the order is zero.
+        let order = Scalar::reduce(to_bytes(&curve_order()).as_slice());
+        assert!(order.is_zero());
+        assert!(Point::generator().mul_secret(&order).is_infinity());
+    }
+
+    #[test]
+    fn test_mul_secret() {
+        let g = Point::generator();
+        let scalars = [
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "F7476532FE5753EBCBEA26FE02FFF18BF015546F85CAF78AC8D599547F7D3AAC",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364140"
+        ];
+
+        for hex in scalars.iter() {
+            let n = from_bytes(hex.from_hex().unwrap().as_slice());
+            assert_eq!(g.mul_secret(&scalar(&n)), g.mul(&n));
+        }
+
+        let three = 3u.to_biguint().unwrap();
+        assert_eq!(g.negate().mul_secret(&scalar(&three)), g.mul(&three).negate());
+    }
+
+    #[test]
+    fn test_field_element() {
+        let p = field_prime();
+        let one = 1u.to_biguint().unwrap();
+        let two = 2u.to_biguint().unwrap();
+        let minus_one = FieldElement::from_biguint(&(p - one));
+        let zero = FieldElement::from_u32(0);
+
+        assert_eq!(minus_one.add(&minus_one).to_biguint(), p - two);
+        assert_eq!(minus_one.mul(&minus_one).to_biguint(), one);
+        assert_eq!(zero.sub(&FieldElement::from_u32(1)).to_biguint(), p - one);
+        assert!(minus_one.add(&FieldElement::from_u32(1)).is_zero());
+
+        let a = 12345u.to_biguint().unwrap();
+        let a_inv = FieldElement::from_biguint(&a).inverse().to_biguint();
+        assert_eq!(a_inv, mod_inverse(&a, &p));
+    }
+
+    fn scalar(n: &BigUint) -> Scalar {
+        Scalar::from_bytes(to_bytes(n).as_slice()).unwrap()
+    }
+
+    fn value(scalar: Scalar) -> BigUint {
+        from_bytes(scalar.to_bytes().as_slice())
+    }
+
+    #[test]
+    fn test_scalar() {
+        let n = curve_order();
+        let one = 1u.to_biguint().unwrap();
+
+        // Only numbers below the order are scalars, but anything reduces.
+        assert!(Scalar::from_bytes(to_bytes(&n).as_slice()).is_none());
+        assert!(Scalar::from_bytes(to_bytes(&one).slice_from(1)).is_none());
+        let max = Vec::from_elem(32, 0xFFu8);
+        assert_eq!(value(Scalar::reduce(max.as_slice())), from_bytes(max.as_slice()) % n);
+
+        let minus_one = scalar(&(n - one));
+        assert_eq!(value(minus_one.add(&minus_one)), n - 2u.to_biguint().unwrap());
+        assert_eq!(value(minus_one.mul(&minus_one)), one);
+        assert!(minus_one.add(&scalar(&one)).is_zero());
+        assert_eq!(value(minus_one.negate()), one);
+        assert!(scalar(&Zero::zero()).negate().is_zero());
+        assert_eq!(value(minus_one.negate_if(false)), n - one);
+
+        let a = from_bytes("F7476532FE5753EBCBEA26FE02FFF18BF015546F85CAF78AC8D599547F7D3AAC".from_hex().unwrap().as_slice());
+        let b = from_bytes("C6047F9441ED7D6D3045406E95C07CD85C778E4B8CEF3CA7ABAC09B95C709EE5".from_hex().unwrap().as_slice());
+        assert_eq!(value(scalar(&a).mul(&scalar(&b))), (a * b) % n);
+        assert_eq!(value(scalar(&a).inverse()), mod_inverse(&a, &n));
+
+        // The lower half of the range ends at (n - 1) / 2.
+        let half = n >> 1;
+        assert!(!scalar(&half).is_high());
+        assert!(scalar(&(half + one)).is_high());
+    }
+
+    #[test]
+    fn test_add_double() {
+        let g = Point::generator();
+        let two_g = g.mul(&2u.to_biguint().unwrap());
+        let three_g = g.mul(&3u.to_biguint().unwrap());
+        assert_eq!(g.add(&g), two_g);
+        assert_eq!(g.add(&two_g), three_g);
+        assert_eq!(g.add(&g.negate()), Point::Infinity);
+        assert_eq!(g.add(&Point::Infinity), g);
+    }
+
+    #[test]
+    fn test_from_bytes_compressed() {
+        let g = Point::generator();
+        let compressed = g.to_compressed();
+        assert_eq!(compressed.to_hex().as_slice(),
+                   "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+        assert_eq!(Point::from_bytes(compressed.as_slice()), Some(g.clone()));
+
+        let negated = g.negate().to_compressed();
+        assert_eq!(negated[0], 0x03);
+        assert_eq!(Point::from_bytes(negated.as_slice()), Some(g.negate()));
+    }
+
+    #[test]
+    fn test_from_bytes_uncompressed() {
+        let g = Point::generator();
+        assert_eq!(Point::from_bytes(g.to_uncompressed().as_slice()), Some(g.clone()));
+
+        let mut not_on_curve = g.to_uncompressed();
+        *not_on_curve.index_mut(&64) ^= 1;
+        assert_eq!(Point::from_bytes(not_on_curve.as_slice()), None);
+    }
+
+    #[test]
+    fn test_lift_x_invalid() {
+        // From the BIP 340 test vectors: not the X coordinate of any point.
+        let x = "EEFDEA4CDB677750A420FEE807EACF21EB9898AE79B9768766E4FAA04A2D4A34";
+        assert!(Point::lift_x(x.from_hex().unwrap().as_slice()).is_none());
+
+        // Exceeds the field size.
+        let x = "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC30";
+        assert!(Point::lift_x(x.from_hex().unwrap().as_slice()).is_none());
+    }
+}
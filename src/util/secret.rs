@@ -0,0 +1,182 @@
+//! Memory for secrets, like private keys and the decrypted contents of a
+//! wallet file, that's wiped as soon as it's no longer needed.
+//!
+//! With the `mlock` feature, the memory is also locked so that it can't be
+//! swapped out to disk. Locking is best effort: if it fails (usually because
+//! RLIMIT_MEMLOCK is too low), the secret is still kept, just unlocked.
+
+use std::fmt;
+use std::intrinsics;
+
+#[cfg(feature = "mlock")]
+use libc::{c_void, size_t};
+#[cfg(feature = "mlock")]
+use libc::funcs::posix88::mman::{mlock, munlock};
+
+/// A fixed-capacity buffer of secret bytes. It's zeroed when it's dropped,
+/// never grows (since growing would leave a copy behind in the old
+/// allocation), and never shows its contents when it's formatted.
+pub struct SecretBytes {
+    data: Vec<u8>
+}
+
+impl SecretBytes {
+    /// Takes ownership of a Vec holding a secret. The buffer is full: its
+    /// capacity is the Vec's length. The secret is only copied if the Vec has
+    /// spare capacity, in which case the Vec is wiped.
+    pub fn new(mut data: Vec<u8>) -> SecretBytes {
+        // Shrinking the Vec could reallocate without wiping the old memory.
+        if data.capacity() != data.len() {
+            let copy = SecretBytes::from_slice(data.as_slice());
+            wipe(data.as_mut_slice());
+            return copy;
+        }
+
+        lock(&data);
+        SecretBytes { data: data }
+    }
+
+    /// Creates an empty buffer that can hold up to `capacity` bytes.
+    pub fn with_capacity(capacity: uint) -> SecretBytes {
+        let data = Vec::with_capacity(capacity);
+        lock(&data);
+        SecretBytes { data: data }
+    }
+
+    /// Copies a secret into a new buffer of exactly its length.
+    pub fn from_slice(data: &[u8]) -> SecretBytes {
+        let mut secret = SecretBytes::with_capacity(data.len());
+        secret.push_all(data);
+        secret
+    }
+
+    /// Appends bytes to the buffer. Fails if they don't fit in its capacity.
+    pub fn push_all(&mut self, data: &[u8]) {
+        assert!(self.data.len() + data.len() <= self.data.capacity(), "SecretBytes can't grow");
+        self.data.push_all(data);
+    }
+
+    /// Gets the secret as a slice of bytes.
+    pub fn as_slice(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    pub fn len(&self) -> uint {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Clone for SecretBytes {
+    // The copy gets its own buffer, which is wiped when it's dropped too.
+    fn clone(&self) -> SecretBytes {
+        SecretBytes::from_slice(self.as_slice())
+    }
+}
+
+impl PartialEq for SecretBytes {
+    // Compares in constant time, so that how long it takes doesn't give away
+    // where the first difference is.
+    fn eq(&self, other: &SecretBytes) -> bool {
+        if self.len() != other.len() { return false; }
+
+        let mut difference = 0u8;
+        for (a, b) in self.as_slice().iter().zip(other.as_slice().iter()) {
+            difference |= *a ^ *b;
+        }
+        difference == 0
+    }
+}
+
+impl fmt::Show for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretBytes([{} bytes hidden])", self.len())
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        // Wipe the whole allocation, not just the part that's in use.
+        unsafe {
+            intrinsics::volatile_set_memory(self.data.as_mut_ptr(), 0, self.data.capacity());
+        }
+        unlock(&self.data);
+    }
+}
+
+/// Overwrites a buffer with zeros, in a way the compiler won't optimize out
+/// even though the buffer is never read again. Use it on secrets that can't
+/// be kept in a SecretBytes, like buffers that other libraries return.
+pub fn wipe(data: &mut [u8]) {
+    unsafe {
+        intrinsics::volatile_set_memory(data.as_mut_ptr(), 0, data.len());
+    }
+}
+
+// Locks the memory a buffer has allocated, including any unused capacity.
+#[cfg(feature = "mlock")]
+fn lock(data: &Vec<u8>) {
+    // A Vec with no capacity doesn't point at real memory.
+    if data.capacity() == 0 { return; }
+    unsafe { mlock(data.as_ptr() as *const c_void, data.capacity() as size_t); }
+}
+
+#[cfg(not(feature = "mlock"))]
+fn lock(_data: &Vec<u8>) {}
+
+// Unlocks the memory a buffer has allocated. This unlocks whole pages, which
+// might be shared with another secret that's still alive.
+#[cfg(feature = "mlock")]
+fn unlock(data: &Vec<u8>) {
+    if data.capacity() == 0 { return; }
+    unsafe { munlock(data.as_ptr() as *const c_void, data.capacity() as size_t); }
+}
+
+#[cfg(not(feature = "mlock"))]
+fn unlock(_data: &Vec<u8>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::{SecretBytes, wipe};
+
+    #[test]
+    fn test_from_slice() {
+        let secret = SecretBytes::from_slice(&[1, 2, 3]);
+        assert_eq!(secret.as_slice(), [1u8, 2, 3].as_slice());
+        assert_eq!(secret.clone(), secret);
+        assert!(secret != SecretBytes::from_slice(&[1, 2, 4]));
+        assert!(secret != SecretBytes::from_slice(&[1, 2]));
+    }
+
+    #[test]
+    fn test_push_all() {
+        let mut secret = SecretBytes::with_capacity(4);
+        secret.push_all(&[1, 2]);
+        secret.push_all(&[3, 4]);
+        assert_eq!(secret.as_slice(), [1u8, 2, 3, 4].as_slice());
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_push_all_past_capacity() {
+        let mut secret = SecretBytes::with_capacity(2);
+        secret.push_all(&[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_show_hides_contents() {
+        let secret = SecretBytes::new(vec![0xab, 0xcd]);
+        let shown = format!("{}", secret);
+        assert_eq!(shown.as_slice(), "SecretBytes([2 bytes hidden])");
+    }
+
+    #[test]
+    fn test_wipe() {
+        let mut data = vec![1u8, 2, 3];
+        wipe(data.as_mut_slice());
+        assert_eq!(data, vec![0u8, 0, 0]);
+    }
+}
@@ -0,0 +1,158 @@
+//! Shamir's secret sharing, which splits a secret into shares so that any
+//! `threshold` of them give it back and fewer tell nothing about it.
+//!
+//! Each byte of the secret is shared on its own, as the constant term of a
+//! random polynomial of degree `threshold - 1` over GF(256), and a share is
+//! the polynomials' values at the share's index. The field is the one that
+//! AES and SLIP-39 use, reduced by x^8 + x^4 + x^3 + x + 1.
+
+use openssl;
+
+use util::secret::SecretBytes;
+
+/// The most shares a secret can be split into. Indexes are nonzero bytes.
+pub static MAX_SHARES: uint = 255;
+
+/// Splits a secret into `count` shares, any `threshold` of which give it
+/// back. The shares are in order of their indexes, which start at 1. Fails
+/// if `threshold` is 0 or more than `count`, or `count` is more than
+/// `MAX_SHARES`.
+pub fn split(secret: &[u8], threshold: uint, count: uint) -> Vec<SecretBytes> {
+    assert!(threshold >= 1 && threshold <= count && count <= MAX_SHARES);
+
+    // The coefficients of x, x^2, ... for each byte of the secret in turn.
+    let coefficients = SecretBytes::new(openssl::crypto::rand::rand_bytes(secret.len() * (threshold - 1)));
+    split_with_coefficients(secret, coefficients.as_slice(), threshold, count)
+}
+
+/// Combines shares, each given with its index, back into the secret. Any
+/// `threshold` of the shares from `split()` give the secret back, but so do
+/// fewer shares, or wrong ones: they just give the wrong secret. Returns None
+/// if there are no shares, their lengths differ, or an index is 0 or is
+/// given twice.
+pub fn combine(shares: &[(u8, &[u8])]) -> Option<SecretBytes> {
+    if shares.is_empty() { return None; }
+    let (_, first) = shares[0];
+
+    for (i, &(index, share)) in shares.iter().enumerate() {
+        let is_repeated = shares.slice_to(i).iter().any(|&(other, _)| other == index);
+        if index == 0 || is_repeated || share.len() != first.len() { return None; }
+    }
+
+    // Lagrange interpolation at 0. In GF(256), subtracting is the same as
+    // adding, which is XOR.
+    let mut secret = Vec::from_elem(first.len(), 0u8);
+    for &(index, share) in shares.iter() {
+        let mut basis = 1u8;
+        for &(other, _) in shares.iter() {
+            if other != index {
+                basis = multiply(basis, multiply(other, inverse(index ^ other)));
+            }
+        }
+
+        for (byte, &value) in secret.iter_mut().zip(share.iter()) {
+            *byte ^= multiply(value, basis);
+        }
+    }
+    Some(SecretBytes::new(secret))
+}
+
+// Evaluates the polynomials for each byte of the secret at the indexes of the
+// shares.
+fn split_with_coefficients(secret: &[u8], coefficients: &[u8], threshold: uint, count: uint) -> Vec<SecretBytes> {
+    range(1, count + 1).map(|index| {
+        let mut share = SecretBytes::with_capacity(secret.len());
+        for (i, &byte) in secret.iter().enumerate() {
+            // Horner's method, from the highest power of x down.
+            let mut value = 0u8;
+            for j in range(0, threshold - 1).rev() {
+                value = multiply(value, index as u8) ^ coefficients[i * (threshold - 1) + j];
+            }
+            share.push_all(&[multiply(value, index as u8) ^ byte]);
+        }
+        share
+    }).collect()
+}
+
+// Multiplies two elements of GF(256).
+fn multiply(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    while b != 0 {
+        if b & 1 != 0 { product ^= a; }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry { a ^= 0x1B; }
+        b >>= 1;
+    }
+    product
+}
+
+// Finds the multiplicative inverse of a nonzero element of GF(256), which is
+// the element to the power of 254.
+fn inverse(a: u8) -> u8 {
+    let mut result = 1u8;
+    for _ in range(0, 254u) {
+        result = multiply(result, a);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::FromHex;
+
+    use super::{split, combine, split_with_coefficients, multiply, inverse};
+
+    #[test]
+    fn test_field() {
+        // From FIPS 197, section 4.2, and the AES S-box's inverse of 0x53.
+        assert_eq!(multiply(0x57, 0x83), 0xC1);
+        assert_eq!(inverse(0x53), 0xCA);
+        assert_eq!(multiply(0x53, 0xCA), 1);
+    }
+
+    #[test]
+    fn test_split_with_coefficients() {
+        let coefficients: Vec<u8> = range(0x10, 0x1Au8).collect();
+        let shares = split_with_coefficients(b"cykas", coefficients.as_slice(), 3, 5);
+        let expected = ["62786a6072", "0711171127", "0610161026", "281a705298", "291b715399"];
+        for (share, expected) in shares.iter().zip(expected.iter()) {
+            assert_eq!(share.as_slice(), expected.from_hex().unwrap().as_slice());
+        }
+
+        // Two shares aren't enough.
+        let secret = combine(&[(1, shares[0].as_slice()), (2, shares[1].as_slice())]).unwrap();
+        assert_eq!(secret.as_slice(), "415f414f41".from_hex().unwrap().as_slice());
+    }
+
+    #[test]
+    fn test_split_and_combine() {
+        let secret = b"correct horse battery staple";
+        let shares = split(secret, 3, 5);
+        assert_eq!(shares.len(), 5);
+
+        for i in range(0, 5u) {
+            for j in range(i + 1, 5u) {
+                for k in range(j + 1, 5u) {
+                    // In any order.
+                    let combined = combine(&[(k as u8 + 1, shares[k].as_slice()),
+                                             (i as u8 + 1, shares[i].as_slice()),
+                                             (j as u8 + 1, shares[j].as_slice())]).unwrap();
+                    assert_eq!(combined.as_slice(), secret);
+                }
+            }
+        }
+
+        let one_share = split(secret, 1, 2);
+        assert_eq!(one_share[1].as_slice(), secret);
+    }
+
+    #[test]
+    fn test_combine_invalid() {
+        let shares = split(b"secret", 2, 3);
+        assert!(combine(&[]).is_none());
+        assert!(combine(&[(1, shares[0].as_slice()), (1, shares[0].as_slice())]).is_none());
+        assert!(combine(&[(0, shares[0].as_slice()), (2, shares[1].as_slice())]).is_none());
+        assert!(combine(&[(1, shares[0].as_slice()), (2, shares[1].slice_to(5))]).is_none());
+    }
+}
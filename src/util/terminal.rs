@@ -0,0 +1,110 @@
+//! Turning off the terminal's echo, so that passphrases and mnemonic words
+//! aren't shown on the screen as they're typed.
+//!
+//! This goes through the POSIX termios interface. The layout of its struct
+//! differs between systems, so it's only declared for Linux and OS X; on
+//! others, input is echoed as usual.
+
+pub use self::termios::EchoOff;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod termios {
+    use std::mem;
+
+    use libc::{c_int, c_uchar};
+
+    // The file descriptor of standard input.
+    static STDIN_FILENO: c_int = 0;
+
+    // Tells tcsetattr() to make the change right away.
+    static TCSANOW: c_int = 0;
+
+    // The local mode flag that turns echo on. It's the same on Linux and OS X.
+    static ECHO: TcFlag = 0x8;
+
+    #[cfg(target_os = "linux")]
+    type TcFlag = ::libc::c_uint;
+
+    #[cfg(target_os = "linux")]
+    #[allow(dead_code)]
+    #[repr(C)]
+    struct Termios {
+        c_iflag: TcFlag,
+        c_oflag: TcFlag,
+        c_cflag: TcFlag,
+        c_lflag: TcFlag,
+        c_line: c_uchar,
+        c_cc: [c_uchar, ..32],
+        c_ispeed: ::libc::c_uint,
+        c_ospeed: ::libc::c_uint
+    }
+
+    #[cfg(target_os = "macos")]
+    type TcFlag = ::libc::c_ulong;
+
+    #[cfg(target_os = "macos")]
+    #[allow(dead_code)]
+    #[repr(C)]
+    struct Termios {
+        c_iflag: TcFlag,
+        c_oflag: TcFlag,
+        c_cflag: TcFlag,
+        c_lflag: TcFlag,
+        c_cc: [c_uchar, ..20],
+        c_ispeed: ::libc::c_ulong,
+        c_ospeed: ::libc::c_ulong
+    }
+
+    extern {
+        fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        fn tcsetattr(fd: c_int, optional_actions: c_int, termios: *const Termios) -> c_int;
+    }
+
+    /// Turns off echo on standard input for as long as it's alive, and turns
+    /// it back on when it's dropped. Does nothing if standard input isn't a
+    /// terminal.
+    pub struct EchoOff {
+        saved: Option<Termios>
+    }
+
+    impl EchoOff {
+        pub fn new() -> EchoOff {
+            unsafe {
+                // The struct isn't copyable, so the settings are read twice:
+                // once to change and once to restore.
+                let mut saved: Termios = mem::zeroed();
+                let mut quiet: Termios = mem::zeroed();
+                if tcgetattr(STDIN_FILENO, &mut saved) != 0 || tcgetattr(STDIN_FILENO, &mut quiet) != 0 {
+                    return EchoOff { saved: None };
+                }
+
+                quiet.c_lflag &= !ECHO;
+                if tcsetattr(STDIN_FILENO, TCSANOW, &quiet) != 0 {
+                    return EchoOff { saved: None };
+                }
+                EchoOff { saved: Some(saved) }
+            }
+        }
+    }
+
+    impl Drop for EchoOff {
+        fn drop(&mut self) {
+            match self.saved {
+                Some(ref saved) => unsafe { tcsetattr(STDIN_FILENO, TCSANOW, saved); },
+                None => {}
+            }
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod termios {
+    /// Stands in for turning off echo, which isn't supported on this system.
+    pub struct EchoOff;
+
+    impl EchoOff {
+        pub fn new() -> EchoOff {
+            EchoOff
+        }
+    }
+}
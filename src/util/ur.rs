@@ -0,0 +1,584 @@
+//! Uniform Resources (BCR-2020-005), the format that air-gapped wallets use
+//! to pass PSBTs and other data through QR codes. A UR is its type and a CBOR
+//! message written as bytewords (BCR-2020-012), and messages too big for one
+//! QR code are split into numbered parts that a camera reads in any order.
+//! Past the simple parts that hold one fragment of the message each, the
+//! parts are fountain coded: each mixes a few fragments together, picked at
+//! random with a seed that the part's number gives, so that a reader can
+//! work out the missing fragments from whichever parts it gets.
+
+use std::mem;
+use std::num::Int;
+
+use util::hash::{crc32, sha256};
+
+// The minimal bytewords encoding: the first and last letters of each of the
+// 256 words, in order of the byte values they stand for.
+static BYTEWORDS_MINIMAL: &'static str =
+    "aeadaoaxaaahamatayasbkbdbnbtbabsbebybgbwbbbzcmchcscfcycwcecackctcxclcpcndkdadsdidedtdrdndwdpdmdl\
+     dyeheyeoeeecenemetesftfrfnfsfmfhfzfpfwfxfyfefgflfdgagegrgsgtglgwgdgygmgughgohfhghdhkhthphhhlhyhe\
+     hnhsidiaieihiyioisinimjejzjnjtjljojsjpjkjykpkoktkskkknkgkekikblblalylflslrlplnltloldlelulklgmnmy\
+     mhmemomumwmdmtmsmknlnyndnsntnnnenboyoeotoxonolospdptpkpypspmplpepfpaprqdqzrerprlrorhrdrkrfryrnrs\
+     rtsesasrssskswstspsosgsbsfsntotktitttdtetytltbtstptatnuyuoutueurvtvyvovlvevwvavdvswlwdwmwpwewyws\
+     wtwnwzwfwkykynylyaytzszoztzczezm";
+
+// CBOR major types.
+static CBOR_UNSIGNED: u8 = 0;
+static CBOR_BYTES: u8 = 2;
+static CBOR_ARRAY: u8 = 4;
+
+// The length of the checksum at the end of bytewords.
+static CHECKSUM_LENGTH: uint = 4;
+
+/// The UR type of a PSBT, as most air-gapped wallets expect it.
+pub static PSBT_TYPE: &'static str = "crypto-psbt";
+
+/// Encodes a CBOR message as a UR of the given type. If the message is longer
+/// than `max_fragment_length` bytes, it's split into evenly sized fragments
+/// that each go in a part of a multi-part UR, and every part is needed to
+/// put the message back together. The parts are the simple ones that hold a
+/// single fragment each, without the fountain-coded parts that mix several
+/// together, so showing them over and over is enough for a reader to get
+/// them all.
+pub fn encode(ur_type: &str, message: &[u8], max_fragment_length: uint) -> Vec<String> {
+    if message.len() <= max_fragment_length {
+        return vec![format!("ur:{}/{}", ur_type, bytewords_encode(message))];
+    }
+
+    let mut count = 1;
+    while (message.len() + count - 1) / count > max_fragment_length {
+        count += 1;
+    }
+    let fragment_length = (message.len() + count - 1) / count;
+
+    // The last fragment is padded with zeros.
+    let mut padded = message.to_vec();
+    padded.grow(fragment_length * count - message.len(), 0);
+    let checksum = crc32(message);
+
+    padded.as_slice().chunks(fragment_length).enumerate().map(|(i, fragment)| {
+        let mut part = Vec::new();
+        cbor_head(&mut part, CBOR_ARRAY, 5);
+        cbor_head(&mut part, CBOR_UNSIGNED, (i + 1) as u64);
+        cbor_head(&mut part, CBOR_UNSIGNED, count as u64);
+        cbor_head(&mut part, CBOR_UNSIGNED, message.len() as u64);
+        cbor_head(&mut part, CBOR_UNSIGNED, checksum as u64);
+        part.push_all(cbor_bytes(fragment).as_slice());
+        format!("ur:{}/{}-{}/{}", ur_type, i + 1, count, bytewords_encode(part.as_slice()))
+    }).collect()
+}
+
+/// Encodes data as a CBOR byte string, which is the message of a
+/// `crypto-psbt` UR.
+pub fn cbor_bytes(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() + 9);
+    cbor_head(&mut result, CBOR_BYTES, data.len() as u64);
+    result.push_all(data);
+    result
+}
+
+/// Encodes data as minimal bytewords: two letters per byte, followed by a
+/// CRC-32 checksum of the data.
+pub fn bytewords_encode(data: &[u8]) -> String {
+    let checksum = crc32(data);
+    let checksum_bytes = [(checksum >> 24) as u8, (checksum >> 16) as u8, (checksum >> 8) as u8, checksum as u8];
+
+    let mut result = String::with_capacity((data.len() + 4) * 2);
+    for &byte in data.iter().chain(checksum_bytes.iter()) {
+        let index = byte as uint * 2;
+        result.push_str(BYTEWORDS_MINIMAL.slice(index, index + 2));
+    }
+    result
+}
+
+/// Decodes minimal bytewords, in either case, and checks their checksum.
+/// Returns None if they aren't valid.
+pub fn bytewords_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if text.len() % 2 != 0 || text.len() < CHECKSUM_LENGTH * 2 { return None; }
+
+    let mut result = Vec::with_capacity(text.len() / 2);
+    for pair in text.chunks(2) {
+        let pair = [(pair[0] as char).to_lowercase() as u8, (pair[1] as char).to_lowercase() as u8];
+        match BYTEWORDS_MINIMAL.as_bytes().chunks(2).position(|word| word == pair.as_slice()) {
+            Some(byte) => result.push(byte as u8),
+            None => return None
+        }
+    }
+
+    let length = result.len() - CHECKSUM_LENGTH;
+    let checksum = result.slice_from(length).iter().fold(0u32, |checksum, &byte| checksum << 8 | byte as u32);
+    if checksum != crc32(result.slice_to(length)) { return None; }
+    result.truncate(length);
+    Some(result)
+}
+
+/// Decodes a CBOR byte string, like the message of a `crypto-psbt` UR.
+/// Returns None if the message is anything else.
+pub fn decode_cbor_bytes(message: &[u8]) -> Option<Vec<u8>> {
+    let mut position = 0;
+    match cbor_read_bytes(message, &mut position) {
+        Some(data) if position == message.len() => Some(data),
+        _ => None
+    }
+}
+
+/// Puts a UR back together from its parts, as they're read from QR codes.
+/// The parts can come in any order and more than once, and can include the
+/// fountain-coded parts that mix several fragments together.
+pub struct Decoder {
+    ur_type: Option<String>,
+    // What the parts of a multi-part UR all say about the whole of it: the
+    // number of fragments, the length and checksum of the message, and the
+    // length of a fragment.
+    fragment_count: uint,
+    message_length: uint,
+    checksum: u32,
+    fragment_length: uint,
+    fragments: Vec<Option<Vec<u8>>>,
+    // The parts that mix fragments that haven't been worked out yet, with
+    // the indexes of the fragments they mix.
+    mixed_parts: Vec<(Vec<uint>, Vec<u8>)>,
+    message: Option<Vec<u8>>
+}
+
+impl Decoder {
+    /// Creates a Decoder that hasn't received any parts.
+    pub fn new() -> Decoder {
+        Decoder {
+            ur_type: None,
+            fragment_count: 0,
+            message_length: 0,
+            checksum: 0,
+            fragment_length: 0,
+            fragments: Vec::new(),
+            mixed_parts: Vec::new(),
+            message: None
+        }
+    }
+
+    /// Adds a part of a UR, as it's written in a QR code. Returns false if
+    /// it isn't a valid UR, or doesn't belong with the parts received before.
+    /// If the message turns out not to match its checksum when every fragment
+    /// is in, it's thrown away and the parts have to be received again.
+    pub fn receive(&mut self, text: &str) -> bool {
+        let text: String = text.trim().chars().map(|ch| ch.to_lowercase()).collect();
+        if !text.as_slice().starts_with("ur:") { return false; }
+        let components: Vec<&str> = text.as_slice().slice_from(3).split('/').collect();
+        let ur_type = components[0];
+        if ur_type.is_empty() || !ur_type.chars().all(|ch| ch.is_lowercase() || ch.is_digit(10) || ch == '-') {
+            return false;
+        }
+        if self.ur_type.as_ref().map_or(false, |previous| previous.as_slice() != ur_type) { return false; }
+
+        // "ur:type/body" for a single part, "ur:type/sequence-count/body" for
+        // one of many.
+        if components.len() != 2 && components.len() != 3 { return false; }
+        let data = match bytewords_decode(components[components.len() - 1]) {
+            Some(data) => data,
+            None => return false
+        };
+
+        if components.len() == 2 {
+            if self.ur_type.is_some() && self.message.is_none() { return false; }
+            self.ur_type = Some(ur_type.to_string());
+            self.message = Some(data);
+            true
+        } else {
+            self.receive_part(ur_type, data.as_slice())
+        }
+    }
+
+    /// Gets the UR's type, once a part of it has been received.
+    pub fn get_ur_type(&self) -> Option<&str> {
+        self.ur_type.as_ref().map(|ur_type| ur_type.as_slice())
+    }
+
+    /// Gets how many of the UR's fragments have been worked out so far, and
+    /// how many there are. A single-part UR has one fragment.
+    pub fn get_progress(&self) -> (uint, uint) {
+        if self.message.is_some() && self.fragments.is_empty() { return (1, 1); }
+        (self.fragments.iter().filter(|fragment| fragment.is_some()).count(), self.fragment_count)
+    }
+
+    /// Gets the UR's CBOR message, once every part that's needed has been
+    /// received.
+    pub fn get_message(&self) -> Option<&[u8]> {
+        self.message.as_ref().map(|message| message.as_slice())
+    }
+
+    // Adds a part of a multi-part UR, from its CBOR data.
+    fn receive_part(&mut self, ur_type: &str, data: &[u8]) -> bool {
+        // [sequence number, fragment count, message length, checksum, fragment]
+        let mut position = 0;
+        let mut values = [0u64, ..4];
+        if cbor_read_head(data, &mut position) != Some((CBOR_ARRAY, 5)) { return false; }
+        for value in values.iter_mut() {
+            *value = match cbor_read_head(data, &mut position) {
+                Some((major_type, value)) if major_type == CBOR_UNSIGNED && value <= 0xFFFFFFFF => value,
+                _ => return false
+            };
+        }
+        let fragment = match cbor_read_bytes(data, &mut position) {
+            Some(fragment) if position == data.len() => fragment,
+            _ => return false
+        };
+
+        let [sequence, fragment_count, message_length, checksum] = values;
+        let (fragment_count, message_length) = (fragment_count as uint, message_length as uint);
+        if sequence == 0 || fragment_count == 0 || fragment.is_empty() ||
+           fragment.len() * fragment_count < message_length ||
+           fragment.len() * (fragment_count - 1) >= message_length {
+            return false;
+        }
+
+        if self.ur_type.is_none() {
+            self.ur_type = Some(ur_type.to_string());
+            self.fragment_count = fragment_count;
+            self.message_length = message_length;
+            self.checksum = checksum as u32;
+            self.fragment_length = fragment.len();
+            self.fragments = Vec::from_elem(fragment_count, None);
+        } else if self.fragments.is_empty() || fragment_count != self.fragment_count ||
+                  message_length != self.message_length || checksum as u32 != self.checksum ||
+                  fragment.len() != self.fragment_length {
+            return false;
+        }
+        if self.message.is_some() { return true; }
+
+        let indexes = choose_fragments(sequence as u32, fragment_count, self.checksum);
+        self.add_fragments(indexes, fragment);
+
+        if self.fragments.iter().all(|fragment| fragment.is_some()) {
+            let mut message = Vec::with_capacity(self.fragment_length * self.fragment_count);
+            for fragment in self.fragments.iter() {
+                message.push_all(fragment.as_ref().unwrap().as_slice());
+            }
+            message.truncate(self.message_length);
+
+            if crc32(message.as_slice()) != self.checksum {
+                *self = Decoder::new();
+                return false;
+            }
+            self.message = Some(message);
+        }
+        true
+    }
+
+    // Adds the XOR of the fragments with the given indexes, and works out
+    // as many more fragments as it can, by taking the known ones out of the
+    // mixed parts until only one is left in them.
+    fn add_fragments(&mut self, indexes: Vec<uint>, data: Vec<u8>) {
+        let mut queue = vec![(indexes, data)];
+        loop {
+            let (indexes, mut data) = match queue.pop() {
+                Some(part) => part,
+                None => break
+            };
+
+            let mut unknown = Vec::new();
+            for &index in indexes.iter() {
+                match self.fragments[index] {
+                    Some(ref fragment) => {
+                        for (byte, &other) in data.iter_mut().zip(fragment.iter()) {
+                            *byte ^= other;
+                        }
+                    }
+                    None => unknown.push(index)
+                }
+            }
+
+            if unknown.len() == 1 {
+                let index = unknown[0];
+                *self.fragments.index_mut(&index) = Some(data);
+
+                // The mixed parts with this fragment in them can be reduced.
+                let mixed_parts = mem::replace(&mut self.mixed_parts, Vec::new());
+                for (indexes, data) in mixed_parts.into_iter() {
+                    if indexes.contains(&index) {
+                        queue.push((indexes, data));
+                    } else {
+                        self.mixed_parts.push((indexes, data));
+                    }
+                }
+            } else if unknown.len() > 1 && !self.mixed_parts.iter().any(|&(ref indexes, _)| *indexes == unknown) {
+                self.mixed_parts.push((unknown, data));
+            }
+        }
+    }
+}
+
+// The xoshiro256** random number generator, which picks the fragments that
+// go in each fountain-coded part.
+struct Xoshiro256 {
+    state: [u64, ..4]
+}
+
+impl Xoshiro256 {
+    // Seeds the generator with the SHA-256 hash of the seed.
+    fn new(seed: &[u8]) -> Xoshiro256 {
+        let hash = sha256(seed);
+        let mut state = [0u64, ..4];
+        for (i, word) in hash.as_slice().chunks(8).enumerate() {
+            state[i] = word.iter().fold(0, |value, &byte| value << 8 | byte as u64);
+        }
+        Xoshiro256 { state: state }
+    }
+
+    fn next(&mut self) -> u64 {
+        let result = (self.state[1] * 5).rotate_left(7) * 9;
+        let shifted = self.state[1] << 17;
+        self.state[2] ^= self.state[0];
+        self.state[3] ^= self.state[1];
+        self.state[1] ^= self.state[2];
+        self.state[0] ^= self.state[3];
+        self.state[2] ^= shifted;
+        self.state[3] = self.state[3].rotate_left(45);
+        result
+    }
+
+    // A number from 0 up to but not including 1.
+    fn next_double(&mut self) -> f64 {
+        self.next() as f64 / 18446744073709551616.0
+    }
+
+    // A number from `low` to `high`, including both.
+    fn next_int(&mut self, low: uint, high: uint) -> uint {
+        (self.next_double() * (high - low + 1) as f64) as uint + low
+    }
+}
+
+// Picks how many fragments go in a fountain-coded part: d of them, with a
+// probability proportional to 1/d, using Vose's alias method.
+fn choose_degree(fragment_count: uint, rng: &mut Xoshiro256) -> uint {
+    let weights: Vec<f64> = range(1, fragment_count + 1).map(|degree| 1.0 / degree as f64).collect();
+    let total = weights.iter().fold(0.0, |sum, &weight| sum + weight);
+    let mut scaled: Vec<f64> = weights.iter().map(|&weight| weight * fragment_count as f64 / total).collect();
+
+    let mut small = Vec::new();
+    let mut large = Vec::new();
+    for i in range(0, fragment_count).rev() {
+        if scaled[i] < 1.0 { small.push(i); } else { large.push(i); }
+    }
+
+    let mut probabilities = Vec::from_elem(fragment_count, 1.0f64);
+    let mut aliases = Vec::from_elem(fragment_count, 0u);
+    while !small.is_empty() && !large.is_empty() {
+        let (less, more) = (small.pop().unwrap(), large.pop().unwrap());
+        *probabilities.index_mut(&less) = scaled[less];
+        *aliases.index_mut(&less) = more;
+        let remainder = scaled[more] + (scaled[less] - 1.0);
+        *scaled.index_mut(&more) = remainder;
+        if remainder < 1.0 { small.push(more); } else { large.push(more); }
+    }
+
+    let (column, toss) = (rng.next_double(), rng.next_double());
+    let i = (fragment_count as f64 * column) as uint;
+    (if toss < probabilities[i] { i } else { aliases[i] }) + 1
+}
+
+// Picks the indexes of the fragments in the part with the given sequence
+// number, in order. The first parts hold one fragment each.
+fn choose_fragments(sequence: u32, fragment_count: uint, checksum: u32) -> Vec<uint> {
+    if sequence as uint <= fragment_count {
+        return vec![sequence as uint - 1];
+    }
+
+    let mut seed = Vec::with_capacity(8);
+    for &value in [sequence, checksum].iter() {
+        seed.push_all(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+    }
+    let mut rng = Xoshiro256::new(seed.as_slice());
+    let degree = choose_degree(fragment_count, &mut rng);
+
+    // The first few of the indexes, shuffled.
+    let mut remaining: Vec<uint> = range(0, fragment_count).collect();
+    let mut shuffled = Vec::with_capacity(fragment_count);
+    while !remaining.is_empty() {
+        let i = rng.next_int(0, remaining.len() - 1);
+        shuffled.push(remaining[i]);
+        remaining.remove(i);
+    }
+    shuffled.truncate(degree);
+    shuffled.sort();
+    shuffled
+}
+
+// Reads the head of a CBOR data item at the position, and moves the position
+// past it. Returns its major type and its value or length, or None if it
+// isn't a valid head of a definite length item.
+fn cbor_read_head(data: &[u8], position: &mut uint) -> Option<(u8, u64)> {
+    if *position >= data.len() { return None; }
+    let initial = data[*position];
+    *position += 1;
+
+    let (major_type, additional) = (initial >> 5, initial & 0x1F);
+    let length = match additional {
+        0...23 => return Some((major_type, additional as u64)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return None
+    };
+    if *position + length > data.len() { return None; }
+
+    let value = data.slice(*position, *position + length).iter().fold(0u64, |value, &byte| value << 8 | byte as u64);
+    *position += length;
+    Some((major_type, value))
+}
+
+// Reads a CBOR byte string at the position, and moves the position past it.
+fn cbor_read_bytes(data: &[u8], position: &mut uint) -> Option<Vec<u8>> {
+    let length = match cbor_read_head(data, position) {
+        Some((major_type, length)) if major_type == CBOR_BYTES && length <= (data.len() - *position) as u64 => {
+            length as uint
+        }
+        _ => return None
+    };
+    let result = data.slice(*position, *position + length).to_vec();
+    *position += length;
+    Some(result)
+}
+
+// Writes the head of a CBOR data item: its major type, and a length or value
+// in as few bytes as possible.
+fn cbor_head(output: &mut Vec<u8>, major_type: u8, value: u64) {
+    let major_type = major_type << 5;
+    if value < 24 {
+        output.push(major_type | value as u8);
+    } else if value < 0x100 {
+        output.push(major_type | 24);
+        output.push(value as u8);
+    } else if value < 0x10000 {
+        output.push(major_type | 25);
+        output.push_all(&[(value >> 8) as u8, value as u8]);
+    } else if value < 0x100000000 {
+        output.push(major_type | 26);
+        output.push_all(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+    } else {
+        output.push(major_type | 27);
+        for i in range(0, 8u).rev() {
+            output.push((value >> (i * 8)) as u8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, cbor_bytes, bytewords_encode, bytewords_decode, decode_cbor_bytes};
+    use super::{Decoder, Xoshiro256, choose_degree, choose_fragments, PSBT_TYPE};
+    use util::hash::crc32;
+
+    #[test]
+    fn test_bytewords_encode() {
+        // From BCR-2020-012.
+        assert_eq!(bytewords_encode(&[0x00, 0x01, 0x02, 0x80, 0xFF]).as_slice(), "aeadaolazmjendeoti");
+    }
+
+    #[test]
+    fn test_encode_single_part() {
+        let message = cbor_bytes(b"psbt\xff");
+        assert_eq!(encode(PSBT_TYPE, message.as_slice(), 40), vec!["ur:crypto-psbt/fejojkidjyzmfxbtnlwt".to_string()]);
+    }
+
+    #[test]
+    fn test_encode_multi_part() {
+        let data: Vec<u8> = range(0, 100u8).collect();
+        let message = cbor_bytes(data.as_slice());
+        let parts = encode(PSBT_TYPE, message.as_slice(), 40);
+        assert_eq!(parts, vec![
+            "ur:crypto-psbt/1-3/lpadaxcsiycynskikeechdcphdieaeadaoaxaaahamatayasbkbdbnbtbabsbebybgbwbbbzcmchcscfcycwcecackctdynngwde".to_string(),
+            "ur:crypto-psbt/2-3/lpaoaxcsiycynskikeechdcpcxclcpcndkdadsdidedtdrdndwdpdmdldyeheyeoeeecenemetesftfrfnfsfmfhfzfpsspmcflg".to_string(),
+            "ur:crypto-psbt/3-3/lpaxaxcsiycynskikeechdcpfwfxfyfefgflfdgagegrgsgtglgwgdgygmgughgohfhghdhkhthphhhlhyhehnhsidiaaoglwmyn".to_string()
+        ]);
+    }
+
+    #[test]
+    fn test_bytewords_decode() {
+        assert_eq!(bytewords_decode("aeadaolazmjendeoti"), Some(vec![0x00, 0x01, 0x02, 0x80, 0xFF]));
+        assert_eq!(bytewords_decode("AEADAOLAZMJENDEOTI"), Some(vec![0x00, 0x01, 0x02, 0x80, 0xFF]));
+        // A wrong checksum, a byte that isn't a word and an odd length.
+        assert_eq!(bytewords_decode("aeadaolazmjendeotj"), None);
+        assert_eq!(bytewords_decode("aeadaolazmjendeoqq"), None);
+        assert_eq!(bytewords_decode("aeadaolazmjendeot"), None);
+    }
+
+    #[test]
+    fn test_xoshiro256() {
+        // From the reference implementation's tests.
+        let mut rng = Xoshiro256::new(b"Wolf");
+        let numbers: Vec<u64> = range(0, 12u).map(|_| rng.next() % 100).collect();
+        assert_eq!(numbers, vec![42, 81, 85, 8, 82, 84, 76, 73, 70, 88, 2, 74]);
+    }
+
+    #[test]
+    fn test_choose_degree() {
+        let degrees: Vec<uint> = range(1, 21u).map(|n| {
+            let mut rng = Xoshiro256::new(format!("Wolf-{}", n).as_bytes());
+            choose_degree(11, &mut rng)
+        }).collect();
+        assert_eq!(degrees, vec![11, 3, 6, 5, 2, 1, 2, 11, 1, 3, 9, 10, 10, 4, 2, 1, 1, 2, 1, 1]);
+    }
+
+    #[test]
+    fn test_choose_fragments() {
+        let mut rng = Xoshiro256::new(b"Wolf");
+        let message: Vec<u8> = range(0, 1024u).map(|_| rng.next_int(0, 255) as u8).collect();
+        let checksum = crc32(message.as_slice());
+        assert_eq!(checksum, 0x2f19f3bb);
+
+        let fragments: Vec<Vec<uint>> = range(1, 21u32).map(|sequence| choose_fragments(sequence, 11, checksum)).collect();
+        assert_eq!(fragments, vec![
+            vec![0], vec![1], vec![2], vec![3], vec![4], vec![5], vec![6], vec![7], vec![8], vec![9], vec![10],
+            vec![9], vec![2, 5, 6, 8, 9, 10], vec![8], vec![1, 5], vec![1], vec![0, 2, 4, 5, 8, 10], vec![5],
+            vec![2], vec![2]
+        ]);
+    }
+
+    #[test]
+    fn test_decode_single_part() {
+        let mut decoder = Decoder::new();
+        assert!(decoder.receive("ur:crypto-psbt/fejojkidjyzmfxbtnlwt"));
+        assert_eq!(decoder.get_ur_type(), Some(PSBT_TYPE));
+        assert_eq!(decoder.get_progress(), (1, 1));
+        assert_eq!(decode_cbor_bytes(decoder.get_message().unwrap()), Some(b"psbt\xff".to_vec()));
+
+        let mut decoder = Decoder::new();
+        assert!(!decoder.receive("ur:crypto-psbt/fejojkidjyzmfxbtnlwd"));
+        assert!(!decoder.receive("crypto-psbt/fejojkidjyzmfxbtnlwt"));
+        assert!(!decoder.receive("ur:crypto psbt/fejojkidjyzmfxbtnlwt"));
+        assert_eq!(decoder.get_message(), None);
+    }
+
+    #[test]
+    fn test_decode_multi_part() {
+        let data: Vec<u8> = range(0, 100u8).collect();
+        // Part 1 is fragment 0, part 4 mixes fragments 0 and 2, and part 9
+        // mixes fragments 1 and 2.
+        let first = "ur:crypto-psbt/1-3/lpadaxcsiycynskikeechdcphdieaeadaoaxaaahamatayasbkbdbnbtbabsbebybgbwbbbzcmchcscfcycwcecackctdynngwde";
+        let fourth = "ur:crypto-psbt/4-3/lpaaaxcsiycynskikeechdcpcydifyfyfyfygsgsgsgsfyfyfyfyhhhhhhhhfyfyfyfygsgsgsgsfyfyfyfykekekekekslkonsg";
+        let ninth = "ur:crypto-psbt/9-3/lpasaxcsiycynskikeechdcpididiyiyididjtjtididiyiyididkbkbididiyiyididjtjtididiyiyididhyhycpcpwstkhevo";
+
+        let mut decoder = Decoder::new();
+        assert!(decoder.receive(ninth));
+        assert!(decoder.receive(fourth));
+        assert!(decoder.receive(fourth));
+        assert_eq!(decoder.get_progress(), (0, 3));
+        assert_eq!(decoder.get_message(), None);
+        assert!(decoder.receive(first));
+        assert_eq!(decoder.get_progress(), (3, 3));
+        assert_eq!(decode_cbor_bytes(decoder.get_message().unwrap()), Some(data.clone()));
+
+        let mut decoder = Decoder::new();
+        let upper: String = first.chars().map(|ch| ch.to_uppercase()).collect();
+        assert!(decoder.receive(upper.as_slice()));
+        assert_eq!(decoder.get_progress(), (1, 3));
+        // A part of a UR of another type.
+        assert!(!decoder.receive(first.replace("crypto-psbt", "bytes").as_slice()));
+        for part in encode(PSBT_TYPE, cbor_bytes(data.as_slice()).as_slice(), 40).iter() {
+            assert!(decoder.receive(part.as_slice()));
+        }
+        assert_eq!(decode_cbor_bytes(decoder.get_message().unwrap()), Some(data));
+    }
+}
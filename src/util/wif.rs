@@ -1,7 +1,6 @@
 //! Bitcoin Wallet Import Format (WIF) encoding, decoding, and checking.
 
-use openssl;
-use openssl::crypto::hash::HashType::SHA256;
+use util::hash;
 
 // The length of checksums used in the Wallet Import Format.
 static CHECKSUM_LENGTH: uint = 4;
@@ -43,21 +42,15 @@ pub fn check(data: &[u8]) -> bool {
 
 // Computes a checksum of the given data.
 fn checksum(data: &[u8]) -> Vec<u8> {
-    let double_hash = double_sha256(data);
+    let double_hash = hash::double_sha256(data);
     double_hash.slice(0, 4).to_vec()
 }
 
-// Performs a double SHA256 hash of the given data.
-fn double_sha256(data: &[u8]) -> Vec<u8> {
-    let first_hash = openssl::crypto::hash::hash(SHA256, data);
-    openssl::crypto::hash::hash(SHA256, first_hash.as_slice())
-}
-
 #[cfg(test)]
 mod tests {
     use serialize::hex::FromHex;
 
-    use super::{encode, decode, check, checksum, double_sha256};
+    use super::{encode, decode, check, checksum};
 
     #[test]
     fn test_encode() {
@@ -118,13 +111,5 @@ mod tests {
         let expected = "D61967F6".from_hex().unwrap();
         assert_eq!(checksum(data.as_slice()), expected);
     }
-
-    #[test]
-    fn test_double_sha256() {
-        let data = "00010966776006953D5567439E5E39F86A0D273BEE".from_hex().unwrap();
-        let expected = "D61967F63C7DD183914A4AE452C9F6AD5D462CE3D277798075B107615C1A8A30";
-        let expected = expected.from_hex().unwrap();
-        assert_eq!(double_sha256(data.as_slice()), expected);
-    }
 }
 
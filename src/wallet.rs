@@ -5,14 +5,25 @@
 use openssl;
 use serialize::hex::{ToHex, FromHex};
 
-use std::io::{File, BufferedReader, IoResult, IoError, OtherIoError};
+use std::io::{File, BufferedReader, MemReader, MemWriter, IoResult, IoError, OtherIoError};
 use std::io::fs::PathExtensions;
 
-use util::base58;
-use protocol::address::Address;
+use protocol::address::{Address, AddressType};
 use protocol::private_key::PrivateKey;
 use protocol::private_key;
+use protocol::extended_key::{ExtendedPrivateKey, CHAIN_CODE_LENGTH};
+use protocol::derivation_path::{DerivationPath, HARDENED, EXTERNAL_CHAIN, INTERNAL_CHAIN};
+use protocol::descriptor;
+use protocol::descriptor::KeyOrigin;
+use protocol::multisig::{Multisig, MultisigType, Cosigner};
+use protocol::psbt::Psbt;
+use protocol::proof::{Proof, KeyProof};
+use protocol::segwit;
+use protocol::taproot::SIGHASH_ALL;
+use util::hash;
+use util::secret::SecretBytes;
 use wallet_parser;
+use wallet_parser::{Document, Section, Span};
 
 // The length of the private key that the PKCS5 algorithm should generate.
 static PKCS5_KEY_LENGTH: uint = 32;
@@ -23,24 +34,107 @@ static PKCS5_SALT_LENGTH: uint = 16;
 // The number of iterations the PKCS5 algorithm should use.
 static PKCS5_ITERATIONS: uint = 4000;
 
+// The special key that a multisig alias's cosigners are stored under is this
+// followed by the alias.
+static MULTISIG_KEY_PREFIX: &'static str = "!multisig_";
+
+// The special key that the private keys are stored under, each encrypted on
+// its own as a record of its IV followed by its ciphertext. Older wallet files
+// have them all encrypted together under '!encrypted_data' instead.
+static ENCRYPTED_KEYS_KEY: &'static str = "!encrypted_keys";
+
+// The special keys that an encrypted address section and its IV are stored
+// under.
+static ENCRYPTED_ADDRESSES_KEY: &'static str = "!encrypted_addresses";
+static ADDRESSES_IV_KEY: &'static str = "!addresses_iv";
+
 // The length of the random initialization vector (IV) that the AES algorithm
 // should use.
 static AES_IV_LENGTH: uint = 16;
 
+/// The special alias for change addresses. In an HD wallet, its keys are
+/// derived from the internal chain of the account instead of the external
+/// one.
+pub static CHANGE_ALIAS: &'static str = "change";
+
+/// The number of unused addresses in a row after which address discovery
+/// gives up on a chain, as recommended by BIP 44.
+pub static DEFAULT_GAP_LIMIT: uint = 20;
+
 /// A Wallet contains a Path to the wallet file, and groups of addresses and
-/// private keys that are associated with aliases.
+/// private keys that are associated with aliases. An HD wallet also has a
+/// master key that all of its new private keys are derived from, and can have
+/// multisig aliases, whose addresses are shared with other cosigners. The
+/// aliases and addresses can be encrypted in the wallet file too, so that
+/// they're kept private. A loaded Wallet remembers how its file was laid out,
+/// so that comments written in it by hand survive saving it again, and how it
+/// was encrypted, so that saving it unchanged gives exactly the same file.
 pub struct Wallet {
     path: Path,
-    entries: Vec<(String, Vec<WalletEntry>)>
+    master_key: Option<ExtendedPrivateKey>,
+    entries: Vec<(String, Vec<WalletEntry>)>,
+    multisigs: Vec<(String, Multisig)>,
+    encrypt_addresses: bool,
+    document: Option<Document>,
+    address_document: Option<Document>,
+    salt: Option<Vec<u8>>,
+    key_records: Vec<Encrypted>,
+    encrypted_data: Option<Encrypted>,
+    encrypted_addresses: Option<Encrypted>
+}
+
+// Data as it was encrypted in the wallet file that a Wallet was loaded from:
+// the IV and ciphertext, and a hash of the plaintext, to tell whether the
+// data has changed since.
+struct Encrypted {
+    iv: Vec<u8>,
+    ciphertext: Vec<u8>,
+    plaintext_hash: Vec<u8>
+}
+
+impl Encrypted {
+    fn new(iv: Vec<u8>, ciphertext: Vec<u8>, plaintext: &[u8]) -> Encrypted {
+        Encrypted { iv: iv, ciphertext: ciphertext, plaintext_hash: hash::sha256(plaintext) }
+    }
+}
+
+// A Source is a part of a wallet file being loaded, for error messages: the
+// file itself, or its decrypted addresses, with the name to give it.
+struct Source<'a> {
+    document: &'a Document,
+    name: &'a str
+}
+
+impl<'a> Source<'a> {
+    // Makes an IoError about the key or value at the given span.
+    fn error_at(&self, span: &Span, desc: &'static str, message: String) -> IoError {
+        self.document.error_at(self.name, span, desc, message)
+    }
+
+    // Decodes the values of one of the document's keys as hex, or makes an
+    // IoError about the key if they aren't valid hex.
+    fn hex_values(&self, section: &Section) -> IoResult<Vec<u8>> {
+        match section.get_values().concat().as_slice().from_hex() {
+            Ok(data) => Ok(data),
+            Err(_) => Err(self.error_at(&section.spans[0], "invalid hex",
+                                        format!("'{}' field is not valid hex", section.key)))
+        }
+    }
 }
 
 // A WalletEntry contains a Bitcoin address and the associated private key, if
 // it's available. (If the private key for an address isn't found in the
-// encrypted part of the wallet file, then a warning should be displayed.)
-#[deriving(Clone)]
+// encrypted part of the wallet file, then a warning should be displayed.) Keys
+// derived from the master key also have their derivation path. In a multisig
+// alias, the private key is our own key in the address's script. Entries
+// aren't cloneable, so that private keys aren't copied around by accident.
+// The metadata is the user's notes on the address, like who it was given to,
+// as names and values in the order they're in the wallet file.
 struct WalletEntry {
     address: Address,
-    private_key: Option<PrivateKey>
+    private_key: Option<PrivateKey>,
+    path: Option<DerivationPath>,
+    metadata: Vec<(String, String)>
 }
 
 impl Wallet {
@@ -51,116 +145,351 @@ impl Wallet {
             panic!("Wallet file '{}' already exists, will not overwrite!", path.display());
         }
 
-        Wallet { path: path.clone(), entries: Vec::new() }
+        Wallet {
+            path: path.clone(),
+            master_key: None,
+            entries: Vec::new(),
+            multisigs: Vec::new(),
+            encrypt_addresses: false,
+            document: None,
+            address_document: None,
+            salt: None,
+            key_records: Vec::new(),
+            encrypted_data: None,
+            encrypted_addresses: None
+        }
+    }
+
+    /// Creates a blank HD Wallet at the given Path, which derives its keys
+    /// from the given master key. Fails if the wallet file already exists.
+    pub fn new_hd(path: &Path, master_key: ExtendedPrivateKey) -> Wallet {
+        let mut wallet = Wallet::new(path);
+        wallet.master_key = Some(master_key);
+        wallet
     }
 
-    /// Loads a Wallet from the given wallet file Path. Returns an IoError on
-    /// failure, and specifically an OtherIoError if the contents of the file
-    /// are invalid.
+    /// Loads a Wallet from the given wallet file Path, whether its addresses
+    /// are encrypted or not. Returns an IoError on failure, and specifically
+    /// an OtherIoError if the contents of the file are invalid, which says
+    /// where in the file the problem is.
     pub fn load(path: &Path) -> IoResult<Wallet> {
         let file = try!(File::open(path));
         let mut reader = BufferedReader::new(file);
-        let parsed = try!(wallet_parser::parse(&mut reader));
+        let name = path.display().to_string();
+        let name = name.as_slice();
+        let document = try!(wallet_parser::parse_document(&mut reader, name));
+        let (address_document, encrypted_addresses) = match try!(decrypt_addresses(&document, name)) {
+            Some((address_document, encrypted)) => (Some(address_document), Some(encrypted)),
+            None => (None, None)
+        };
 
-        let mut wallet = Wallet { path: path.clone(), entries: Vec::new() };
+        let mut wallet = Wallet {
+            path: path.clone(),
+            master_key: None,
+            entries: Vec::new(),
+            multisigs: Vec::new(),
+            encrypt_addresses: address_document.is_some(),
+            document: Some(document.clone()),
+            address_document: address_document.clone(),
+            salt: None,
+            key_records: Vec::new(),
+            encrypted_data: None,
+            encrypted_addresses: encrypted_addresses
+        };
 
+        // The keys and their values come from the decrypted addresses first,
+        // if they're encrypted, and then the rest of the file.
+        let address_name = address_section_name(name);
+        let file_source = Source { document: &document, name: name };
+        let address_source = address_document.as_ref().map(|document| {
+            Source { document: document, name: address_name.as_slice() }
+        });
+        let sections = file_sections(&file_source, address_source.as_ref());
+
+        let mut master_fingerprint = None;
         let mut salt = None;
         let mut iv = None;
         let mut encrypted_data = None;
+        let mut key_records = None;
+
+        // Where the master fingerprint, encrypted data, multisig aliases and
+        // addresses are, for errors found after they're all read.
+        let mut master_fingerprint_location = None;
+        let mut encrypted_data_location = None;
+        let mut multisig_locations = Vec::new();
+        let mut entry_locations = Vec::new();
 
-        for (key, values) in parsed.into_iter() {
-            if key.as_slice().starts_with("!") {
-                if key.as_slice() == "!salt" {
-                    salt = values.concat().as_slice().from_hex().ok();
-                } else if key.as_slice() == "!iv" {
-                    iv = values.concat().as_slice().from_hex().ok();
-                } else if key.as_slice() == "!encrypted_data" {
-                    encrypted_data = values.concat().as_slice().from_hex().ok();
+        for &(source, section) in sections.iter() {
+            let key = section.key.as_slice();
+            let key_span = &section.spans[0];
+            let values = section.get_values();
+
+            if key.starts_with("!") {
+                if key == "!master_fingerprint" {
+                    master_fingerprint = Some(try!(source.hex_values(section)));
+                    master_fingerprint_location = Some((source, key_span));
+                } else if key == "!salt" {
+                    let value = try!(source.hex_values(section));
+                    if value.len() != PKCS5_SALT_LENGTH {
+                        return Err(source.error_at(key_span, "invalid salt",
+                                                   format!("The salt isn't {} bytes long", PKCS5_SALT_LENGTH)));
+                    }
+                    salt = Some(value);
+                } else if key == "!iv" {
+                    let value = try!(source.hex_values(section));
+                    if value.len() != AES_IV_LENGTH {
+                        return Err(source.error_at(key_span, "invalid iv",
+                                                   format!("The iv isn't {} bytes long", AES_IV_LENGTH)));
+                    }
+                    iv = Some(value);
+                } else if key == "!encrypted_data" {
+                    encrypted_data = Some(try!(source.hex_values(section)));
+                    encrypted_data_location = Some((source, key_span));
+                } else if key == ENCRYPTED_KEYS_KEY {
+                    // The ciphertext is whole AES blocks, which are as long
+                    // as the IV.
+                    let mut records = Vec::with_capacity(values.len());
+                    for (i, value) in values.iter().enumerate() {
+                        let span = &section.spans[i + 1];
+                        match value.as_slice().from_hex() {
+                            Ok(ref record) if record.len() >= 2 * AES_IV_LENGTH && record.len() % AES_IV_LENGTH == 0 => {
+                                records.push((record.slice_to(AES_IV_LENGTH).to_vec(),
+                                              record.slice_from(AES_IV_LENGTH).to_vec(), source, span));
+                            }
+                            _ => {
+                                return Err(source.error_at(span, "invalid encrypted key",
+                                                           format!("'{}' isn't an iv followed by the ciphertext of a key",
+                                                                   value)));
+                            }
+                        }
+                    }
+                    key_records = Some(records);
+                } else if key.starts_with(MULTISIG_KEY_PREFIX) {
+                    let alias = key.slice_from(MULTISIG_KEY_PREFIX.len()).to_string();
+                    match parse_multisig(values.as_slice()) {
+                        Some(multisig) => wallet.multisigs.push((alias, multisig)),
+                        None => {
+                            return Err(source.error_at(key_span, "invalid multisig",
+                                                       format!("'{}' field is invalid", key)));
+                        }
+                    }
+                    multisig_locations.push((source, key_span));
                 } else {
-                    return Err(IoError {
-                        kind: OtherIoError,
-                        desc: "invalid special key",
-                        detail: Some(format!("Unexpected key '{}' in wallet file", key))
-                    });
+                    return Err(source.error_at(key_span, "invalid special key",
+                                               format!("Unexpected key '{}' in wallet file", key)));
                 }
             } else {
                 let mut entries = Vec::with_capacity(values.len());
 
-                for value in values.iter() {
-                    let address_data = base58::decode(value.as_slice());
-                    let address = match address_data {
-                        Some(data) => Address::new(data.as_slice()),
+                for (i, value) in values.iter().enumerate() {
+                    let span = &section.spans[i + 1];
+
+                    // So does metadata, given as name=value.
+                    if value.as_slice().contains_char('=') {
+                        let added = match (entries.last_mut(), parse_metadata(value.as_slice())) {
+                            (Some(entry), Some((metadata_name, metadata))) => {
+                                if entry.metadata.iter().any(|&(ref other, _)| *other == metadata_name) {
+                                    false
+                                } else {
+                                    entry.metadata.push((metadata_name, metadata));
+                                    true
+                                }
+                            }
+                            _ => false
+                        };
+
+                        if !added {
+                            return Err(source.error_at(span, "invalid address metadata",
+                                                       format!("Metadata '{}' is invalid, repeated or doesn't follow an address", value)));
+                        }
+                        continue;
+                    }
+
+                    // A derivation path belongs to the address before it.
+                    // Testnet addresses start with "m" too, so it's only a
+                    // path if it parses as one.
+                    let path = DerivationPath::parse(value.as_slice());
+                    if path.is_some() {
+                        let entry = entries.last_mut();
+
+                        if entry.is_none() || entry.as_ref().unwrap().path.is_some() {
+                            return Err(source.error_at(span, "unexpected derivation path",
+                                                       format!("Derivation path '{}' doesn't follow an address", value)));
+                        }
+
+                        entry.unwrap().path = path;
+                        continue;
+                    }
+
+                    match Address::decode(value.as_slice()) {
+                        Some(address) => {
+                            entries.push(WalletEntry { address: address, private_key: None, path: None,
+                                                       metadata: Vec::new() });
+                            entry_locations.push((source, span));
+                        }
                         None => {
-                            return Err(IoError {
-                                kind: OtherIoError,
-                                desc: "invalid base-58 string",
-                                detail: Some(format!("Address '{}' is not a valid base-58 string", value))
-                            });
+                            return Err(source.error_at(span, "invalid address",
+                                                       format!("Address '{}' is not a valid Bitcoin address", value)));
                         }
-                    };
-
-                    if address.is_some() {
-                        entries.push(WalletEntry { address: address.unwrap(), private_key: None });
-                    } else {
-                        return Err(IoError {
-                            kind: OtherIoError,
-                            desc: "invalid address",
-                            detail: Some(format!("Address '{}' is not a valid Bitcoin address", value))
-                        });
                     }
                 }
 
-                wallet.entries.push((key, entries));
+                wallet.entries.push((key.to_string(), entries));
             }
         }
 
-        if encrypted_data.is_none() {
-            return Err(IoError {
-                kind: OtherIoError,
-                desc: "encrypted data not found or invalid",
-                detail: Some(format!("'!encrypted_data' field not found or invalid"))
-            });
-        } else if salt.is_none() {
-            return Err(IoError {
-                kind: OtherIoError,
-                desc: "salt not found or invalid",
-                detail: Some(format!("'!salt' field not found or invalid"))
-            });
-        } else if iv.is_none() {
-            return Err(IoError {
-                kind: OtherIoError,
-                desc: "iv not found or invalid",
-                detail: Some(format!("'!iv' field not found or invalid"))
-            });
+        if salt.is_none() {
+            return Err(file_error(name, "salt not found", format!("'!salt' field not found")));
         }
+        let salt = salt.unwrap();
+        let key = derive_key(salt.as_slice());
+        wallet.salt = Some(salt);
 
-        let private_keys = wallet.decrypt(salt.unwrap().as_slice(),
-                                          iv.unwrap().as_slice(),
-                                          encrypted_data.unwrap().as_slice());
+        // The decrypted keys, each with where it is in the file.
+        let mut key_data = Vec::new();
+        match key_records {
+            Some(records) => {
+                match encrypted_data_location {
+                    Some((source, span)) => {
+                        return Err(source.error_at(span, "unexpected encrypted data",
+                                                   format!("The wallet file has both '{}' and '!encrypted_data'",
+                                                           ENCRYPTED_KEYS_KEY)));
+                    }
+                    None => {}
+                }
+
+                for (iv, ciphertext, source, span) in records.into_iter() {
+                    let plaintext = decrypt(&key, iv.as_slice(), ciphertext.as_slice());
+                    wallet.key_records.push(Encrypted::new(iv, ciphertext, plaintext.as_slice()));
+                    key_data.push((plaintext, source, span));
+                }
+            }
+            None => {
+                if encrypted_data.is_none() {
+                    return Err(file_error(name, "encrypted keys not found",
+                                          format!("'{}' field not found", ENCRYPTED_KEYS_KEY)));
+                } else if iv.is_none() {
+                    return Err(file_error(name, "iv not found", format!("'!iv' field not found")));
+                }
+                let (data_source, data_span) = encrypted_data_location.unwrap();
+
+                let (iv, encrypted_data) = (iv.unwrap(), encrypted_data.unwrap());
+                let plaintext = decrypt(&key, iv.as_slice(), encrypted_data.as_slice());
+                wallet.encrypted_data = Some(Encrypted::new(iv, encrypted_data, plaintext.as_slice()));
+
+                // The keys are split up the way they're stored in newer
+                // wallet files, with the master key in one piece.
+                let mut rest = plaintext.as_slice();
+                if master_fingerprint.is_some() && rest.len() >= CHAIN_CODE_LENGTH + private_key::LENGTH {
+                    let (master_data, keys) = rest.split_at(CHAIN_CODE_LENGTH + private_key::LENGTH);
+                    key_data.push((SecretBytes::from_slice(master_data), data_source, data_span));
+                    rest = keys;
+                }
+                for data in rest.chunks(private_key::LENGTH) {
+                    key_data.push((SecretBytes::from_slice(data), data_source, data_span));
+                }
+            }
+        }
+        let mut key_data = key_data.into_iter();
+
+        // In an HD wallet, the master key's chain code and private key come
+        // before the private keys of the addresses.
+        if master_fingerprint.is_some() {
+            let master_key = match key_data.next() {
+                Some((ref data, _, _)) if data.len() == CHAIN_CODE_LENGTH + private_key::LENGTH => {
+                    let (chain_code, data) = data.as_slice().split_at(CHAIN_CODE_LENGTH);
+                    PrivateKey::new(data).map(|key| ExtendedPrivateKey::from_master_parts(chain_code, key))
+                }
+                _ => None
+            };
+
+            match master_key {
+                Some(ref key) if Some(key.fingerprint()) == master_fingerprint => {}
+                _ => {
+                    let (source, span) = master_fingerprint_location.unwrap();
+                    return Err(source.error_at(span, "master key mismatch",
+                                               format!("The master key is missing or doesn't match '!master_fingerprint'")));
+                }
+            }
+
+            wallet.master_key = master_key;
+        } else if !multisig_locations.is_empty() {
+            let (source, span) = multisig_locations[0];
+            return Err(source.error_at(span, "multisig without master key",
+                                       format!("Multisig aliases are only supported in HD wallets")));
+        }
+
+        let mut private_keys = Vec::new();
+        for (data, source, span) in key_data {
+            if data.len() != private_key::LENGTH {
+                return Err(source.error_at(span, "invalid private key data",
+                                           format!("The decrypted private key data has the wrong length")));
+            }
+
+            match PrivateKey::new(data.as_slice()) {
+                Some(key) => private_keys.push(key),
+                None => {
+                    return Err(source.error_at(span, "invalid private key",
+                                               format!("The encrypted data contains an invalid private key")));
+                }
+            }
+        }
 
         let mut private_keys_iter = private_keys.into_iter();
+        let mut entry_locations = entry_locations.into_iter();
+
+        for &(ref alias, ref mut entries) in wallet.entries.iter_mut() {
+            let multisig = find_multisig(wallet.multisigs.as_slice(), alias.as_slice());
 
-        for &(_, ref mut entries) in wallet.entries.iter_mut() {
             for entry in entries.iter_mut() {
                 let private_key = private_keys_iter.next();
+                let (source, span) = entry_locations.next().unwrap();
 
                 if private_key.is_none() {
-                    return Err(IoError {
-                        kind: OtherIoError,
-                        desc: "missing private key",
-                        detail: Some(format!("There are more addresses than private keys in the wallet file"))
-                    });
+                    return Err(source.error_at(span, "missing private key",
+                                               format!("There are more addresses than private keys in the wallet file, \
+                                                        starting with '{}'", entry.address.encode())));
                 }
 
                 let private_key = private_key.unwrap();
 
-                if private_key.to_address() != entry.address {
-                    return Err(IoError {
-                        kind: OtherIoError,
-                        desc: "address and private key mismatch",
-                        detail: Some(format!("The private key given for '{}' is wrong",
-                                             base58::encode(entry.address.get_data())))
+                // A multisig address is checked against the cosigners' keys
+                // at the same chain and index as our key.
+                let expected_address = match multisig {
+                    Some(multisig) => {
+                        let master_key = wallet.master_key.as_ref().unwrap();
+                        entry.path.as_ref().and_then(|path| multisig_entry_address(master_key, multisig, path))
+                    }
+                    None if entry.address.get_type() == AddressType::P2WSH => {
+                        return Err(source.error_at(span, "unsupported address",
+                                                   format!("Address '{}' is a P2WSH address, which can't belong to a single key",
+                                                           entry.address.encode())));
+                    }
+                    // A P2PKH address can be of either form of the public
+                    // key: the compressed one in HD wallets, or the
+                    // uncompressed one in keys from outside them and in HD
+                    // wallets made by older versions of Cykas.
+                    None if private_key.to_compressed_address() == entry.address => {
+                        Some(private_key.to_compressed_address())
+                    }
+                    None => Some(derive_address(&private_key, entry.address.get_type(), false))
+                };
+
+                if expected_address.as_ref() != Some(&entry.address) {
+                    return Err(source.error_at(span, "address and private key mismatch",
+                                               format!("The private key given for '{}' is wrong", entry.address.encode())));
+                }
+
+                if entry.path.is_some() {
+                    let derived = wallet.master_key.as_ref().and_then(|master_key| {
+                        master_key.derive_path(entry.path.as_ref().unwrap())
                     });
+
+                    if derived.as_ref().map(|key| &key.private_key) != Some(&private_key) {
+                        return Err(source.error_at(span, "derivation path mismatch",
+                                                   format!("The private key for '{}' isn't derived from the master key at {}",
+                                                           entry.address.encode(), entry.path.as_ref().unwrap().encode())));
+                    }
                 }
 
                 entry.private_key = Some(private_key);
@@ -170,115 +499,1531 @@ impl Wallet {
         Ok(wallet)
     }
 
-    /// Saves the Wallet to its wallet file. Returns an IoError on failure.
+    /// Saves the Wallet to its wallet file. If `set_encrypt_addresses()` was
+    /// turned on (or the wallet was loaded with its addresses encrypted), the
+    /// aliases, addresses and multisig cosigners are encrypted too. If the
+    /// wallet was loaded from a file, the comments, blank lines and order of
+    /// the keys in it are kept, as `wallet_parser::merge()` describes, and
+    /// new aliases go after the ones that were there. Each private key is
+    /// encrypted on its own, and only when it's new, so generating a key adds
+    /// a record without changing the others, and saving a wallet that hasn't
+    /// changed writes exactly the file it was loaded from (as the addresses
+    /// are only encrypted again if they've changed). An older wallet file,
+    /// with the private keys all encrypted together, keeps them that way until
+    /// they change. Returns an IoError on failure.
     pub fn save(&self) -> IoResult<()> {
         // TODO: make a backup copy first, to delete when the new file is
         // closed.
 
-        let (salt, iv, encrypted_data) = self.encrypt();
+        // The salt stays the same, so that the key does too, and whatever
+        // hasn't changed can keep its old IV and ciphertext.
+        let salt = match self.salt {
+            Some(ref salt) => salt.clone(),
+            None => openssl::crypto::rand::rand_bytes(PKCS5_SALT_LENGTH)
+        };
+        let key = derive_key(salt.as_slice());
+        let key_records = match self.encrypted_data {
+            Some(ref old) if old.plaintext_hash == hash::sha256(self.private_data().as_slice()) => None,
+            _ => {
+                let records = self.private_records().iter().map(|plaintext| {
+                    let (mut record, ciphertext) = encrypt_if_changed(&key, plaintext.as_slice(),
+                                                                      self.key_records.iter());
+                    record.push_all(ciphertext.as_slice());
+                    record
+                }).collect::<Vec<Vec<u8>>>();
+                Some(records)
+            }
+        };
+        let converted = self.encrypted_data.is_some() && key_records.is_some();
+
+        // The sections of the file as it was loaded, including the encrypted
+        // ones, that the new sections are laid out like.
+        let mut old_sections: Vec<&Section> = Vec::new();
+        for document in self.address_document.iter().chain(self.document.iter()) {
+            old_sections.extend(document.sections.iter());
+        }
+
+        let mut sections = self.address_sections();
+        let mut key_sections = self.key_sections(salt.as_slice(), key_records);
+        if self.encrypt_addresses {
+            let address_document = Document {
+                sections: try!(wallet_parser::merge(old_sections.as_slice(), sections)),
+                trailer: self.address_document.as_ref().map_or(vec!["\n".to_string()], |document| {
+                    document.trailer.clone()
+                })
+            };
+            let mut address_section = MemWriter::new();
+            try!(address_document.write_to(&mut address_section));
+            let (addresses_iv, ciphertext) = encrypt_if_changed(&key, address_section.get_ref(),
+                                                                self.encrypted_addresses.iter());
+
+            key_sections.push(Section::new(ADDRESSES_IV_KEY, vec![
+                "".to_string(),
+                "# The aliases and addresses, encrypted with AES-256-CBC using the".to_string(),
+                "# same key as the private key data (the same passphrase, salt and".to_string(),
+                "# PBKDF2 parameters), but with the following iv. The decrypted".to_string(),
+                "# data is text in the same format as this file: each alias followed".to_string(),
+                "# by a colon and its addresses, then the multisig cosigners. The".to_string(),
+                "# private keys are in the same order as the addresses are listed".to_string(),
+                "# there.".to_string()
+            ], vec![vec![addresses_iv.as_slice().to_hex()]]));
+            key_sections.push(Section::new(ENCRYPTED_ADDRESSES_KEY, Vec::new(), hex_values(ciphertext.as_slice())));
+            sections = key_sections;
+        } else {
+            sections.extend(key_sections.into_iter());
+        }
+
+        let defaults = sections.clone();
+        let mut sections = try!(wallet_parser::merge(old_sections.as_slice(), sections));
 
+        // The comments at the top of the private key part of the file say
+        // whether the addresses are encrypted, so they're written afresh when
+        // that changes.
+        if self.document.is_some() && self.encrypt_addresses != self.address_document.is_some() {
+            match sections.iter_mut().find(|section| !is_address_section_key(section.key.as_slice())) {
+                Some(section) => {
+                    let default = defaults.iter().find(|default| default.key == section.key).unwrap();
+                    section.leading = default.leading.clone();
+                }
+                None => {}
+            }
+        }
+
+        // So are the comments before the salt, which say what it's used
+        // with, when the private keys are split into separate records.
+        if converted {
+            match sections.iter_mut().find(|section| section.key.as_slice() == "!salt") {
+                Some(section) => {
+                    let default = defaults.iter().find(|default| default.key == section.key).unwrap();
+                    section.leading = default.leading.clone();
+                }
+                None => {}
+            }
+        }
+
+        let document = Document {
+            sections: sections,
+            trailer: self.document.as_ref().map_or(vec!["\n".to_string()], |document| document.trailer.clone())
+        };
         let mut file = try!(File::create(&self.path));
-        let f = &mut file;
+        document.write_to(&mut file)
+    }
 
+    // Helper function for Wallet::save(). Makes the sections with the aliases
+    // and addresses, and the cosigners of multisig aliases, as they're
+    // written in a new wallet file.
+    fn address_sections(&self) -> Vec<Section> {
+        let mut sections = Vec::with_capacity(self.entries.len() + self.multisigs.len());
         for &(ref alias, ref entries) in self.entries.iter() {
-            try!(writeln!(f, "{}:", alias));
+            let mut values = vec![Vec::new()];
             for entry in entries.iter() {
-                try!(writeln!(f, "  {}", base58::encode(entry.address.get_data())));
+                let mut line = vec![entry.address.encode()];
+                match entry.path {
+                    Some(ref path) => line.push(path.encode()),
+                    None => {}
+                }
+                for &(ref name, ref value) in entry.metadata.iter() {
+                    line.push(encode_metadata(name.as_slice(), value.as_slice()));
+                }
+                values.push(line);
             }
+            sections.push(Section::new(alias.as_slice(), Vec::new(), values));
         }
 
-        try!(writeln!(f, ""));
-        try!(writeln!(f, "# Private key data encrypted with AES-256-CBC using"));
-        try!(writeln!(f, "# PBKDF2-HMAC-SHA1 with {} iterations and the", PKCS5_ITERATIONS));
-        try!(writeln!(f, "# following salt and iv:"));
-        try!(writeln!(f, "!salt: {}", salt.as_slice().to_hex()));
-        try!(writeln!(f, "!iv: {}", iv.as_slice().to_hex()));
-        try!(writeln!(f, ""));
-        try!(writeln!(f, "# The decrypted data consists of concatenated 32-byte"));
-        try!(writeln!(f, "# private keys in the same order as the addresses are"));
-        try!(writeln!(f, "# are listed in this file."));
-        try!(writeln!(f, "!encrypted_data:"));
+        for &(ref alias, ref multisig) in self.multisigs.iter() {
+            let comments = vec![
+                "".to_string(),
+                format!("# {}-of-{} multisig for '{}'. Each cosigner's key is given as",
+                        multisig.get_threshold(), multisig.get_cosigners().len(), alias),
+                "# their master key fingerprint, the path of their account key,".to_string(),
+                "# and the account key.".to_string()
+            ];
+            let mut values = vec![vec![multisig.get_threshold().to_string(),
+                                       multisig_type_token(multisig.get_type()).to_string()]];
+            for cosigner in multisig.get_cosigners().iter() {
+                values.push(vec![cosigner.origin.fingerprint.to_hex(), cosigner.origin.path.encode(),
+                                 cosigner.account_key.encode()]);
+            }
+            sections.push(Section::new(format!("{}{}", MULTISIG_KEY_PREFIX, alias).as_slice(), comments, values));
+        }
+
+        sections
+    }
 
-        for chunk in encrypted_data.as_slice().chunks(38) {
-            try!(writeln!(f, "  {}", chunk.to_hex()));
+    // Helper function for Wallet::save(). Makes the sections with the master
+    // key's fingerprint and the encrypted private keys, as they're written in
+    // a new wallet file. The private keys are the given records, or if there
+    // aren't any, the data they were all encrypted in together when the
+    // wallet was loaded.
+    fn key_sections(&self, salt: &[u8], key_records: Option<Vec<Vec<u8>>>) -> Vec<Section> {
+        // The first section follows the address section, or says where it is.
+        let mut comments = if self.encrypt_addresses {
+            vec![
+                "# The aliases and addresses in this wallet are encrypted. See the".to_string(),
+                format!("# comment above '{}' for how to decrypt them.", ENCRYPTED_ADDRESSES_KEY),
+                "".to_string()
+            ]
+        } else {
+            vec!["".to_string()]
+        };
+
+        let mut sections = Vec::with_capacity(4);
+        match self.master_key {
+            Some(ref master_key) => {
+                comments.push("# Keys with a derivation path are derived from the BIP 32".to_string());
+                comments.push("# master key with this fingerprint:".to_string());
+                sections.push(Section::new("!master_fingerprint", comments,
+                                           vec![vec![master_key.fingerprint().to_hex()]]));
+                comments = vec!["".to_string()];
+            }
+            None => {}
         }
-        try!(writeln!(f, ""));
 
-        Ok(())
+        let records = match key_records {
+            Some(records) => records,
+            None => {
+                let old = self.encrypted_data.as_ref().unwrap();
+                self.push_data_sections(&mut sections, comments, salt, old);
+                return sections;
+            }
+        };
+
+        comments.push("# Private keys encrypted with AES-256-CBC using".to_string());
+        comments.push(format!("# PBKDF2-HMAC-SHA1 with {} iterations and the", PKCS5_ITERATIONS));
+        comments.push("# following salt:".to_string());
+        sections.push(Section::new("!salt", comments, vec![vec![salt.to_hex()]]));
+
+        let mut comments = vec![
+            "".to_string(),
+            "# Each line is one key encrypted on its own: a 16-byte iv".to_string(),
+            "# followed by the ciphertext.".to_string()
+        ];
+        if self.master_key.is_some() {
+            comments.push("# The first decrypts to the 32-byte master chain code and".to_string());
+            comments.push("# 32-byte master private key, and the rest to 32-byte".to_string());
+            comments.push("# private keys in the same order as the addresses are".to_string());
+            comments.push("# listed in this file.".to_string());
+        } else {
+            comments.push("# They decrypt to 32-byte private keys in the same order".to_string());
+            comments.push("# as the addresses are listed in this file.".to_string());
+        }
+        let mut values = vec![Vec::new()];
+        values.extend(records.iter().map(|record| vec![record.to_hex()]));
+        sections.push(Section::new(ENCRYPTED_KEYS_KEY, comments, values));
+
+        sections
     }
 
-    // Helper function for Wallet::save(). Encrypts the private keys in the
-    // Wallet and returns a tuple containing the salt, iv, and ciphertext.
-    fn encrypt(&self) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
-        let salt = openssl::crypto::rand::rand_bytes(PKCS5_SALT_LENGTH);
-        let key = openssl::crypto::pkcs5::pbkdf2_hmac_sha1("asdf", salt.as_slice(),
-                                                           PKCS5_ITERATIONS, PKCS5_KEY_LENGTH);
-        let iv = openssl::crypto::rand::rand_bytes(AES_IV_LENGTH);
+    // Helper function for key_sections(). Adds the sections of an older
+    // wallet file, with the private keys all encrypted together as they were
+    // when it was loaded.
+    fn push_data_sections(&self, sections: &mut Vec<Section>, mut comments: Vec<String>, salt: &[u8],
+                          old: &Encrypted) {
+        comments.push("# Private key data encrypted with AES-256-CBC using".to_string());
+        comments.push(format!("# PBKDF2-HMAC-SHA1 with {} iterations and the", PKCS5_ITERATIONS));
+        comments.push("# following salt and iv:".to_string());
+        sections.push(Section::new("!salt", comments, vec![vec![salt.to_hex()]]));
+        sections.push(Section::new("!iv", Vec::new(), vec![vec![old.iv.to_hex()]]));
+
+        let comments = if self.master_key.is_some() {
+            vec![
+                "".to_string(),
+                "# The decrypted data consists of the 32-byte master chain".to_string(),
+                "# code and 32-byte master private key, followed by".to_string(),
+                "# concatenated 32-byte private keys in the same order as".to_string(),
+                "# the addresses are listed in this file.".to_string()
+            ]
+        } else {
+            vec![
+                "".to_string(),
+                "# The decrypted data consists of concatenated 32-byte".to_string(),
+                "# private keys in the same order as the addresses are".to_string(),
+                "# are listed in this file.".to_string()
+            ]
+        };
+        sections.push(Section::new("!encrypted_data", comments, hex_values(old.ciphertext.as_slice())));
+    }
 
-        let mut private_data = vec![];
+    // Helper function for Wallet::save(). Collects the private keys in the
+    // Wallet, to be encrypted one by one, after the master key's chain code
+    // and private key if it has one.
+    fn private_records(&self) -> Vec<SecretBytes> {
+        let mut records = Vec::new();
+        match self.master_key {
+            Some(ref master_key) => {
+                let mut record = SecretBytes::with_capacity(CHAIN_CODE_LENGTH + private_key::LENGTH);
+                record.push_all(master_key.chain_code.as_slice());
+                record.push_all(master_key.private_key.get_data());
+                records.push(record);
+            }
+            None => {}
+        }
         for &(_, ref keyring) in self.entries.iter() {
             for entry in keyring.iter() {
-                let entry = entry.clone(); // TODO: shouldn't have to clone...
-                if entry.private_key.is_none() { continue; }
-                private_data.push_all(entry.private_key.unwrap().get_data());
+                match entry.private_key {
+                    Some(ref private_key) => records.push(SecretBytes::from_slice(private_key.get_data())),
+                    None => {}
+                }
             }
         }
 
-        let ciphertext = openssl::crypto::symm::encrypt(
-            openssl::crypto::symm::Type::AES_256_CBC,
-            key.as_slice(),
-            iv.clone(),
-            private_data.as_slice()
-        );
+        records
+    }
+
+    // Helper function for Wallet::save(). Collects the private keys the way
+    // an older wallet file encrypted them, all together.
+    fn private_data(&self) -> SecretBytes {
+        // The private data is built in a buffer of exactly the right size,
+        // since growing it would leave copies of the keys behind.
+        let records = self.private_records();
+        let length = records.iter().fold(0, |length, record| length + record.len());
+        let mut private_data = SecretBytes::with_capacity(length);
+        for record in records.iter() {
+            private_data.push_all(record.as_slice());
+        }
+
+        private_data
+    }
+
+    /// Checks whether the aliases and addresses will be encrypted when the
+    /// wallet is saved.
+    pub fn get_encrypt_addresses(&self) -> bool {
+        self.encrypt_addresses
+    }
+
+    /// Sets whether to encrypt the aliases and addresses when the wallet is
+    /// saved, with the same passphrase as the private keys. Encrypted
+    /// addresses keep a backup of the wallet file from revealing what the
+    /// wallet holds, but the wallet has to be decrypted to see them.
+    pub fn set_encrypt_addresses(&mut self, encrypt_addresses: bool) {
+        self.encrypt_addresses = encrypt_addresses;
+    }
+
+    /// Gets the master key of an HD wallet. Returns None if the wallet isn't
+    /// an HD wallet.
+    pub fn get_master_key(&self) -> Option<&ExtendedPrivateKey> {
+        self.master_key.as_ref()
+    }
+
+    /// Gets the aliases in the wallet, in the order they're listed in the
+    /// wallet file.
+    pub fn get_aliases(&self) -> Vec<&str> {
+        self.entries.iter().map(|&(ref alias, _)| alias.as_slice()).collect()
+    }
 
-        (salt, iv, ciphertext)
+    /// Gets the addresses with the given alias. Returns None if there's no
+    /// such alias.
+    pub fn get_addresses(&self, alias: &str) -> Option<Vec<Address>> {
+        self.find_entries(alias).map(|entries| {
+            entries.iter().map(|entry| entry.address.clone()).collect()
+        })
     }
 
-    // Helper function for Wallet::load(). Decrypts the given ciphertext with
-    // the given salt and iv, and returns a vector of Bitcoin private keys.
-    fn decrypt(&self, salt: &[u8], iv: &[u8], ciphertext: &[u8]) -> Vec<PrivateKey> {
-        let key = openssl::crypto::pkcs5::pbkdf2_hmac_sha1("asdf", salt, PKCS5_ITERATIONS, PKCS5_KEY_LENGTH);
+    /// Gets the metadata of the given address: the user's notes on it, like a
+    /// label or the date it was given out, as names and values. Returns None
+    /// if the address isn't in the wallet.
+    pub fn get_metadata(&self, address: &Address) -> Option<&[(String, String)]> {
+        for &(_, ref entries) in self.entries.iter() {
+            for entry in entries.iter() {
+                if entry.address == *address {
+                    return Some(entry.metadata.as_slice());
+                }
+            }
+        }
+        None
+    }
+
+    /// Sets the metadata with the given name on an address, replacing its old
+    /// value, or removes it if the value is None. Names can only contain
+    /// letters, numbers and underscores, and values can't be empty or contain
+    /// quotes or line breaks.
+    pub fn set_metadata(&mut self, address: &Address, name: &str, value: Option<&str>) -> Result<(), &'static str> {
+        if !is_valid_metadata_name(name) {
+            return Err("names can only contain letters, numbers and underscores");
+        }
+        match value {
+            Some(value) if !is_valid_metadata_value(value) => {
+                return Err("values can't be empty or contain quotes or line breaks");
+            }
+            _ => {}
+        }
+
+        for &(_, ref mut entries) in self.entries.iter_mut() {
+            for entry in entries.iter_mut() {
+                if entry.address != *address { continue; }
+
+                let position = entry.metadata.iter().position(|&(ref other, _)| other.as_slice() == name);
+                match (position, value) {
+                    (Some(i), Some(value)) => *entry.metadata.index_mut(&i) = (name.to_string(), value.to_string()),
+                    (Some(i), None) => { entry.metadata.remove(i); }
+                    (None, Some(value)) => entry.metadata.push((name.to_string(), value.to_string())),
+                    (None, None) => {}
+                }
+                return Ok(());
+            }
+        }
+        Err("the address isn't in the wallet")
+    }
+
+    /// Gets the private key for the given address, if it's a single-key
+    /// address in the wallet. Returns None for multisig addresses, since
+    /// their keys can't spend from them alone.
+    pub fn get_private_key(&self, address: &Address) -> Option<&PrivateKey> {
+        for &(ref alias, ref entries) in self.entries.iter() {
+            if find_multisig(self.multisigs.as_slice(), alias.as_slice()).is_some() { continue; }
+
+            for entry in entries.iter() {
+                if entry.address == *address {
+                    return entry.private_key.as_ref();
+                }
+            }
+        }
+        None
+    }
+
+    /// Describes each address with the given alias as an output descriptor
+    /// with a checksum, for importing into a watch-only wallet. The
+    /// descriptors contain public keys (and where they were derived from, for
+    /// keys derived from the master key) but nothing private. A multisig
+    /// alias's descriptors contain all of the cosigners' keys. Returns None if
+    /// there's no such alias.
+    pub fn get_descriptors(&self, alias: &str) -> Option<Vec<String>> {
+        let multisig = self.get_multisig(alias);
+
+        self.find_entries(alias).map(|entries| {
+            entries.iter().map(|entry| {
+                // Multisig entries always have a path ending in chain/index.
+                match (multisig, &entry.path) {
+                    (Some(multisig), &Some(ref path)) => {
+                        let (chain, index) = chain_and_index(path).unwrap();
+                        return multisig.to_descriptor_at(chain, index).encode();
+                    }
+                    _ => {}
+                }
+
+                let descriptor = match entry.private_key {
+                    Some(ref private_key) => {
+                        let origin = match (&self.master_key, &entry.path) {
+                            (&Some(ref master_key), &Some(ref path)) => {
+                                descriptor::key_origin(master_key.fingerprint().as_slice(), path)
+                            }
+                            _ => String::new()
+                        };
+                        descriptor::from_public_key(&entry.address, &private_key.to_public_key(),
+                                                    origin.as_slice())
+                    }
+                    None => descriptor::from_address(&entry.address)
+                };
+
+                // Descriptors made from keys and addresses only ever contain
+                // valid characters.
+                descriptor::add_checksum(descriptor.as_slice()).unwrap()
+            }).collect()
+        })
+    }
+
+    /// Describes each chain of each account the wallet has derived addresses
+    /// from as a ranged output descriptor with the account's extended public
+    /// key, so that a watch-only wallet can find addresses the master key
+    /// derives in the future too. Returns an empty list if the wallet isn't
+    /// an HD wallet. P2PKH addresses that older versions of Cykas derived
+    /// from uncompressed public keys are left out, since extended keys can't
+    /// describe them. Multisig aliases are described with all of their
+    /// cosigners' account keys, after the single-key accounts.
+    pub fn get_account_descriptors(&self) -> Vec<String> {
+        let master_key = match self.master_key {
+            Some(ref master_key) => master_key,
+            None => return Vec::new()
+        };
+
+        // Find the chains in the standard account layout, in the order their
+        // addresses first appear.
+        let mut chains: Vec<(AddressType, DerivationPath, u32)> = Vec::new();
+        for &(_, ref entries) in self.entries.iter() {
+            for entry in entries.iter() {
+                let indexes = match entry.path {
+                    Some(ref path) => path.get_indexes(),
+                    None => continue
+                };
+                if indexes.len() != 5 { continue; }
+
+                let address_type = entry.address.get_type();
+                let account_path = DerivationPath::new(indexes.slice_to(3));
+                let chain = indexes[3];
+
+                let is_standard = indexes[2] >= HARDENED &&
+                                  account_path == DerivationPath::account(address_type, indexes[2] - HARDENED) &&
+                                  (chain == EXTERNAL_CHAIN || chain == INTERNAL_CHAIN);
+                if !is_standard { continue; }
+                if address_type == AddressType::P2PKH &&
+                   entry.private_key.as_ref().map(|key| key.to_compressed_address()) != Some(entry.address.clone()) {
+                    continue;
+                }
+
+                let chain = (address_type, account_path, chain);
+                if !chains.contains(&chain) {
+                    chains.push(chain);
+                }
+            }
+        }
+
+        let origin_fingerprint = master_key.fingerprint();
+        let mut result = Vec::with_capacity(chains.len());
+        for &(address_type, ref account_path, chain) in chains.iter() {
+            // The account path only has valid indexes, since addresses were
+            // derived below it.
+            let account_key = master_key.derive_path(account_path).unwrap().to_extended_public_key();
+            let origin = descriptor::key_origin(origin_fingerprint.as_slice(), account_path);
+            let descriptor = descriptor::from_extended_public_key(address_type, &account_key,
+                                                                  origin.as_slice(), chain);
+            result.push(descriptor::add_checksum(descriptor.as_slice()).unwrap());
+        }
+
+        for &(ref alias, ref multisig) in self.multisigs.iter() {
+            let entries = match self.find_entries(alias.as_slice()) {
+                Some(entries) => entries,
+                None => continue
+            };
+
+            let mut chains: Vec<u32> = Vec::new();
+            for entry in entries.iter() {
+                match entry.path.as_ref().and_then(|path| chain_and_index(path)) {
+                    Some((chain, _)) if !chains.contains(&chain) => chains.push(chain),
+                    _ => {}
+                }
+            }
+
+            for &chain in chains.iter() {
+                result.push(multisig.to_descriptor(chain).encode());
+            }
+        }
+        result
+    }
+
+    // Finds the entries with the given alias.
+    fn find_entries(&self, alias: &str) -> Option<&Vec<WalletEntry>> {
+        self.entries.iter()
+            .find(|&&(ref key, _)| key.as_slice() == alias)
+            .map(|&(_, ref entries)| entries)
+    }
+
+    /// Gets the multisig wallet of the given alias, or None if it isn't a
+    /// multisig alias.
+    pub fn get_multisig(&self, alias: &str) -> Option<&Multisig> {
+        find_multisig(self.multisigs.as_slice(), alias)
+    }
+
+    /// Gets this wallet's account key for multisig wallets of the given type,
+    /// at the BIP 48 path of the given account, to give to the other
+    /// cosigners. Returns None if the wallet isn't an HD wallet.
+    pub fn cosigner_key(&self, multisig_type: MultisigType, account: u32) -> Option<Cosigner> {
+        self.master_key.as_ref().and_then(|master_key| {
+            let path = multisig_type.account_path(account);
+            master_key.derive_path(&path).map(|account_key| {
+                Cosigner {
+                    origin: KeyOrigin { fingerprint: master_key.fingerprint(), path: path },
+                    account_key: account_key.to_extended_public_key()
+                }
+            })
+        })
+    }
+
+    /// Checks whether at least one of the multisig wallet's keys belongs to
+    /// this wallet, so that it can sign for it.
+    pub fn is_cosigner(&self, multisig: &Multisig) -> bool {
+        match self.master_key {
+            Some(ref master_key) => !own_cosigners(master_key, multisig).is_empty(),
+            None => false
+        }
+    }
+
+    /// Adds a multisig alias, which has the addresses of the given multisig
+    /// wallet, and derives its first address. The cosigners' keys are saved
+    /// in the plaintext part of the wallet file. Fails if the alias is
+    /// already used, or if none of the multisig wallet's keys belong to this
+    /// wallet.
+    pub fn add_multisig(&mut self, alias: &str, multisig: Multisig) {
+        if self.find_entries(alias).is_some() || self.get_multisig(alias).is_some() {
+            panic!("Alias '{}' is already used", alias);
+        }
+        if !self.is_cosigner(&multisig) {
+            panic!("None of the multisig wallet's keys belong to this wallet");
+        }
+
+        self.multisigs.push((alias.to_string(), multisig));
+        self.gen_multisig(alias, 1);
+    }
+
+    /// Derives the next `n` addresses of a multisig alias, appending them to
+    /// it. Each address's private key is this wallet's first key in the
+    /// multisig wallet, at the same chain and index. Fails if the alias isn't
+    /// a multisig alias.
+    pub fn gen_multisig(&mut self, alias: &str, n: uint) {
+        let entries = {
+            let multisig = self.get_multisig(alias).expect("Not a multisig alias");
+            let master_key = self.master_key.as_ref().unwrap();
+
+            // Only the first of our keys is stored; signing derives the rest.
+            let own_path = own_cosigners(master_key, multisig)[0].origin.path.clone();
+            let chain_path = own_path.child(EXTERNAL_CHAIN);
+
+            let mut entries = Vec::with_capacity(n);
+            let mut next_index = self.next_hd_index(&chain_path);
+
+            while entries.len() < n {
+                let path = chain_path.child(next_index);
+                let address = multisig.address(EXTERNAL_CHAIN, next_index);
+                next_index += 1;
+
+                // Skip indexes where any cosigner's key is invalid.
+                match (master_key.derive_path(&path), address) {
+                    (Some(key), Some(address)) => {
+                        entries.push(WalletEntry {
+                            address: address,
+                            private_key: Some(key.private_key),
+                            path: Some(path),
+                            metadata: Vec::new()
+                        });
+                    }
+                    _ => continue
+                }
+            }
+            entries
+        };
+
+        self.add_entries(alias, entries);
+    }
+
+    /// Adds this wallet's signatures to the inputs of a PSBT that spend from
+    /// its multisig addresses, with every one of its keys in each input's
+    /// script. The witness script (and redeem script, for P2SH-P2WSH) are
+    /// added too, if they're missing. Returns the number of signatures added.
+    ///
+    /// Inputs are only signed with SIGHASH_ALL, so that the other cosigners
+    /// can't change the outputs afterwards. Inputs that ask for another
+    /// sighash type, that have already been finalized, or whose scripts don't
+    /// match the wallet's are left alone.
+    pub fn sign_psbt(&self, psbt: &mut Psbt) -> uint {
+        let master_key = match self.master_key {
+            Some(ref master_key) => master_key,
+            None => return 0
+        };
+
+        let mut signature_count = 0;
+
+        for input_index in range(0, psbt.inputs.len()) {
+            if psbt.is_finalized(input_index) { continue; }
+
+            match psbt.sighash_type(input_index) {
+                Some(hash_type) if hash_type != SIGHASH_ALL as u32 => continue,
+                _ => {}
+            }
+
+            let spent_output = match psbt.spent_output(input_index) {
+                Some(output) => output,
+                None => continue
+            };
 
-        assert_eq!(salt.len(), PKCS5_SALT_LENGTH); // TODO: handle error.
-        assert_eq!(iv.len(), AES_IV_LENGTH); // TODO: handle error.
+            let address = match Address::from_script_pubkey(spent_output.script_pubkey.as_slice()) {
+                Some(address) => address,
+                None => continue
+            };
 
-        let plaintext = openssl::crypto::symm::decrypt(
-            openssl::crypto::symm::Type::AES_256_CBC,
-            key.as_slice(), iv.to_vec(), ciphertext
-        );
+            let (multisig, chain, index) = match self.find_multisig_address(&address) {
+                Some(found) => found,
+                None => continue
+            };
 
-        let raw_keys = plaintext.as_slice().chunks(private_key::LENGTH);
-        let private_keys = raw_keys.map(|key| PrivateKey::new(key).unwrap()).collect();
+            // The address was derived from these scripts when it was added.
+            let witness_script = multisig.witness_script(chain, index).unwrap();
+            let redeem_script = multisig.redeem_script(chain, index);
 
-        private_keys
+            // A PSBT with different scripts isn't spending what it says it is.
+            let scripts_match =
+                psbt.witness_script(input_index).map_or(true, |script| script == witness_script.as_slice()) &&
+                psbt.redeem_script(input_index).map_or(true, |script| {
+                    redeem_script.as_ref().map_or(false, |redeem_script| script == redeem_script.as_slice())
+                });
+            if !scripts_match { continue; }
+
+            for cosigner in own_cosigners(master_key, multisig).iter() {
+                let path = cosigner.origin.path.child(chain).child(index);
+                let key = match master_key.derive_path(&path) {
+                    Some(key) => key,
+                    None => continue
+                };
+
+                // The input index is in range and SIGHASH_ALL is valid, so
+                // this can't fail.
+                let signature = segwit::sign(&psbt.unsigned_tx, input_index, witness_script.as_slice(),
+                                             spent_output.value, &key.private_key, SIGHASH_ALL).unwrap();
+                let public_key = key.private_key.to_public_key().to_compressed();
+                psbt.add_partial_signature(input_index, public_key.as_slice(), signature.as_slice());
+                signature_count += 1;
+            }
+
+            psbt.set_witness_script(input_index, witness_script.as_slice());
+            match redeem_script {
+                Some(ref redeem_script) => psbt.set_redeem_script(input_index, redeem_script.as_slice()),
+                None => {}
+            }
+        }
+
+        signature_count
+    }
+
+    // Finds the multisig wallet that the given address belongs to, and the
+    // chain and index it was derived at.
+    fn find_multisig_address(&self, address: &Address) -> Option<(&Multisig, u32, u32)> {
+        for &(ref alias, ref multisig) in self.multisigs.iter() {
+            let entries = match self.find_entries(alias.as_slice()) {
+                Some(entries) => entries,
+                None => continue
+            };
+
+            for entry in entries.iter() {
+                if entry.address != *address { continue; }
+                match entry.path.as_ref().and_then(|path| chain_and_index(path)) {
+                    Some((chain, index)) => return Some((multisig, chain, index)),
+                    None => {}
+                }
+            }
+        }
+        None
     }
 
     /// Generates a single private key, appending it to the keyring with the
-    /// given alias.
-    pub fn gen(&mut self, alias: &str) {
-        self.gen_multiple(alias, 1);
+    /// given alias. The address stored for it is of the given type. Returns
+    /// an error if a random key can't be generated.
+    pub fn gen(&mut self, alias: &str, address_type: AddressType) -> IoResult<()> {
+        self.gen_multiple(alias, 1, address_type)
     }
 
     /// Generates `n` private keys, appending them to the keyring with the
-    /// given alias.
-    pub fn gen_multiple(&mut self, alias: &str, n: uint) {
-        let entries: Vec<WalletEntry> =
-            range(0, n).map(|_| {
-                let private_key = PrivateKey::generate();
-                let address = private_key.to_address();
-                WalletEntry { address: address, private_key: Some(private_key) }
-            }).collect();
+    /// given alias. The addresses stored for them are of the given type. In
+    /// an HD wallet, the keys are derived from the master key instead of
+    /// being random, using the next unused indexes of the first account for
+    /// that address type (on the internal chain for the `change` alias), and
+    /// P2PKH addresses use the compressed public key, as other BIP 44
+    /// wallets' do. Returns an error, without adding any keys, if a random
+    /// key can't be generated.
+    pub fn gen_multiple(&mut self, alias: &str, n: uint, address_type: AddressType) -> IoResult<()> {
+        let chain = if alias == CHANGE_ALIAS { INTERNAL_CHAIN } else { EXTERNAL_CHAIN };
+        let chain_path = DerivationPath::account(address_type, 0).child(chain);
+
+        let mut entries: Vec<WalletEntry> = Vec::with_capacity(n);
+        let mut next_index = self.next_hd_index(&chain_path);
+
+        while entries.len() < n {
+            let (private_key, path) = match self.master_key {
+                Some(ref master_key) => {
+                    let path = chain_path.child(next_index);
+                    next_index += 1;
+
+                    // BIP 32 says to skip indexes that give an invalid key.
+                    match master_key.derive_path(&path) {
+                        Some(key) => (key.private_key, Some(path)),
+                        None => continue
+                    }
+                }
+                None => (try!(PrivateKey::generate()), None)
+            };
+
+            let address = derive_address(&private_key, address_type, path.is_some());
+            entries.push(WalletEntry { address: address, private_key: Some(private_key), path: path,
+                                      metadata: Vec::new() });
+        }
+
+        self.add_entries(alias, entries);
+        Ok(())
+    }
+
+    /// Proves that the wallet holds the private key for every address it
+    /// lists, by signing the challenge and the hash of the wallet file with
+    /// each of them. See `protocol::proof` for the details.
+    pub fn prove(&self, challenge: &str, wallet_hash: &[u8]) -> Proof {
+        let mut keys = Vec::new();
+
+        for &(ref alias, ref entries) in self.entries.iter() {
+            let multisig = find_multisig(self.multisigs.as_slice(), alias.as_slice());
+
+            for entry in entries.iter() {
+                let private_key = match entry.private_key {
+                    Some(ref private_key) => private_key,
+                    None => continue
+                };
+
+                // Loading the wallet checked that multisig entries have a
+                // path ending in chain/index.
+                let witness_script = multisig.map(|multisig| {
+                    let (chain, index) = chain_and_index(entry.path.as_ref().unwrap()).unwrap();
+                    multisig.witness_script(chain, index).unwrap()
+                });
+
+                keys.push(KeyProof::sign(challenge, wallet_hash, &entry.address, private_key, witness_script));
+            }
+        }
+
+        Proof { challenge: challenge.to_string(), wallet_hash: wallet_hash.to_vec(), keys: keys }
+    }
+
+    /// Adds a private key that was made outside the wallet, such as from
+    /// dice rolls, to the keyring with the given alias, and returns its
+    /// address, which is of the given type. The key isn't derived from the
+    /// master key, even in an HD wallet, so it has to be backed up with the
+    /// wallet file.
+    pub fn add_private_key(&mut self, alias: &str, private_key: PrivateKey,
+                           address_type: AddressType) -> Address {
+        let address = derive_address(&private_key, address_type, false);
+        let entry = WalletEntry { address: address.clone(), private_key: Some(private_key), path: None,
+                                  metadata: Vec::new() };
+        self.add_entries(alias, vec![entry]);
+        address
+    }
+
+    /// Derives the addresses of the given type that an HD wallet restored
+    /// from its seed should contain, following BIP 44's account discovery.
+    /// `used` lists the addresses known to have been used. On each chain of
+    /// each account, addresses are derived until `gap_limit` of them in a row
+    /// are unused. Discovery stops at the first account after the first one
+    /// that has no used addresses.
+    ///
+    /// The first account's external addresses go under the given alias and
+    /// its change addresses under the `change` alias. Later accounts use the
+    /// alias with the account number appended, e.g. `receive1` and
+    /// `receive1_change`. Returns the number of accounts found. Fails if the
+    /// wallet has no master key.
+    pub fn discover(&mut self, alias: &str, address_type: AddressType, gap_limit: uint,
+                    used: &[Address]) -> uint {
+        let master_key = self.master_key.clone().expect("Address discovery needs an HD wallet");
+        let mut account = 0u32;
+
+        loop {
+            let account_path = DerivationPath::account(address_type, account);
+            let account_key = match master_key.derive_path(&account_path) {
+                Some(key) => key,
+                None => break
+            };
+
+            let mut account_used = false;
+            let mut chains = Vec::with_capacity(2);
+
+            for &chain in [EXTERNAL_CHAIN, INTERNAL_CHAIN].iter() {
+                let chain_key = match account_key.derive_child(chain) {
+                    Some(key) => key,
+                    None => break
+                };
+
+                let mut entries = Vec::new();
+                let mut unused_in_a_row = 0u;
+                let mut index = 0u32;
+
+                while unused_in_a_row < gap_limit {
+                    match chain_key.derive_child(index) {
+                        Some(key) => {
+                            let address = derive_address(&key.private_key, address_type, true);
+                            if used.contains(&address) {
+                                account_used = true;
+                                unused_in_a_row = 0;
+                            } else {
+                                unused_in_a_row += 1;
+                            }
+
+                            let path = account_path.child(chain).child(index);
+                            entries.push(WalletEntry {
+                                address: address,
+                                private_key: Some(key.private_key),
+                                path: Some(path),
+                                metadata: Vec::new()
+                            });
+                        }
+                        None => {}
+                    }
+                    index += 1;
+                }
+
+                chains.push(entries);
+            }
+
+            if account > 0 && !account_used { break; }
+
+            let (receive_alias, change_alias) = if account == 0 {
+                (alias.to_string(), CHANGE_ALIAS.to_string())
+            } else {
+                (format!("{}{}", alias, account), format!("{}{}_{}", alias, account, CHANGE_ALIAS))
+            };
+
+            let mut chains = chains.into_iter();
+            self.add_entries(receive_alias.as_slice(), chains.next().unwrap_or(Vec::new()));
+            self.add_entries(change_alias.as_slice(), chains.next().unwrap_or(Vec::new()));
+
+            account += 1;
 
+            // Without any used addresses, there's no way to tell whether later
+            // accounts exist.
+            if !account_used { break; }
+        }
+
+        account as uint
+    }
+
+    // Appends entries to the keyring with the given alias, creating it if it
+    // doesn't exist yet.
+    fn add_entries(&mut self, alias: &str, entries: Vec<WalletEntry>) {
         let index = self.entries.iter().position(|&(ref key, _)| key.as_slice() == alias);
         match index {
             Some(idx) => {
                 let &(_, ref mut values) = self.entries.index_mut(&idx);
-                values.push_all(entries.as_slice());
+                values.extend(entries.into_iter());
             },
             None => {
                 self.entries.push((alias.to_string(), entries));
             }
         }
     }
+
+    // Finds the index of the next key to derive on the given chain of an HD
+    // wallet, which is one past the highest index used so far.
+    fn next_hd_index(&self, chain_path: &DerivationPath) -> u32 {
+        let mut next_index = 0;
+        for &(_, ref entries) in self.entries.iter() {
+            for entry in entries.iter() {
+                let path = match entry.path {
+                    Some(ref path) => path,
+                    None => continue
+                };
+
+                let indexes = path.get_indexes();
+                let is_on_chain = indexes.len() == chain_path.get_indexes().len() + 1 &&
+                                  path.starts_with(chain_path) &&
+                                  indexes[indexes.len() - 1] < HARDENED;
+
+                if is_on_chain {
+                    let index = indexes[indexes.len() - 1];
+                    if index >= next_index { next_index = index + 1; }
+                }
+            }
+        }
+        next_index
+    }
+}
+
+/// Reads the addresses listed in the wallet file at the given Path, in order,
+/// without decrypting anything. This is all that's needed to check a proof
+/// made by `Wallet::prove()`. Returns an OtherIoError if the wallet's
+/// addresses are encrypted.
+pub fn read_addresses(path: &Path) -> IoResult<Vec<Address>> {
+    let file = try!(File::open(path));
+    let mut reader = BufferedReader::new(file);
+    let name = path.display().to_string();
+    let document = try!(wallet_parser::parse_document(&mut reader, name.as_slice()));
+    let source = Source { document: &document, name: name.as_slice() };
+
+    if document.sections.iter().any(|section| section.key.as_slice() == ENCRYPTED_ADDRESSES_KEY) {
+        return Err(file_error(name.as_slice(), "addresses encrypted",
+                              format!("The wallet's addresses are encrypted, so they can't be read without decrypting it")));
+    }
+
+    let mut addresses = Vec::new();
+    for section in document.sections.iter() {
+        if section.key.as_slice().starts_with("!") { continue; }
+
+        for (i, value) in section.get_values().iter().enumerate() {
+            // Skip metadata and derivation paths.
+            if value.as_slice().contains_char('=') || DerivationPath::parse(value.as_slice()).is_some() { continue; }
+
+            match Address::decode(value.as_slice()) {
+                Some(address) => addresses.push(address),
+                None => {
+                    return Err(source.error_at(&section.spans[i + 1], "invalid address",
+                                               format!("Address '{}' is not a valid Bitcoin address", value)));
+                }
+            }
+        }
+    }
+
+    Ok(addresses)
+}
+
+/// Checks the wallet file at the given Path more strictly than loading it
+/// does, without changing it. Every address has to be a valid mainnet address
+/// and be listed only once. The file has to load, which decrypts it and
+/// checks that each private key gives its address, and it can't have more
+/// private keys than addresses, since the extra ones would be lost when the
+/// wallet is saved. Returns a description of each problem found, with where
+/// it is in the file, or an IoError if the file can't be read.
+pub fn check(path: &Path) -> IoResult<Vec<String>> {
+    let file = try!(File::open(path));
+    let mut reader = BufferedReader::new(file);
+    let name = path.display().to_string();
+    let name = name.as_slice();
+
+    let document = match wallet_parser::parse_document(&mut reader, name) {
+        Ok(document) => document,
+        Err(e) => return Ok(vec![try!(describe_problem(e))])
+    };
+    let address_document = match decrypt_addresses(&document, name) {
+        Ok(decrypted) => decrypted.map(|(address_document, _)| address_document),
+        Err(e) => return Ok(vec![try!(describe_problem(e))])
+    };
+
+    let address_name = address_section_name(name);
+    let file_source = Source { document: &document, name: name };
+    let address_source = address_document.as_ref().map(|document| {
+        Source { document: document, name: address_name.as_slice() }
+    });
+    let sections = file_sections(&file_source, address_source.as_ref());
+
+    let mut problems = Vec::new();
+    let mut invalid_addresses = false;
+    // Each address, with the alias and line it's first listed at.
+    let mut addresses: Vec<(Address, &str, uint)> = Vec::new();
+
+    for &(source, section) in sections.iter() {
+        let alias = section.key.as_slice();
+        if alias.starts_with("!") { continue; }
+
+        for (i, value) in section.get_values().iter().enumerate() {
+            // Skip metadata and derivation paths.
+            if value.as_slice().contains_char('=') || DerivationPath::parse(value.as_slice()).is_some() { continue; }
+
+            let span = &section.spans[i + 1];
+            let address = match Address::check(value.as_slice()) {
+                Ok(address) => address,
+                Err(reason) => {
+                    problems.push(try!(describe_problem(source.error_at(span, "invalid address",
+                        format!("Address '{}' is invalid: {}", value, reason)))));
+                    invalid_addresses = true;
+                    continue;
+                }
+            };
+
+            let first = addresses.iter().find(|&&(ref other, _, _)| *other == address).map(|&(_, alias, line)| {
+                (alias, line)
+            });
+            match first {
+                Some((other_alias, line)) => {
+                    problems.push(try!(describe_problem(source.error_at(span, "duplicate address",
+                        format!("Address '{}' is already listed under '{}' on line {}", value, other_alias, line)))));
+                }
+                None => addresses.push((address, alias, span.line))
+            }
+        }
+    }
+
+    // Loading the wallet would only stop at the first invalid address.
+    if invalid_addresses { return Ok(problems); }
+
+    let wallet = match Wallet::load(path) {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            problems.push(try!(describe_problem(e)));
+            return Ok(problems);
+        }
+    };
+
+    // Loading ignores private keys after the last address, so they're
+    // counted here.
+    let (private_keys, keys_key) = if wallet.encrypted_data.is_some() {
+        // The keys can be in the decrypted addresses as well as the rest of
+        // the file, as they can when loading.
+        let parsed: Vec<(String, Vec<String>)> = sections.iter().map(|&(_, section)| {
+            (section.key.clone(), section.get_values())
+        }).collect();
+        let plaintext = match (find_hex(parsed.as_slice(), "!salt"), find_hex(parsed.as_slice(), "!iv"),
+                               find_hex(parsed.as_slice(), "!encrypted_data")) {
+            (Some(Some(salt)), Some(Some(iv)), Some(Some(encrypted_data))) => {
+                decrypt(&derive_key(salt.as_slice()), iv.as_slice(), encrypted_data.as_slice())
+            }
+            _ => {
+                problems.push(try!(describe_problem(file_error(name, "encrypted data not found",
+                    format!("The '!salt', '!iv' and '!encrypted_data' fields can't be read")))));
+                return Ok(problems);
+            }
+        };
+        let master_key_length = if wallet.master_key.is_some() { CHAIN_CODE_LENGTH + private_key::LENGTH } else { 0 };
+        ((plaintext.as_slice().len() - master_key_length) / private_key::LENGTH, "!encrypted_data")
+    } else {
+        let master_keys = if wallet.master_key.is_some() { 1 } else { 0 };
+        (wallet.key_records.len() - master_keys, ENCRYPTED_KEYS_KEY)
+    };
+    let address_count = wallet.entries.iter().fold(0, |count, &(_, ref entries)| count + entries.len());
+
+    if private_keys > address_count {
+        let message = format!("There are {} private keys but only {} addresses, so the extra keys would be lost \
+                               when the wallet is saved", private_keys, address_count);
+        let error = match sections.iter().find(|&&(_, section)| section.key.as_slice() == keys_key) {
+            Some(&(source, section)) => source.error_at(&section.spans[0], "extra private keys", message),
+            None => file_error(name, "extra private keys", message)
+        };
+        problems.push(try!(describe_problem(error)));
+    }
+
+    Ok(problems)
+}
+
+// Helper function for check(). Describes an error about the contents of a
+// wallet file as a problem with it, or returns the error if it's about
+// reading the file instead.
+fn describe_problem(e: IoError) -> IoResult<String> {
+    if e.kind != OtherIoError { return Err(e); }
+    match e.detail {
+        Some(detail) => Ok(detail),
+        None => Ok(e.desc.to_string())
+    }
+}
+
+// Derives the key that the private key data and the address section are
+// encrypted with from the passphrase and the given salt.
+fn derive_key(salt: &[u8]) -> SecretBytes {
+    assert_eq!(salt.len(), PKCS5_SALT_LENGTH); // TODO: handle error.
+
+    SecretBytes::new(openssl::crypto::pkcs5::pbkdf2_hmac_sha1("asdf", salt, PKCS5_ITERATIONS, PKCS5_KEY_LENGTH))
+}
+
+// Encrypts data with the given key and a new random iv, and returns the iv
+// and the ciphertext.
+fn encrypt(key: &SecretBytes, plaintext: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let iv = openssl::crypto::rand::rand_bytes(AES_IV_LENGTH);
+    let ciphertext = openssl::crypto::symm::encrypt(
+        openssl::crypto::symm::Type::AES_256_CBC,
+        key.as_slice(),
+        iv.clone(),
+        plaintext
+    );
+    (iv, ciphertext)
+}
+
+// Helper function for Wallet::save(). Encrypts the data, unless it's the same
+// as one of the given data from when the wallet was loaded, in which case it's
+// left as it was. Returns the iv and the ciphertext.
+fn encrypt_if_changed<'a, I: Iterator<&'a Encrypted>>(key: &SecretBytes, plaintext: &[u8], mut old: I)
+                                                    -> (Vec<u8>, Vec<u8>) {
+    let plaintext_hash = hash::sha256(plaintext);
+    match old.find(|old| old.plaintext_hash == plaintext_hash) {
+        Some(old) => (old.iv.clone(), old.ciphertext.clone()),
+        None => encrypt(key, plaintext)
+    }
+}
+
+// Decrypts the given ciphertext with the given key and iv, and returns the
+// plaintext.
+fn decrypt(key: &SecretBytes, iv: &[u8], ciphertext: &[u8]) -> SecretBytes {
+    assert_eq!(iv.len(), AES_IV_LENGTH); // TODO: handle error.
+
+    SecretBytes::new(openssl::crypto::symm::decrypt(
+        openssl::crypto::symm::Type::AES_256_CBC,
+        key.as_slice(), iv.to_vec(), ciphertext
+    ))
+}
+
+// Parses an address's metadata as written in the wallet file: name=value, with
+// the value in quotes if it has characters that a value can't have without
+// them. Returns None if it isn't valid.
+fn parse_metadata(token: &str) -> Option<(String, String)> {
+    let (name, value) = match token.find('=') {
+        Some(i) => (token.slice_to(i), token.slice_from(i + 1)),
+        None => return None
+    };
+
+    let value = if value.starts_with("\"") {
+        if value.len() < 2 || !value.ends_with("\"") { return None; }
+        value.slice(1, value.len() - 1)
+    } else {
+        value
+    };
+
+    if is_valid_metadata_name(name) && is_valid_metadata_value(value) {
+        Some((name.to_string(), value.to_string()))
+    } else {
+        None
+    }
+}
+
+// Writes an address's metadata the way parse_metadata() reads it, quoting the
+// value unless it's a single plain word.
+fn encode_metadata(name: &str, value: &str) -> String {
+    if value.chars().all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '-' || ch == '.' || ch == '/') {
+        format!("{}={}", name, value)
+    } else {
+        format!("{}=\"{}\"", name, value)
+    }
+}
+
+fn is_valid_metadata_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|ch| ch.is_alphanumeric() || ch == '_')
+}
+
+fn is_valid_metadata_value(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|ch| ch != '"' && !ch.is_control())
+}
+
+// Splits binary data into the values of a key, in hex, in lines of 38 bytes
+// after the key's own line.
+fn hex_values(data: &[u8]) -> Vec<Vec<String>> {
+    let mut values = vec![Vec::new()];
+    values.extend(data.chunks(38).map(|chunk| vec![chunk.to_hex()]));
+    values
+}
+
+// If the parsed wallet file, which has the given name, has an encrypted
+// address section, decrypts and parses it. Returns the addresses along with
+// how they were encrypted.
+fn decrypt_addresses(document: &Document, name: &str) -> IoResult<Option<(Document, Encrypted)>> {
+    let parsed = document.get_keys_and_values();
+    let ciphertext = match find_hex(parsed.as_slice(), ENCRYPTED_ADDRESSES_KEY) {
+        Some(ciphertext) => ciphertext,
+        None => return Ok(None)
+    };
+
+    let (ciphertext, salt, iv) = match (ciphertext,
+                                           find_hex(parsed.as_slice(), "!salt"),
+                                           find_hex(parsed.as_slice(), ADDRESSES_IV_KEY)) {
+        (Some(ciphertext), Some(Some(salt)), Some(Some(iv)))
+            if salt.len() == PKCS5_SALT_LENGTH && iv.len() == AES_IV_LENGTH => (ciphertext, salt, iv),
+        _ => {
+            return Err(file_error(name, "encrypted addresses invalid",
+                                  format!("'{}', '!salt' or '{}' is missing or invalid",
+                                          ENCRYPTED_ADDRESSES_KEY, ADDRESSES_IV_KEY)));
+        }
+    };
+
+    // Addresses can't be listed in both places, or it wouldn't be clear
+    // which order the private keys are in.
+    match document.sections.iter().find(|section| is_address_section_key(section.key.as_slice())) {
+        Some(section) => {
+            return Err(document.error_at(name, &section.spans[0], "unexpected plaintext addresses",
+                                         format!("The wallet file has encrypted addresses, but also lists '{}' in plaintext",
+                                                 section.key)));
+        }
+        None => {}
+    }
+
+    let plaintext = decrypt(&derive_key(salt.as_slice()), iv.as_slice(), ciphertext.as_slice());
+    let address_name = address_section_name(name);
+    let address_document = match String::from_utf8(plaintext.as_slice().to_vec()) {
+        Ok(text) => {
+            let mut reader = MemReader::new(text.into_bytes());
+            try!(wallet_parser::parse_document(&mut reader, address_name.as_slice()))
+        }
+        Err(_) => {
+            return Err(file_error(name, "encrypted addresses invalid", format!("The addresses couldn't be decrypted")));
+        }
+    };
+
+    match address_document.sections.iter().find(|section| !is_address_section_key(section.key.as_slice())) {
+        Some(section) => {
+            return Err(address_document.error_at(address_name.as_slice(), &section.spans[0],
+                                                  "encrypted addresses invalid",
+                                                  format!("The encrypted addresses contain '{}', which belongs outside them",
+                                                          section.key)));
+        }
+        None => {}
+    }
+
+    let encrypted = Encrypted::new(iv, ciphertext, plaintext.as_slice());
+    Ok(Some((address_document, encrypted)))
+}
+
+// Helper function for Wallet::load() and check(). Lists the keys of a wallet
+// file with the part of the file each one is in: the decrypted addresses
+// first, if they were encrypted, and then the rest of the file.
+fn file_sections<'a>(file: &'a Source<'a>, addresses: Option<&'a Source<'a>>) -> Vec<(&'a Source<'a>, &'a Section)> {
+    let mut sections = Vec::new();
+    for source in addresses.into_iter().chain(Some(file).into_iter()) {
+        for section in source.document.sections.iter() {
+            let key = section.key.as_slice();
+            if key != ENCRYPTED_ADDRESSES_KEY && key != ADDRESSES_IV_KEY {
+                sections.push((source, section));
+            }
+        }
+    }
+    sections
+}
+
+// The name that errors in the decrypted addresses of the named wallet file
+// give them. Their lines and columns are in the decrypted text.
+fn address_section_name(name: &str) -> String {
+    format!("{} (encrypted addresses)", name)
+}
+
+// Makes an IoError about the named wallet file as a whole, like a key that's
+// missing from it.
+fn file_error(name: &str, desc: &'static str, message: String) -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: desc,
+        detail: Some(format!("{}: {}", name, message))
+    }
+}
+
+// Helper function for decrypt_addresses(). Finds the given key in the parsed
+// wallet file and decodes its values as hex. Returns None if the key isn't
+// there, or Some(None) if its values aren't valid hex.
+fn find_hex(parsed: &[(String, Vec<String>)], name: &str) -> Option<Option<Vec<u8>>> {
+    parsed.iter()
+        .find(|&&(ref key, _)| key.as_slice() == name)
+        .map(|&(_, ref values)| values.concat().as_slice().from_hex().ok())
+}
+
+// Checks whether a key in the wallet file belongs in the address section:
+// an alias, or the cosigners of a multisig alias.
+fn is_address_section_key(key: &str) -> bool {
+    !key.starts_with("!") || key.starts_with(MULTISIG_KEY_PREFIX)
+}
+
+// Derives the address of the given type from a private key. P2PKH addresses
+// are of the compressed public key if `compressed` is true, as they are for
+// keys derived from the master key, or else the uncompressed one. P2SH
+// addresses in a wallet are always P2SH-wrapped P2WPKH, since that's the only
+// script Cykas derives them from, and P2TR addresses never have a script
+// tree. P2WSH addresses can't be derived from a single key.
+fn derive_address(private_key: &PrivateKey, address_type: AddressType, compressed: bool) -> Address {
+    match address_type {
+        AddressType::P2PKH if compressed => private_key.to_compressed_address(),
+        AddressType::P2PKH => private_key.to_address(),
+        AddressType::P2SH => private_key.to_p2sh_p2wpkh_address(),
+        AddressType::P2WPKH => private_key.to_p2wpkh_address(),
+        AddressType::P2WSH => panic!("P2WSH addresses aren't derived from a single key"),
+        AddressType::P2TR => private_key.to_p2tr_address()
+    }
+}
+
+// Finds the multisig wallet of the given alias.
+fn find_multisig<'a>(multisigs: &'a [(String, Multisig)], alias: &str) -> Option<&'a Multisig> {
+    multisigs.iter()
+        .find(|&&(ref key, _)| key.as_slice() == alias)
+        .map(|&(_, ref multisig)| multisig)
 }
 
+// Finds the keys in a multisig wallet that are derived from the given master
+// key, in the order the cosigners are listed.
+fn own_cosigners<'a>(master_key: &ExtendedPrivateKey, multisig: &'a Multisig) -> Vec<&'a Cosigner> {
+    let fingerprint = master_key.fingerprint();
+    multisig.get_cosigners().iter().filter(|cosigner| {
+        cosigner.origin.fingerprint == fingerprint &&
+        master_key.derive_path(&cosigner.origin.path).map(|key| key.to_extended_public_key()) ==
+            Some(cosigner.account_key.clone())
+    }).collect()
+}
+
+// Splits the last two indexes off a path to a key in an account, which are
+// the chain and the index of the key in it. Returns None if the path is too
+// short or either index is hardened.
+fn chain_and_index(path: &DerivationPath) -> Option<(u32, u32)> {
+    let indexes = path.get_indexes();
+    if indexes.len() < 2 { return None; }
+
+    let (chain, index) = (indexes[indexes.len() - 2], indexes[indexes.len() - 1]);
+    if chain >= HARDENED || index >= HARDENED { return None; }
+    Some((chain, index))
+}
+
+// Derives the address of a multisig wallet that our key at the given path is
+// in. The path has to be one of our account keys in the multisig wallet,
+// followed by the chain and index. Returns None if it isn't.
+fn multisig_entry_address(master_key: &ExtendedPrivateKey, multisig: &Multisig,
+                          path: &DerivationPath) -> Option<Address> {
+    let (chain, index) = match chain_and_index(path) {
+        Some(chain_and_index) => chain_and_index,
+        None => return None
+    };
+
+    let is_own_path = own_cosigners(master_key, multisig).iter().any(|cosigner| {
+        cosigner.origin.path.child(chain).child(index) == *path
+    });
+    if !is_own_path { return None; }
+
+    multisig.address(chain, index)
+}
+
+// Gets the token that a multisig type is written as in the wallet file, which
+// can't contain dashes.
+fn multisig_type_token(multisig_type: MultisigType) -> &'static str {
+    match multisig_type {
+        MultisigType::P2WSH => "wsh",
+        MultisigType::P2SHP2WSH => "sh_wsh"
+    }
+}
+
+// Parses the values of a multisig alias's special key: the threshold, the
+// multisig type, and then three values for each cosigner: the fingerprint of
+// their master key, the path of their account key, and the account key.
+// Returns None if any of them are invalid.
+fn parse_multisig(values: &[String]) -> Option<Multisig> {
+    if values.len() < 2 || (values.len() - 2) % 3 != 0 { return None; }
+
+    let threshold = match from_str::<uint>(values[0].as_slice()) {
+        Some(threshold) => threshold,
+        None => return None
+    };
+
+    let multisig_type = match values[1].as_slice() {
+        "wsh" => MultisigType::P2WSH,
+        "sh_wsh" => MultisigType::P2SHP2WSH,
+        _ => return None
+    };
+
+    let mut cosigners = Vec::new();
+    for fields in values.slice_from(2).chunks(3) {
+        let (fingerprint, path, account_key) = (fields[0].as_slice(), fields[1].as_slice(), fields[2].as_slice());
+        if !path.starts_with("m") { return None; }
+
+        // Put the key back together the way a descriptor writes it.
+        let key = format!("[{}{}]{}", fingerprint, path.slice_from(1), account_key);
+        match Cosigner::parse(key.as_slice()) {
+            Some(cosigner) => cosigners.push(cosigner),
+            None => return None
+        }
+    }
+
+    Multisig::new(threshold, multisig_type, cosigners)
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::{ToHex, FromHex};
+    use std::io::{File, BufferedReader, TempDir};
+
+    use protocol::address::{Address, AddressType};
+    use protocol::private_key::PrivateKey;
+    use wallet_parser;
+    use super::{Wallet, check, derive_key, encrypt, PKCS5_SALT_LENGTH, ENCRYPTED_KEYS_KEY};
+
+    static PRIVATE_KEY: &'static str = "F7476532FE5753EBCBEA26FE02FFF18BF015546F85CAF78AC8D599547F7D3AAC";
+
+    // Writes a wallet file the way older versions of Cykas did, with its one
+    // private key encrypted under '!encrypted_data', and returns its address.
+    fn write_legacy_wallet(path: &Path) -> Address {
+        let data = PRIVATE_KEY.from_hex().unwrap();
+        let address = PrivateKey::new(data.as_slice()).unwrap().to_address();
+        let salt = Vec::from_elem(PKCS5_SALT_LENGTH, 0x5Au8);
+        let (iv, ciphertext) = encrypt(&derive_key(salt.as_slice()), data.as_slice());
+
+        let contents = format!("# Written by hand.\nreceive:\n  {}\n\n!salt: {}\n!iv: {}\n!encrypted_data:\n  {}\n",
+                               address.encode(), salt.to_hex(), iv.to_hex(), ciphertext.to_hex());
+        File::create(path).write_str(contents.as_slice()).unwrap();
+        address
+    }
+
+    // Loads the wallet at the path and saves it again, and returns what was
+    // saved.
+    fn load_and_save(path: &Path) -> Vec<u8> {
+        Wallet::load(path).unwrap().save().unwrap();
+        File::open(path).read_to_end().unwrap()
+    }
+
+    // Reads the values of a key in the wallet file at the path.
+    fn read_values(path: &Path, key: &str) -> Option<Vec<String>> {
+        let mut reader = BufferedReader::new(File::open(path).unwrap());
+        let parsed = wallet_parser::parse(&mut reader, "test").unwrap();
+        parsed.into_iter().find(|&(ref k, _)| k.as_slice() == key).map(|(_, values)| values)
+    }
+
+    #[test]
+    fn test_save_unchanged_legacy_wallet() {
+        let dir = TempDir::new("cykas").unwrap();
+        let path = dir.path().join("wallet.txt");
+        write_legacy_wallet(&path);
+
+        let first = load_and_save(&path);
+        let second = load_and_save(&path);
+        assert_eq!(first, second);
+        assert!(String::from_utf8(first).unwrap().as_slice().contains("!encrypted_data:"));
+    }
+
+    #[test]
+    fn test_save_unchanged_wallet() {
+        let dir = TempDir::new("cykas").unwrap();
+        let path = dir.path().join("wallet.txt");
+        let mut wallet = Wallet::new(&path);
+        wallet.gen("receive", AddressType::P2WPKH).unwrap();
+        wallet.gen("receive", AddressType::P2PKH).unwrap();
+        wallet.save().unwrap();
+
+        let first = load_and_save(&path);
+        let second = load_and_save(&path);
+        assert_eq!(first, second);
+        assert!(String::from_utf8(first).unwrap().as_slice().contains("!encrypted_keys:"));
+    }
+
+    #[test]
+    fn test_check_testnet_address() {
+        // Testnet P2PKH addresses start with "m", like derivation paths.
+        let dir = TempDir::new("cykas").unwrap();
+        let path = dir.path().join("wallet.txt");
+        File::create(&path).write_str("receive:\n  mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn\n").unwrap();
+
+        let problems = check(&path).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].as_slice().contains("Address 'mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn' is invalid: \
+                                                 it's a testnet address"));
+    }
+
+    #[test]
+    fn test_save_encrypted_addresses() {
+        let dir = TempDir::new("cykas").unwrap();
+        let path = dir.path().join("wallet.txt");
+        let mut wallet = Wallet::new(&path);
+        wallet.gen("receive", AddressType::P2WPKH).unwrap();
+        wallet.set_encrypt_addresses(true);
+        wallet.save().unwrap();
+        let addresses = wallet.get_addresses("receive").unwrap();
+
+        // The file explains how to decrypt the addresses, without giving
+        // them away.
+        let contents = File::open(&path).read_to_string().unwrap();
+        assert!(contents.as_slice().contains("# The aliases and addresses, encrypted with AES-256-CBC using the\n"));
+        assert!(!contents.as_slice().contains(addresses[0].encode().as_slice()));
+        assert!(read_values(&path, "receive").is_none());
+
+        let wallet = Wallet::load(&path).unwrap();
+        assert!(wallet.get_encrypt_addresses());
+        assert_eq!(wallet.get_addresses("receive").unwrap(), addresses);
+
+        let first = load_and_save(&path);
+        let second = load_and_save(&path);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_gen_appends_key_record() {
+        let dir = TempDir::new("cykas").unwrap();
+        let path = dir.path().join("wallet.txt");
+        let mut wallet = Wallet::new(&path);
+        wallet.gen_multiple("receive", 2, AddressType::P2WPKH).unwrap();
+        wallet.save().unwrap();
+        let records = read_values(&path, ENCRYPTED_KEYS_KEY).unwrap();
+
+        let mut wallet = Wallet::load(&path).unwrap();
+        wallet.gen("receive", AddressType::P2WPKH).unwrap();
+        wallet.save().unwrap();
+        let new_records = read_values(&path, ENCRYPTED_KEYS_KEY).unwrap();
+
+        assert_eq!(new_records.len(), records.len() + 1);
+        assert_eq!(new_records.slice_to(records.len()), records.as_slice());
+    }
+
+    #[test]
+    fn test_save_legacy_wallet_in_new_layout() {
+        let dir = TempDir::new("cykas").unwrap();
+        let path = dir.path().join("wallet.txt");
+        let address = write_legacy_wallet(&path);
+
+        // Adding a key changes the private key data, which converts it.
+        let mut wallet = Wallet::load(&path).unwrap();
+        wallet.gen("receive", AddressType::P2WPKH).unwrap();
+        wallet.save().unwrap();
+
+        assert_eq!(read_values(&path, ENCRYPTED_KEYS_KEY).unwrap().len(), 2);
+        assert!(read_values(&path, "!encrypted_data").is_none());
+        assert!(read_values(&path, "!iv").is_none());
+
+        let wallet = Wallet::load(&path).unwrap();
+        let private_key = wallet.get_private_key(&address).unwrap();
+        assert_eq!(private_key.get_data(), PRIVATE_KEY.from_hex().unwrap().as_slice());
+        assert_eq!(wallet.get_addresses("receive").unwrap().len(), 2);
+        let contents = File::open(&path).read_to_string().unwrap();
+        assert!(contents.as_slice().starts_with("# Written by hand.\n"));
+    }
+}
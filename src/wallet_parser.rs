@@ -1,68 +1,413 @@
 //! Simple parser for a very simple key-values file format used by Cykas
 //! wallets.
+//!
+//! Besides the keys and values, a wallet file can be parsed into a Document,
+//! which keeps the comments, blank lines and layout too, so that a file that
+//! was edited by hand can be written back with only the values that changed.
+//! Errors give the file, line and column they're about, and show the line,
+//! so that a file that was edited by hand can be fixed quickly.
 
-use std::io::{Buffer, IoResult, IoError, OtherIoError};
+use std::cmp;
+use std::io::{Buffer, MemWriter, IoResult, IoError, OtherIoError};
 
 // There are only two tokens to worry about in Cykas' wallet file format: keys
-// and values, both Strings.
+// and values, both Strings. Part of a value can be quoted, to hold spaces and
+// other characters, and the quotes are kept in it.
 #[deriving(PartialEq,Show)]
 enum Token {
     Key(String),
     Value(String)
 }
 
-/// Tokenizes the given Buffer and parses that into a Vec that maps String keys
-/// to vectors of Strings. Returns an IoError on failure.
-pub fn parse<T: Buffer>(input: &mut T) -> IoResult<Vec<(String, Vec<String>)>> {
-    let tokens = try!(tokenize(input));
-    let mut tokens_iter = tokens.into_iter();
-    let mut result: Vec<(String, Vec<String>)> = Vec::new();
-
-    for token in tokens_iter {
-        match token {
-            Token::Key(key) => {
-                if result.iter().any(|&(ref alias, _)| *alias == key) {
-                    return Err(IoError {
-                        kind: OtherIoError,
-                        desc: "unexpected key",
-                        detail: Some(format!("Key '{}' used more than once in wallet file", key))
-                    });
-                }
+/// Where a key or value is in the text it was parsed from.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Span {
+    /// The byte offsets of its start and end.
+    pub start: uint,
+    pub end: uint,
+    /// The line and column it starts at, counted from 1. Columns are counted
+    /// in characters.
+    pub line: uint,
+    pub column: uint
+}
+
+/// A wallet file as it was written, so that it can be written back the same
+/// way. Writing a Document that was parsed from a file gives exactly that
+/// file.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Document {
+    /// The keys and their values, in the order they're in the file.
+    pub sections: Vec<Section>,
+    /// The comments and blank lines after the last key's values.
+    pub trailer: Vec<String>
+}
+
+/// A key and its values, along with the comments and blank lines before it.
+/// The text is kept a line at a time, with the line endings. Comments and
+/// blank lines between a key's values stay with the key, while the ones after
+/// its last value belong to the key after it.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Section {
+    /// The comments and blank lines before the key.
+    pub leading: Vec<String>,
+    pub key: String,
+    /// The lines from the key to its last value, each with the values on it.
+    pub lines: Vec<(String, Vec<String>)>,
+    /// Where the key and then each of its values are in the text the section
+    /// was parsed from. It's empty for a section that wasn't parsed.
+    pub spans: Vec<Span>
+}
+
+impl Section {
+    /// Lays out a key and its values the way Cykas writes them: the first
+    /// group of values on the key's line, and each group after that on a line
+    /// of its own, indented. The comments go before the key, a line each,
+    /// and an empty one is a blank line.
+    pub fn new(key: &str, comments: Vec<String>, values: Vec<Vec<String>>) -> Section {
+        let mut lines = Vec::with_capacity(values.len());
+        for (i, group) in values.into_iter().enumerate() {
+            let text = if i == 0 {
+                if group.is_empty() { format!("{}:\n", key) } else { format!("{}: {}\n", key, group.connect(" ")) }
+            } else {
+                format!("  {}\n", group.connect(" "))
+            };
+            lines.push((text, group));
+        }
+        if lines.is_empty() {
+            lines.push((format!("{}:\n", key), Vec::new()));
+        }
+
+        Section {
+            leading: comments.into_iter().map(|comment| format!("{}\n", comment)).collect(),
+            key: key.to_string(),
+            lines: lines,
+            spans: Vec::new()
+        }
+    }
+
+    /// Gets the key's values, in order.
+    pub fn get_values(&self) -> Vec<String> {
+        let mut values = Vec::new();
+        for &(_, ref line_values) in self.lines.iter() {
+            values.push_all(line_values.as_slice());
+        }
+        values
+    }
+}
+
+impl Document {
+    /// Gets the keys and their values, in the order they're in the file, as
+    /// `parse()` returns them.
+    pub fn get_keys_and_values(&self) -> Vec<(String, Vec<String>)> {
+        self.sections.iter().map(|section| (section.key.clone(), section.get_values())).collect()
+    }
 
-                result.push((key, Vec::new()));
+    /// Writes the document out as text.
+    pub fn write_to(&self, f: &mut Writer) -> IoResult<()> {
+        for section in self.sections.iter() {
+            for line in section.leading.iter() {
+                try!(f.write_str(line.as_slice()));
+            }
+            for &(ref line, _) in section.lines.iter() {
+                try!(f.write_str(line.as_slice()));
             }
-            Token::Value(val) => {
-                if result.is_empty() {
-                    return Err(IoError {
-                        kind: OtherIoError,
-                        desc: "unexpected value",
-                        detail: Some(format!("Wallet file starts with a value instead of a key"))
-                    });
+        }
+        for line in self.trailer.iter() {
+            try!(f.write_str(line.as_slice()));
+        }
+        Ok(())
+    }
+
+    /// Makes an IoError about the key or value at the given span of the
+    /// document, which was parsed from the file with the given name. Like
+    /// the errors from parsing, it says where in the file that is and shows
+    /// the line.
+    pub fn error_at(&self, name: &str, span: &Span, desc: &'static str, message: String) -> IoError {
+        // A Document writes exactly the text it was parsed from.
+        let mut text = MemWriter::new();
+        let _ = self.write_to(&mut text);
+        let text = String::from_utf8_lossy(text.get_ref()).into_owned();
+        error_in(name, text.as_slice(), span, desc, message)
+    }
+}
+
+/// Tokenizes the given Buffer and parses that into a Vec that maps String keys
+/// to vectors of Strings. The name of the file it's reading is used in error
+/// messages. Returns an IoError on failure.
+pub fn parse<T: Buffer>(input: &mut T, name: &str) -> IoResult<Vec<(String, Vec<String>)>> {
+    parse_document(input, name).map(|document| document.get_keys_and_values())
+}
+
+/// Parses the given Buffer into a Document, which keeps the comments and
+/// layout along with the keys and values. Returns an IoError on failure, for
+/// the same reasons as `parse()`.
+pub fn parse_document<T: Buffer>(input: &mut T, name: &str) -> IoResult<Document> {
+    let text = try!(input.read_to_string());
+    let text = text.as_slice();
+    let tokens = try!(tokenize(text, name));
+    let spans: Vec<Span> = tokens.iter().map(|&(_, ref span)| span.clone()).collect();
+    let offsets: Vec<uint> = spans.iter().map(|span| span.start).collect();
+
+    let key_indexes: Vec<uint> = tokens.iter().enumerate().filter_map(|(i, &(ref token, _))| {
+        match *token {
+            Token::Key(_) => Some(i),
+            Token::Value(_) => None
+        }
+    }).collect();
+
+    if !tokens.is_empty() && key_indexes.first() != Some(&0) {
+        return Err(error_in(name, text, &spans[0], "unexpected value",
+                            format!("The file starts with a value instead of a key")));
+    }
+
+    let mut sections: Vec<Section> = Vec::with_capacity(key_indexes.len());
+    // Where the comments and blank lines before the next key start.
+    let mut position = 0u;
+
+    for (n, &first) in key_indexes.iter().enumerate() {
+        let key = match tokens[first] {
+            (Token::Key(ref key), _) => key.clone(),
+            _ => unreachable!()
+        };
+        let key_offset = offsets[first];
+        if sections.iter().any(|section| section.key == key) {
+            return Err(error_in(name, text, &spans[first], "unexpected key",
+                                format!("Key '{}' is used more than once", key)));
+        }
+
+        // The key's lines end with the line of its last value, unless the
+        // next key is on that line too.
+        let (last, next_offset) = if n + 1 < key_indexes.len() {
+            (key_indexes[n + 1] - 1, offsets[key_indexes[n + 1]])
+        } else {
+            (tokens.len() - 1, text.len())
+        };
+        let last_offset = offsets[last];
+        let end_of_line = text.slice_from(last_offset).find('\n').map_or(text.len(), |i| last_offset + i + 1);
+        let end = if end_of_line < next_offset { end_of_line } else { next_offset };
+
+        let mut lines = Vec::new();
+        let mut line_start = key_offset;
+        for line in split_lines(text.slice(key_offset, end)).into_iter() {
+            let line_end = line_start + line.len();
+            let values = tokens.slice(first + 1, last + 1).iter().filter_map(|&(ref token, ref span)| {
+                match *token {
+                    Token::Value(ref value) if span.start >= line_start && span.start < line_end => Some(value.clone()),
+                    _ => None
                 }
+            }).collect();
+            lines.push((line, values));
+            line_start = line_end;
+        }
+
+        sections.push(Section {
+            leading: split_lines(text.slice(position, key_offset)),
+            key: key,
+            lines: lines,
+            spans: spans.slice(first, last + 1).to_vec()
+        });
+        position = end;
+    }
+
+    Ok(Document { sections: sections, trailer: split_lines(text.slice_from(position)) })
+}
 
-                let index_last = result.len() - 1;
-                let &(_, ref mut values) = result.index_mut(&index_last);
-                values.push(val);
+/// Lays out newly made sections the way the ones with the same keys were laid
+/// out in an old document, so that saving a file keeps what was written in
+/// it by hand. A section whose values haven't changed is kept exactly as it
+/// was. One that only has values added at the end keeps its old lines, with
+/// the new ones after them. Otherwise, only the comments before the key are
+/// kept.
+///
+/// The sections that were in the old document stay in the same order, and
+/// the new ones go after the section that comes before them in `new`, or
+/// first if there isn't one. Old sections that aren't in `new` are dropped.
+/// Returns an IoError if two of the old sections have the same key, as they
+/// can when they come from more than one document.
+pub fn merge(old: &[&Section], new: Vec<Section>) -> IoResult<Vec<Section>> {
+    let keys: Vec<String> = new.iter().map(|section| section.key.clone()).collect();
+    let mut new: Vec<Option<Section>> = new.into_iter().map(|section| Some(section)).collect();
+    let mut result = Vec::with_capacity(new.len());
+
+    for old_section in old.iter() {
+        match keys.iter().position(|key| *key == old_section.key) {
+            Some(i) => {
+                let section = match new.index_mut(&i).take() {
+                    Some(section) => section,
+                    None => {
+                        return Err(IoError {
+                            kind: OtherIoError,
+                            desc: "unexpected key",
+                            detail: Some(format!("Key '{}' is used more than once", old_section.key))
+                        });
+                    }
+                };
+                result.push(merge_section(*old_section, section));
             }
+            None => {}
         }
     }
 
+    for (i, section) in new.into_iter().enumerate() {
+        let section = match section {
+            Some(section) => section,
+            None => continue
+        };
+
+        // The section before it in `new` is in the result by now, since
+        // they're added in order.
+        let position = if i == 0 {
+            0
+        } else {
+            result.iter().position(|other: &Section| other.key == keys[i - 1]).unwrap() + 1
+        };
+        if position > 0 {
+            end_last_line(&mut result.index_mut(&(position - 1)).lines);
+        }
+        result.insert(position, section);
+    }
+
     Ok(result)
 }
 
-// Read the given Buffer and tokenize all of the input. Returns a vector of
-// Tokens on success, or an IoError on failure.
-fn tokenize<T: Buffer>(input: &mut T) -> IoResult<Vec<Token>> {
-    let mut tokens: Vec<Token> = Vec::new();
-    let mut current_token: Option<String> = None;
+// Helper function for merge(). Lays out a section that's in both the old
+// document and the new one.
+fn merge_section(old: &Section, new: Section) -> Section {
+    let old_values = old.get_values();
+    let new_values = new.get_values();
+    if old_values == new_values { return old.clone(); }
+
+    // Find the line of the new section that the added values start on, if
+    // they start a line of their own.
+    let mut split = None;
+    if new_values.len() > old_values.len() && new_values.slice_to(old_values.len()) == old_values.as_slice() {
+        let mut count = 0;
+        for (i, &(_, ref values)) in new.lines.iter().enumerate() {
+            if i > 0 && count >= old_values.len() {
+                if count == old_values.len() { split = Some(i); }
+                break;
+            }
+            count += values.len();
+        }
+    }
+
+    let lines = match split {
+        Some(i) => {
+            let mut lines = old.lines.clone();
+            end_last_line(&mut lines);
+            lines.extend(new.lines.into_iter().skip(i));
+            lines
+        }
+        None => keep_comments(old, new.lines)
+    };
+    Section { leading: old.leading.clone(), key: new.key, lines: lines, spans: Vec::new() }
+}
+
+// Helper function for merge_section(). Lays out the lines of a section whose
+// values have changed, keeping the comments and blank lines that came before
+// a line's first value in the old section, so that a comment stays with the
+// value after it. A line whose values haven't changed is kept as it was.
+fn keep_comments(old: &Section, new_lines: Vec<(String, Vec<String>)>) -> Vec<(String, Vec<String>)> {
+    let mut lines = Vec::with_capacity(new_lines.len());
+    for (i, (text, values)) in new_lines.into_iter().enumerate() {
+        // The key's line is the first one in both.
+        let position = if i == 0 {
+            Some(0)
+        } else {
+            old.lines.iter().skip(1).position(|&(_, ref old_values)| {
+                !values.is_empty() && old_values.first() == values.first()
+            }).map(|j| j + 1)
+        };
+
+        let (old_text, old_values) = match position {
+            Some(j) => {
+                let mut start = j;
+                while start > 1 {
+                    let (_, ref values) = old.lines[start - 1];
+                    if !values.is_empty() { break; }
+                    start -= 1;
+                }
+                for k in range(start, j) {
+                    end_last_line(&mut lines);
+                    lines.push(old.lines[k].clone());
+                }
+                old.lines[j].clone()
+            }
+            None => (String::new(), Vec::new())
+        };
+
+        end_last_line(&mut lines);
+        if position.is_some() && old_values == values {
+            lines.push((old_text, old_values));
+        } else {
+            lines.push((text, values));
+        }
+    }
+    lines
+}
+
+// Makes sure the last of a section's lines ends with a line ending, so that
+// something can be written after it. A line might not have one if it's at
+// the end of the file, or the next key was on the same line.
+fn end_last_line(lines: &mut Vec<(String, Vec<String>)>) {
+    match lines.last_mut() {
+        Some(&(ref mut text, _)) if !text.as_slice().ends_with("\n") => text.push('\n'),
+        _ => {}
+    }
+}
+
+// Splits text into lines, each with its line ending, except maybe the last.
+fn split_lines(text: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, ch) in text.char_indices() {
+        if ch == '\n' {
+            lines.push(text.slice(start, i + 1).to_string());
+            start = i + 1;
+        }
+    }
+    if start < text.len() {
+        lines.push(text.slice_from(start).to_string());
+    }
+    lines
+}
+
+// Tokenizes all of the given text, from the file with the given name.
+// Returns a vector of Tokens, each with its span, on success, or an IoError
+// on failure.
+fn tokenize(text: &str, name: &str) -> IoResult<Vec<(Token, Span)>> {
+    let mut tokens: Vec<(Token, Span)> = Vec::new();
+    let mut current_token: Option<(String, Span)> = None;
     let mut in_comment = false;
+    // The opening quote of the quote being read, if any.
+    let mut quote: Option<Span> = None;
     let mut line_num = 1u;
+    let mut line_start = 0u;
 
-    for ch in input.chars() {
-        let ch = try!(ch);
+    for (offset, ch) in text.char_indices() {
+        let span = Span {
+            start: offset,
+            end: text.char_range_at(offset).next,
+            line: line_num,
+            column: text.slice(line_start, offset).chars().count() + 1
+        };
+
+        // Anything but a line break goes in a quote, up to the closing quote.
+        if quote.is_some() {
+            if ch == '\n' {
+                return Err(error_in(name, text, quote.as_ref().unwrap(), "unterminated quote",
+                                    format!("Unterminated quote")));
+            }
+            let (mut token_string, token_span) = current_token.take().unwrap();
+            token_string.push(ch);
+            current_token = Some((token_string, token_span));
+            if ch == '"' { quote = None; }
+            continue;
+        }
 
         if ch == '\n' {
             line_num += 1;
+            line_start = span.end;
             if in_comment { in_comment = false; }
         }
 
@@ -70,50 +415,107 @@ fn tokenize<T: Buffer>(input: &mut T) -> IoResult<Vec<Token>> {
 
         if ch == '#' {
             in_comment = true;
-            if current_token.is_some() {
-                tokens.push(Token::Value(current_token.take().unwrap()));
-            }
+            end_token(&mut tokens, &mut current_token, offset);
         } else if ch.is_whitespace() {
+            end_token(&mut tokens, &mut current_token, offset);
+        } else if ch.is_alphanumeric() || ch == '_' || ch == '!' || ch == '/' || ch == '\'' || ch == '=' ||
+                  ch == '-' || ch == '.' || ch == '"' {
             if current_token.is_some() {
-                tokens.push(Token::Value(current_token.take().unwrap()));
-            }
-        } else if ch.is_alphanumeric() || ch == '_' || ch == '!' {
-            if current_token.is_some() {
-                let mut token_string = current_token.take().unwrap();
+                let (mut token_string, token_span) = current_token.take().unwrap();
                 token_string.push(ch);
-                current_token = Some(token_string);
+                current_token = Some((token_string, token_span));
             } else {
-                current_token = Some(ch.to_string());
+                current_token = Some((ch.to_string(), span.clone()));
             }
+            if ch == '"' { quote = Some(span); }
         } else if ch == ':' && current_token.is_some() {
-            tokens.push(Token::Key(current_token.take().unwrap()));
+            let (token, mut token_span) = current_token.take().unwrap();
+            token_span.end = offset;
+            // Quotes and the punctuation in metadata and derivation paths
+            // are only for values.
+            if !token.as_slice().chars().all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '!') {
+                return Err(error_in(name, text, &token_span, "invalid key",
+                                    format!("Key '{}' can only have letters, digits, '_' and '!'", token)));
+            }
+            tokens.push((Token::Key(token), token_span));
         } else {
-            return Err(IoError {
-                kind: OtherIoError,
-                desc: "unexpected input",
-                detail: Some(format!("Unexpected char '{}' on line {}", ch, line_num))
-            });
+            return Err(error_in(name, text, &span, "unexpected input", format!("Unexpected char '{}'", ch)));
         }
     }
 
-    if current_token.is_some() {
-        tokens.push(Token::Value(current_token.take().unwrap()));
+    match quote {
+        Some(ref span) => return Err(error_in(name, text, span, "unterminated quote", format!("Unterminated quote"))),
+        None => {}
     }
 
+    end_token(&mut tokens, &mut current_token, text.len());
     Ok(tokens)
 }
 
+// Helper function for tokenize(). Adds the value being read, if there is one,
+// to the tokens, ending at the given offset.
+fn end_token(tokens: &mut Vec<(Token, Span)>, current_token: &mut Option<(String, Span)>, end: uint) {
+    match current_token.take() {
+        Some((token, mut span)) => {
+            span.end = end;
+            tokens.push((Token::Value(token), span));
+        }
+        None => {}
+    }
+}
+
+// Makes an IoError about the text at the given span, in the file with the
+// given name. It gives the file, line and column, and shows the line with
+// that text underlined.
+fn error_in(name: &str, text: &str, span: &Span, desc: &'static str, message: String) -> IoError {
+    let line_start = text.slice_to(span.start).rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text.slice_from(span.start).find('\n').map_or(text.len(), |i| span.start + i);
+    let line = text.slice(line_start, line_end).trim_right_chars('\r');
+
+    // Tabs stay tabs under the line, so that the underline lines up.
+    let mut underline: String = text.slice(line_start, span.start).chars().map(|ch| {
+        if ch == '\t' { '\t' } else { ' ' }
+    }).collect();
+    let length = text.slice(span.start, cmp::min(span.end, line_end)).chars().count();
+    for _ in range(0, cmp::max(length, 1)) {
+        underline.push('^');
+    }
+
+    IoError {
+        kind: OtherIoError,
+        desc: desc,
+        detail: Some(format!("{}:{}:{}: {}\n    {}\n    {}", name, span.line, span.column, message, line, underline))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::io::{MemReader, OtherIoError};
+    use std::io::{MemReader, MemWriter, OtherIoError};
+
+    use super::{Token, Span, Document, Section};
+    use super::{parse, parse_document, merge, tokenize};
 
-    use super::Token;
-    use super::{parse, tokenize};
+    static DOCUMENT: &'static str = "# top\n\nalias: # note\n  1abc m/0 # first\n\n  # second\n  1def\n\n\
+                                     # salt\n!salt: 00 !iv: 11\n# end\n";
+
+    fn without_offsets(tokens: Vec<(Token, Span)>) -> Vec<Token> {
+        tokens.into_iter().map(|(token, _)| token).collect()
+    }
+
+    fn write(document: &Document) -> String {
+        let mut writer = MemWriter::new();
+        document.write_to(&mut writer).unwrap();
+        String::from_utf8(writer.get_ref().to_vec()).unwrap()
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
 
     #[test]
     fn test_parse() {
         let mut buf = MemReader::new(b" a: 1 2 !b: 3\n4# #c: 5\nd:6".to_vec());
-        let result = parse(&mut buf);
+        let result = parse(&mut buf, "test");
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), vec![("a".to_string(), vec!["1".to_string(), "2".to_string()]),
                                          ("!b".to_string(), vec!["3".to_string(), "4".to_string()]),
@@ -123,7 +525,7 @@ mod tests {
     #[test]
     fn test_parse_nothing() {
         let mut buf = MemReader::new(vec![]);
-        let result = parse(&mut buf);
+        let result = parse(&mut buf, "test");
         assert!(result.is_ok());
         assert!(result.unwrap().is_empty());
     }
@@ -131,7 +533,7 @@ mod tests {
     #[test]
     fn test_parse_unexpected_value() {
         let mut buf = MemReader::new(b"abc def: hij".to_vec());
-        let result = parse(&mut buf);
+        let result = parse(&mut buf, "test");
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().kind, OtherIoError);
     }
@@ -139,40 +541,176 @@ mod tests {
     #[test]
     fn test_parse_unexpected_key() {
         let mut buf = MemReader::new(b"a: 1 b: 2 a: 3".to_vec());
-        let result = parse(&mut buf);
+        let result = parse(&mut buf, "test");
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().kind, OtherIoError);
     }
 
+    #[test]
+    fn test_parse_error_location() {
+        let result = parse(&mut MemReader::new(b"a: 1\n\tb: 2 $x\n".to_vec()), "WALLET.txt");
+        assert_eq!(result.unwrap_err().detail,
+                   Some("WALLET.txt:2:7: Unexpected char '$'\n    \tb: 2 $x\n    \t     ^".to_string()));
+
+        let result = parse(&mut MemReader::new(b"alias: 1\nalias: 2".to_vec()), "test");
+        assert_eq!(result.unwrap_err().detail,
+                   Some("test:2:1: Key 'alias' is used more than once\n    alias: 2\n    ^^^^^".to_string()));
+
+        let result = parse(&mut MemReader::new(b"a: 1 label=\"x\nb: 2".to_vec()), "test");
+        assert_eq!(result.unwrap_err().detail,
+                   Some("test:1:12: Unterminated quote\n    a: 1 label=\"x\n               ^".to_string()));
+    }
+
+    #[test]
+    fn test_parse_document() {
+        let document = parse_document(&mut MemReader::new(DOCUMENT.as_bytes().to_vec()), "test").unwrap();
+        assert_eq!(document.sections.len(), 3);
+
+        let alias = &document.sections[0];
+        assert_eq!(alias.leading, strings(&["# top\n", "\n"]));
+        assert_eq!(alias.key.as_slice(), "alias");
+        assert_eq!(alias.lines.iter().map(|&(ref line, _)| line.clone()).collect::<Vec<String>>(),
+                   strings(&["alias: # note\n", "  1abc m/0 # first\n", "\n", "  # second\n", "  1def\n"]));
+        assert_eq!(alias.get_values(), strings(&["1abc", "m/0", "1def"]));
+
+        // Two keys on one line.
+        assert_eq!(document.sections[1].leading, strings(&["\n", "# salt\n"]));
+        assert_eq!(document.sections[1].lines, vec![("!salt: 00 ".to_string(), strings(&["00"]))]);
+        assert!(document.sections[2].leading.is_empty());
+        assert_eq!(document.sections[2].lines, vec![("!iv: 11\n".to_string(), strings(&["11"]))]);
+        assert_eq!(document.trailer, strings(&["# end\n"]));
+
+        assert_eq!(write(&document).as_slice(), DOCUMENT);
+
+        assert_eq!(alias.spans.len(), 4);
+        let error = document.error_at("test", &alias.spans[2], "bad path", "Bad path".to_string());
+        assert_eq!(error.detail, Some("test:4:8: Bad path\n      1abc m/0 # first\n           ^^^".to_string()));
+    }
+
+    #[test]
+    fn test_parse_document_without_keys() {
+        let document = parse_document(&mut MemReader::new(b"\n# just a comment".to_vec()), "test").unwrap();
+        assert!(document.sections.is_empty());
+        assert_eq!(document.trailer, strings(&["\n", "# just a comment"]));
+    }
+
+    #[test]
+    fn test_section_new() {
+        let section = Section::new("a", strings(&["# comment", ""]), vec![strings(&["1"]), strings(&["2", "3"])]);
+        assert_eq!(section.leading, strings(&["# comment\n", "\n"]));
+        assert_eq!(section.lines, vec![("a: 1\n".to_string(), strings(&["1"])),
+                                       ("  2 3\n".to_string(), strings(&["2", "3"]))]);
+
+        let section = Section::new("b", Vec::new(), vec![Vec::new(), strings(&["1"])]);
+        assert_eq!(section.lines[0], ("b:\n".to_string(), Vec::new()));
+    }
+
+    #[test]
+    fn test_merge() {
+        let old = parse_document(&mut MemReader::new(DOCUMENT.as_bytes().to_vec()), "test").unwrap();
+        let new = vec![
+            Section::new("alias", Vec::new(), vec![Vec::new(), strings(&["1abc", "m/0"]), strings(&["1def"]),
+                                                   strings(&["1ghi"])]),
+            Section::new("added", strings(&["# added"]), vec![Vec::new(), strings(&["1jkl"])]),
+            Section::new("!salt", strings(&["# new salt"]), vec![strings(&["22"])])
+        ];
+        let old_sections: Vec<&Section> = old.sections.iter().collect();
+        let merged = Document { sections: merge(old_sections.as_slice(), new).unwrap(), trailer: old.trailer.clone() };
+
+        // The new address goes after the old ones, the new alias after the
+        // old alias, the salt keeps its comments, and the iv is dropped.
+        assert_eq!(write(&merged).as_slice(),
+                   "# top\n\nalias: # note\n  1abc m/0 # first\n\n  # second\n  1def\n  1ghi\n\
+                    # added\nadded:\n  1jkl\n\n# salt\n!salt: 22\n# end\n");
+
+        // Comments stay with the value after them when a value in the middle
+        // changes.
+        let text = "a: # note\n  # one\n  1abc\n  # two\n  1def x=1 # first\n\n  # three\n  1ghi\n";
+        let old = parse_document(&mut MemReader::new(text.as_bytes().to_vec()), "test").unwrap();
+        let new = vec![Section::new("a", Vec::new(), vec![Vec::new(), strings(&["1abc"]), strings(&["1def", "x=2"]),
+                                                          strings(&["1ghi"])])];
+        let old_sections: Vec<&Section> = old.sections.iter().collect();
+        let merged = Document { sections: merge(old_sections.as_slice(), new).unwrap(), trailer: Vec::new() };
+        assert_eq!(write(&merged).as_slice(),
+                   "a: # note\n  # one\n  1abc\n  # two\n  1def x=2\n\n  # three\n  1ghi\n");
+    }
+
+    #[test]
+    fn test_merge_repeated_key() {
+        let first = parse_document(&mut MemReader::new(b"a: 1abc\n".to_vec()), "test").unwrap();
+        let second = parse_document(&mut MemReader::new(b"a: 1def\n".to_vec()), "test").unwrap();
+        let old_sections = vec![&first.sections[0], &second.sections[0]];
+        let new = vec![Section::new("a", Vec::new(), vec![strings(&["1abc"])])];
+        assert_eq!(merge(old_sections.as_slice(), new).unwrap_err().kind, OtherIoError);
+    }
+
     #[test]
     fn test_tokenize() {
-        let mut buf = MemReader::new(b" 0 a: 1 2 3 !b: 4\n5# #c: 6\nd:7".to_vec());
-        let tokens = tokenize(&mut buf);
+        let tokens = tokenize(" 0 a: 1 2 3 !b: 4\n5# #c: 6\nd:7", "test");
         assert!(tokens.is_ok());
-        assert_eq!(tokens.unwrap(), vec![Token::Value("0".to_string()),
-                                         Token::Key("a".to_string()),
-                                         Token::Value("1".to_string()),
-                                         Token::Value("2".to_string()),
-                                         Token::Value("3".to_string()),
-                                         Token::Key("!b".to_string()),
-                                         Token::Value("4".to_string()),
-                                         Token::Value("5".to_string()),
-                                         Token::Key("d".to_string()),
-                                         Token::Value("7".to_string())]);
+        assert_eq!(without_offsets(tokens.unwrap()), vec![Token::Value("0".to_string()),
+                                                          Token::Key("a".to_string()),
+                                                          Token::Value("1".to_string()),
+                                                          Token::Value("2".to_string()),
+                                                          Token::Value("3".to_string()),
+                                                          Token::Key("!b".to_string()),
+                                                          Token::Value("4".to_string()),
+                                                          Token::Value("5".to_string()),
+                                                          Token::Key("d".to_string()),
+                                                          Token::Value("7".to_string())]);
+    }
+
+    #[test]
+    fn test_tokenize_spans() {
+        let tokens = tokenize("a: 1\n  bc", "test").unwrap();
+        assert_eq!(tokens, vec![(Token::Key("a".to_string()), Span { start: 0, end: 1, line: 1, column: 1 }),
+                                (Token::Value("1".to_string()), Span { start: 3, end: 4, line: 1, column: 4 }),
+                                (Token::Value("bc".to_string()), Span { start: 7, end: 9, line: 2, column: 3 })]);
+    }
+
+    #[test]
+    fn test_tokenize_derivation_path() {
+        let tokens = tokenize("a: 1abc m/0'/0'/5'", "test");
+        assert!(tokens.is_ok());
+        assert_eq!(without_offsets(tokens.unwrap()), vec![Token::Key("a".to_string()),
+                                                          Token::Value("1abc".to_string()),
+                                                          Token::Value("m/0'/0'/5'".to_string())]);
+    }
+
+    #[test]
+    fn test_tokenize_quoted() {
+        let tokens = tokenize("a: 1abc label=\"Alice's #2: paid\" date=2014-12-24\n", "test");
+        assert!(tokens.is_ok());
+        assert_eq!(without_offsets(tokens.unwrap()), vec![Token::Key("a".to_string()),
+                                                          Token::Value("1abc".to_string()),
+                                                          Token::Value("label=\"Alice's #2: paid\"".to_string()),
+                                                          Token::Value("date=2014-12-24".to_string())]);
+    }
+
+    #[test]
+    fn test_tokenize_invalid_key() {
+        assert!(tokenize("m/0: 1", "test").is_err());
+        assert!(tokenize("a=b: 1", "test").is_err());
+        assert!(tokenize("\"a\": 1", "test").is_err());
+        assert!(tokenize("a: b=\"c: d\"", "test").is_ok());
+    }
+
+    #[test]
+    fn test_tokenize_unterminated_quote() {
+        assert!(tokenize("a: label=\"Alice\nb: 1", "test").is_err());
+        assert!(tokenize("a: label=\"Alice", "test").is_err());
     }
 
     #[test]
     fn test_tokenize_nothing() {
-        let mut buf = MemReader::new(vec![]);
-        let tokens = tokenize(&mut buf);
+        let tokens = tokenize("", "test");
         assert!(tokens.is_ok());
         assert!(tokens.unwrap().is_empty());
     }
 
     #[test]
     fn test_tokenize_unexpected_colon() {
-        let mut buf = MemReader::new(b"a::1".to_vec());
-        let tokens = tokenize(&mut buf);
+        let tokens = tokenize("a::1", "test");
         assert!(tokens.is_err());
         assert_eq!(tokens.unwrap_err().kind, OtherIoError);
     }